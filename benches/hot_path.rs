@@ -0,0 +1,190 @@
+//! Benchmarks of the RPC and event hot paths: a send round-trip through a
+//! loopback signal-cli mock, broadcast fan-out, and webhook dispatch.
+//!
+//! Run with `cargo bench`; compare runs with `--save-baseline`/`--baseline`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use signal_cli_api::event::Event;
+use signal_cli_api::state::{AppState, WebhookConfig};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+
+/// A signal-cli mock in bench mode: every request is answered at once with a
+/// canned result, without the validation and bookkeeping of the regression
+/// suite's mock, so the numbers measure the API rather than the mock.
+async fn start_bench_mock() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let Ok(request) = serde_json::from_str::<serde_json::Value>(&line) else {
+                        continue;
+                    };
+                    let response = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": { "timestamp": 1 },
+                    });
+                    let mut out = response.to_string();
+                    out.push('\n');
+                    if writer.write_all(out.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+/// AppState connected to the bench mock, with the reader and writer loops running.
+async fn connected_state() -> AppState {
+    let stream = tokio::net::TcpStream::connect(start_bench_mock().await)
+        .await
+        .unwrap();
+    let (reader, writer) = stream.into_split();
+    let (writer_tx, writer_rx) = tokio::sync::mpsc::channel::<String>(256);
+    tokio::spawn(signal_cli_api::jsonrpc::writer_loop(writer_rx, writer));
+    let state = AppState::new(writer_tx);
+    tokio::spawn(signal_cli_api::jsonrpc::reader_loop(
+        reader,
+        state.broadcast_tx.clone(),
+        state.pending.clone(),
+        state.metrics.clone(),
+        state.plugins.clone(),
+    ));
+    state
+}
+
+fn incoming(n: u64) -> Event {
+    Event::from(
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "receive",
+            "params": {
+                "account": "+123",
+                "envelope": {
+                    "source": "+111",
+                    "sourceNumber": "+111",
+                    "timestamp": n,
+                    "dataMessage": { "message": format!("message {n}"), "timestamp": n }
+                }
+            }
+        })
+        .to_string(),
+    )
+}
+
+fn send_round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let state = rt.block_on(connected_state());
+    let params =
+        serde_json::json!({ "account": "+123", "recipient": ["+456"], "message": "bench" });
+    c.bench_function("send_round_trip", |b| {
+        b.iter(|| rt.block_on(state.rpc("send", params.clone())).unwrap());
+    });
+}
+
+fn broadcast_fan_out(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("broadcast_fan_out");
+    for subscribers in [1usize, 16, 128] {
+        group.throughput(Throughput::Elements(subscribers as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(subscribers),
+            &subscribers,
+            |b, &n| {
+                let (tx, _) = tokio::sync::broadcast::channel::<Event>(256);
+                let mut receivers: Vec<_> = (0..n).map(|_| tx.subscribe()).collect();
+                let mut seq = 0;
+                b.iter(|| {
+                    seq += 1;
+                    tx.send(incoming(seq)).unwrap();
+                    rt.block_on(async {
+                        for rx in &mut receivers {
+                            let event = rx.recv().await.unwrap();
+                            std::hint::black_box(event.bytes_for_client(false));
+                        }
+                    });
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// A webhook receiver counting deliveries and waking the bench on each.
+async fn start_receiver(delivered: Arc<AtomicU64>, notify: Arc<Notify>) -> SocketAddr {
+    let app = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(move || {
+            let delivered = delivered.clone();
+            let notify = notify.clone();
+            async move {
+                delivered.fetch_add(1, Ordering::Relaxed);
+                notify.notify_one();
+                axum::http::StatusCode::OK
+            }
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+fn webhook_dispatch(c: &mut Criterion) {
+    const BATCH: u64 = 100;
+    let rt = Runtime::new().unwrap();
+    let delivered = Arc::new(AtomicU64::new(0));
+    let notify = Arc::new(Notify::new());
+    let state = rt.block_on(async {
+        let addr = start_receiver(delivered.clone(), notify.clone()).await;
+        let state = connected_state().await;
+        let hook: WebhookConfig = serde_json::from_value(
+            serde_json::json!({ "id": "bench", "url": format!("http://{addr}/hook") }),
+        )
+        .unwrap();
+        state.webhooks.write().await.push(hook);
+        tokio::spawn(signal_cli_api::webhooks::dispatch_loop(state.clone()));
+        // Let the dispatcher subscribe and open the webhook's lane.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        state
+    });
+
+    let mut group = c.benchmark_group("webhook_dispatch");
+    group.throughput(Throughput::Elements(BATCH));
+    group.bench_function(BenchmarkId::from_parameter(BATCH), |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                let target = delivered.load(Ordering::Relaxed) + BATCH;
+                // BATCH stays below the broadcast capacity, so the dispatcher never lags.
+                for n in 0..BATCH {
+                    let _ = state.broadcast_tx.send(incoming(n));
+                }
+                while delivered.load(Ordering::Relaxed) < target {
+                    notify.notified().await;
+                }
+            });
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    send_round_trip,
+    broadcast_fan_out,
+    webhook_dispatch
+);
+criterion_main!(benches);
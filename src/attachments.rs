@@ -0,0 +1,448 @@
+use base64::Engine;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::exposition::Exposition;
+use crate::state::{now_millis, AppState};
+
+/// How often the retention job runs by default.
+pub const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Which attachment types may be sent, checked against the type sniffed
+/// from the content rather than the one the client declares.
+#[derive(Clone, Debug)]
+pub struct MimePolicy {
+    /// Allowed types; `type/*` matches a whole family.
+    pub allowed: Vec<String>,
+    /// Accept attachments whose declared type contradicts the sniffed one.
+    pub allow_mismatch: bool,
+}
+
+impl MimePolicy {
+    /// Parse a comma-separated allowlist such as `image/*,application/pdf`.
+    pub fn parse(list: &str, allow_mismatch: bool) -> Result<Self, String> {
+        let allowed: Vec<String> = list
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        if let Some(bad) = allowed.iter().find(|t| {
+            t.split_once('/')
+                .is_none_or(|(a, b)| a.is_empty() || b.is_empty())
+        }) {
+            return Err(format!("invalid MIME type {bad:?}"));
+        }
+        Ok(Self {
+            allowed,
+            allow_mismatch,
+        })
+    }
+
+    fn allows(&self, mime: &str) -> bool {
+        self.allowed
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(family) => mime.split_once('/').is_some_and(|(f, _)| f == family),
+                None => pattern == mime,
+            })
+    }
+
+    /// Check one attachment; `declared` is the type from its data URI, if any.
+    pub fn check(&self, declared: Option<&str>, data: &[u8]) -> Result<(), String> {
+        let sniffed = sniff(data);
+        if !self.allows(sniffed) {
+            return Err(format!("type {sniffed} is not allowed"));
+        }
+        if let Some(declared) = declared {
+            let declared = declared.to_ascii_lowercase();
+            if !self.allow_mismatch && !compatible(&declared, sniffed) {
+                return Err(format!("declared as {declared} but content is {sniffed}"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a declared type is consistent with the sniffed one. Generic
+/// declarations and text subtypes (which can't be told apart) always are.
+fn compatible(declared: &str, sniffed: &str) -> bool {
+    declared == sniffed
+        || declared == "application/octet-stream"
+        || (declared.starts_with("text/") && sniffed == "text/plain")
+        || (declared == "image/jpg" && sniffed == "image/jpeg")
+}
+
+/// The content type of `data` from its magic bytes: common media and
+/// document formats, executables and scripts, then UTF-8 text; anything else
+/// is `application/octet-stream`.
+pub fn sniff(data: &[u8]) -> &'static str {
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+    match data {
+        _ if at(0, b"\xFF\xD8\xFF") => "image/jpeg",
+        _ if at(0, b"\x89PNG\r\n\x1a\n") => "image/png",
+        _ if at(0, b"GIF87a") || at(0, b"GIF89a") => "image/gif",
+        _ if at(0, b"RIFF") && at(8, b"WEBP") => "image/webp",
+        _ if at(0, b"RIFF") && at(8, b"WAVE") => "audio/wav",
+        _ if at(0, b"BM") && data.len() > 14 => "image/bmp",
+        _ if at(4, b"ftypheic") || at(4, b"ftypheix") || at(4, b"ftypmif1") => "image/heic",
+        _ if at(4, b"ftypqt") => "video/quicktime",
+        _ if at(4, b"ftypM4A") => "audio/mp4",
+        _ if at(4, b"ftyp") => "video/mp4",
+        _ if at(0, b"\x1A\x45\xDF\xA3") => "video/webm",
+        _ if at(0, b"OggS") => "audio/ogg",
+        _ if at(0, b"ID3") || at(0, b"\xFF\xFB") || at(0, b"\xFF\xF3") => "audio/mpeg",
+        _ if at(0, b"#!AMR") => "audio/amr",
+        _ if at(0, b"%PDF-") => "application/pdf",
+        _ if at(0, b"PK\x03\x04") => "application/zip",
+        _ if at(0, b"\x1F\x8B") => "application/gzip",
+        _ if at(0, b"MZ") => "application/x-msdownload",
+        _ if at(0, b"\x7FELF") => "application/x-executable",
+        _ if at(0, b"\xCF\xFA\xED\xFE")
+            || at(0, b"\xCE\xFA\xED\xFE")
+            || at(0, b"\xCA\xFE\xBA\xBE") =>
+        {
+            "application/x-mach-binary"
+        }
+        _ if at(0, b"#!") => "text/x-shellscript",
+        _ if !data.contains(&0) && std::str::from_utf8(data).is_ok() => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Where outgoing attachments are staged, which directories the retention
+/// job prunes, and the disk usage it last measured.
+pub struct AttachmentStore {
+    /// Directory `base64_attachments` are written to before sending (None =
+    /// they are passed to signal-cli as given).
+    pub staging_dir: Option<PathBuf>,
+    /// signal-cli's attachment folder, pruned and measured alongside staging.
+    pub signal_dir: Option<PathBuf>,
+    /// Files older than this are deleted (None = keep forever).
+    pub retention: Option<Duration>,
+    pub sweep_interval: Duration,
+    /// Allowed `base64_attachments` types (None = anything goes).
+    pub policy: Option<MimePolicy>,
+    /// Free bytes to keep on the filesystems of both directories: below
+    /// this, uploads are refused and the oldest cached files are pruned.
+    pub min_free: Option<u64>,
+    staged: AtomicU64,
+    pruned: AtomicU64,
+    refused: AtomicU64,
+    staging_bytes: AtomicU64,
+    signal_bytes: AtomicU64,
+}
+
+impl Default for AttachmentStore {
+    fn default() -> Self {
+        Self::new(None, None, None)
+    }
+}
+
+impl AttachmentStore {
+    pub fn new(
+        staging_dir: Option<PathBuf>,
+        signal_dir: Option<PathBuf>,
+        retention: Option<Duration>,
+    ) -> Self {
+        Self {
+            staging_dir,
+            signal_dir,
+            retention,
+            sweep_interval: DEFAULT_SWEEP_INTERVAL,
+            policy: None,
+            min_free: None,
+            staged: AtomicU64::new(0),
+            pruned: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+            staging_bytes: AtomicU64::new(0),
+            signal_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Check a send payload's `base64_attachments` (plain base64, or
+    /// `data:<mime>;filename=<name>;base64,<data>`) against the MIME policy
+    /// and, with a staging directory, write them to files listed under
+    /// `attachments` instead. Without either, the payload is left alone.
+    pub fn stage(&self, params: &mut Value) -> Result<(), String> {
+        if self.staging_dir.is_none() && self.policy.is_none() {
+            return Ok(());
+        }
+        let Some(encoded) = params.get("base64_attachments") else {
+            return Ok(());
+        };
+        let Value::Array(encoded) = encoded else {
+            return Err("base64_attachments must be an array of strings".into());
+        };
+        let mut decoded = Vec::with_capacity(encoded.len());
+        for (i, item) in encoded.iter().enumerate() {
+            let item = item
+                .as_str()
+                .ok_or("base64_attachments must be an array of strings")?;
+            let uri = parse_data_uri(item);
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(uri.data)
+                .map_err(|e| format!("base64_attachments[{i}] is not valid base64: {e}"))?;
+            if let Some(policy) = &self.policy {
+                policy
+                    .check(uri.mime, &bytes)
+                    .map_err(|e| format!("base64_attachments[{i}] rejected: {e}"))?;
+            }
+            decoded.push((uri.filename, bytes));
+        }
+
+        let Some(dir) = &self.staging_dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("attachment dir {}: {e}", dir.display()))?;
+        let mut paths = Vec::with_capacity(decoded.len());
+        for (filename, bytes) in decoded {
+            let n = self.staged.fetch_add(1, Ordering::Relaxed);
+            let name = match filename {
+                Some(f) => format!("{}-{n}-{f}", now_millis()),
+                None => format!("{}-{n}", now_millis()),
+            };
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).map_err(|e| format!("staging attachment: {e}"))?;
+            paths.push(json!(path.to_string_lossy()));
+        }
+        if let Some(obj) = params.as_object_mut() {
+            obj.remove("base64_attachments");
+        }
+        match params.get_mut("attachments") {
+            Some(Value::Array(existing)) => existing.extend(paths),
+            _ => params["attachments"] = Value::Array(paths),
+        }
+        Ok(())
+    }
+
+    fn dirs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.staging_dir.iter().chain(self.signal_dir.iter())
+    }
+
+    /// Refuse an upload when a watched filesystem is below the `min_free`
+    /// watermark.
+    pub fn check_space(&self) -> Result<(), String> {
+        let Some(min_free) = self.min_free else {
+            return Ok(());
+        };
+        for dir in self.dirs() {
+            if let Some(free) = free_space(dir).filter(|free| *free < min_free) {
+                self.refused.fetch_add(1, Ordering::Relaxed);
+                return Err(format!(
+                    "insufficient storage: {free} bytes free for {}, {min_free} required",
+                    dir.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete files past retention from the staging and signal-cli
+    /// directories, and record what's left on disk. Below the `min_free`
+    /// watermark, the oldest files are deleted too until enough space is
+    /// free again.
+    pub fn sweep(&self) {
+        if let Some(min_free) = self.min_free {
+            for dir in self.dirs() {
+                if free_space(dir).is_some_and(|free| free < min_free) {
+                    let pruned = prune_oldest(dir, min_free);
+                    tracing::warn!(dir = %dir.display(), pruned, "Low on disk space, pruned cached attachments");
+                    self.pruned.fetch_add(pruned, Ordering::Relaxed);
+                }
+            }
+        }
+        let cutoff = self
+            .retention
+            .and_then(|r| SystemTime::now().checked_sub(r));
+        for (dir, bytes) in [
+            (&self.staging_dir, &self.staging_bytes),
+            (&self.signal_dir, &self.signal_bytes),
+        ] {
+            if let Some(dir) = dir {
+                let (kept, pruned) = sweep_dir(dir, cutoff);
+                bytes.store(kept, Ordering::Relaxed);
+                self.pruned.fetch_add(pruned, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Append disk usage and pruning counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        if self.min_free.is_some() {
+            let mut free = Vec::new();
+            for (labels, dir) in [
+                (&[("dir", "staging")][..], &self.staging_dir),
+                (&[("dir", "signal")][..], &self.signal_dir),
+            ] {
+                if let Some(bytes) = dir.as_deref().and_then(free_space) {
+                    free.push((labels, bytes));
+                }
+            }
+            exp.gauge(
+                "signal_disk_free_bytes",
+                "Free bytes on the filesystem of an attachment directory",
+                &free,
+            );
+            exp.counter(
+                "signal_uploads_refused_low_disk",
+                "Attachment uploads refused below the free disk watermark",
+                &[(&[], self.refused.load(Ordering::Relaxed))],
+            );
+        }
+        let mut usage = Vec::new();
+        if self.staging_dir.is_some() {
+            usage.push((
+                &[("dir", "staging")][..],
+                self.staging_bytes.load(Ordering::Relaxed),
+            ));
+        }
+        if self.signal_dir.is_some() {
+            usage.push((
+                &[("dir", "signal")][..],
+                self.signal_bytes.load(Ordering::Relaxed),
+            ));
+        }
+        if usage.is_empty() {
+            return;
+        }
+        exp.gauge(
+            "signal_attachment_disk_bytes",
+            "Bytes used by cached attachments",
+            &usage,
+        );
+        exp.counter(
+            "signal_attachments_pruned",
+            "Cached attachments deleted by the retention job",
+            &[(&[], self.pruned.load(Ordering::Relaxed))],
+        );
+    }
+}
+
+/// Decode a `base64_attachments` entry into its file name (if given) and bytes.
+pub fn decode(item: &str) -> Result<(Option<String>, Vec<u8>), String> {
+    let uri = parse_data_uri(item);
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(uri.data)
+        .map_err(|e| format!("invalid base64 attachment: {e}"))?;
+    Ok((uri.filename, bytes))
+}
+
+/// A `base64_attachments` entry split into its parts.
+struct DataUri<'a> {
+    mime: Option<&'a str>,
+    /// Sanitized to a plain file name.
+    filename: Option<String>,
+    data: &'a str,
+}
+
+/// Split an optional `data:<mime>;filename=<name>;base64,` prefix off an
+/// attachment.
+fn parse_data_uri(item: &str) -> DataUri<'_> {
+    let plain = DataUri {
+        mime: None,
+        filename: None,
+        data: item,
+    };
+    let Some(rest) = item.strip_prefix("data:") else {
+        return plain;
+    };
+    let Some((meta, data)) = rest.split_once(',') else {
+        return plain;
+    };
+    let mut parts = meta.split(';');
+    let mime = parts.next().filter(|m| m.contains('/'));
+    let filename = parts
+        .find_map(|part| part.strip_prefix("filename="))
+        .map(|f| f.replace(['/', '\\'], "_"))
+        .filter(|f| !f.is_empty() && f != "." && f != "..");
+    DataUri {
+        mime,
+        filename,
+        data,
+    }
+}
+
+/// Remove files modified before `cutoff` (if any) and return the bytes kept
+/// and the number of files removed.
+fn sweep_dir(dir: &Path, cutoff: Option<SystemTime>) -> (u64, u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return (0, 0);
+    };
+    let (mut kept, mut pruned) = (0, 0);
+    for entry in entries.flatten() {
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_file() {
+            continue;
+        }
+        let expired = cutoff.is_some_and(|c| meta.modified().is_ok_and(|m| m < c));
+        if expired {
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => pruned += 1,
+                Err(e) => tracing::warn!("Could not prune {}: {e}", entry.path().display()),
+            }
+        } else {
+            kept += meta.len();
+        }
+    }
+    (kept, pruned)
+}
+
+/// Free bytes available to unprivileged users on the filesystem holding
+/// `dir`, or its nearest existing ancestor.
+pub fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let existing = dir.ancestors().find(|d| d.exists())?;
+    let path = std::ffi::CString::new(existing.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)] // the field types differ between platforms
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Delete files in `dir`, oldest first, until its filesystem has `min_free`
+/// bytes free or the directory is empty. Returns the number deleted.
+fn prune_oldest(dir: &Path, min_free: u64) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut files: Vec<(SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter_map(|e| {
+            let meta = e.metadata().ok().filter(|m| m.is_file())?;
+            Some((meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), e.path()))
+        })
+        .collect();
+    files.sort();
+    let mut pruned = 0;
+    for (_, path) in files {
+        if free_space(dir).is_some_and(|free| free >= min_free) {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => pruned += 1,
+            Err(e) => tracing::warn!("Could not prune {}: {e}", path.display()),
+        }
+    }
+    pruned
+}
+
+/// Periodically prunes expired attachments and refreshes disk usage.
+pub async fn run(state: AppState) {
+    let store = state.attachments.clone();
+    if store.staging_dir.is_none() && store.signal_dir.is_none() {
+        return;
+    }
+    loop {
+        let sweeper = store.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || sweeper.sweep()).await {
+            tracing::warn!("Attachment sweep failed: {e}");
+        }
+        tokio::time::sleep(store.sweep_interval).await;
+    }
+}
@@ -0,0 +1,305 @@
+use axum::body::Body;
+use axum::extract::{FromRequestParts, RawPathParams, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Json;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::jwt::{self, Grant, Scope};
+use crate::state::{now_millis, AppState};
+use crate::tenant::Tenant;
+
+/// Header naming the token whose secret signed the request.
+pub const KEY_ID_HEADER: &str = "x-signature-key-id";
+/// Header carrying the signing time in Unix seconds.
+pub const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+/// Header carrying the hex HMAC-SHA256 signature (optionally "sha256=" prefixed).
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Default tolerance between the signing timestamp and server time.
+pub const DEFAULT_REPLAY_WINDOW: Duration = Duration::from_secs(300);
+
+/// Largest request body buffered for signature verification.
+const MAX_SIGNED_BODY: usize = 64 * 1024 * 1024;
+
+/// Paths that stay reachable without credentials (the UI page is static and
+/// asks for a token itself, unless OIDC login is configured).
+const PUBLIC_PATHS: &[&str] = &["/v1/health", "/metrics", "/ui"];
+
+/// With OIDC configured, the operator surface (UI and admin endpoints)
+/// always requires a login session, a provider-issued JWT or an API token.
+fn operator_path(path: &str) -> bool {
+    path == "/ui" || path == "/v1/admin" || path.starts_with("/v1/admin/")
+}
+
+/// An API token. Callers authenticate with `Authorization: Bearer <secret>`,
+/// or sign requests with the secret (see [`signature`]).
+#[derive(Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub secret: String,
+}
+
+impl ApiToken {
+    /// Parse an `id:secret` pair as given on the command line.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once(':') {
+            Some((id, secret)) if !id.is_empty() && !secret.is_empty() => Ok(Self {
+                id: id.to_string(),
+                secret: secret.to_string(),
+            }),
+            _ => Err(format!("invalid token {spec:?}, expected id:secret")),
+        }
+    }
+}
+
+/// ID of the API token a request authenticated with, attached to the request
+/// for handlers that account usage per token.
+#[derive(Clone, Debug)]
+pub struct TokenId(pub String);
+
+/// The canonical string covered by a request signature:
+/// `"<timestamp>\n<METHOD>\n<path?query>\n<hex sha256 of body>"`.
+pub fn canonical_request(
+    timestamp: u64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> String {
+    let body_hash = hex::encode(Sha256::digest(body));
+    format!(
+        "{timestamp}\n{}\n{path_and_query}\n{body_hash}",
+        method.to_uppercase()
+    )
+}
+
+/// Hex HMAC-SHA256 of the canonical request under `secret`.
+pub fn signature(
+    secret: &str,
+    timestamp: u64,
+    method: &str,
+    path_and_query: &str,
+    body: &[u8],
+) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(canonical_request(timestamp, method, path_and_query, body).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Remembers recently seen signatures so a captured request can't be
+/// replayed inside the timestamp window.
+#[derive(Default)]
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<String, u64>>, // signature -> expiry (ms)
+}
+
+impl ReplayGuard {
+    /// Record `signature`; false if it was already used and hasn't expired.
+    pub fn check_and_record(&self, signature: &str, window: Duration) -> bool {
+        let now = now_millis();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, expiry| *expiry > now);
+        if seen.contains_key(signature) {
+            return false;
+        }
+        // A timestamp may be up to `window` in the future, so keep it for twice that.
+        seen.insert(signature.to_string(), now + 2 * window.as_millis() as u64);
+        true
+    }
+}
+
+fn unauthorized(error: &str) -> Response {
+    (StatusCode::UNAUTHORIZED, Json(json!({ "error": error }))).into_response()
+}
+
+fn forbidden(error: &str) -> Response {
+    (StatusCode::FORBIDDEN, Json(json!({ "error": error }))).into_response()
+}
+
+/// Compare secrets in time that doesn't depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Verify a bearer JWT: first as a machine-to-machine token (which carries a
+/// [`Grant`]), then as a token from the OIDC provider (which doesn't).
+async fn verify_bearer_jwt(st: &AppState, token: &str) -> Result<Option<Grant>, String> {
+    let mut error = "missing or invalid credentials".to_string();
+    if let Some(auth) = &st.jwt {
+        match auth.authenticate(token).await {
+            Ok(grant) => return Ok(Some(grant)),
+            Err(e) => error = e,
+        }
+    }
+    if let Some(oidc) = &st.oidc {
+        match oidc.verify_token(token).await {
+            Ok(_) => return Ok(None),
+            Err(e) if st.jwt.is_none() => error = e,
+            Err(_) => {}
+        }
+    }
+    Err(error)
+}
+
+/// Enforce a JWT grant's scopes and account allowlist on the matched route,
+/// then make the grant available to handlers.
+async fn authorize(st: &AppState, grant: Grant, request: Request, next: Next) -> Response {
+    let scope = Scope::required(request.method(), request.uri().path());
+    if !grant.allows(scope) {
+        return forbidden(&format!("token lacks the {} scope", scope.as_str()));
+    }
+    let (mut parts, body) = request.into_parts();
+    if let Ok(params) = RawPathParams::from_request_parts(&mut parts, st).await {
+        if let Some((_, number)) = params.iter().find(|(key, _)| *key == "number") {
+            let account = st.resolve_account(number);
+            if !grant.allows_account(&account, |a| st.resolve_account(a)) {
+                return forbidden(&format!("token may not use account {account}"));
+            }
+        }
+    }
+    tracing::debug!(subject = grant.subject, "Authenticated JWT");
+    parts.extensions.insert(grant);
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Keep a tenant's token to the routes open to tenants and to the tenant's
+/// accounts, then make the tenant available to handlers. Webhook routes are
+/// admin-only for others; their handlers keep a tenant to its own webhooks.
+async fn confine(st: &AppState, tenant: Arc<Tenant>, request: Request, next: Next) -> Response {
+    tenant.inc_request();
+    let (mut parts, body) = request.into_parts();
+    let account_route = RawPathParams::from_request_parts(&mut parts, st)
+        .await
+        .is_ok_and(|params| params.iter().any(|(key, _)| key == "number"));
+    let path = parts.uri.path().to_string();
+    if !Tenant::may_use(&path, account_route) {
+        return forbidden(&format!("tenant {} may not use {path}", tenant.name));
+    }
+    let grant = tenant.grant();
+    parts.extensions.insert(tenant);
+    let request = Request::from_parts(parts, body);
+    if path == "/v1/webhooks" || path.starts_with("/v1/webhooks/") {
+        return next.run(request).await;
+    }
+    authorize(st, grant, request, next).await
+}
+
+/// Middleware enforcing token auth once any token (or a JWT issuer) is
+/// configured. Accepts a bearer secret, an HMAC-signed request or a bearer
+/// JWT; with none configured the API stays open. With OIDC configured, a
+/// login session or a bearer JWT from the provider is accepted too, and the UI
+/// and admin endpoints are never open. A tenant's token is [`confine`]d.
+pub async fn require_auth(
+    State(st): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let tokens = st.tokens.read().await.clone();
+    let path = request.uri().path();
+    let operator = st.oidc.is_some() && operator_path(path);
+    let open = tokens.is_empty() && st.jwt.is_none();
+    if path.starts_with("/auth/") || (!operator && (open || PUBLIC_PATHS.contains(&path))) {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    if let Some(oidc) = &st.oidc {
+        if oidc.session(header(headers, "cookie")).is_some() {
+            return next.run(request).await;
+        }
+        if path == "/ui" && header(headers, "authorization").is_none() {
+            return Redirect::to("/auth/login?next=/ui").into_response();
+        }
+    }
+
+    if let Some(key_id) = header(headers, KEY_ID_HEADER) {
+        let Some(token) = tokens.iter().find(|t| t.id == key_id) else {
+            return unauthorized("unknown signing key");
+        };
+        let Some(timestamp) = header(headers, TIMESTAMP_HEADER).and_then(|t| t.parse::<u64>().ok())
+        else {
+            return unauthorized("missing or invalid signature timestamp");
+        };
+        let Some(sig) = header(headers, SIGNATURE_HEADER) else {
+            return unauthorized("missing signature");
+        };
+        let sig = sig
+            .strip_prefix("sha256=")
+            .unwrap_or(sig)
+            .to_ascii_lowercase();
+        let now_secs = now_millis() / 1000;
+        if now_secs.abs_diff(timestamp) > st.hmac_window.as_secs() {
+            return unauthorized("signature timestamp outside the allowed window");
+        }
+
+        let token_id = TokenId(token.id.clone());
+        let (mut parts, body) = request.into_parts();
+        let Ok(bytes) = axum::body::to_bytes(body, MAX_SIGNED_BODY).await else {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({ "error": "body too large to verify" })),
+            )
+                .into_response();
+        };
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        let expected = signature(
+            &token.secret,
+            timestamp,
+            parts.method.as_str(),
+            path,
+            &bytes,
+        );
+        if !constant_time_eq(expected.as_bytes(), sig.as_bytes()) {
+            return unauthorized("signature mismatch");
+        }
+        if !st.replay_guard.check_and_record(&sig, st.hmac_window) {
+            return unauthorized("signature already used");
+        }
+        parts.extensions.insert(token_id);
+        let request = Request::from_parts(parts, Body::from(bytes));
+        if let Some(tenant) = st.tenants.for_token(&token.id) {
+            return confine(&st, tenant.clone(), request, next).await;
+        }
+        return next.run(request).await;
+    }
+
+    let bearer = header(headers, "authorization").and_then(|v| v.strip_prefix("Bearer "));
+    let token = bearer.and_then(|secret| {
+        tokens
+            .iter()
+            .find(|t| constant_time_eq(t.secret.as_bytes(), secret.as_bytes()))
+    });
+    if let Some(token) = token {
+        let token_id = TokenId(token.id.clone());
+        request.extensions_mut().insert(token_id);
+        if let Some(tenant) = st.tenants.for_token(&token.id) {
+            return confine(&st, tenant.clone(), request, next).await;
+        }
+        return next.run(request).await;
+    }
+    match bearer {
+        Some(token) if jwt::looks_like_jwt(token) && (st.jwt.is_some() || st.oidc.is_some()) => {
+            match verify_bearer_jwt(&st, token).await {
+                Ok(Some(grant)) => authorize(&st, grant, request, next).await,
+                Ok(None) => next.run(request).await,
+                Err(e) => unauthorized(&e),
+            }
+        }
+        _ => unauthorized("missing or invalid credentials"),
+    }
+}
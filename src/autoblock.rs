@@ -0,0 +1,293 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::EventKind;
+use crate::spam::{Action, Quarantined};
+use crate::state::{now_millis, AppState};
+use crate::store::{envelope_source, split_notification};
+
+/// What gets a sender blocked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// A message from the sender matched a `--spam-rule`.
+    Spam,
+    /// The sender sent more than `max` messages within `per`.
+    Rate { max: usize, per: Duration },
+}
+
+impl Trigger {
+    /// Parse `spam` or `rate:<max>/<secs>`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid =
+            || format!("invalid auto-block trigger {spec:?}, expected spam or rate:<max>/<secs>");
+        if spec == "spam" {
+            return Ok(Trigger::Spam);
+        }
+        let (max, secs) = spec
+            .strip_prefix("rate:")
+            .and_then(|r| r.split_once('/'))
+            .ok_or_else(invalid)?;
+        let max: usize = max.parse().map_err(|_| invalid())?;
+        let secs: u64 = secs.parse().map_err(|_| invalid())?;
+        if max == 0 || secs == 0 {
+            return Err(invalid());
+        }
+        Ok(Trigger::Rate {
+            max,
+            per: Duration::from_secs(secs),
+        })
+    }
+}
+
+/// The trigger as written on the command line.
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trigger::Spam => f.write_str("spam"),
+            Trigger::Rate { max, per } => write!(f, "rate:{max}/{}", per.as_secs()),
+        }
+    }
+}
+
+/// A sender blocked by the policy.
+#[derive(Clone, Debug, Serialize)]
+pub struct Block {
+    pub id: String,
+    pub account: String,
+    pub sender: String,
+    /// The trigger, e.g. `rate:10/60` or `spam:url`.
+    pub reason: String,
+    pub blocked_at: u64,
+}
+
+/// Blocks senders that trip a trigger, and remembers the blocks so they can
+/// be undone. A sender whose block is undone isn't blocked again.
+pub struct AutoBlocker {
+    pub triggers: Vec<Trigger>,
+    blocks: Mutex<Vec<Block>>,
+    exempt: Mutex<HashSet<(String, String)>>,
+    next_id: AtomicU64,
+}
+
+impl AutoBlocker {
+    pub fn new(triggers: Vec<Trigger>) -> Self {
+        Self {
+            triggers,
+            blocks: Mutex::new(Vec::new()),
+            exempt: Mutex::new(HashSet::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    fn on_spam(&self) -> bool {
+        self.triggers.contains(&Trigger::Spam)
+    }
+
+    /// Whether `sender` may still be blocked: not already blocked by the
+    /// policy, nor unblocked through it.
+    fn may_block(&self, account: &str, sender: &str) -> bool {
+        let key = (account.to_string(), sender.to_string());
+        !self.exempt.lock().unwrap().contains(&key)
+            && !self
+                .blocks
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|b| b.account == account && b.sender == sender)
+    }
+
+    fn record(&self, account: &str, sender: &str, reason: String) -> Block {
+        let seq = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let block = Block {
+            id: format!("block-{seq}"),
+            account: account.to_string(),
+            sender: sender.to_string(),
+            reason,
+            blocked_at: now_millis(),
+        };
+        self.blocks.lock().unwrap().push(block.clone());
+        block
+    }
+
+    /// Blocks made for `account`, oldest first.
+    pub fn list(&self, account: &str) -> Vec<Block> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|b| b.account == account)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, account: &str, id: &str) -> Option<Block> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|b| b.account == account && b.id == id)
+            .cloned()
+    }
+
+    /// Forget a block once the sender is unblocked, and exempt the sender
+    /// from the policy from now on.
+    pub fn undo(&self, block: &Block) {
+        self.blocks.lock().unwrap().retain(|b| b.id != block.id);
+        self.exempt
+            .lock()
+            .unwrap()
+            .insert((block.account.clone(), block.sender.clone()));
+    }
+}
+
+/// The sender of an envelope: its number, or its UUID when it has none.
+fn sender_of(envelope: &Value) -> &str {
+    match envelope_source(envelope) {
+        "" => envelope
+            .get("sourceUuid")
+            .and_then(|v| v.as_str())
+            .unwrap_or(""),
+        sender => sender,
+    }
+}
+
+/// Watches incoming messages (and, for the `spam` trigger, spam matches) and
+/// blocks senders that trip a trigger with signal-cli's `block`, publishing
+/// an `auto_block` system event for each.
+pub async fn run(state: AppState) {
+    let Some(blocker) = state.auto_block.clone() else {
+        return;
+    };
+    let mut rx = state.broadcast_tx.subscribe();
+    let mut matches = state.spam.as_ref().map(|s| s.subscribe());
+    let mut recent: HashMap<(String, String), VecDeque<Instant>> = HashMap::new();
+    let longest = blocker
+        .triggers
+        .iter()
+        .filter_map(|t| match t {
+            Trigger::Rate { per, .. } => Some(*per),
+            Trigger::Spam => None,
+        })
+        .max();
+    loop {
+        // A message and whether it matched a spam rule (and which).
+        let (account, sender, spam) = tokio::select! {
+            event = rx.recv() => match event {
+                Ok(event) if event.kind() == EventKind::Message => {
+                    let Some((account, envelope)) = split_notification(event.json()) else {
+                        continue;
+                    };
+                    (account.to_string(), sender_of(envelope).to_string(), None)
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("Auto-block watcher lagged, {n} notifications skipped");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Some(matched) = recv_match(&mut matches) => {
+                let Quarantined { account, rules, action, envelope, .. } = matched;
+                let sender = sender_of(&envelope).to_string();
+                // Tagged messages also arrive on the broadcast channel and are
+                // counted there; dropped ones only come this way.
+                if action == Action::Tag && !blocker.on_spam() {
+                    continue;
+                }
+                (account, sender, Some((rules, action)))
+            }
+        };
+        if sender.is_empty() || sender == account || !state.leadership.is_leader() {
+            continue;
+        }
+
+        let mut reason = None;
+        if let Some((rules, _)) = &spam {
+            if blocker.on_spam() {
+                reason = Some(format!("spam:{}", rules.join(",")));
+            }
+        }
+        let counts = spam
+            .as_ref()
+            .is_none_or(|(_, action)| *action == Action::Drop);
+        if let (Some(longest), true) = (longest, counts) {
+            let now = Instant::now();
+            recent
+                .entry((account.clone(), sender.clone()))
+                .or_default()
+                .push_back(now);
+            recent.retain(|_, times| {
+                while times
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) > longest)
+                {
+                    times.pop_front();
+                }
+                !times.is_empty()
+            });
+            let times = &recent[&(account.clone(), sender.clone())];
+            for trigger in &blocker.triggers {
+                if let Trigger::Rate { max, per } = trigger {
+                    if reason.is_none()
+                        && times
+                            .iter()
+                            .filter(|t| now.duration_since(**t) <= *per)
+                            .count()
+                            > *max
+                    {
+                        reason = Some(trigger.to_string());
+                    }
+                }
+            }
+        }
+
+        let Some(reason) = reason else {
+            continue;
+        };
+        if !blocker.may_block(&account, &sender) {
+            continue;
+        }
+        match state
+            .rpc(
+                "block",
+                json!({ "account": account, "recipient": [sender] }),
+            )
+            .await
+        {
+            Ok(_) => {
+                let block = blocker.record(&account, &sender, reason);
+                tracing::info!(
+                    account,
+                    sender,
+                    reason = block.reason,
+                    "Sender blocked by the auto-block policy"
+                );
+                recent.remove(&(account, sender));
+                state.publish_system("auto_block", json!(block));
+            }
+            Err(e) => tracing::warn!(account, sender, "Auto-block failed: {e}"),
+        }
+    }
+}
+
+/// The next spam match, or never when there is no spam filter.
+async fn recv_match(
+    matches: &mut Option<tokio::sync::broadcast::Receiver<Quarantined>>,
+) -> Option<Quarantined> {
+    let Some(rx) = matches else {
+        return std::future::pending().await;
+    };
+    loop {
+        match rx.recv().await {
+            Ok(matched) => return Some(matched),
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
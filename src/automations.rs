@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::group_id::GroupId;
+use crate::state::{now_millis, AppState};
+use crate::store::split_notification;
+use crate::trust;
+
+/// Where a welcome message goes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WelcomeTarget {
+    /// To the group, for everyone to see.
+    #[default]
+    Group,
+    /// To the new member alone.
+    Member,
+}
+
+/// A message sent when someone joins a group. `{member}` in the template is
+/// replaced by the new member's number and `{group}` by the group's name.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Welcome {
+    pub message: String,
+    #[serde(default)]
+    pub to: WelcomeTarget,
+}
+
+impl Welcome {
+    pub fn render(&self, member: &str, group_name: &str) -> String {
+        self.message
+            .replace("{member}", member)
+            .replace("{group}", group_name)
+    }
+}
+
+/// What happens automatically in one group.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Automations {
+    #[serde(default)]
+    pub welcome: Option<Welcome>,
+}
+
+/// Automations per (account, group), and the group members last seen, to
+/// tell who is new when a group changes.
+#[derive(Default)]
+pub struct AutomationStore {
+    configs: RwLock<HashMap<(String, GroupId), Automations>>,
+    members: Mutex<HashMap<(String, GroupId), HashSet<String>>>,
+}
+
+impl AutomationStore {
+    pub fn get(&self, account: &str, group: &GroupId) -> Option<Automations> {
+        self.configs
+            .read()
+            .unwrap()
+            .get(&(account.to_string(), group.clone()))
+            .cloned()
+    }
+
+    pub fn set(&self, account: &str, group: &GroupId, automations: Automations) {
+        self.configs
+            .write()
+            .unwrap()
+            .insert((account.to_string(), group.clone()), automations);
+    }
+
+    pub fn remove(&self, account: &str, group: &GroupId) -> bool {
+        let key = (account.to_string(), group.clone());
+        self.members.lock().unwrap().remove(&key);
+        self.configs.write().unwrap().remove(&key).is_some()
+    }
+
+    /// Record the current members of a group, returning the ones that
+    /// weren't there last time. The first call for a group returns nobody.
+    pub fn update_members(
+        &self,
+        account: &str,
+        group: &GroupId,
+        current: HashSet<String>,
+    ) -> Vec<String> {
+        let mut members = self.members.lock().unwrap();
+        let key = (account.to_string(), group.clone());
+        let joined = match members.get(&key) {
+            Some(previous) => {
+                let mut joined: Vec<String> = current.difference(previous).cloned().collect();
+                joined.sort();
+                joined
+            }
+            None => Vec::new(),
+        };
+        members.insert(key, current);
+        joined
+    }
+}
+
+/// A group's name and the numbers (or UUIDs) of its members, from
+/// `listGroups` for that group.
+pub async fn group_members(
+    state: &AppState,
+    account: &str,
+    group: &GroupId,
+) -> Result<(String, HashSet<String>), String> {
+    let groups = state
+        .rpc(
+            "listGroups",
+            json!({ "account": account, "group-id": group }),
+        )
+        .await?;
+    let is_group = |g: &&Value| {
+        g.get("id")
+            .and_then(|v| v.as_str())
+            .is_some_and(|id| GroupId::from(id) == *group)
+    };
+    let entry = match &groups {
+        Value::Array(list) => list.iter().find(is_group),
+        other => Some(other).filter(is_group),
+    };
+    let Some(entry) = entry else {
+        return Err(format!("group {group} not found"));
+    };
+    let name = entry
+        .get("name")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let members = entry
+        .get("members")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|m| match m {
+            Value::String(s) => Some(s.clone()),
+            m => m
+                .get("number")
+                .or_else(|| m.get("uuid"))
+                .and_then(|v| v.as_str())
+                .map(String::from),
+        })
+        .collect();
+    Ok((name, members))
+}
+
+/// Watches group update envelopes and sends the configured welcome message
+/// to members who joined since the group was last seen.
+pub async fn run(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Group automations lagged, {n} notifications skipped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if !state.leadership.is_leader() {
+            continue;
+        }
+        let Some((account, envelope)) = split_notification(event.json()) else {
+            continue;
+        };
+        let Some(info) = envelope.get("dataMessage").and_then(|d| d.get("groupInfo")) else {
+            continue;
+        };
+        if info.get("type").and_then(|t| t.as_str()) != Some("UPDATE") {
+            continue;
+        }
+        let Some(group) = info
+            .get("groupId")
+            .and_then(|g| g.as_str())
+            .map(GroupId::from)
+        else {
+            continue;
+        };
+        let Some(welcome) = state
+            .automations
+            .get(account, &group)
+            .and_then(|a| a.welcome)
+        else {
+            continue;
+        };
+        let (name, members) = match group_members(&state, account, &group).await {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!(account, group = %group, "Could not list group members: {e}");
+                continue;
+            }
+        };
+        for member in state.automations.update_members(account, &group, members) {
+            if member == account {
+                continue;
+            }
+            let mut params =
+                json!({ "account": account, "message": welcome.render(&member, &name) });
+            match welcome.to {
+                WelcomeTarget::Group => params["group-id"] = json!(group),
+                WelcomeTarget::Member => params["recipient"] = json!([member]),
+            }
+            match trust::check(&state, account, &params).await {
+                Ok(untrusted) if untrusted.is_empty() => {}
+                Ok(untrusted) => {
+                    let recipients: Vec<&str> =
+                        untrusted.iter().map(|u| u.recipient.as_str()).collect();
+                    tracing::warn!(account, group = %group, member, ?recipients, "Welcome message held back by the trust policy");
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!(account, group = %group, member, "Welcome message failed: {e}");
+                    continue;
+                }
+            }
+            if let Some(pacer) = &state.pacer {
+                pacer.acquire().await;
+            }
+            match state.rpc("send", params.clone()).await {
+                Ok(result) => {
+                    state.metrics.inc_sent();
+                    let timestamp = result
+                        .get("timestamp")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_else(now_millis);
+                    state.store.record_outgoing(account, &params, timestamp);
+                    tracing::info!(account, group = %group, member, "Sent welcome message");
+                }
+                Err(e) => {
+                    tracing::warn!(account, group = %group, member, "Welcome message failed: {e}")
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::exposition::Exposition;
+use crate::state::{now_millis, AppState};
+use crate::store::civil_date;
+
+/// Largest message SNS and SQS accept.
+pub const MAX_MESSAGE_BYTES: usize = 256 * 1024;
+
+/// Where envelopes are published.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// An SNS topic, by ARN.
+    Sns { region: String, topic_arn: String },
+    /// An SQS queue, by URL.
+    Sqs { region: String, queue_url: String },
+}
+
+impl Target {
+    /// Parse an SNS topic ARN (`arn:aws:sns:<region>:<account>:<topic>`) or
+    /// an SQS queue URL (`https://sqs.<region>.amazonaws.com/<account>/<queue>`).
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid =
+            || format!("invalid AWS sink {spec:?}, expected an SNS topic ARN or an SQS queue URL");
+        if let Some(rest) = spec.strip_prefix("arn:aws:sns:") {
+            let region = rest
+                .split(':')
+                .next()
+                .filter(|r| !r.is_empty())
+                .ok_or_else(invalid)?;
+            if rest.split(':').count() != 3 {
+                return Err(invalid());
+            }
+            return Ok(Target::Sns {
+                region: region.to_string(),
+                topic_arn: spec.to_string(),
+            });
+        }
+        let url = reqwest::Url::parse(spec).map_err(|_| invalid())?;
+        let region = url
+            .host_str()
+            .and_then(|h| h.strip_prefix("sqs."))
+            .and_then(|h| h.strip_suffix(".amazonaws.com"))
+            .filter(|r| !r.is_empty() && !r.contains('.'))
+            .ok_or_else(invalid)?;
+        if url.scheme() != "https" || url.path_segments().is_none_or(|s| s.count() != 2) {
+            return Err(invalid());
+        }
+        Ok(Target::Sqs {
+            region: region.to_string(),
+            queue_url: spec.to_string(),
+        })
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Target::Sns { .. } => "sns",
+            Target::Sqs { .. } => "sqs",
+        }
+    }
+
+    fn region(&self) -> &str {
+        match self {
+            Target::Sns { region, .. } | Target::Sqs { region, .. } => region,
+        }
+    }
+
+    /// The URL requests go to, on `endpoint` (scheme and host) if given.
+    fn url(&self, endpoint: Option<&str>) -> String {
+        let url = match self {
+            Target::Sns { region, .. } => format!("https://sns.{region}.amazonaws.com/"),
+            Target::Sqs { queue_url, .. } => queue_url.clone(),
+        };
+        match (endpoint, reqwest::Url::parse(&url)) {
+            (Some(endpoint), Ok(parsed)) => {
+                format!("{}{}", endpoint.trim_end_matches('/'), parsed.path())
+            }
+            _ => url,
+        }
+    }
+
+    /// The form parameters publishing `message`.
+    fn params<'a>(&'a self, message: &'a str) -> Vec<(&'a str, &'a str)> {
+        match self {
+            Target::Sns { topic_arn, .. } => vec![
+                ("Action", "Publish"),
+                ("Message", message),
+                ("TopicArn", topic_arn),
+                ("Version", "2010-03-31"),
+            ],
+            Target::Sqs { .. } => vec![
+                ("Action", "SendMessage"),
+                ("MessageBody", message),
+                ("Version", "2012-11-05"),
+            ],
+        }
+    }
+}
+
+/// The ARN or queue URL.
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Sns { topic_arn, .. } => f.write_str(topic_arn),
+            Target::Sqs { queue_url, .. } => f.write_str(queue_url),
+        }
+    }
+}
+
+/// Long-lived AWS credentials (an IAM user's access key), as the AWS SDKs
+/// read them from the environment.
+#[derive(Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl Credentials {
+    /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`. They are read once,
+    /// so temporary credentials (`AWS_SESSION_TOKEN`) are refused: they would
+    /// expire while the server runs.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        if var("AWS_SESSION_TOKEN").is_some() {
+            return Err("the AWS sink can't refresh temporary credentials; unset AWS_SESSION_TOKEN and use an IAM user's access key".to_string());
+        }
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(Self {
+                access_key_id,
+                secret_access_key,
+            }),
+            _ => Err("the AWS sink needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY".to_string()),
+        }
+    }
+}
+
+/// `application/x-www-form-urlencoded` as AWS signs it: everything but
+/// unreserved characters percent-encoded, spaces included.
+pub fn aws_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `YYYYMMDD'T'HHMMSS'Z'` of a time in ms since the epoch.
+pub fn amz_date(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    let date = civil_date(days).replace('-', "");
+    format!(
+        "{date}T{:02}{:02}{:02}Z",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// The parts of a request that go into its signature.
+pub struct Request<'a> {
+    pub method: &'a str,
+    pub host: &'a str,
+    pub path: &'a str,
+    /// In canonical form: sorted and encoded.
+    pub query: &'a str,
+    pub content_type: &'a str,
+    /// When the request is made, see [`amz_date`].
+    pub amz_date: &'a str,
+    pub body: &'a [u8],
+}
+
+/// The `Authorization` header of an AWS Signature Version 4 request. Only
+/// `content-type`, `host` and `x-amz-date` are signed.
+pub fn sign_v4(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    request: &Request,
+) -> String {
+    let Request {
+        method,
+        host,
+        path,
+        query,
+        content_type,
+        amz_date,
+        body,
+    } = *request;
+    let headers = [
+        ("content-type", content_type),
+        ("host", host),
+        ("x-amz-date", amz_date),
+    ];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(k, v)| format!("{k}:{}\n", v.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(k, _)| *k)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{method}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date,
+    );
+    for part in [region, service, "aws4_request"] {
+        key = hmac(&key, part);
+    }
+    let signature = hex::encode(hmac(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+/// Publishes incoming envelopes to an SNS topic or SQS queue, for consumers
+/// that can't take webhooks.
+pub struct AwsSink {
+    pub target: Target,
+    credentials: Credentials,
+    /// Scheme and host to send to instead of AWS's, e.g. a local emulator.
+    pub endpoint: Option<String>,
+    published: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl AwsSink {
+    pub fn new(target: Target, credentials: Credentials, endpoint: Option<String>) -> Self {
+        Self {
+            target,
+            credentials,
+            endpoint,
+            published: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    async fn publish(&self, client: &reqwest::Client, message: &str) -> Result<(), String> {
+        if message.len() > MAX_MESSAGE_BYTES {
+            return Err(format!(
+                "envelope of {} bytes is over the {MAX_MESSAGE_BYTES}-byte limit",
+                message.len()
+            ));
+        }
+        let url = self.target.url(self.endpoint.as_deref());
+        let parsed = reqwest::Url::parse(&url).map_err(|e| e.to_string())?;
+        let host = match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(format!("no host in {url}")),
+        };
+        let body = self
+            .target
+            .params(message)
+            .iter()
+            .map(|(k, v)| format!("{k}={}", aws_encode(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let content_type = "application/x-www-form-urlencoded; charset=utf-8";
+        let date = amz_date(now_millis());
+        let request = Request {
+            method: "POST",
+            host: &host,
+            path: parsed.path(),
+            query: "",
+            content_type,
+            amz_date: &date,
+            body: body.as_bytes(),
+        };
+        let authorization = sign_v4(
+            &self.credentials,
+            self.target.region(),
+            self.target.service(),
+            &request,
+        );
+        let response = client
+            .post(url)
+            .header("content-type", content_type)
+            .header("x-amz-date", &date)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!(
+                "{status}: {}",
+                text.chars().take(300).collect::<String>()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Append sink counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let service = self.target.service();
+        let labels = [("service", service)];
+        exp.counter(
+            "signal_aws_sink_published",
+            "Envelopes published to SNS or SQS",
+            &[(&labels, self.published.load(Ordering::Relaxed))],
+        );
+        exp.counter(
+            "signal_aws_sink_failed",
+            "Envelopes that couldn't be published to SNS or SQS",
+            &[(&labels, self.failed.load(Ordering::Relaxed))],
+        );
+    }
+}
+
+/// Publishes each incoming envelope, in the normalized form streams get,
+/// while this replica is the leader (see [`crate::leader`]). A failed
+/// publish is logged and counted, not retried.
+pub async fn run(state: AppState) {
+    let Some(sink) = state.aws_sink.clone() else {
+        return;
+    };
+    let client = match crate::outbound::client_builder(state.outbound_proxy.as_ref())
+        .and_then(|b| b.build().map_err(|e| e.to_string()))
+    {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("AWS sink disabled: {e}");
+            return;
+        }
+    };
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("AWS sink lagged, {n} envelopes not published");
+                sink.failed.fetch_add(n, Ordering::Relaxed);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if event.notification().is_none() || !state.leadership.is_leader() {
+            continue;
+        }
+        match sink.publish(&client, event.for_client(false)).await {
+            Ok(()) => sink.published.fetch_add(1, Ordering::Relaxed),
+            Err(e) => {
+                tracing::warn!(
+                    service = sink.target.service(),
+                    "Publishing an envelope failed: {e}"
+                );
+                sink.failed.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::exposition::Exposition;
+use crate::send_result::{Outcome, SendReport};
+use crate::state::AppState;
+use crate::store::{delivery_receipt, sent_by};
+
+/// How long a canary waits for the delivery receipt unless `--canary-timeout`
+/// or `?timeout=` says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Longest `?timeout=` a caller may ask for, in seconds.
+pub const MAX_TIMEOUT_SECS: u64 = 300;
+
+/// Text of the canary message.
+pub const MESSAGE: &str = "signal-cli-api canary";
+
+/// An end-to-end probe: a message from `account` to `recipient`, a device
+/// that answers with delivery receipts, which must come back within the
+/// timeout.
+pub struct Canary {
+    pub account: String,
+    pub recipient: String,
+    pub timeout: Duration,
+    passed: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// A canary that got its receipt.
+#[derive(Debug)]
+pub struct Pass {
+    pub timestamp: u64,
+    pub latency: Duration,
+}
+
+impl Canary {
+    pub fn new(account: String, recipient: String, timeout: Duration) -> Self {
+        Self {
+            account,
+            recipient,
+            timeout,
+            passed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Send the canary message and wait up to `timeout` for its receipt.
+    pub async fn probe(&self, state: &AppState, timeout: Duration) -> Result<Pass, String> {
+        let result = self.attempt(state, timeout).await;
+        match &result {
+            Ok(pass) => {
+                self.passed.fetch_add(1, Ordering::Relaxed);
+                tracing::info!(
+                    latency_ms = pass.latency.as_millis() as u64,
+                    "Canary passed"
+                );
+            }
+            Err(e) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(recipient = self.recipient, "Canary failed: {e}");
+            }
+        }
+        result
+    }
+
+    async fn attempt(&self, state: &AppState, timeout: Duration) -> Result<Pass, String> {
+        // Subscribed before sending, so a quick receipt isn't missed.
+        let mut rx = state.broadcast_tx.subscribe();
+        if let Some(pacer) = &state.pacer {
+            pacer.acquire().await;
+        }
+        let start = Instant::now();
+        let params =
+            json!({ "account": self.account, "recipient": [self.recipient], "message": MESSAGE });
+        let result = state
+            .rpc("send", params)
+            .await
+            .map_err(|e| format!("send failed: {e}"))?;
+        let report = SendReport::from_result(&result);
+        if let Some(failed) = report.recipients.iter().find(|r| r.status != Outcome::Sent) {
+            return Err(format!(
+                "send failed: {}",
+                json!(failed.status).as_str().unwrap_or("failed")
+            ));
+        }
+        let timestamp = result
+            .get("timestamp")
+            .and_then(|v| v.as_u64())
+            .ok_or("send returned no timestamp")?;
+        let wait = async {
+            loop {
+                match rx.recv().await {
+                    Ok(event)
+                        if delivery_receipt(event.json(), &self.account, timestamp)
+                            .is_some_and(|envelope| sent_by(envelope, &self.recipient)) =>
+                    {
+                        return Ok(())
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err("the receive channel closed".to_string()),
+                }
+            }
+        };
+        match tokio::time::timeout(timeout.saturating_sub(start.elapsed()), wait).await {
+            Ok(Ok(())) => Ok(Pass {
+                timestamp,
+                latency: start.elapsed(),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(format!("no delivery receipt within {}s", timeout.as_secs())),
+        }
+    }
+
+    /// Append canary counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        exp.counter(
+            "signal_canary_runs",
+            "Canary probes by result",
+            &[
+                (&[("result", "pass")], self.passed.load(Ordering::Relaxed)),
+                (&[("result", "fail")], self.failed.load(Ordering::Relaxed)),
+            ],
+        );
+    }
+}
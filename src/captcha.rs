@@ -0,0 +1,91 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::state::now_millis;
+
+/// How long a stored captcha token is kept; hCaptcha tokens expire after two
+/// minutes anyway.
+pub const CAPTCHA_TTL: Duration = Duration::from_secs(120);
+
+const SCHEME: &str = "signalcaptcha://";
+
+/// A captcha token from signalcaptcha.org, as signal-cli expects it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Captcha {
+    #[serde(skip)]
+    pub token: String,
+    /// What the captcha was solved for: "registration" or "challenge".
+    pub action: String,
+    pub expires_at: u64,
+}
+
+/// Strip what browsers and clipboards add around a pasted captcha: quotes,
+/// whitespace, the `signalcaptcha://` scheme and percent-encoded dots.
+pub fn normalize(input: &str) -> String {
+    let s = input.trim().trim_matches(|c| c == '"' || c == '\'').trim();
+    let s = s.strip_prefix(SCHEME).unwrap_or(s);
+    s.replace("%2E", ".")
+        .replace("%2e", ".")
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Extract and check the token from a pasted `signalcaptcha://` URL (or a
+/// bare token): `signal-<provider>.<sitekey>.<action>.<token>`.
+pub fn parse(input: &str) -> Result<Captcha, String> {
+    let token = normalize(input);
+    let parts: Vec<&str> = token.splitn(4, '.').collect();
+    let valid_chars = token
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+    match parts.as_slice() {
+        [provider, sitekey, action, rest]
+            if provider.starts_with("signal-")
+                && !sitekey.is_empty()
+                && !action.is_empty()
+                && !rest.is_empty()
+                && valid_chars =>
+        {
+            Ok(Captcha {
+                action: action.to_string(),
+                expires_at: now_millis() + CAPTCHA_TTL.as_millis() as u64,
+                token,
+            })
+        }
+        _ => Err(
+            "not a Signal captcha; paste the full signalcaptcha:// link from the captcha page"
+                .into(),
+        ),
+    }
+}
+
+/// Captchas stored for the next registration call of an account.
+#[derive(Default)]
+pub struct CaptchaStore {
+    entries: Mutex<HashMap<String, Captcha>>,
+}
+
+impl CaptchaStore {
+    pub fn put(&self, account: &str, captcha: Captcha) {
+        let mut entries = self.entries.lock().unwrap();
+        let now = now_millis();
+        entries.retain(|_, c| c.expires_at > now);
+        entries.insert(account.to_string(), captcha);
+    }
+
+    pub fn get(&self, account: &str) -> Option<Captcha> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(account)
+            .filter(|c| c.expires_at > now_millis())
+            .cloned()
+    }
+
+    /// Remove and return the account's captcha, if it hasn't expired.
+    pub fn take(&self, account: &str) -> Option<Captcha> {
+        let captcha = self.entries.lock().unwrap().remove(account)?;
+        (captcha.expires_at > now_millis()).then_some(captcha)
+    }
+}
@@ -0,0 +1,192 @@
+use axum::body::{to_bytes, Body, HttpBody};
+use axum::extract::{Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::state::AppState;
+
+/// Largest response body renamed; bigger ones pass through as they are.
+const MAX_BODY: usize = 32 * 1024 * 1024;
+
+/// Fields holding JSON a client stored, or named by a client (a webhook's
+/// header names), returned as they were given.
+const OPAQUE_FIELDS: &[&str] = &["value", "metadata", "headers"];
+
+/// Response extension naming the objects whose keys are data, such as topic
+/// names or conversation-state keys, which are never renamed. Each is a
+/// JSON pointer: `""` is the body itself, `*` stands for any array index.
+#[derive(Clone, Copy, Debug)]
+pub struct DataKeys(pub &'static [&'static str]);
+
+impl DataKeys {
+    /// Whether the object at `path` (its keys and indices from the body
+    /// down) is keyed by data.
+    fn covers(&self, path: &[String]) -> bool {
+        self.0.iter().any(|pointer| {
+            let segments: Vec<&str> = pointer.split('/').skip(1).collect();
+            segments.len() == path.len()
+                && segments
+                    .iter()
+                    .zip(path)
+                    .all(|(s, p)| *s == p || (*s == "*" && p.parse::<usize>().is_ok()))
+        })
+    }
+}
+
+/// How keys in JSON responses are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyCase {
+    /// As the API and signal-cli write them: signal-cli's fields are
+    /// camelCase, many of the API's own snake_case.
+    #[default]
+    Original,
+    Camel,
+    Snake,
+}
+
+#[derive(Deserialize)]
+struct CaseQuery {
+    case: Option<KeyCase>,
+}
+
+/// Whether a key is an identifier (letters, digits and single underscores,
+/// starting with a lowercase letter) rather than data such as a phone
+/// number, a sync type or a group ID, which is never renamed.
+fn is_identifier(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !key.contains("__")
+        && !key.ends_with('_')
+}
+
+/// `username_link` → `usernameLink`.
+pub fn to_camel(key: &str) -> String {
+    let mut out = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                out.push(c.to_ascii_uppercase());
+                upper = false;
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `usernameLink` → `username_link`.
+pub fn to_snake(key: &str) -> String {
+    let mut out = String::with_capacity(key.len() + 4);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('_');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Rename the keys of every object in `value`, leaving the contents of
+/// [`OPAQUE_FIELDS`] and the keys of the objects `data` names alone.
+pub fn rename(value: Value, case: KeyCase, data: DataKeys) -> Value {
+    let convert = match case {
+        KeyCase::Original => return value,
+        KeyCase::Camel => to_camel,
+        KeyCase::Snake => to_snake,
+    };
+    rename_at(value, convert, data, &mut Vec::new())
+}
+
+fn rename_at(
+    value: Value,
+    convert: fn(&str) -> String,
+    data: DataKeys,
+    path: &mut Vec<String>,
+) -> Value {
+    match value {
+        Value::Object(map) => {
+            let keyed_by_data = data.covers(path);
+            let renamed = map
+                .into_iter()
+                .map(|(key, v)| {
+                    path.push(key.clone());
+                    let v = if OPAQUE_FIELDS.contains(&key.as_str()) {
+                        v
+                    } else {
+                        rename_at(v, convert, data, path)
+                    };
+                    path.pop();
+                    let key = if !keyed_by_data && is_identifier(&key) {
+                        convert(&key)
+                    } else {
+                        key
+                    };
+                    (key, v)
+                })
+                .collect::<Map<String, Value>>();
+            Value::Object(renamed)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| {
+                    path.push(i.to_string());
+                    let v = rename_at(v, convert, data, path);
+                    path.pop();
+                    v
+                })
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Middleware writing the keys of JSON responses in one case: the one the
+/// request asks for with `?case=camel|snake|original`, else `--json-case`.
+pub async fn rename_keys(State(st): State<AppState>, request: Request, next: Next) -> Response {
+    let case = match Query::<CaseQuery>::try_from_uri(request.uri()) {
+        Ok(Query(q)) => q.case.unwrap_or(st.json_case),
+        Err(_) => {
+            let error = "case must be one of: camel, snake, original";
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    };
+    let response = next.run(request).await;
+    let data = response
+        .extensions()
+        .get::<DataKeys>()
+        .copied()
+        .unwrap_or(DataKeys(&[]));
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    let fits = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_BODY as u64);
+    if case == KeyCase::Original || !is_json || !fits {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_BODY).await else {
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(rename(value, case, data).to_string()))
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::state::now_millis;
+
+/// How long a started change may wait for its verification code.
+pub const CHANGE_TTL: Duration = Duration::from_secs(3600);
+
+/// Where a number change stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// `startChangeNumber` succeeded; a code was sent to the new number.
+    Started,
+    /// `finishChangeNumber` succeeded; signal-cli now uses the new number.
+    Verified,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PendingChange {
+    pub account: String,
+    pub new_number: String,
+    pub stage: Stage,
+    pub started_at: u64,
+}
+
+/// Number changes in progress, keyed by the account's current number.
+#[derive(Default)]
+pub struct ChangeNumbers {
+    pending: Mutex<HashMap<String, PendingChange>>,
+}
+
+/// Whether `number` looks like an E.164 phone number.
+pub fn is_e164(number: &str) -> bool {
+    number.strip_prefix('+').is_some_and(|digits| {
+        (7..=15).contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit())
+    })
+}
+
+impl ChangeNumbers {
+    /// The account's change in progress. Changes that were started but not
+    /// verified within [`CHANGE_TTL`] are forgotten.
+    pub fn get(&self, account: &str) -> Option<PendingChange> {
+        let mut pending = self.pending.lock().unwrap();
+        let expired = now_millis().saturating_sub(CHANGE_TTL.as_millis() as u64);
+        pending.retain(|_, c| c.stage == Stage::Verified || c.started_at > expired);
+        pending.get(account).cloned()
+    }
+
+    pub fn start(&self, account: &str, new_number: &str) -> PendingChange {
+        let change = PendingChange {
+            account: account.to_string(),
+            new_number: new_number.to_string(),
+            stage: Stage::Started,
+            started_at: now_millis(),
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(account.to_string(), change.clone());
+        change
+    }
+
+    pub fn verified(&self, account: &str) -> Option<PendingChange> {
+        let mut pending = self.pending.lock().unwrap();
+        let change = pending.get_mut(account)?;
+        change.stage = Stage::Verified;
+        Some(change.clone())
+    }
+
+    pub fn remove(&self, account: &str) -> Option<PendingChange> {
+        self.pending.lock().unwrap().remove(account)
+    }
+}
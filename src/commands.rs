@@ -0,0 +1,117 @@
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, Mutex};
+
+use crate::outbound;
+use crate::state::AppState;
+use crate::store::envelope_source;
+use crate::webhooks;
+
+/// A parsed bot command from an incoming message.
+#[derive(Debug, PartialEq)]
+pub struct ParsedCommand<'a> {
+    /// Everything after the prefix, trimmed.
+    pub text: &'a str,
+    /// `text` split on whitespace.
+    pub args: Vec<&'a str>,
+}
+
+/// Match `message` against a command `prefix`. The prefix must be followed by
+/// whitespace or the end of the message, so "!stat" doesn't match "!status".
+pub fn parse_command<'a>(prefix: &str, message: &'a str) -> Option<ParsedCommand<'a>> {
+    let rest = message.trim_start().strip_prefix(prefix)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let text = rest.trim();
+    Some(ParsedCommand {
+        text,
+        args: text.split_whitespace().collect(),
+    })
+}
+
+/// How long a command handler has to answer a delivery.
+pub const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deliveries to command handlers run by the same bounds as a webhook's: a
+/// queue of [`webhooks::QUEUE_CAPACITY`] drained by
+/// [`webhooks::DEFAULT_MAX_CONCURRENCY`] workers.
+async fn worker(client: reqwest::Client, queue: Arc<Mutex<mpsc::Receiver<(String, Value)>>>) {
+    loop {
+        // The lock is released before delivering, so the next idle worker
+        // can take the following delivery.
+        let Some((url, body)) = queue.lock().await.recv().await else {
+            break;
+        };
+        if let Err(e) = client.post(&url).json(&body).send().await {
+            tracing::warn!("Command delivery to {url} failed: {e}");
+        }
+    }
+}
+
+/// Subscribes to the broadcast channel and POSTs incoming messages that match
+/// a registered command prefix to that command's handler URL.
+pub async fn dispatch_loop(state: AppState) {
+    let client = match outbound::client_builder(state.outbound_proxy.as_ref()).and_then(|b| {
+        b.timeout(DELIVERY_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())
+    }) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Command router disabled: {e}");
+            return;
+        }
+    };
+    let (tx, queue) = mpsc::channel(webhooks::QUEUE_CAPACITY);
+    let queue = Arc::new(Mutex::new(queue));
+    for _ in 0..webhooks::DEFAULT_MAX_CONCURRENCY {
+        tokio::spawn(worker(client.clone(), queue.clone()));
+    }
+    let mut rx = state.broadcast_tx.subscribe();
+
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        if !state.leadership.is_leader() {
+            continue;
+        }
+        let Some((account, envelope)) = event.notification() else {
+            continue;
+        };
+        let Some(data) = envelope.get("dataMessage") else {
+            continue;
+        };
+        let Some(text) = data.get("message").and_then(|v| v.as_str()) else {
+            continue;
+        };
+
+        let commands = state.commands.read().await;
+        for cmd in commands.iter() {
+            if cmd.account.as_deref().is_some_and(|a| a != account) {
+                continue;
+            }
+            let Some(parsed_cmd) = parse_command(&cmd.prefix, text) else {
+                continue;
+            };
+            let body = json!({
+                "command": cmd.prefix,
+                "args": parsed_cmd.args,
+                "text": parsed_cmd.text,
+                "account": account,
+                "source": envelope_source(envelope),
+                "group_id": data.get("groupInfo").and_then(|g| g.get("groupId")),
+                "timestamp": data.get("timestamp").or_else(|| envelope.get("timestamp")),
+                "envelope": envelope,
+            });
+            if tx.try_send((cmd.url.clone(), body)).is_err() {
+                tracing::warn!(command = cmd.prefix, "Command queue full, delivery dropped");
+            }
+        }
+    }
+}
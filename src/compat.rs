@@ -0,0 +1,174 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::send_result::{Outcome, SendReport};
+use crate::state::AppState;
+use crate::store::split_notification;
+
+/// Messages kept per account for polling clients between polls.
+pub const POLL_CAPACITY: usize = 1000;
+
+/// Other REST APIs whose wire format can be emulated with `--compat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compat {
+    /// bbernhard/signal-cli-rest-api, as used by Home Assistant's
+    /// signal_messenger and other existing integrations.
+    Bbernhard,
+}
+
+// ---------------------------------------------------------------------------
+// Response shapes
+// ---------------------------------------------------------------------------
+
+/// bbernhard's `/v2/send` body: the timestamp as a string. Failed recipients
+/// make the whole send an error, as they do there.
+pub fn send_response(result: &Value, report: &SendReport) -> Result<Value, String> {
+    if report.summary.failed > 0 {
+        let failed: Vec<String> = report
+            .recipients
+            .iter()
+            .filter(|r| r.status != Outcome::Sent)
+            .map(|r| {
+                format!(
+                    "{} ({})",
+                    r.recipient,
+                    json!(r.status).as_str().unwrap_or("failed")
+                )
+            })
+            .collect();
+        return Err(format!("Failed to send message to {}", failed.join(", ")));
+    }
+    let timestamp = result
+        .get("timestamp")
+        .and_then(|v| v.as_u64())
+        .unwrap_or_default();
+    Ok(json!({ "timestamp": timestamp.to_string() }))
+}
+
+/// bbernhard's group ID: `group.` + base64 of signal-cli's base64 ID.
+pub fn group_id(internal_id: &str) -> String {
+    format!("group.{}", STANDARD.encode(internal_id))
+}
+
+/// A signal-cli `listGroups` entry in bbernhard's shape.
+pub fn group(g: &Value) -> Value {
+    let internal_id = g.get("id").and_then(|v| v.as_str()).unwrap_or("");
+    let addresses = |key: &str| -> Vec<Value> {
+        g.get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|m| match m {
+                Value::String(s) => Some(json!(s)),
+                m => m
+                    .get("number")
+                    .filter(|n| !n.is_null())
+                    .or_else(|| m.get("uuid"))
+                    .cloned(),
+            })
+            .collect()
+    };
+    json!({
+        "name": g.get("name").cloned().unwrap_or(json!("")),
+        "id": group_id(internal_id),
+        "internal_id": internal_id,
+        "members": addresses("members"),
+        "blocked": g.get("isBlocked").and_then(|v| v.as_bool()).unwrap_or(false),
+        "pending_invites": addresses("pendingMembers"),
+        "pending_requests": addresses("requestingMembers"),
+        "invite_link": g.get("groupInviteLink").and_then(|v| v.as_str()).unwrap_or(""),
+        "admins": addresses("admins"),
+    })
+}
+
+/// bbernhard's `/v1/about`.
+pub fn about() -> Value {
+    json!({
+        "versions": ["v1", "v2"],
+        "build": 2,
+        "mode": "json-rpc",
+        "version": env!("CARGO_PKG_VERSION"),
+        "capabilities": { "v2/send": ["quotes", "mentions"] },
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Polling receive
+// ---------------------------------------------------------------------------
+
+/// Incoming notifications buffered per account for `GET /v1/receive/{number}`
+/// without a WebSocket upgrade (bbernhard's polling mode).
+#[derive(Default)]
+pub struct PollQueue {
+    accounts: Mutex<HashMap<String, VecDeque<Value>>>,
+    arrived: Notify,
+}
+
+impl PollQueue {
+    fn push(&self, parsed: &Value) {
+        let Some((account, envelope)) = split_notification(parsed) else {
+            return;
+        };
+        let entry = json!({ "envelope": envelope, "account": account });
+        {
+            let mut accounts = self.accounts.lock().unwrap();
+            let queue = accounts.entry(account.to_string()).or_default();
+            queue.push_back(entry);
+            while queue.len() > POLL_CAPACITY {
+                queue.pop_front();
+            }
+        }
+        self.arrived.notify_waiters();
+    }
+
+    /// Take everything buffered for `account` (including notifications that
+    /// didn't name an account).
+    fn drain(&self, account: &str) -> Vec<Value> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let mut out: Vec<Value> = Vec::new();
+        for key in [account, ""] {
+            if let Some(queue) = accounts.get_mut(key) {
+                out.extend(queue.drain(..));
+            }
+        }
+        out
+    }
+
+    /// Wait up to `timeout` for messages for `account`, returning as soon as
+    /// any are available.
+    pub async fn poll(&self, account: &str, timeout: Duration) -> Vec<Value> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let arrived = self.arrived.notified();
+            let messages = self.drain(account);
+            if !messages.is_empty() {
+                return messages;
+            }
+            if tokio::time::timeout_at(deadline, arrived).await.is_err() {
+                return Vec::new();
+            }
+        }
+    }
+}
+
+/// Buffers incoming notifications for polling clients while a compat mode is on.
+pub async fn record_loop(state: AppState) {
+    if state.compat.is_none() {
+        return;
+    }
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => state.poll_queue.push(event.json()),
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Poll queue lagged, {n} notifications dropped");
+            }
+            Err(_) => break,
+        }
+    }
+}
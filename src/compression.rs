@@ -0,0 +1,89 @@
+use axum::body::{Body, BodyDataStream, Bytes};
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::Response;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio_stream::Stream;
+
+use crate::state::AppState;
+
+/// Whether `Accept-Encoding` allows gzip (`gzip` or `*`, not with `q=0`).
+pub fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok());
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && q.is_some_and(|q| q > 0.0)
+        })
+}
+
+/// A body gzipped chunk by chunk. Each chunk is flushed, so every event of
+/// a stream reaches the client as soon as it is written.
+struct GzipStream {
+    inner: BodyDataStream,
+    /// None once the stream is finished.
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl Stream for GzipStream {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+            let chunk = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(e)) => {
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(std::io::Error::other(e))));
+                }
+                None => {
+                    let rest = this.encoder.take().map(|e| e.finish());
+                    return Poll::Ready(rest.map(|r| r.map(Bytes::from)));
+                }
+            };
+            if let Err(e) = encoder.write_all(&chunk).and_then(|_| encoder.flush()) {
+                this.encoder = None;
+                return Poll::Ready(Some(Err(e)));
+            }
+            let out = std::mem::take(encoder.get_mut());
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(Bytes::from(out))));
+            }
+        }
+    }
+}
+
+/// Gzip a Server-Sent Events response when `--sse-gzip` is on and the
+/// request's `Accept-Encoding` allows it; otherwise return it as it is.
+pub fn negotiate(st: &AppState, request: &HeaderMap, response: Response) -> Response {
+    if !st.sse_gzip || !accepts_gzip(request) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let stream = GzipStream {
+        inner: body.into_data_stream(),
+        encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
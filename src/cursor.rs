@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::Event;
+use crate::exposition::{Exposition, Labels};
+use crate::migrate;
+use crate::state::AppState;
+
+/// Events kept for pull consumers unless `--event-log-size` says otherwise.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// Events a fetch returns when it sets no `limit`, and the most it may ask for.
+pub const DEFAULT_BATCH: usize = 100;
+pub const MAX_BATCH: usize = 1000;
+
+/// How often cursors are written to `--cursor-file` when they changed.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Format of `--cursor-file`, recorded in it as `schema_version`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What `--cursor-file` holds.
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    schema_version: u32,
+    /// Sequence number of the newest event, so numbering carries on after a
+    /// restart instead of going back below the consumers' cursors.
+    sequence: u64,
+    consumers: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Log {
+    /// Oldest first, with their sequence numbers.
+    events: VecDeque<(u64, Event)>,
+    /// Sequence number of the newest event.
+    last: u64,
+    /// The sequence number each consumer acknowledged last.
+    consumers: BTreeMap<String, u64>,
+}
+
+impl Log {
+    /// The sequence number of the oldest event still held.
+    fn oldest(&self) -> u64 {
+        self.events.front().map_or(self.last + 1, |(seq, _)| *seq)
+    }
+}
+
+/// Events handed to a consumer, after its cursor.
+pub struct Batch {
+    /// The sequence number the consumer acknowledged last.
+    pub cursor: u64,
+    /// Events after the cursor that were dropped from the log before the
+    /// consumer fetched them.
+    pub missed: u64,
+    pub events: Vec<(u64, Event)>,
+}
+
+/// Why an acknowledgement was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AckError {
+    UnknownConsumer,
+    /// The sequence number is past the newest event.
+    Ahead(u64),
+}
+
+/// A numbered log of recent events for pull consumers: each named consumer
+/// fetches the events after its cursor and acknowledges them to move it on,
+/// so an event is handed out until it has been acknowledged (at least once).
+/// Events are kept in memory, the newest `capacity` of them; cursors are
+/// saved to `--cursor-file` if given.
+pub struct EventLog {
+    pub capacity: usize,
+    log: Mutex<Log>,
+    pub file: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            log: Mutex::default(),
+            file: None,
+            dirty: AtomicBool::new(false),
+        }
+    }
+}
+
+impl EventLog {
+    /// Load cursors saved in `file`, if any.
+    pub fn new(capacity: usize, file: Option<PathBuf>) -> Result<Self, String> {
+        let saved: Saved = match &file {
+            Some(path) => match migrate::load(&migrate::CURSORS, path)? {
+                Some(value) => serde_json::from_value(value)
+                    .map_err(|e| format!("cursor file {}: {e}", path.display()))?,
+                None => Saved::default(),
+            },
+            None => Saved::default(),
+        };
+        let last = saved
+            .consumers
+            .values()
+            .copied()
+            .fold(saved.sequence, u64::max);
+        let log = Log {
+            events: VecDeque::new(),
+            last,
+            consumers: saved.consumers,
+        };
+        Ok(Self {
+            capacity,
+            log: Mutex::new(log),
+            file,
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Append an event, dropping the oldest beyond `capacity`.
+    pub fn push(&self, event: Event) {
+        let mut log = self.log.lock().unwrap();
+        log.last += 1;
+        let seq = log.last;
+        log.events.push_back((seq, event));
+        let excess = log.events.len().saturating_sub(self.capacity);
+        log.events.drain(..excess);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Up to `limit` events after `consumer`'s cursor that `keep` accepts.
+    /// A consumer fetching for the first time starts at the oldest event held.
+    pub fn fetch(&self, consumer: &str, limit: usize, keep: impl Fn(&Event) -> bool) -> Batch {
+        let mut log = self.log.lock().unwrap();
+        let start = log.oldest() - 1;
+        let cursor = *log
+            .consumers
+            .entry(consumer.to_string())
+            .or_insert_with(|| {
+                self.dirty.store(true, Ordering::Relaxed);
+                start
+            });
+        let missed = log.oldest().saturating_sub(cursor + 1);
+        let events = log
+            .events
+            .iter()
+            .filter(|(seq, e)| *seq > cursor && keep(e))
+            .take(limit)
+            .cloned()
+            .collect();
+        Batch {
+            cursor,
+            missed,
+            events,
+        }
+    }
+
+    /// Move `consumer`'s cursor to `seq`, returning where it is now. An
+    /// acknowledgement behind the cursor leaves it where it is.
+    pub fn ack(&self, consumer: &str, seq: u64) -> Result<u64, AckError> {
+        let mut log = self.log.lock().unwrap();
+        let last = log.last;
+        let cursor = log
+            .consumers
+            .get_mut(consumer)
+            .ok_or(AckError::UnknownConsumer)?;
+        if seq > last {
+            return Err(AckError::Ahead(last));
+        }
+        if seq > *cursor {
+            *cursor = seq;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        Ok(*cursor)
+    }
+
+    /// Forget a consumer, returning whether it existed.
+    pub fn remove(&self, consumer: &str) -> bool {
+        let removed = self
+            .log
+            .lock()
+            .unwrap()
+            .consumers
+            .remove(consumer)
+            .is_some();
+        if removed {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// Every consumer's cursor, and the sequence number of the newest event.
+    pub fn consumers(&self) -> (BTreeMap<String, u64>, u64) {
+        let log = self.log.lock().unwrap();
+        (log.consumers.clone(), log.last)
+    }
+
+    /// Write cursors to the cursor file if they changed since the last save.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved = {
+            let log = self.log.lock().unwrap();
+            Saved {
+                schema_version: SCHEMA_VERSION,
+                sequence: log.last,
+                consumers: log.consumers.clone(),
+            }
+        };
+        let data = serde_json::to_vec(&saved).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| {
+                self.dirty.store(true, Ordering::Relaxed);
+                format!("saving cursors to {}: {e}", path.display())
+            })
+    }
+
+    /// Append the log's size and each consumer's lag to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let log = self.log.lock().unwrap();
+        exp.gauge(
+            "signal_event_log_events",
+            "Events held for pull consumers",
+            &[(&[], log.events.len() as u64)],
+        );
+        let labels: Vec<[(&str, &str); 1]> = log
+            .consumers
+            .keys()
+            .map(|c| [("consumer", c.as_str())])
+            .collect();
+        let samples: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(log.consumers.values())
+            .map(|(l, cursor)| (&l[..], log.last.saturating_sub(*cursor)))
+            .collect();
+        exp.gauge(
+            "signal_cursor_lag",
+            "Events a pull consumer hasn't acknowledged",
+            &samples,
+        );
+    }
+}
+
+/// Subscribes to the broadcast channel and appends every event to the log.
+pub async fn record_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => state.event_log.push(event),
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Event log lagged, {n} events not recorded")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Periodically saves cursors to `--cursor-file`.
+pub async fn save_loop(state: AppState) {
+    if state.event_log.file.is_none() {
+        return;
+    }
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+        let log = state.event_log.clone();
+        match tokio::task::spawn_blocking(move || log.save()).await {
+            Ok(Err(e)) => tracing::warn!("{e}"),
+            Err(e) => tracing::warn!("Saving cursors failed: {e}"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
@@ -2,6 +2,216 @@ use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::process::{Child, Command};
 
+/// Tracing target for lines the managed daemon writes to stderr.
+pub const DAEMON_LOG_TARGET: &str = "signal_cli";
+
+/// When the spawned daemon receives messages (`signal-cli daemon --receive-mode`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReceiveMode {
+    #[default]
+    OnStart,
+    OnConnection,
+    Manual,
+}
+
+/// Signal endpoint reached through [`DaemonProxy`] before the daemon starts.
+pub const SIGNAL_SERVICE_URL: &str = "https://chat.signal.org/";
+
+/// How long the startup check waits for the proxy (Tor circuits are slow to build).
+pub const PROXY_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variable signal-cli's launcher script adds to the JVM options.
+pub const JVM_OPTIONS_ENV: &str = "SIGNAL_CLI_OPTS";
+
+/// SOCKS proxy the spawned daemon sends its Signal traffic through, such as
+/// Tor's `socks5h://127.0.0.1:9050`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DaemonProxy {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl DaemonProxy {
+    /// Parse a `socks5://` or `socks5h://` URL with an explicit port and
+    /// optional credentials.
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let invalid = |why: &str| {
+            format!(
+                "invalid daemon proxy {}: {why}",
+                crate::runtime_config::redact_url(url)
+            )
+        };
+        let parsed = reqwest::Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        if !matches!(parsed.scheme(), "socks5" | "socks5h") {
+            return Err(invalid("expected socks5:// or socks5h://"));
+        }
+        let host = parsed
+            .host_str()
+            .filter(|h| !h.is_empty())
+            .ok_or_else(|| invalid("missing host"))?;
+        let port = parsed.port().ok_or_else(|| invalid("missing port"))?;
+        let decode = |s: &str| percent_decode(s);
+        Ok(Self {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            username: Some(parsed.username())
+                .filter(|u| !u.is_empty())
+                .map(decode),
+            password: parsed.password().map(decode),
+        })
+    }
+
+    /// The proxy as a URL, with the password replaced; safe to show.
+    pub fn display_url(&self) -> String {
+        let auth = match (&self.username, &self.password) {
+            (Some(user), Some(_)) => format!("{user}:redacted@"),
+            (Some(user), None) => format!("{user}@"),
+            _ => String::new(),
+        };
+        format!("socks5h://{auth}{}:{}", self.host, self.port)
+    }
+
+    /// JVM system properties routing the daemon's sockets through the proxy.
+    /// The password isn't among them, as these end up in the process's
+    /// arguments, which any local user can read; see [`Self::password_argfile`].
+    pub fn java_options(&self) -> Vec<String> {
+        let mut options = vec![
+            format!("-DsocksProxyHost={}", self.host),
+            format!("-DsocksProxyPort={}", self.port),
+        ];
+        if let Some(user) = &self.username {
+            options.push(format!("-Djava.net.socks.username={user}"));
+        }
+        options
+    }
+
+    /// The password's system property as a `java @argfile` holds it: quoted,
+    /// with backslashes, quotes and line breaks escaped.
+    pub fn password_argfile(&self) -> Option<String> {
+        let password = self.password.as_deref()?;
+        let mut escaped = String::with_capacity(password.len());
+        for c in password.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '"' => escaped.push_str("\\\""),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        Some(format!("\"-Djava.net.socks.password={escaped}\"\n"))
+    }
+
+    fn to_proxy(&self) -> Result<reqwest::Proxy, String> {
+        let mut proxy = reqwest::Proxy::all(format!("socks5h://{}:{}", self.host, self.port))
+            .map_err(|e| e.to_string())?;
+        if let Some(user) = &self.username {
+            proxy = proxy.basic_auth(user, self.password.as_deref().unwrap_or_default());
+        }
+        Ok(proxy)
+    }
+
+    /// Make a request to `target` through the proxy; any HTTP response
+    /// proves the path works.
+    pub async fn check(&self, target: &str) -> Result<(), String> {
+        let fail = |e: String| {
+            format!(
+                "cannot reach {target} through daemon proxy {}: {e}",
+                self.display_url()
+            )
+        };
+        let client = reqwest::Client::builder()
+            .proxy(self.to_proxy().map_err(fail)?)
+            .timeout(PROXY_CHECK_TIMEOUT)
+            .build()
+            .map_err(|e| fail(e.to_string()))?;
+        let response = client
+            .get(target)
+            .send()
+            .await
+            .map_err(|e| fail(e.to_string()))?;
+        tracing::info!(
+            "Daemon proxy {} reached {target} ({})",
+            self.display_url(),
+            response.status()
+        );
+        Ok(())
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn serialize_proxy<S: serde::Serializer>(
+    proxy: &Option<DaemonProxy>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match proxy {
+        Some(p) => s.serialize_some(&p.display_url()),
+        None => s.serialize_none(),
+    }
+}
+
+/// Receive options passed through to an auto-spawned daemon.
+#[derive(Clone, Debug, Default, serde::Serialize)]
+pub struct DaemonOptions {
+    pub receive_mode: ReceiveMode,
+    pub ignore_attachments: bool,
+    pub ignore_stories: bool,
+    pub send_read_receipts: bool,
+    /// SOCKS proxy for the daemon's connections to Signal (None = direct).
+    #[serde(serialize_with = "serialize_proxy")]
+    pub proxy: Option<DaemonProxy>,
+}
+
+impl DaemonOptions {
+    /// Extra `signal-cli daemon` arguments. Defaults are omitted so older
+    /// signal-cli releases without these flags still start.
+    pub fn args(&self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        match self.receive_mode {
+            ReceiveMode::OnStart => {}
+            ReceiveMode::OnConnection => args.extend(["--receive-mode", "on-connection"]),
+            ReceiveMode::Manual => args.extend(["--receive-mode", "manual"]),
+        }
+        if self.ignore_attachments {
+            args.push("--ignore-attachments");
+        }
+        if self.ignore_stories {
+            args.push("--ignore-stories");
+        }
+        if self.send_read_receipts {
+            args.push("--send-read-receipts");
+        }
+        args
+    }
+}
+
 /// A managed signal-cli daemon child process.
 /// Kills the entire process group on drop.
 pub struct ManagedDaemon {
@@ -46,10 +256,37 @@ fn find_signal_cli() -> anyhow::Result<String> {
     }
 }
 
+/// A file only this user can read, removed when dropped: JVM options the
+/// daemon's process arguments mustn't show, passed as `@path`.
+struct Argfile(std::path::PathBuf);
+
+impl Argfile {
+    fn create(name: &str, contents: &str) -> std::io::Result<Self> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        let argfile = Self(path);
+        file.write_all(contents.as_bytes())?;
+        Ok(argfile)
+    }
+}
+
+impl Drop for Argfile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
 /// Spawn signal-cli daemon on a random available port and wait until it's ready.
 /// The child is placed in its own process group via setsid() so that
 /// dropping ManagedDaemon kills the entire tree (including Java grandchildren).
-pub async fn spawn() -> anyhow::Result<ManagedDaemon> {
+pub async fn spawn(options: &DaemonOptions) -> anyhow::Result<ManagedDaemon> {
     let bin = find_signal_cli()?;
     tracing::info!("Found signal-cli at {bin}");
 
@@ -60,13 +297,45 @@ pub async fn spawn() -> anyhow::Result<ManagedDaemon> {
     };
     let addr = format!("127.0.0.1:{port}");
 
-    tracing::info!("Spawning signal-cli daemon on {addr}");
+    let extra_args = options.args();
+    // Proxy settings go in the launcher's JVM options, after any the user set.
+    // The password goes in an argfile the JVM reads at startup; it is
+    // removed once the daemon is up.
+    let mut jvm_options = None;
+    let mut _password_file = None;
+    if let Some(proxy) = &options.proxy {
+        proxy
+            .check(SIGNAL_SERVICE_URL)
+            .await
+            .map_err(anyhow::Error::msg)?;
+        let mut all: Vec<String> = std::env::var(JVM_OPTIONS_ENV)
+            .into_iter()
+            .filter(|o| !o.is_empty())
+            .collect();
+        all.extend(proxy.java_options());
+        if let Some(contents) = proxy.password_argfile() {
+            let file = Argfile::create(&format!("signal-cli-api-{port}.args"), &contents)?;
+            all.push(format!("@{}", file.0.display()));
+            _password_file = Some(file);
+        }
+        jvm_options = Some(all.join(" "));
+        tracing::info!(
+            "signal-cli connects to Signal through {}",
+            proxy.display_url()
+        );
+    }
+    tracing::info!(
+        "Spawning signal-cli daemon on {addr} {}",
+        extra_args.join(" ")
+    );
     // SAFETY: pre_exec runs in the forked child before exec. setsid() is
     // async-signal-safe and creates a new session/process group, which lets
     // us kill the entire group (including Java grandchildren) on shutdown.
     let mut child = unsafe {
         Command::new(&bin)
             .args(["daemon", "--tcp", &addr])
+            .args(&extra_args)
+            .envs(jvm_options.map(|o| (JVM_OPTIONS_ENV, o)))
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::piped())
             .kill_on_drop(true)
@@ -119,5 +388,16 @@ pub async fn spawn() -> anyhow::Result<ManagedDaemon> {
     }
     tracing::info!("signal-cli daemon ready on {addr}");
 
+    // Forward the daemon's stderr into our log (and so to the log stream).
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            use tokio::io::AsyncBufReadExt;
+            let mut lines = tokio::io::BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::info!(target: DAEMON_LOG_TARGET, "{line}");
+            }
+        });
+    }
+
     Ok(ManagedDaemon { child, pid, addr })
 }
@@ -0,0 +1,62 @@
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+use crate::state::AppState;
+
+/// Request header with the client's own timeout in milliseconds.
+pub const TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// The time budget of a request: the client's timeout or the server default,
+/// whichever is shorter (None = unbounded).
+pub fn budget(header: Option<&str>, default: Option<Duration>) -> Result<Option<Duration>, String> {
+    let requested = match header {
+        Some(value) => match value.trim().parse::<u64>() {
+            Ok(ms) => Some(Duration::from_millis(ms)),
+            Err(_) => {
+                return Err(format!(
+                    "invalid {TIMEOUT_HEADER} {value:?}, expected milliseconds"
+                ))
+            }
+        },
+        None => None,
+    };
+    Ok(match (requested, default) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    })
+}
+
+/// Middleware running the request with its deadline, so RPC calls made while
+/// handling it don't outlive the response anyone is waiting for.
+pub async fn propagate(State(st): State<AppState>, request: Request, next: Next) -> Response {
+    let header = request
+        .headers()
+        .get(TIMEOUT_HEADER)
+        .map(|v| v.to_str().unwrap_or_default());
+    match budget(header, st.request_timeout) {
+        Ok(Some(budget)) => {
+            DEADLINE
+                .scope(Instant::now() + budget, next.run(request))
+                .await
+        }
+        Ok(None) => next.run(request).await,
+        Err(error) => (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response(),
+    }
+}
+
+/// `timeout` cut down to what is left of the current request's budget.
+/// Calls made outside a request (background jobs) keep `timeout`.
+pub fn clamp(timeout: Duration) -> Duration {
+    DEADLINE
+        .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+        .map_or(timeout, |remaining| remaining.min(timeout))
+}
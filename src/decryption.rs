@@ -0,0 +1,111 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::EventKind;
+use crate::state::AppState;
+
+/// A session is reset with the same contact at most this often.
+pub const RESET_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// An envelope signal-cli could not decrypt, as reported in the
+/// notification's `exception` field.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub account: Option<String>,
+    /// Sender, when the envelope names one (sealed-sender failures may not).
+    pub source: Option<String>,
+    /// signal-cli's exception type, e.g. `ProtocolInvalidMessageException`.
+    pub kind: String,
+    pub message: String,
+}
+
+impl Failure {
+    /// Read a failure from a notification line (JSON-RPC `params`-wrapped or bare).
+    pub fn parse(parsed: &Value) -> Option<Self> {
+        let root = parsed.get("params").unwrap_or(parsed);
+        let exception = root.get("exception")?;
+        let text = |v: Option<&Value>| v.and_then(|v| v.as_str()).map(String::from);
+        let envelope = root.get("envelope");
+        Some(Self {
+            account: text(root.get("account")),
+            source: text(envelope.and_then(|e| e.get("sourceNumber")))
+                .or_else(|| text(envelope.and_then(|e| e.get("source"))))
+                .or_else(|| text(envelope.and_then(|e| e.get("sourceUuid")))),
+            kind: text(exception.get("type")).unwrap_or_else(|| "unknown".into()),
+            message: text(exception.get("message")).unwrap_or_default(),
+        })
+    }
+
+    /// Whether the failure points at a broken session, which a reset repairs
+    /// (as opposed to e.g. an untrusted identity or a duplicate).
+    pub fn is_session_error(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "ProtocolInvalidMessageException"
+                | "ProtocolNoSessionException"
+                | "ProtocolInvalidKeyIdException"
+                | "ProtocolInvalidKeyException"
+        )
+    }
+}
+
+/// Counts decryption failures reported by signal-cli and, with
+/// `--reset-sessions`, asks the sender to start a new session (at most once
+/// per [`RESET_INTERVAL`] per contact).
+pub async fn watch_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    let mut last_reset: HashMap<(String, String), Instant> = HashMap::new();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Decryption failure watcher lagged, {n} notifications skipped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if event.kind() != EventKind::Error {
+            continue;
+        }
+        let Some(failure) = Failure::parse(event.json()) else {
+            continue;
+        };
+        state.metrics.inc_decryption_failure(&failure.kind);
+        tracing::warn!(
+            account = failure.account,
+            source = failure.source,
+            kind = failure.kind,
+            "Envelope could not be decrypted: {}",
+            failure.message
+        );
+        // Every replica sees the failure; only the leader resets the session.
+        if !state.reset_sessions || !failure.is_session_error() || !state.leadership.is_leader() {
+            continue;
+        }
+        let (Some(account), Some(source)) = (failure.account, failure.source) else {
+            continue;
+        };
+        let key = (account, source);
+        if last_reset
+            .get(&key)
+            .is_some_and(|at| at.elapsed() < RESET_INTERVAL)
+        {
+            continue;
+        }
+        last_reset.retain(|_, at| at.elapsed() < RESET_INTERVAL);
+        let (account, source) = &key;
+        match state
+            .rpc(
+                "sendEndSessionMessage",
+                json!({ "account": account, "recipient": [source] }),
+            )
+            .await
+        {
+            Ok(_) => tracing::info!(account, source, "Session reset after decryption failure"),
+            Err(e) => tracing::warn!(account, source, "Session reset failed: {e}"),
+        }
+        last_reset.insert(key, Instant::now());
+    }
+}
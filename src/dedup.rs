@@ -0,0 +1,232 @@
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Outcome of checking a send against the suppression windows.
+pub enum Decision<'a> {
+    /// Windows are now reserved for the targets not already covered: send
+    /// to those.
+    Send(Reservation<'a>),
+    /// Every target was already sent it — don't send. Carries the highest
+    /// occurrence count so far.
+    Suppressed(u32),
+}
+
+/// Windows opened by [`Deduplicator::check`] for a send still under way.
+/// Dropped without [`Reservation::keep`] (the send was refused or failed),
+/// they close again so the send can be retried.
+pub struct Reservation<'a> {
+    dedup: &'a Deduplicator,
+    keys: Vec<u64>,
+    opened: Instant,
+    suppressed: Vec<String>,
+    kept: bool,
+}
+
+impl Reservation<'_> {
+    /// Recipients already sent this message inside their window, to be left
+    /// out of this send.
+    pub fn suppressed(&self) -> &[String] {
+        &self.suppressed
+    }
+
+    /// The send went out or was queued: leave the windows open.
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+        let mut windows = self.dedup.windows.lock().unwrap();
+        for key in &self.keys {
+            // Unless it closed meanwhile and another send opened the key again.
+            if windows.get(key).is_some_and(|w| w.opened == self.opened) {
+                windows.remove(key);
+            }
+        }
+    }
+}
+
+struct Window {
+    opened: Instant,
+    /// How long the window stays open: the sender's token's, or the global one.
+    length: Duration,
+    count: u32,
+    account: String,
+    /// The send as it went to this window's target alone.
+    params: Value,
+}
+
+/// Coalesces identical sends (same account, body and attachments) to the same
+/// recipient, or group, within a time window. The first send opens the window
+/// and the rest are counted, also while the first is still under way; a send to
+/// several recipients goes only to those not already sent it. When a window
+/// closes a single "<message> (xN)" summary is produced. A send that fails
+/// closes its windows again, so it can be retried.
+#[derive(Default)]
+pub struct Deduplicator {
+    windows: Mutex<HashMap<u64, Window>>,
+}
+
+/// The recipients named by a send, `recipients` or `recipient`.
+fn recipients(params: &Value) -> Vec<&str> {
+    match params.get("recipients").or_else(|| params.get("recipient")) {
+        Some(Value::Array(list)) => list.iter().filter_map(|v| v.as_str()).collect(),
+        Some(Value::String(s)) => vec![s.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+/// Hash of everything that makes two sends to `target` (a recipient, or
+/// none for a group send) "the same message". Taken before attachments are
+/// staged, which replaces them with file names.
+fn key(account: &str, target: Option<&str>, params: &Value) -> u64 {
+    let mut h = DefaultHasher::new();
+    account.hash(&mut h);
+    target.hash(&mut h);
+    params
+        .get("group-id")
+        .or_else(|| params.get("groupId"))
+        .map(|v| v.to_string())
+        .hash(&mut h);
+    params.get("message").map(|v| v.to_string()).hash(&mut h);
+    params
+        .get("base64_attachments")
+        .and_then(Value::as_array)
+        .map(|list| list.iter().map(Value::as_str).collect::<Vec<_>>())
+        .hash(&mut h);
+    h.finish()
+}
+
+/// `params` addressed to `recipient` alone.
+fn to_one(params: &Value, recipient: &str) -> Value {
+    let mut params = params.clone();
+    if let Some(o) = params.as_object_mut() {
+        o.remove("recipient");
+        o.insert("recipients".into(), Value::from(vec![recipient]));
+    }
+    params
+}
+
+/// `params` without its recipients, to compare sends made to different ones.
+fn without_recipients(params: &Value) -> Value {
+    let mut params = params.clone();
+    if let Some(o) = params.as_object_mut() {
+        o.remove("recipients");
+    }
+    params
+}
+
+/// Leave `drop` out of the recipients of `params`.
+pub fn remove_recipients(params: &mut Value, drop: &[String]) {
+    for field in ["recipients", "recipient"] {
+        if let Some(Value::Array(list)) = params.get_mut(field) {
+            list.retain(|r| !r.as_str().is_some_and(|r| drop.iter().any(|d| d == r)));
+        }
+    }
+}
+
+/// Parse a per-token window, `id:secs`, as given to `--dedup-window-token`.
+pub fn parse_token_window(spec: &str) -> Result<(String, Duration), String> {
+    let invalid = || format!("invalid dedup window {spec:?}, expected id:secs");
+    let (id, secs) = spec.split_once(':').ok_or_else(invalid)?;
+    let secs: u64 = secs.parse().map_err(|_| invalid())?;
+    if id.is_empty() {
+        return Err(invalid());
+    }
+    Ok((id.to_string(), Duration::from_secs(secs)))
+}
+
+impl Deduplicator {
+    /// Count the send as a duplicate for each target (recipient, or the
+    /// group) that has a window open for it, and open one for each other
+    /// target, so identical sends arriving together go out once.
+    pub fn check(&self, account: &str, params: &Value, window: Duration) -> Decision<'_> {
+        let targets: Vec<Option<&str>> = match recipients(params) {
+            list if list.is_empty() => vec![None],
+            list => list.into_iter().map(Some).collect(),
+        };
+        let opened = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let mut keys = Vec::new();
+        let mut suppressed = Vec::new();
+        let mut count = 0;
+        for target in targets {
+            let key = key(account, target, params);
+            if let Some(w) = windows
+                .get_mut(&key)
+                .filter(|w| w.opened.elapsed() < window)
+            {
+                w.count += 1;
+                count = count.max(w.count);
+                suppressed.extend(target.map(String::from));
+                continue;
+            }
+            if keys.contains(&key) {
+                continue;
+            }
+            windows.insert(
+                key,
+                Window {
+                    opened,
+                    length: window,
+                    count: 1,
+                    account: account.to_string(),
+                    params: target.map_or_else(|| params.clone(), |r| to_one(params, r)),
+                },
+            );
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Decision::Suppressed(count);
+        }
+        Decision::Send(Reservation {
+            dedup: self,
+            keys,
+            opened,
+            suppressed,
+            kept: false,
+        })
+    }
+
+    /// Close windows that have been open for their length. Returns (account,
+    /// params) summaries for windows that suppressed at least one duplicate,
+    /// with the message suffixed by the total occurrence count. Summaries
+    /// that differ only in their recipient are merged into one send.
+    pub fn expire(&self) -> Vec<(String, Value)> {
+        let mut windows = self.windows.lock().unwrap();
+        let mut summaries: Vec<(String, Value)> = Vec::new();
+        windows.retain(|_, w| {
+            if w.opened.elapsed() < w.length {
+                return true;
+            }
+            if w.count > 1 {
+                let mut params = w.params.clone();
+                let text = params.get("message").and_then(|v| v.as_str()).unwrap_or("");
+                params["message"] = Value::String(format!("{text} (x{})", w.count));
+                let same = |(account, other): &&mut (String, Value)| {
+                    *account == w.account
+                        && without_recipients(other) == without_recipients(&params)
+                };
+                match summaries.iter_mut().find(same) {
+                    Some((_, merged)) => {
+                        let more = params.get("recipients").and_then(Value::as_array).cloned();
+                        if let Some(Value::Array(list)) = merged.get_mut("recipients") {
+                            list.extend(more.into_iter().flatten());
+                        }
+                    }
+                    None => summaries.push((w.account.clone(), params)),
+                }
+            }
+            false
+        });
+        summaries
+    }
+}
@@ -0,0 +1,34 @@
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A device unseen for longer than this gets a `staleWarning`.
+pub const STALE_AFTER: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// Signal's device ID for the primary device, the phone that registered the
+/// account; linked devices get higher IDs.
+const PRIMARY_DEVICE_ID: u64 = 1;
+
+/// Add normalized fields to a signal-cli `listDevices` entry: `created` and
+/// `lastSeen` (ms since epoch, null when signal-cli doesn't say), `isPrimary`,
+/// and `staleWarning`, a message when the device hasn't been seen for
+/// [`STALE_AFTER`] (null otherwise). signal-cli's own fields are kept.
+pub fn enrich(device: &mut Value, now_ms: u64) {
+    let Value::Object(d) = device else {
+        return;
+    };
+    let created = d.get("createdTimestamp").and_then(|v| v.as_u64());
+    let last_seen = d.get("lastSeenTimestamp").and_then(|v| v.as_u64());
+    let unseen_days = last_seen
+        .map(|seen| Duration::from_millis(now_ms.saturating_sub(seen)))
+        .filter(|unseen| *unseen > STALE_AFTER)
+        .map(|unseen| unseen.as_secs() / (24 * 3600));
+    let fields = json!({
+        "created": created,
+        "lastSeen": last_seen,
+        "isPrimary": d.get("id").and_then(|v| v.as_u64()) == Some(PRIMARY_DEVICE_ID),
+        "staleWarning": unseen_days.map(|days| format!("not seen for {days} days; remove it if it is no longer in use")),
+    });
+    if let Value::Object(fields) = fields {
+        d.extend(fields);
+    }
+}
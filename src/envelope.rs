@@ -0,0 +1,253 @@
+use serde_json::{json, Map, Value};
+
+use crate::group_id::GroupId;
+
+/// Identifier of the normalized notification format emitted to clients.
+pub const SCHEMA: &str = "signal-cli-api/envelope/v1";
+
+/// Normalize a notification from signal-cli into the stable v1 format:
+///
+/// ```json
+/// { "schema": "signal-cli-api/envelope/v1", "account": "+1555...",
+///   "envelope": { "source": "+1555...", "sourceNumber": "+1555...",
+///                 "sourceUuid": "...", "timestamp": 1700000000000, ... } }
+/// ```
+///
+/// Both the JSON-RPC `params`-wrapped shape and the bare shape are accepted.
+/// `source` is always the phone number when one is known, `sourceUuid` is
+/// filled from a UUID-valued `source` (newer signal-cli), data messages carry
+/// a `timestamp`, and group IDs are standard base64. Quotes get the same
+/// `author`/`authorNumber`/`authorUuid` treatment as the source, stickers get
+/// a `url` for the image (when the account is known) and shared contacts a
+/// `vcard` rendering. Unknown fields pass through, and the API's group
+/// change events keep their `groupChange` beside the envelope.
+///
+/// Returns `Ok(None)` for lines that aren't envelope notifications, and an
+/// error when the envelope doesn't match the schema.
+pub fn normalize(parsed: &Value) -> Result<Option<Value>, String> {
+    let root = parsed.get("params").unwrap_or(parsed);
+    let Some(envelope) = root.get("envelope") else {
+        return Ok(None);
+    };
+    let Value::Object(raw) = envelope else {
+        return Err("envelope is not an object".into());
+    };
+    let mut env: Map<String, Value> = raw.clone();
+
+    for key in ["source", "sourceNumber", "sourceUuid", "sourceName"] {
+        if env.get(key).is_some_and(|v| !v.is_string() && !v.is_null()) {
+            return Err(format!("envelope.{key} must be a string"));
+        }
+    }
+    for key in [
+        "timestamp",
+        "sourceDevice",
+        "serverReceivedTimestamp",
+        "serverDeliveredTimestamp",
+    ] {
+        if env.get(key).is_some_and(|v| !v.is_u64() && !v.is_null()) {
+            return Err(format!("envelope.{key} must be a non-negative integer"));
+        }
+    }
+
+    unify_address(&mut env, "source", "sourceNumber", "sourceUuid");
+
+    let envelope_ts = env.get("timestamp").cloned();
+    if let Some(Value::Object(data)) = env.get_mut("dataMessage") {
+        if !data.contains_key("timestamp") {
+            if let Some(ts) = envelope_ts {
+                data.insert("timestamp".into(), ts);
+            }
+        }
+        if let Some(Value::Object(group)) = data.get_mut("groupInfo") {
+            if let Some(Value::String(id)) = group.get("groupId") {
+                let normalized = GroupId::from(id.as_str()).to_string();
+                group.insert("groupId".into(), json!(normalized));
+            }
+        }
+        if let Some(quote) = data.get_mut("quote") {
+            normalize_quote(quote)?;
+        }
+        if let Some(sticker) = data.get_mut("sticker") {
+            normalize_sticker(sticker, root.get("account").and_then(|v| v.as_str()))?;
+        }
+        if let Some(contacts) = data.get_mut("sharedContacts") {
+            let Value::Array(contacts) = contacts else {
+                return Err("dataMessage.sharedContacts must be an array".into());
+            };
+            for contact in contacts.iter_mut().filter_map(Value::as_object_mut) {
+                let card = vcard(contact);
+                contact.insert("vcard".into(), json!(card));
+            }
+        }
+    }
+
+    let mut out = json!({ "schema": SCHEMA, "envelope": env });
+    if let Some(account) = root.get("account").and_then(|v| v.as_str()) {
+        out["account"] = json!(account);
+    }
+    if let Some(exception) = root.get("exception") {
+        out["exception"] = exception.clone();
+    }
+    if let Some(change) = root.get("groupChange") {
+        out["groupChange"] = change.clone();
+    }
+    Ok(Some(out))
+}
+
+/// Replace attachment contents with where to fetch them: every attachment
+/// (an object with a string `id` and a `contentType`), wherever it sits in
+/// the notification, loses any inline `data` and gets a `url` under
+/// /v1/attachments.
+pub fn strip_attachment_data(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let (Some(Value::String(id)), true) =
+                (map.get("id"), map.contains_key("contentType"))
+            {
+                let url = format!("/v1/attachments/{id}");
+                map.remove("data");
+                map.insert("url".into(), json!(url));
+            }
+            map.values_mut().for_each(strip_attachment_data);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_attachment_data),
+        _ => {}
+    }
+}
+
+/// Fill the plain, number and UUID fields of an address from one another: the
+/// plain field holds the number when known, otherwise the UUID.
+fn unify_address(map: &mut Map<String, Value>, plain: &str, number_key: &str, uuid_key: &str) {
+    let str_field = |map: &Map<String, Value>, key: &str| {
+        map.get(key).and_then(|v| v.as_str()).map(String::from)
+    };
+    let value = str_field(map, plain);
+    let number =
+        str_field(map, number_key).or_else(|| value.clone().filter(|s| s.starts_with('+')));
+    let uuid = str_field(map, uuid_key).or_else(|| value.filter(|s| !s.starts_with('+')));
+    if let Some(number) = &number {
+        map.insert(plain.into(), json!(number));
+        map.insert(number_key.into(), json!(number));
+    }
+    if let Some(uuid) = uuid {
+        map.insert(uuid_key.into(), json!(uuid));
+    }
+}
+
+/// `quote`: `{id, author, authorNumber, authorUuid, text, ...}`, where `id` is
+/// the quoted message's timestamp.
+fn normalize_quote(quote: &mut Value) -> Result<(), String> {
+    let Value::Object(quote) = quote else {
+        return Err("dataMessage.quote must be an object".into());
+    };
+    for key in ["author", "authorNumber", "authorUuid", "text"] {
+        if quote
+            .get(key)
+            .is_some_and(|v| !v.is_string() && !v.is_null())
+        {
+            return Err(format!("dataMessage.quote.{key} must be a string"));
+        }
+    }
+    if quote.get("id").is_some_and(|v| !v.is_u64()) {
+        return Err("dataMessage.quote.id must be a non-negative integer".into());
+    }
+    unify_address(quote, "author", "authorNumber", "authorUuid");
+    Ok(())
+}
+
+/// `sticker`: `{packId, stickerId, url}`, with `url` pointing at
+/// GET /v1/sticker-packs/{account}/{packId}/stickers/{stickerId}.
+fn normalize_sticker(sticker: &mut Value, account: Option<&str>) -> Result<(), String> {
+    let Value::Object(sticker) = sticker else {
+        return Err("dataMessage.sticker must be an object".into());
+    };
+    let Some(pack_id) = sticker
+        .get("packId")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+    else {
+        return Err("dataMessage.sticker.packId must be a string".into());
+    };
+    let Some(sticker_id) = sticker.get("stickerId").and_then(|v| v.as_u64()) else {
+        return Err("dataMessage.sticker.stickerId must be a non-negative integer".into());
+    };
+    if let Some(account) = account {
+        let url = format!("/v1/sticker-packs/{account}/{pack_id}/stickers/{sticker_id}");
+        sticker.insert("url".into(), json!(url));
+    }
+    Ok(())
+}
+
+/// Render a signal-cli shared contact as a vCard 3.0.
+fn vcard(contact: &Map<String, Value>) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('\\', "\\\\")
+            .replace(',', "\\,")
+            .replace(';', "\\;")
+            .replace('\n', "\\n")
+    }
+    let field = |v: &Value, key: &str| escape(v.get(key).and_then(|s| s.as_str()).unwrap_or(""));
+    let kind = |v: &Value| match v.get("type").and_then(|t| t.as_str()) {
+        Some("MOBILE") => ";TYPE=CELL".to_string(),
+        Some(t @ ("HOME" | "WORK")) => format!(";TYPE={t}"),
+        _ => String::new(),
+    };
+    let entries = |key: &str| {
+        contact
+            .get(key)
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let name = contact.get("name").cloned().unwrap_or(Value::Null);
+    let display = match field(&name, "display") {
+        d if !d.is_empty() => d,
+        _ => [field(&name, "given"), field(&name, "family")]
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+    let mut lines = vec![
+        "BEGIN:VCARD".to_string(),
+        "VERSION:3.0".to_string(),
+        format!("FN:{display}"),
+        format!(
+            "N:{};{};{};{};{}",
+            field(&name, "family"),
+            field(&name, "given"),
+            field(&name, "middle"),
+            field(&name, "prefix"),
+            field(&name, "suffix")
+        ),
+    ];
+    for phone in entries("phone") {
+        lines.push(format!("TEL{}:{}", kind(&phone), field(&phone, "value")));
+    }
+    for email in entries("email") {
+        lines.push(format!("EMAIL{}:{}", kind(&email), field(&email, "value")));
+    }
+    for addr in entries("address") {
+        lines.push(format!(
+            "ADR{}:{};;{};{};{};{};{}",
+            kind(&addr),
+            field(&addr, "pobox"),
+            field(&addr, "street"),
+            field(&addr, "city"),
+            field(&addr, "region"),
+            field(&addr, "postcode"),
+            field(&addr, "country")
+        ));
+    }
+    if let Some(org) = contact
+        .get("organization")
+        .and_then(|v| v.as_str())
+        .filter(|o| !o.is_empty())
+    {
+        lines.push(format!("ORG:{}", escape(org)));
+    }
+    lines.push("END:VCARD".to_string());
+    lines.join("\r\n") + "\r\n"
+}
@@ -0,0 +1,239 @@
+use axum::body::Bytes;
+use serde_json::{json, Value};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::envelope;
+use crate::language::{self, Language};
+use crate::store::split_notification;
+
+/// What a notification on the broadcast channel carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Message,
+    /// A group update signal-cli reports as a data message with
+    /// `groupInfo.type` `UPDATE`, saying only that the group changed.
+    GroupUpdate,
+    /// What changed in a group, published by the API after a `GroupUpdate`
+    /// (see [`crate::group_events`]).
+    GroupChange(GroupChange),
+    Receipt,
+    Typing,
+    Sync,
+    /// An envelope signal-cli could not decrypt.
+    Error,
+    /// Published by the API itself (see `AppState::publish_system`).
+    System,
+    /// Any other notification, or a line that isn't JSON.
+    Other,
+}
+
+impl EventKind {
+    fn of(parsed: &Value) -> Self {
+        if parsed.get("system").is_some() {
+            return Self::System;
+        }
+        let root = parsed.get("params").unwrap_or(parsed);
+        let Some(envelope) = root.get("envelope") else {
+            return Self::Other;
+        };
+        if let Some(change) = root.get("groupChange") {
+            change["type"]
+                .as_str()
+                .and_then(GroupChange::parse)
+                .map_or(Self::Other, Self::GroupChange)
+        } else if root.get("exception").is_some() {
+            Self::Error
+        } else if envelope["dataMessage"]["groupInfo"]["type"] == "UPDATE" {
+            Self::GroupUpdate
+        } else if envelope.get("dataMessage").is_some() {
+            Self::Message
+        } else if envelope.get("receiptMessage").is_some() {
+            Self::Receipt
+        } else if envelope.get("typingMessage").is_some() {
+            Self::Typing
+        } else if envelope.get("syncMessage").is_some() {
+            Self::Sync
+        } else {
+            Self::Other
+        }
+    }
+
+    /// The name webhooks filter on in their `events` list (None for `Other`).
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Message => Some("message"),
+            Self::GroupUpdate => Some("group_update"),
+            Self::GroupChange(GroupChange::MemberAdded) => Some("group_member_added"),
+            Self::GroupChange(GroupChange::MemberRemoved) => Some("group_member_removed"),
+            Self::GroupChange(GroupChange::NameChanged) => Some("group_name_changed"),
+            Self::GroupChange(GroupChange::AdminGranted) => Some("group_admin_granted"),
+            Self::GroupChange(GroupChange::AdminRevoked) => Some("group_admin_revoked"),
+            Self::Receipt => Some("receipt"),
+            Self::Typing => Some("typing"),
+            Self::Sync => Some("sync"),
+            Self::Error => Some("error"),
+            Self::System => Some("system"),
+            Self::Other => None,
+        }
+    }
+}
+
+/// A kind of change to a group, from comparing its state before and after
+/// an update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupChange {
+    MemberAdded,
+    MemberRemoved,
+    NameChanged,
+    AdminGranted,
+    AdminRevoked,
+}
+
+impl GroupChange {
+    const ALL: [Self; 5] = [
+        Self::MemberAdded,
+        Self::MemberRemoved,
+        Self::NameChanged,
+        Self::AdminGranted,
+        Self::AdminRevoked,
+    ];
+
+    /// The `type` of a `groupChange` payload; webhooks filter on it prefixed
+    /// with `group_`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MemberAdded => "member_added",
+            Self::MemberRemoved => "member_removed",
+            Self::NameChanged => "name_changed",
+            Self::AdminGranted => "admin_granted",
+            Self::AdminRevoked => "admin_revoked",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+/// A notification on the broadcast channel. It is parsed once, when it
+/// arrives, and cloning it only bumps a reference count, so subscribers share
+/// the line, its JSON and its normalized form however large the envelope is.
+#[derive(Clone, Debug)]
+pub struct Event(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    kind: EventKind,
+    line: Arc<str>,
+    json: Value,
+    normalized: OnceLock<Arc<str>>,
+    language: OnceLock<Option<Language>>,
+    /// When the API got it.
+    received_at: Instant,
+}
+
+impl Event {
+    /// An event from a line and its parsed JSON.
+    pub fn new(line: impl Into<Arc<str>>, json: Value) -> Self {
+        Self(Arc::new(Inner {
+            kind: EventKind::of(&json),
+            line: line.into(),
+            json,
+            normalized: OnceLock::new(),
+            language: OnceLock::new(),
+            received_at: Instant::now(),
+        }))
+    }
+
+    /// A `{"system": ...}` event from the API itself.
+    pub fn system(event: Value) -> Self {
+        let json = json!({ "system": event });
+        Self::new(json.to_string(), json)
+    }
+
+    pub fn kind(&self) -> EventKind {
+        self.0.kind
+    }
+
+    pub fn json(&self) -> &Value {
+        &self.0.json
+    }
+
+    /// The line as signal-cli sent it.
+    pub fn line(&self) -> &Arc<str> {
+        &self.0.line
+    }
+
+    /// Time since the API got the event.
+    pub fn age(&self) -> Duration {
+        self.0.received_at.elapsed()
+    }
+
+    /// When the Signal server received the envelope (ms since epoch).
+    pub fn server_received(&self) -> Option<u64> {
+        self.notification()?
+            .1
+            .get("serverReceivedTimestamp")?
+            .as_u64()
+    }
+
+    /// The account and envelope of a signal-cli notification.
+    pub fn notification(&self) -> Option<(&str, &Value)> {
+        split_notification(&self.0.json)
+    }
+
+    /// The language of a message's text, detected on first use (see
+    /// [`language::detect`]).
+    pub fn language(&self) -> Option<&Language> {
+        self.0
+            .language
+            .get_or_init(|| {
+                let (_, envelope) = self.notification()?;
+                language::detect(envelope["dataMessage"].get("message")?.as_str()?)
+            })
+            .as_ref()
+    }
+
+    /// The text to emit to a client: the line unchanged when `raw` is
+    /// requested, it isn't an envelope or it fails validation, otherwise its
+    /// normalized form (see [`envelope::normalize`]) with the message's
+    /// `language` when detected, which is computed on first use and then shared.
+    pub fn for_client(&self, raw: bool) -> &Arc<str> {
+        if raw {
+            return &self.0.line;
+        }
+        self.0
+            .normalized
+            .get_or_init(|| match envelope::normalize(&self.0.json) {
+                Ok(Some(mut normalized)) => {
+                    if let Some(language) = self.language() {
+                        normalized["envelope"]["dataMessage"]["language"] = json!(language);
+                    }
+                    normalized.to_string().into()
+                }
+                Ok(None) => self.0.line.clone(),
+                Err(e) => {
+                    tracing::warn!(
+                        "Envelope failed {} validation ({e}); forwarding it raw",
+                        envelope::SCHEMA
+                    );
+                    self.0.line.clone()
+                }
+            })
+    }
+
+    /// [`Event::for_client`] as a byte buffer for a socket or HTTP body,
+    /// sharing the event's text rather than copying it.
+    pub fn bytes_for_client(&self, raw: bool) -> Bytes {
+        Bytes::from_owner(Arc::<[u8]>::from(self.for_client(raw).clone()))
+    }
+}
+
+impl From<String> for Event {
+    /// Parse a line; one that isn't JSON becomes an `Other` event.
+    fn from(line: String) -> Self {
+        let json = serde_json::from_str(&line).unwrap_or(Value::Null);
+        Self::new(line, json)
+    }
+}
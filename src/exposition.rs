@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds, in milliseconds, of the latency histogram buckets.
+pub const LATENCY_BUCKETS_MS: [u64; 12] = [
+    5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000,
+];
+
+/// A latency histogram over [`LATENCY_BUCKETS_MS`].
+#[derive(Default)]
+pub struct Histogram {
+    /// Observations per bucket (not cumulative); the last one is `+Inf`.
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&le| ms <= le)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+    }
+}
+
+/// Text exposition formats understood by Prometheus-compatible scrapers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Prometheus text format 0.0.4.
+    Prometheus,
+    /// OpenMetrics 1.0.0 text format.
+    OpenMetrics,
+}
+
+impl Format {
+    /// Pick the format from an HTTP `Accept` header value.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        match accept {
+            Some(a) if a.contains("application/openmetrics-text") => Format::OpenMetrics,
+            _ => Format::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Label set for a single sample, e.g. `&[("lane", "high")]`.
+pub type Labels<'a> = &'a [(&'a str, &'a str)];
+
+/// Builder for a metrics exposition body.
+pub struct Exposition {
+    format: Format,
+    out: String,
+}
+
+fn escape_help(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn escape_label(s: &str) -> String {
+    crate::privacy::redact(s)
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Exposition {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            out: String::new(),
+        }
+    }
+
+    /// A counter family. `name` is given without the `_total` suffix; samples
+    /// are always exposed as `<name>_total`.
+    pub fn counter(&mut self, name: &str, help: &str, samples: &[(Labels, u64)]) {
+        let family = match self.format {
+            Format::Prometheus => format!("{name}_total"),
+            Format::OpenMetrics => name.to_string(),
+        };
+        self.family(&family, &format!("{name}_total"), "counter", help, samples);
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, samples: &[(Labels, u64)]) {
+        self.family(name, name, "gauge", help, samples);
+    }
+
+    /// A histogram family: cumulative `<name>_bucket` samples per label set,
+    /// then `<name>_sum` and `<name>_count`.
+    pub fn histogram(&mut self, name: &str, help: &str, samples: &[(Labels, &Histogram)]) {
+        self.out.push_str(&format!(
+            "# HELP {name} {}\n# TYPE {name} histogram\n",
+            escape_help(help)
+        ));
+        for (labels, histogram) in samples {
+            let mut cumulative = 0;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = LATENCY_BUCKETS_MS
+                    .get(i)
+                    .map_or("+Inf".to_string(), |le| le.to_string());
+                let mut bucket_labels = labels.to_vec();
+                bucket_labels.push(("le", &le));
+                self.sample(&format!("{name}_bucket"), &bucket_labels, cumulative);
+            }
+            self.sample(
+                &format!("{name}_sum"),
+                labels,
+                histogram.sum_ms.load(Ordering::Relaxed),
+            );
+            self.sample(&format!("{name}_count"), labels, cumulative);
+        }
+    }
+
+    fn family(
+        &mut self,
+        family: &str,
+        sample: &str,
+        kind: &str,
+        help: &str,
+        samples: &[(Labels, u64)],
+    ) {
+        self.out.push_str(&format!(
+            "# HELP {family} {}\n# TYPE {family} {kind}\n",
+            escape_help(help)
+        ));
+        for (labels, value) in samples {
+            self.sample(sample, labels, *value);
+        }
+    }
+
+    fn sample(&mut self, name: &str, labels: Labels, value: u64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let rendered: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect();
+            self.out.push_str(&format!("{{{}}}", rendered.join(",")));
+        }
+        self.out.push_str(&format!(" {value}\n"));
+    }
+
+    /// Finish the body; OpenMetrics requires a trailing `# EOF`.
+    pub fn finish(mut self) -> String {
+        if self.format == Format::OpenMetrics {
+            self.out.push_str("# EOF\n");
+        }
+        self.out
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::event::Event;
+use crate::group_id::GroupId;
+use crate::store::envelope_source;
+
+/// Longest filter accepted, in bytes.
+const MAX_LEN: usize = 1024;
+
+/// Deepest nesting of parentheses and `!` accepted.
+const MAX_DEPTH: usize = 32;
+
+/// Fields a filter can name besides `envelope.` paths.
+const FIELDS: &[&str] = &["type", "account", "source", "group", "body", "language"];
+
+/// A condition on events, e.g. `group == "abc=" && body contains "deploy"`.
+///
+/// Operands are fields (see [`FIELDS`]; `envelope.a.b` reads the envelope),
+/// strings in single or double quotes, integers, `true`, `false` and `null`.
+/// `==`, `!=`, `contains` (substring, or element of a list) and `startswith`
+/// compare them; `&&`, `||`, `!` and parentheses combine conditions. A field
+/// on its own is true when it is set and not false or empty. It is kept as
+/// written and parsed once, when the webhook is set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    Truthy(Operand),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Contains,
+    StartsWith,
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Field(String),
+    Literal(Value),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let mut pair = |second: char, token: Token| {
+            chars.next();
+            match chars.next() {
+                Some((_, c)) if c == second => Ok(token),
+                _ => Err(format!("expected {c}{second} at {at}")),
+            }
+        };
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '=' => pair('=', Token::Eq)?,
+            '&' => pair('&', Token::And)?,
+            '|' => pair('|', Token::Or)?,
+            '!' => {
+                chars.next();
+                if chars.next_if(|(_, c)| *c == '=').is_some() {
+                    Token::Ne
+                } else {
+                    Token::Not
+                }
+            }
+            '(' | ')' => {
+                chars.next();
+                if c == '(' {
+                    Token::Open
+                } else {
+                    Token::Close
+                }
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => text.push(c),
+                            None => return Err(format!("unterminated string at {at}")),
+                        },
+                        Some((_, q)) if q == c => break,
+                        Some((_, c)) => text.push(c),
+                        None => return Err(format!("unterminated string at {at}")),
+                    }
+                }
+                Token::Literal(json!(text))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut text = String::new();
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit() || (*c == '-')) {
+                    text.push(c);
+                }
+                let n: i64 = text
+                    .parse()
+                    .map_err(|_| format!("invalid number {text:?} at {at}"))?;
+                Token::Literal(json!(n))
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some((_, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Token::Literal(json!(true)),
+                    "false" => Token::Literal(json!(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Ident(word),
+                }
+            }
+            c => return Err(format!("unexpected {c:?} at {at}")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("nested deeper than {MAX_DEPTH}"));
+        }
+        let expr = match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Expr::Not(Box::new(self.unary()?))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let inner = self.or()?;
+                if self.next() != Some(Token::Close) {
+                    return Err("missing )".into());
+                }
+                inner
+            }
+            _ => self.comparison()?,
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        let op = match self.peek() {
+            Some(Token::Eq) => Op::Eq,
+            Some(Token::Ne) => Op::Ne,
+            Some(Token::Ident(word)) if word == "contains" => Op::Contains,
+            Some(Token::Ident(word)) if word == "startswith" => Op::StartsWith,
+            _ => return Ok(Expr::Truthy(left)),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(Token::Ident(name))
+                if FIELDS.contains(&name.as_str()) || name.starts_with("envelope.") =>
+            {
+                Ok(Operand::Field(name))
+            }
+            Some(Token::Ident(name)) => Err(format!(
+                "unknown field {name:?}, expected one of {} or envelope.<path>",
+                FIELDS.join(", ")
+            )),
+            Some(token) => Err(format!("expected a field or value, found {token:?}")),
+            None => Err("unexpected end of filter".into()),
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.len() > MAX_LEN {
+            return Err(format!("filter is longer than {MAX_LEN} bytes"));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!(
+                "unexpected {token:?} after the end of the condition"
+            ));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Whether `event` meets the condition.
+    pub fn matches(&self, event: &Event) -> bool {
+        eval(&self.expr, event)
+    }
+}
+
+fn eval(expr: &Expr, event: &Event) -> bool {
+    match expr {
+        Expr::Or(a, b) => eval(a, event) || eval(b, event),
+        Expr::And(a, b) => eval(a, event) && eval(b, event),
+        Expr::Not(e) => !eval(e, event),
+        Expr::Truthy(operand) => match value(operand, event) {
+            Value::Null | Value::Bool(false) => false,
+            Value::String(s) => !s.is_empty(),
+            _ => true,
+        },
+        Expr::Compare(a, op, b) => {
+            let (a, b) = (value(a, event), value(b, event));
+            match op {
+                Op::Eq => a == b,
+                Op::Ne => a != b,
+                Op::Contains => match (&a, &b) {
+                    (Value::String(a), Value::String(b)) => a.contains(b.as_str()),
+                    (Value::Array(items), b) => items.contains(b),
+                    _ => false,
+                },
+                Op::StartsWith => {
+                    matches!((&a, &b), (Value::String(a), Value::String(b)) if a.starts_with(b.as_str()))
+                }
+            }
+        }
+    }
+}
+
+/// An operand's value for `event` (null when the event doesn't have it).
+fn value(operand: &Operand, event: &Event) -> Value {
+    let name = match operand {
+        Operand::Literal(value) => return value.clone(),
+        Operand::Field(name) => name.as_str(),
+    };
+    let notification = event.notification();
+    let envelope = notification.map(|(_, e)| e);
+    let text = |s: Option<&str>| s.map_or(Value::Null, |s| json!(s));
+    match name {
+        "type" => text(event.kind().name()),
+        "account" => text(notification.map(|(a, _)| a)),
+        "source" => text(envelope.map(envelope_source).filter(|s| !s.is_empty())),
+        "body" => envelope.map_or(Value::Null, |e| e["dataMessage"]["message"].clone()),
+        "language" => text(event.language().map(|l| l.code)),
+        "group" => {
+            let root = event.json().get("params").unwrap_or(event.json());
+            let id = envelope
+                .and_then(|e| e["dataMessage"]["groupInfo"]["groupId"].as_str())
+                .or_else(|| root["groupChange"]["groupId"].as_str());
+            id.map_or(Value::Null, |id| json!(GroupId::from(id).to_string()))
+        }
+        path => {
+            let path = path.strip_prefix("envelope.").unwrap_or(path);
+            let found = path
+                .split('.')
+                .try_fold(envelope.unwrap_or(&Value::Null), |v, key| v.get(key));
+            found.cloned().unwrap_or(Value::Null)
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.source
+    }
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
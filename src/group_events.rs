@@ -0,0 +1,212 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::{Event, EventKind, GroupChange};
+use crate::group_id::GroupId;
+use crate::groups::member_id;
+use crate::preflight::listed_numbers;
+use crate::state::AppState;
+
+/// What of a group is compared between updates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub name: Option<String>,
+    pub members: BTreeSet<String>,
+    pub admins: BTreeSet<String>,
+}
+
+impl Snapshot {
+    /// Read a signal-cli `listGroups` entry (see [`member_id`]).
+    pub fn of(group: &Value) -> Self {
+        let ids = |key: &str| -> BTreeSet<String> {
+            group
+                .get(key)
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(member_id)
+                .collect()
+        };
+        Self {
+            name: group.get("name").and_then(|v| v.as_str()).map(String::from),
+            members: ids("members"),
+            admins: ids("admins"),
+        }
+    }
+
+    /// What changed from `self` to `after`, each with its payload fields.
+    pub fn diff(&self, after: &Snapshot) -> Vec<(GroupChange, Value)> {
+        let mut changes = Vec::new();
+        let mut sets = |before: &BTreeSet<String>, after: &BTreeSet<String>, added, removed| {
+            let gone: Vec<&String> = before.difference(after).collect();
+            let new: Vec<&String> = after.difference(before).collect();
+            if !new.is_empty() {
+                changes.push((added, json!({ "members": new })));
+            }
+            if !gone.is_empty() {
+                changes.push((removed, json!({ "members": gone })));
+            }
+        };
+        sets(
+            &self.members,
+            &after.members,
+            GroupChange::MemberAdded,
+            GroupChange::MemberRemoved,
+        );
+        sets(
+            &self.admins,
+            &after.admins,
+            GroupChange::AdminGranted,
+            GroupChange::AdminRevoked,
+        );
+        if self.name != after.name {
+            changes.push((
+                GroupChange::NameChanged,
+                json!({ "previousName": self.name }),
+            ));
+        }
+        changes
+    }
+}
+
+/// The last known state of each group per account, which updates are
+/// compared against.
+#[derive(Default)]
+pub struct Snapshots(Mutex<HashMap<(String, String), Snapshot>>);
+
+impl Snapshots {
+    /// Remember the groups of a `listGroups` result that aren't known yet,
+    /// so the first update to them can be compared. Known groups keep their
+    /// snapshot until their update arrives, so no change is lost.
+    pub fn seed(&self, account: &str, groups: &Value) {
+        let mut snapshots = self.0.lock().unwrap();
+        for group in groups.as_array().into_iter().flatten() {
+            let Some(id) = group.get("id").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let key = (account.to_string(), GroupId::from(id).to_string());
+            snapshots.entry(key).or_insert_with(|| Snapshot::of(group));
+        }
+    }
+
+    /// Store a group's new state, returning the one it replaces.
+    pub fn replace(
+        &self,
+        account: &str,
+        group_id: &GroupId,
+        snapshot: Snapshot,
+    ) -> Option<Snapshot> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert((account.to_string(), group_id.to_string()), snapshot)
+    }
+}
+
+/// The notification published for one change: the update's envelope without
+/// its data message, so it names who made the change and when, and a
+/// `groupChange` with the change's `type`, `groupId`, `groupName` and fields.
+fn change_event(
+    account: &str,
+    envelope: &Value,
+    group_id: &GroupId,
+    name: Option<&str>,
+    change: (GroupChange, Value),
+) -> Event {
+    let (kind, fields) = change;
+    let mut envelope = envelope.clone();
+    if let Value::Object(env) = &mut envelope {
+        env.remove("dataMessage");
+    }
+    let mut group_change = json!({ "type": kind.name(), "groupId": group_id, "groupName": name });
+    if let (Value::Object(out), Value::Object(fields)) = (&mut group_change, fields) {
+        out.extend(fields);
+    }
+    let json = json!({ "params": { "account": account, "envelope": envelope, "groupChange": group_change } });
+    Event::new(json.to_string(), json)
+}
+
+/// Remember the groups of `account` as they are now, so that their first
+/// update can be compared.
+async fn seed_account(state: &AppState, account: &str) {
+    match state.rpc("listGroups", json!({ "account": account })).await {
+        Ok(groups) => state.group_snapshots.seed(account, &groups),
+        Err(e) => tracing::warn!(account, "Could not list groups to watch for changes: {e}"),
+    }
+}
+
+/// Turn signal-cli's group updates, which only say that a group changed,
+/// into one event per change: `listGroups` gives the group's new state,
+/// which is compared to the last one seen. Every account's groups are
+/// listed at startup, or for an account signal-cli didn't list then, when
+/// its first notification arrives. A group first seen in an update only
+/// has its state recorded.
+pub async fn watch_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    let mut seeded = HashSet::new();
+    match state.rpc("listAccounts", json!({})).await {
+        Ok(accounts) => {
+            for account in listed_numbers(&accounts) {
+                seed_account(&state, &account).await;
+                seeded.insert(account);
+            }
+        }
+        Err(e) => tracing::warn!("Could not list accounts to watch their groups for changes: {e}"),
+    }
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Group change watcher lagged, {n} notifications skipped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Some((account, envelope)) = event.notification() else {
+            continue;
+        };
+        if !account.is_empty() && !seeded.contains(account) {
+            seeded.insert(account.to_string());
+            seed_account(&state, account).await;
+        }
+        if event.kind() != EventKind::GroupUpdate {
+            continue;
+        }
+        let Some(group_id) = envelope["dataMessage"]["groupInfo"]["groupId"]
+            .as_str()
+            .map(GroupId::from)
+        else {
+            continue;
+        };
+        let params = json!({ "account": account, "group-id": group_id });
+        let group = match state.rpc("listGroups", params).await {
+            Ok(groups) => groups.as_array().and_then(|g| g.first()).cloned(),
+            Err(e) => {
+                tracing::warn!(account, group = %group_id, "Could not fetch updated group: {e}");
+                continue;
+            }
+        };
+        let Some(group) = group else {
+            continue;
+        };
+        state.group_cache.invalidate(account);
+        let after = Snapshot::of(&group);
+        let Some(before) = state
+            .group_snapshots
+            .replace(account, &group_id, after.clone())
+        else {
+            continue;
+        };
+        for change in before.diff(&after) {
+            let _ = state.broadcast_tx.send(change_event(
+                account,
+                envelope,
+                &group_id,
+                after.name.as_deref(),
+                change,
+            ));
+        }
+    }
+}
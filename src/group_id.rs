@@ -0,0 +1,100 @@
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+
+/// A Signal group ID in signal-cli's standard base64 form.
+///
+/// Converting from a string accepts standard base64, URL-safe base64 (with or
+/// without padding), the hex form, and bbernhard's `group.<base64>` form.
+/// Strings that match none of these are kept verbatim so signal-cli can
+/// report the error.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GroupId(String);
+
+/// Raw lengths of v1 (16 byte) and v2 (32 byte) group IDs.
+const ID_LENGTHS: [usize; 2] = [16, 32];
+
+impl GroupId {
+    /// A group ID in any of the accepted forms, or None if the string isn't one.
+    pub fn parse(s: &str) -> Option<Self> {
+        let id = GroupId::from(s);
+        GroupId::decode(&id.0).is_some().then_some(id)
+    }
+
+    fn decode(s: &str) -> Option<Vec<u8>> {
+        if s.len() == 2 * ID_LENGTHS[0] || s.len() == 2 * ID_LENGTHS[1] {
+            if let Ok(bytes) = hex::decode(s) {
+                return Some(bytes);
+            }
+        }
+        let standard: String = s
+            .trim_end_matches('=')
+            .chars()
+            .map(|c| match c {
+                '-' => '+',
+                '_' => '/',
+                c => c,
+            })
+            .collect();
+        STANDARD_NO_PAD
+            .decode(standard)
+            .ok()
+            .filter(|bytes| ID_LENGTHS.contains(&bytes.len()))
+    }
+}
+
+impl From<&str> for GroupId {
+    fn from(s: &str) -> Self {
+        let s = s.trim();
+        if let Some(wrapped) = s.strip_prefix("group.") {
+            let inner = STANDARD
+                .decode(wrapped)
+                .ok()
+                .and_then(|b| String::from_utf8(b).ok());
+            if let Some(inner) = inner {
+                return GroupId::from(inner.as_str());
+            }
+        }
+        match GroupId::decode(s) {
+            Some(bytes) => GroupId(STANDARD.encode(bytes)),
+            None => GroupId(s.to_string()),
+        }
+    }
+}
+
+impl From<String> for GroupId {
+    fn from(s: String) -> Self {
+        GroupId::from(s.as_str())
+    }
+}
+
+impl fmt::Display for GroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for GroupId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for GroupId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(GroupId::from(String::deserialize(deserializer)?))
+    }
+}
+
+/// Normalize the group ID fields ("group-id", "groupId") of a JSON-RPC
+/// params object passed through from a request body.
+pub fn normalize_params(params: &mut Value) {
+    for key in ["group-id", "groupId"] {
+        if let Some(Value::String(id)) = params.get(key) {
+            let normalized = GroupId::from(id.as_str());
+            params[key] = Value::String(normalized.0);
+        }
+    }
+}
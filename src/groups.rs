@@ -0,0 +1,133 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an account's group list is served from cache by default.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Most members a `"mention": "all"` send mentions unless `--max-mentions`
+/// says otherwise.
+pub const DEFAULT_MAX_MENTIONS: usize = 100;
+
+/// Placeholder for a mention in message text; clients show the member's name.
+const MENTION_PLACEHOLDER: char = '\u{FFFC}';
+
+/// A member of a `listGroups` entry by number, or by UUID when signal-cli
+/// doesn't know the number.
+pub fn member_id(member: &Value) -> Option<String> {
+    match member {
+        Value::String(s) => Some(s.clone()),
+        m => ["number", "uuid"]
+            .iter()
+            .find_map(|key| m.get(key).and_then(|v| v.as_str()))
+            .map(String::from),
+    }
+}
+
+/// The members of a `listGroups` entry other than `account`.
+pub fn other_members(group: &Value, account: &str) -> Vec<String> {
+    let members = group
+        .get("members")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten();
+    members
+        .filter_map(member_id)
+        .filter(|m| m != account)
+        .collect()
+}
+
+/// Mention every one of `members` at the start of a send's message: a
+/// placeholder per member, each with a signal-cli `mention`
+/// (`start:length:recipient`, counted in UTF-16 units).
+pub fn mention_all(params: &mut Value, members: &[String]) {
+    let mut text = String::new();
+    let mut mentions = Vec::new();
+    for member in members {
+        mentions.push(format!("{}:1:{member}", text.encode_utf16().count()));
+        text.push(MENTION_PLACEHOLDER);
+        text.push(' ');
+    }
+    text.push_str(params.get("message").and_then(|v| v.as_str()).unwrap_or(""));
+    params["message"] = json!(text);
+    params["mention"] = json!(mentions);
+}
+
+/// Add normalized fields to a signal-cli `listGroups` entry as seen by
+/// `account`: `memberCount`, `isAdmin`, `isBlocked`, `inviteLink`,
+/// `expirationTimer` (seconds, 0 = off) and `description`. signal-cli's own
+/// fields are kept.
+pub fn enrich(group: &mut Value, account: &str) {
+    let Value::Object(g) = group else {
+        return;
+    };
+    let is_account = |m: &Value| match m {
+        Value::String(s) => s == account,
+        m => m.get("number").and_then(|n| n.as_str()) == Some(account),
+    };
+    let list = |key: &str| g.get(key).and_then(|v| v.as_array());
+    let member_count = list("members").map_or(0, |m| m.len());
+    let is_admin = list("admins").is_some_and(|a| a.iter().any(is_account));
+    let fields = json!({
+        "memberCount": member_count,
+        "isAdmin": is_admin,
+        "isBlocked": g.get("isBlocked").and_then(|v| v.as_bool()).unwrap_or(false),
+        "inviteLink": g.get("groupInviteLink").filter(|v| v.is_string()).cloned(),
+        "expirationTimer": g.get("messageExpirationTime").and_then(|v| v.as_u64()).unwrap_or(0),
+        "description": g.get("description").filter(|v| v.is_string()).cloned(),
+    });
+    if let Value::Object(fields) = fields {
+        g.extend(fields);
+    }
+}
+
+/// Enriched group lists per account, kept for a short time so UIs polling
+/// the list don't each cost a `listGroups` call. Changes made through the
+/// API drop the account's entry; changes made elsewhere show up once the
+/// entry expires.
+pub struct GroupCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl Default for GroupCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_TTL)
+    }
+}
+
+impl GroupCache {
+    /// A zero `ttl` disables caching.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn get(&self, account: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(account)
+            .filter(|(at, _)| at.elapsed() < self.ttl)
+            .map(|(_, groups)| groups.clone())
+    }
+
+    pub fn put(&self, account: &str, groups: Value) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        entries.insert(account.to_string(), (Instant::now(), groups));
+    }
+
+    pub fn invalidate(&self, account: &str) {
+        self.entries.lock().unwrap().remove(account);
+    }
+}
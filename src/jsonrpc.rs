@@ -1,23 +1,196 @@
+use crate::event::Event;
+use crate::plugin::Plugins;
 use crate::state::{Metrics, RpcResponse};
 use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::{broadcast, oneshot};
 
+/// Tracing target for raw JSON-RPC frames (`--trace-rpc`).
+pub const TRACE_TARGET: &str = "rpc_frames";
+
+/// Frame tracing mode: 0 = off, 1 = redacted, 2 = raw.
+static TRACE_MODE: AtomicU8 = AtomicU8::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceMode {
+    Off,
+    Redacted,
+    Raw,
+}
+
+/// Enable or disable trace logging of every JSON-RPC line to and from signal-cli.
+pub fn set_trace_mode(mode: TraceMode) {
+    TRACE_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Keys whose values are replaced by a size placeholder when redacting:
+/// message content, attachments, and credentials.
+const REDACTED_KEYS: &[&str] = &[
+    "message",
+    "body",
+    "text",
+    "attachment",
+    "attachments",
+    "base64_attachments",
+    "data",
+    "avatar",
+    "pin",
+    "captcha",
+    "verificationCode",
+    "token",
+];
+
+fn redact_value(v: &mut serde_json::Value) {
+    match v {
+        serde_json::Value::Object(map) => {
+            for (k, val) in map.iter_mut() {
+                if REDACTED_KEYS.contains(&k.as_str()) {
+                    *val = match val {
+                        serde_json::Value::String(s) => {
+                            format!("<redacted {} bytes>", s.len()).into()
+                        }
+                        serde_json::Value::Array(a) => {
+                            format!("<redacted {} items>", a.len()).into()
+                        }
+                        serde_json::Value::Null => serde_json::Value::Null,
+                        _ => "<redacted>".into(),
+                    };
+                } else {
+                    redact_value(val);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// Redact message bodies, attachments and credentials from a JSON-RPC line.
+/// Lines that aren't valid JSON are replaced wholesale.
+pub fn redact_frame(line: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(mut v) => {
+            redact_value(&mut v);
+            v.to_string()
+        }
+        Err(_) => format!("<unparseable {} bytes>", line.len()),
+    }
+}
+
+fn trace_frame(direction: &'static str, line: &str) {
+    match TRACE_MODE.load(Ordering::Relaxed) {
+        1 => {
+            tracing::trace!(target: TRACE_TARGET, direction, frame = %redact_frame(line.trim_end()))
+        }
+        2 => tracing::trace!(target: TRACE_TARGET, direction, frame = %line.trim_end()),
+        _ => {}
+    }
+}
+
+/// A signal-cli release, as its `version` method reports it ("0.13.4").
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(pub u32, pub u32, pub u32);
+
+impl Version {
+    /// Parse "0.13.4", ignoring a "signal-cli " prefix and any suffix
+    /// ("-SNAPSHOT"); the patch level defaults to 0.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches("signal-cli").trim_start();
+        let mut parts = s
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|p| p.parse::<u32>().ok());
+        let (major, minor) = (parts.next()??, parts.next()??);
+        Some(Self(major, minor, parts.next().flatten().unwrap_or(0)))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// A parameter that signal-cli releases before `before` call `old`.
+struct Rename {
+    /// The method it applies to, or every method.
+    method: Option<&'static str>,
+    before: Version,
+    current: &'static str,
+    old: &'static str,
+}
+
+/// Parameter names that changed between signal-cli releases. Routes use the
+/// current names; [`shim`] translates them for older daemons. The contract
+/// tests (tests/contract.rs) find new ones.
+const RENAMES: &[Rename] = &[
+    // 0.10.0 renamed --group to --group-id.
+    Rename {
+        method: None,
+        before: Version(0, 10, 0),
+        current: "group-id",
+        old: "group",
+    },
+    Rename {
+        method: None,
+        before: Version(0, 10, 0),
+        current: "groupId",
+        old: "group",
+    },
+];
+
+/// Rewrite `params` for `method`, written for the current signal-cli, into
+/// what `version` expects. With no version (not detected, or a daemon too
+/// old to report it) they are sent as they are.
+pub fn shim(version: Option<Version>, method: &str, params: &mut serde_json::Value) {
+    let (Some(version), Some(params)) = (version, params.as_object_mut()) else {
+        return;
+    };
+    for rename in RENAMES
+        .iter()
+        .filter(|r| version < r.before && r.method.is_none_or(|m| m == method))
+    {
+        if let Some(value) = params.remove(rename.current) {
+            params.insert(rename.old.to_string(), value);
+        }
+    }
+}
+
+/// An RPC call waiting for signal-cli's response.
+pub struct PendingCall {
+    pub method: String,
+    pub started: Instant,
+    tx: oneshot::Sender<RpcResponse>,
+}
+
+/// Calls awaiting a response, by JSON-RPC id.
+pub type PendingCalls = Arc<DashMap<u64, PendingCall>>;
+
+/// Stop waiting for a call: its caller gets [`crate::state::RPC_CANCELLED_ERROR`]
+/// and a late response is ignored. Returns the call's method, None when no
+/// such call is waiting.
+pub fn cancel(pending: &PendingCalls, id: u64) -> Option<String> {
+    // Dropping the sender wakes the caller.
+    pending.remove(&id).map(|(_, call)| call.method)
+}
+
 /// Read loop: reads newline-delimited JSON from signal-cli, dispatches responses
 /// to pending futures and broadcasts notifications to WebSocket/SSE/webhook clients.
 pub async fn reader_loop(
     reader: OwnedReadHalf,
-    broadcast_tx: broadcast::Sender<String>,
-    pending: Arc<DashMap<u64, oneshot::Sender<RpcResponse>>>,
+    broadcast_tx: broadcast::Sender<Event>,
+    pending: PendingCalls,
     metrics: Arc<Metrics>,
+    plugins: Arc<Plugins>,
 ) {
     let mut lines = BufReader::new(reader).lines();
     while let Ok(Some(line)) = lines.next_line().await {
-        let parsed: serde_json::Value = match serde_json::from_str(&line) {
+        trace_frame("in", &line);
+        let mut parsed: serde_json::Value = match serde_json::from_str(&line) {
             Ok(v) => v,
             Err(e) => {
                 tracing::warn!("Bad JSON from signal-cli: {e}");
@@ -27,15 +200,24 @@ pub async fn reader_loop(
 
         // RPC response (has "id" field)
         if let Some(id) = parsed.get("id").and_then(|v| v.as_u64()) {
-            if let Some((_, tx)) = pending.remove(&id) {
-                let _ = tx.send(parsed);
+            if let Some((_, call)) = pending.remove(&id) {
+                let _ = call.tx.send(parsed);
             }
             continue;
         }
 
-        // Notification (incoming message) — broadcast to all listeners
+        // Notification (incoming message) — broadcast to all listeners,
+        // parsed once here for all of them.
         metrics.inc_received();
-        let _ = broadcast_tx.send(line);
+        let active = plugins.is_active();
+        if active && !plugins.incoming(&mut parsed) {
+            continue;
+        }
+        // Plugins may have changed the envelope, so the line is rebuilt from it.
+        let line = if active { parsed.to_string() } else { line };
+        let event = Event::new(line, parsed);
+        metrics.observe_receive(&event);
+        let _ = broadcast_tx.send(event);
     }
     tracing::error!("signal-cli connection closed");
 }
@@ -43,6 +225,7 @@ pub async fn reader_loop(
 /// Dedicated writer loop: serialises all writes through a single task.
 pub async fn writer_loop(mut rx: tokio::sync::mpsc::Receiver<String>, mut writer: OwnedWriteHalf) {
     while let Some(line) = rx.recv().await {
+        trace_frame("out", &line);
         if let Err(e) = writer.write_all(line.as_bytes()).await {
             tracing::error!("Failed to write to signal-cli: {e}");
             break;
@@ -55,7 +238,7 @@ pub async fn writer_loop(mut rx: tokio::sync::mpsc::Receiver<String>, mut writer
 /// Send a JSON-RPC request and wait for the response, with a timeout.
 pub async fn rpc_call(
     writer_tx: &tokio::sync::mpsc::Sender<String>,
-    pending: &Arc<DashMap<u64, oneshot::Sender<RpcResponse>>>,
+    pending: &PendingCalls,
     next_id: &Arc<AtomicU64>,
     method: &str,
     params: serde_json::Value,
@@ -71,7 +254,14 @@ pub async fn rpc_call(
     });
 
     let (tx, rx) = oneshot::channel();
-    pending.insert(id, tx);
+    pending.insert(
+        id,
+        PendingCall {
+            method: method.to_string(),
+            started: Instant::now(),
+            tx,
+        },
+    );
 
     let mut line = serde_json::to_string(&request).map_err(|e| e.to_string())?;
     line.push('\n');
@@ -80,7 +270,7 @@ pub async fn rpc_call(
 
     let response = match tokio::time::timeout(timeout, rx).await {
         Ok(Ok(resp)) => resp,
-        Ok(Err(_)) => return Err("signal-cli did not respond".to_string()),
+        Ok(Err(_)) => return Err(crate::state::RPC_CANCELLED_ERROR.to_string()),
         Err(_) => {
             // Timeout: clean up the pending entry so it doesn't leak
             pending.remove(&id);
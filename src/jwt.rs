@@ -0,0 +1,395 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::state::now_millis;
+
+/// Clock skew tolerated on `exp` and `nbf`.
+pub const LEEWAY: Duration = Duration::from_secs(60);
+
+/// A token naming a key we don't have triggers a JWKS refetch at most this often.
+const MIN_REFETCH: Duration = Duration::from_secs(30);
+
+/// Cached keys are refreshed after this long, so removed keys stop working.
+pub const JWKS_TTL: Duration = Duration::from_secs(3600);
+
+/// One key of a JSON Web Key Set (RSA or P-256 EC public keys only).
+#[derive(Clone, Debug, Deserialize)]
+pub struct Jwk {
+    #[serde(default)]
+    pub kid: Option<String>,
+    pub kty: String,
+    #[serde(default)]
+    pub alg: Option<String>,
+    #[serde(default)]
+    pub n: Option<String>,
+    #[serde(default)]
+    pub e: Option<String>,
+    #[serde(default)]
+    pub crv: Option<String>,
+    #[serde(default)]
+    pub x: Option<String>,
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+fn b64(part: &str) -> Result<Vec<u8>, String> {
+    URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| "malformed token".to_string())
+}
+
+impl Jwk {
+    /// Check `signature` over `message` with this key, for RS256 or ES256.
+    fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> Result<(), String> {
+        if self.alg.as_deref().is_some_and(|a| a != alg) {
+            return Err(format!(
+                "key is for {}, token uses {alg}",
+                self.alg.as_deref().unwrap_or("")
+            ));
+        }
+        let field = |v: &Option<String>| {
+            v.as_deref()
+                .ok_or("incomplete key")
+                .and_then(|s| b64(s).map_err(|_| "invalid key"))
+        };
+        let ok = match (alg, self.kty.as_str()) {
+            ("RS256", "RSA") => {
+                let (n, e) = (field(&self.n)?, field(&self.e)?);
+                RsaPublicKeyComponents { n: &n, e: &e }
+                    .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+                    .is_ok()
+            }
+            ("ES256", "EC") if self.crv.as_deref() == Some("P-256") => {
+                let mut point = vec![0x04];
+                point.extend(field(&self.x)?);
+                point.extend(field(&self.y)?);
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+            _ => return Err(format!("unsupported algorithm {alg}")),
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err("invalid token signature".into())
+        }
+    }
+}
+
+/// Who a token must come from and be meant for.
+pub struct Expected<'a> {
+    pub issuer: &'a str,
+    /// Accepted `aud` values; any one matching is enough.
+    pub audiences: &'a [String],
+}
+
+/// Check `exp`, `nbf`, `iss` and `aud` of decoded claims.
+pub fn validate_claims(claims: &Value, expected: &Expected) -> Result<(), String> {
+    let now = now_millis() / 1000;
+    let leeway = LEEWAY.as_secs();
+    match claims.get("exp").and_then(|v| v.as_u64()) {
+        Some(exp) if exp + leeway < now => return Err("token expired".into()),
+        Some(_) => {}
+        None => return Err("token has no exp".into()),
+    }
+    if claims
+        .get("nbf")
+        .and_then(|v| v.as_u64())
+        .is_some_and(|nbf| nbf > now + leeway)
+    {
+        return Err("token not yet valid".into());
+    }
+    if claims.get("iss").and_then(|v| v.as_str()) != Some(expected.issuer) {
+        return Err("token from an unexpected issuer".into());
+    }
+    let audiences: Vec<&str> = match claims.get("aud") {
+        Some(Value::String(a)) => vec![a.as_str()],
+        Some(Value::Array(list)) => list.iter().filter_map(|a| a.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if !audiences
+        .iter()
+        .any(|a| expected.audiences.iter().any(|e| e == a))
+    {
+        return Err("token not meant for this API".into());
+    }
+    Ok(())
+}
+
+struct Cached {
+    keys: Vec<Jwk>,
+    fetched: Option<Instant>,
+}
+
+/// Keys fetched from a JWKS URL. Fetched on first use, refreshed after
+/// [`JWKS_TTL`], and refetched whenever a token names a key that isn't
+/// cached, so signing-key rotation is picked up. A failed refresh keeps the
+/// previous keys.
+pub struct KeySet {
+    url: String,
+    client: reqwest::Client,
+    cache: Mutex<Cached>,
+}
+
+impl KeySet {
+    pub fn new(url: String, client: reqwest::Client) -> Self {
+        Self {
+            url,
+            client,
+            cache: Mutex::new(Cached {
+                keys: Vec::new(),
+                fetched: None,
+            }),
+        }
+    }
+
+    async fn fetch(&self, cache: &mut Cached) -> Result<(), String> {
+        let jwks: Jwks = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("fetching JWKS: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("parsing JWKS: {e}"))?;
+        tracing::debug!(url = self.url, keys = jwks.keys.len(), "Fetched JWKS");
+        cache.keys = jwks.keys;
+        cache.fetched = Some(Instant::now());
+        Ok(())
+    }
+
+    /// The key a token header names (or the only matching key when it names none).
+    async fn key(&self, kid: Option<&str>, alg: &str) -> Result<Jwk, String> {
+        let find = |keys: &[Jwk]| -> Option<Jwk> {
+            let kty = if alg.starts_with("ES") { "EC" } else { "RSA" };
+            keys.iter()
+                .find(|k| k.kty == kty && (kid.is_none() || k.kid.as_deref() == kid))
+                .cloned()
+        };
+        let mut cache = self.cache.lock().await;
+        if cache.fetched.is_some_and(|at| at.elapsed() >= JWKS_TTL) {
+            if let Err(e) = self.fetch(&mut cache).await {
+                tracing::warn!(
+                    url = self.url,
+                    "JWKS refresh failed, keeping cached keys: {e}"
+                );
+            }
+        }
+        if let Some(key) = find(&cache.keys) {
+            return Ok(key);
+        }
+        if cache.fetched.is_none_or(|at| at.elapsed() >= MIN_REFETCH) {
+            self.fetch(&mut cache).await?;
+        }
+        find(&cache.keys).ok_or_else(|| "token signed with an unknown key".into())
+    }
+
+    /// Verify a compact JWT's signature and return its claims. Claims are not
+    /// validated here; see [`validate_claims`].
+    pub async fn verify(&self, token: &str) -> Result<Value, String> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(sig), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err("malformed token".into());
+        };
+        let head: Header =
+            serde_json::from_slice(&b64(header)?).map_err(|_| "malformed token header")?;
+        if !matches!(head.alg.as_str(), "RS256" | "ES256") {
+            return Err(format!("unsupported algorithm {}", head.alg));
+        }
+        let key = self.key(head.kid.as_deref(), &head.alg).await?;
+        let message = &token[..header.len() + 1 + payload.len()];
+        key.verify(&head.alg, message.as_bytes(), &b64(sig)?)?;
+        serde_json::from_slice(&b64(payload)?).map_err(|_| "malformed token claims".into())
+    }
+}
+
+/// Whether a bearer credential looks like a compact JWT rather than an API token secret.
+pub fn looks_like_jwt(credential: &str) -> bool {
+    credential.starts_with("ey") && credential.matches('.').count() == 2
+}
+
+// ---------------------------------------------------------------------------
+// Machine-to-machine JWTs
+// ---------------------------------------------------------------------------
+
+/// Claim holding the accounts a JWT may use, unless configured otherwise.
+pub const DEFAULT_ACCOUNTS_CLAIM: &str = "signal_accounts";
+
+/// Issuer whose JWTs are accepted as bearer credentials for machine callers.
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+    pub issuer: String,
+    pub jwks_url: String,
+    /// Accepted `aud` values.
+    pub audiences: Vec<String>,
+    /// Claim listing the accounts the caller may use (absent = all accounts).
+    pub accounts_claim: String,
+}
+
+/// Validates machine-to-machine JWTs and maps their claims to a [`Grant`].
+pub struct JwtAuth {
+    pub config: JwtConfig,
+    keys: KeySet,
+}
+
+impl JwtAuth {
+    pub fn new(config: JwtConfig, client: reqwest::Client) -> Self {
+        let keys = KeySet::new(config.jwks_url.clone(), client);
+        Self { config, keys }
+    }
+
+    /// Verify a token and read its scopes (`scope` string or `scp` array) and
+    /// account allowlist.
+    pub async fn authenticate(&self, token: &str) -> Result<Grant, String> {
+        let claims = self.keys.verify(token).await?;
+        validate_claims(
+            &claims,
+            &Expected {
+                issuer: &self.config.issuer,
+                audiences: &self.config.audiences,
+            },
+        )?;
+        let scopes: Vec<Scope> = match claims.get("scope").or_else(|| claims.get("scp")) {
+            Some(Value::String(list)) => list.split_whitespace().filter_map(Scope::parse).collect(),
+            Some(Value::Array(list)) => list
+                .iter()
+                .filter_map(|s| s.as_str())
+                .filter_map(Scope::parse)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let accounts = match claims.get(&self.config.accounts_claim) {
+            None => None,
+            Some(Value::String(a)) => Some(vec![a.clone()]),
+            Some(Value::Array(list)) => Some(
+                list.iter()
+                    .filter_map(|a| a.as_str().map(String::from))
+                    .collect(),
+            ),
+            Some(_) => {
+                return Err(format!(
+                    "{} claim must be a string or array",
+                    self.config.accounts_claim
+                ))
+            }
+        };
+        Ok(Grant {
+            subject: claims
+                .get("sub")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string(),
+            scopes,
+            accounts,
+        })
+    }
+}
+
+/// What a caller may do. Each scope includes the ones below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// GET endpoints: history, contacts, groups, streams.
+    Read,
+    /// Everything else on the messaging surface: sending, reactions, groups.
+    Write,
+    /// Server configuration: /v1/admin, webhooks, commands, configuration,
+    /// registration and number changes.
+    Admin,
+}
+
+impl Scope {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "signal:read" => Some(Self::Read),
+            "signal:write" => Some(Self::Write),
+            "signal:admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "signal:read",
+            Self::Write => "signal:write",
+            Self::Admin => "signal:admin",
+        }
+    }
+
+    /// The scope a request needs. Sending needs `Write`, even by GET.
+    pub fn required(method: &axum::http::Method, path: &str) -> Self {
+        const ADMIN: &[&str] = &[
+            "/v1/admin",
+            "/v1/webhooks",
+            "/v1/commands",
+            "/v1/topics",
+            "/v1/tokens",
+            "/v1/configuration",
+            "/v1/register",
+            "/v1/unregister",
+        ];
+        let change_number = path.starts_with("/v1/accounts/") && path.contains("/change-number");
+        if change_number
+            || ADMIN.iter().any(|p| {
+                path == *p
+                    || path
+                        .strip_prefix(p)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        {
+            Self::Admin
+        } else if crate::routes::sends_messages(path) {
+            Self::Write
+        } else if method == axum::http::Method::GET || method == axum::http::Method::HEAD {
+            Self::Read
+        } else {
+            Self::Write
+        }
+    }
+}
+
+/// Permissions of a JWT-authenticated caller, attached to the request.
+/// Requests authenticated otherwise (API tokens, sessions) carry none and may
+/// do everything.
+#[derive(Clone, Debug)]
+pub struct Grant {
+    pub subject: String,
+    pub scopes: Vec<Scope>,
+    /// Accounts the caller may use (None = all).
+    pub accounts: Option<Vec<String>>,
+}
+
+impl Grant {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| *s >= scope)
+    }
+
+    /// Whether the caller may act as `account` (aliases are resolved by `resolve`).
+    pub fn allows_account(&self, account: &str, resolve: impl Fn(&str) -> String) -> bool {
+        match &self.accounts {
+            None => true,
+            Some(list) => list.iter().any(|a| resolve(a) == account),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use crate::state::{now_millis, AppState};
+
+/// Largest value a key may hold, as serialized JSON.
+pub const MAX_VALUE_BYTES: usize = 64 * 1024;
+
+/// Most keys one conversation may hold.
+pub const MAX_KEYS: usize = 1000;
+
+/// Most conversations one account may hold keys for.
+pub const MAX_CONVERSATIONS: usize = 10_000;
+
+/// How often expired keys are swept out.
+pub const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A stored value and when it expires (epoch ms; None = never).
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub value: Value,
+    pub updated_at: u64,
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+/// Why a value wasn't stored.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PutError {
+    TooLarge,
+    TooManyKeys,
+    AccountFull,
+}
+
+/// A conversation's keys.
+type Keys = HashMap<String, Entry>;
+
+/// Key-value state for bots, namespaced by account, then conversation, where
+/// a conversation is whatever the bot uses to tell them apart: a number, a
+/// group ID, a session name. Kept in memory.
+#[derive(Default)]
+pub struct KvStore {
+    accounts: RwLock<HashMap<String, HashMap<String, Keys>>>,
+}
+
+impl KvStore {
+    /// The live keys of a conversation.
+    pub fn list(&self, account: &str, conversation: &str) -> HashMap<String, Entry> {
+        let now = now_millis();
+        let accounts = self.accounts.read().unwrap();
+        let Some(keys) = accounts.get(account).and_then(|c| c.get(conversation)) else {
+            return HashMap::new();
+        };
+        keys.iter()
+            .filter(|(_, e)| e.live(now))
+            .map(|(k, e)| (k.clone(), e.clone()))
+            .collect()
+    }
+
+    pub fn get(&self, account: &str, conversation: &str, key: &str) -> Option<Entry> {
+        let accounts = self.accounts.read().unwrap();
+        let entry = accounts.get(account)?.get(conversation)?.get(key)?;
+        entry.live(now_millis()).then(|| entry.clone())
+    }
+
+    /// Store `value` under `key`, replacing any previous value, to expire
+    /// after `ttl` if given (a ttl too long to count is as good as none).
+    pub fn put(
+        &self,
+        account: &str,
+        conversation: &str,
+        key: &str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Entry, PutError> {
+        if value.to_string().len() > MAX_VALUE_BYTES {
+            return Err(PutError::TooLarge);
+        }
+        let now = now_millis();
+        let entry = Entry {
+            value,
+            updated_at: now,
+            expires_at: ttl
+                .map(|ttl| now.saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX))),
+        };
+        let mut accounts = self.accounts.write().unwrap();
+        let conversations = accounts.entry(account.to_string()).or_default();
+        if conversations.len() >= MAX_CONVERSATIONS && !conversations.contains_key(conversation) {
+            conversations.retain(|_, keys| keys.values().any(|e| e.live(now)));
+            if conversations.len() >= MAX_CONVERSATIONS {
+                return Err(PutError::AccountFull);
+            }
+        }
+        let keys = conversations.entry(conversation.to_string()).or_default();
+        keys.retain(|_, e| e.live(now));
+        if keys.len() >= MAX_KEYS && !keys.contains_key(key) {
+            return Err(PutError::TooManyKeys);
+        }
+        keys.insert(key.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Remove a key, returning whether it was there (and live).
+    pub fn remove(&self, account: &str, conversation: &str, key: &str) -> bool {
+        let mut accounts = self.accounts.write().unwrap();
+        let Some(conversations) = accounts.get_mut(account) else {
+            return false;
+        };
+        let Some(keys) = conversations.get_mut(conversation) else {
+            return false;
+        };
+        let removed = keys.remove(key).is_some_and(|e| e.live(now_millis()));
+        if keys.is_empty() {
+            conversations.remove(conversation);
+        }
+        if conversations.is_empty() {
+            accounts.remove(account);
+        }
+        removed
+    }
+
+    /// Remove every key of a conversation, returning how many were live.
+    pub fn clear(&self, account: &str, conversation: &str) -> usize {
+        let now = now_millis();
+        let mut accounts = self.accounts.write().unwrap();
+        let Some(conversations) = accounts.get_mut(account) else {
+            return 0;
+        };
+        let keys = conversations.remove(conversation);
+        if conversations.is_empty() {
+            accounts.remove(account);
+        }
+        keys.map_or(0, |keys| keys.values().filter(|e| e.live(now)).count())
+    }
+
+    /// Drop expired keys and emptied conversations, returning how many keys
+    /// went.
+    pub fn expire(&self) -> usize {
+        let now = now_millis();
+        let mut accounts = self.accounts.write().unwrap();
+        let mut expired = 0;
+        accounts.retain(|_, conversations| {
+            conversations.retain(|_, keys| {
+                let before = keys.len();
+                keys.retain(|_, e| e.live(now));
+                expired += before - keys.len();
+                !keys.is_empty()
+            });
+            !conversations.is_empty()
+        });
+        expired
+    }
+}
+
+/// Sweeps expired keys out of the store every [`EXPIRY_INTERVAL`]. Reads
+/// never see them in the meantime.
+pub async fn expiry_loop(state: AppState) {
+    loop {
+        tokio::time::sleep(EXPIRY_INTERVAL).await;
+        let expired = state.kv.expire();
+        if expired > 0 {
+            tracing::debug!(expired, "Expired conversation state keys");
+        }
+    }
+}
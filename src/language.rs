@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+/// Code webhooks filter on for messages whose language couldn't be told.
+pub const UNDETERMINED: &str = "und";
+
+/// The language detected in a message's text.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Language {
+    /// ISO 639-3 code, e.g. `eng` or `deu`.
+    pub code: &'static str,
+    /// Between 0 and 1.
+    pub confidence: f64,
+}
+
+/// Whether the server was built with language detection (the `lang` feature).
+pub const ENABLED: bool = cfg!(feature = "lang");
+
+/// Detect the language of `text`; None when the guess isn't reliable, e.g.
+/// for a short reply, or detection isn't built in.
+#[cfg(feature = "lang")]
+pub fn detect(text: &str) -> Option<Language> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    Some(Language {
+        code: info.lang().code(),
+        confidence: info.confidence(),
+    })
+}
+
+#[cfg(not(feature = "lang"))]
+pub fn detect(_text: &str) -> Option<Language> {
+    None
+}
+
+/// Whether `code` can appear in a webhook's `languages` filter.
+pub fn valid_code(code: &str) -> bool {
+    code.len() == 3 && code.bytes().all(|b| b.is_ascii_lowercase())
+}
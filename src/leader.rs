@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::exposition::Exposition;
+use crate::state::{now_millis, AppState};
+
+/// Default lifetime of the lease; the leader renews it at a third of this.
+pub const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(15);
+
+/// A lease on a file shared by the replicas, held by one of them at a time.
+#[derive(Clone, Debug)]
+pub struct Lease {
+    pub path: PathBuf,
+    /// This replica's name in the file.
+    pub instance: String,
+    pub ttl: Duration,
+}
+
+/// What the lease file holds.
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    instance: String,
+    expires_at: u64,
+}
+
+impl Lease {
+    /// Take or renew the lease if it is free, expired or already ours,
+    /// returning whether this replica holds it now. The file is locked while
+    /// it is read and written, so two replicas can't both take it.
+    pub fn try_acquire(&self) -> io::Result<bool> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        file.lock()?;
+        let result = self.acquire_locked(&mut file);
+        file.unlock()?;
+        result
+    }
+
+    fn acquire_locked(&self, file: &mut File) -> io::Result<bool> {
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let now = now_millis();
+        let taken = serde_json::from_str::<Holder>(&contents)
+            .is_ok_and(|h| h.instance != self.instance && h.expires_at > now);
+        if taken {
+            return Ok(false);
+        }
+        let holder = Holder {
+            instance: self.instance.clone(),
+            expires_at: now + self.ttl.as_millis() as u64,
+        };
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        file.sync_data()?;
+        Ok(true)
+    }
+
+    /// Give the lease up if this replica holds it, so another can take over
+    /// without waiting for it to expire.
+    pub fn release(&self) -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        file.lock()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        if serde_json::from_str::<Holder>(&contents).is_ok_and(|h| h.instance == self.instance) {
+            file.set_len(0)?;
+        }
+        file.unlock()
+    }
+}
+
+/// Whether this replica is the leader: the one that delivers webhooks, runs
+/// bot commands and group automations and resets sessions, so several
+/// replicas behind a load balancer don't each act on the same incoming
+/// message. Without a lease the replica is always the leader.
+#[derive(Default)]
+pub struct Leadership {
+    pub lease: Option<Lease>,
+    /// Only meaningful with a lease.
+    held: AtomicBool,
+}
+
+impl Leadership {
+    pub fn new(lease: Lease) -> Self {
+        Self {
+            lease: Some(lease),
+            held: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.lease.is_none() || self.held.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, held: bool) {
+        if self.held.swap(held, Ordering::Relaxed) != held {
+            let lease = self
+                .lease
+                .as_ref()
+                .map(|l| l.instance.as_str())
+                .unwrap_or_default();
+            if held {
+                tracing::info!(instance = lease, "This replica is now the leader");
+            } else {
+                tracing::warn!(instance = lease, "This replica is no longer the leader");
+            }
+        }
+    }
+
+    /// Append the leadership gauge to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        exp.gauge(
+            "signal_leader",
+            "1 if this replica delivers webhooks and runs automations",
+            &[(&[], self.is_leader() as u64)],
+        );
+    }
+}
+
+/// Keeps trying to take the lease, and renews it while held. A replica that
+/// fails to renew (the file is unreachable, say) steps down at once rather
+/// than risk two leaders.
+pub async fn lease_loop(state: AppState) {
+    let leadership = state.leadership.clone();
+    let Some(lease) = leadership.lease.clone() else {
+        return;
+    };
+    loop {
+        let attempt = lease.clone();
+        let held = match tokio::task::spawn_blocking(move || attempt.try_acquire()).await {
+            Ok(Ok(held)) => held,
+            Ok(Err(e)) => {
+                tracing::warn!(path = %lease.path.display(), "Could not update the leader lease: {e}");
+                false
+            }
+            Err(e) => {
+                tracing::warn!("Leader lease task failed: {e}");
+                false
+            }
+        };
+        leadership.set(held);
+        tokio::time::sleep(lease.ttl / 3).await;
+    }
+}
@@ -1,6 +1,61 @@
+pub mod attachments;
+pub mod auth;
+pub mod autoblock;
+pub mod automations;
+pub mod aws_sink;
+pub mod canary;
+pub mod captcha;
+pub mod case;
+pub mod change_number;
+pub mod commands;
+pub mod compat;
+pub mod compression;
+pub mod cursor;
 pub mod daemon;
+pub mod deadline;
+pub mod decryption;
+pub mod dedup;
+pub mod devices;
+pub mod envelope;
+pub mod event;
+pub mod exposition;
+pub mod filter;
+pub mod group_events;
+pub mod group_id;
+pub mod groups;
 pub mod jsonrpc;
+pub mod jwt;
+pub mod kv;
+pub mod language;
+pub mod leader;
+pub mod link;
+pub mod logstream;
+pub mod maintenance;
 pub mod middleware;
+pub mod migrate;
+pub mod oidc;
+pub mod outbound;
+pub mod outbox;
+pub mod pacing;
+pub mod plugin;
+pub mod preflight;
+pub mod privacy;
+pub mod profile_refresh;
+pub mod quota;
+pub mod redis_bridge;
+pub mod reply_context;
 pub mod routes;
+pub mod runtime_config;
+pub mod scan;
+pub mod secrets;
+pub mod send_result;
+pub mod shed;
+pub mod spam;
 pub mod state;
+pub mod store;
+pub mod supervisor;
+pub mod sync;
+pub mod tenant;
+pub mod topics;
+pub mod trust;
 pub mod webhooks;
@@ -0,0 +1,128 @@
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::state::{now_millis, AppState};
+
+/// How long `finishLink` may wait for the phone to scan the code.
+pub const LINK_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long a finished link session can still be polled.
+pub const SESSION_TTL: Duration = Duration::from_secs(3600);
+
+/// Where a device link stands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Started,
+    /// `finishLink` was sent; signal-cli waits for the phone to confirm.
+    WaitingForPhone,
+    /// Linked; contacts and groups are being requested from the primary device.
+    SyncingContacts,
+    Done,
+    Failed,
+}
+
+impl Stage {
+    pub fn is_finished(self) -> bool {
+        matches!(self, Stage::Done | Stage::Failed)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LinkSession {
+    pub id: String,
+    pub account: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    pub stage: Stage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+/// Device links started through POST /v1/devices/{number}, by session id.
+#[derive(Default)]
+pub struct LinkSessions {
+    sessions: Mutex<HashMap<String, LinkSession>>,
+    next_id: AtomicU64,
+}
+
+impl LinkSessions {
+    pub fn start(&self, account: &str, device_name: Option<String>) -> LinkSession {
+        let now = now_millis();
+        let seq = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let session = LinkSession {
+            id: format!("link-{seq}"),
+            account: account.to_string(),
+            device_name,
+            stage: Stage::Started,
+            error: None,
+            started_at: now,
+            updated_at: now,
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired = now.saturating_sub(SESSION_TTL.as_millis() as u64);
+        sessions.retain(|_, s| !s.stage.is_finished() || s.updated_at > expired);
+        sessions.insert(session.id.clone(), session.clone());
+        session
+    }
+
+    pub fn get(&self, id: &str) -> Option<LinkSession> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn advance(&self, id: &str, stage: Stage, error: Option<String>) -> Option<LinkSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions.get_mut(id)?;
+        session.stage = stage;
+        session.error = error;
+        session.updated_at = now_millis();
+        Some(session.clone())
+    }
+}
+
+/// Move a session to `stage` and publish a `device_link` system event.
+fn report(state: &AppState, id: &str, stage: Stage, error: Option<String>) {
+    if let Some(session) = state.link_sessions.advance(id, stage, error) {
+        state.publish_system("device_link", json!(session));
+    }
+}
+
+/// Complete a link in the background: wait for the phone in `finishLink`,
+/// then ask the primary device to sync contacts and groups.
+pub async fn run(state: AppState, id: String, params: serde_json::Value) {
+    report(&state, &id, Stage::WaitingForPhone, None);
+    let linked = match state
+        .rpc_with_timeout("finishLink", params.clone(), LINK_TIMEOUT)
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            tracing::warn!(link_session = id, "Device link failed: {e}");
+            return report(&state, &id, Stage::Failed, Some(e));
+        }
+    };
+    report(&state, &id, Stage::SyncingContacts, None);
+    let account = linked
+        .get("number")
+        .and_then(|n| n.as_str())
+        .or_else(|| params["account"].as_str())
+        .unwrap_or_default();
+    match crate::sync::request(&state, account, &crate::sync::SyncKind::ALL).await {
+        Ok(_) => report(&state, &id, Stage::Done, None),
+        Err(e) => {
+            tracing::warn!(link_session = id, "Sync request after linking failed: {e}");
+            report(
+                &state,
+                &id,
+                Stage::Failed,
+                Some(format!("linked, but sync request failed: {e}")),
+            );
+        }
+    }
+}
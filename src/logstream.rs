@@ -0,0 +1,90 @@
+use serde::Serialize;
+use std::fmt::Write as _;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::state::now_millis;
+
+/// Most verbose level captured for log streaming.
+pub const MAX_LEVEL: Level = Level::DEBUG;
+
+/// One formatted log event, as sent to /v1/admin/logs/stream clients.
+#[derive(Clone, Debug, Serialize)]
+pub struct LogLine {
+    pub timestamp: u64,
+    pub level: &'static str,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogLine {
+    pub fn level(&self) -> Level {
+        self.level.parse().unwrap_or(Level::TRACE)
+    }
+}
+
+pub fn channel() -> broadcast::Sender<LogLine> {
+    broadcast::channel(1024).0
+}
+
+/// Tracing layer that publishes events up to [`MAX_LEVEL`] to the log stream
+/// channel, independently of the console filter. Events are only formatted
+/// while someone is listening.
+pub fn layer<S>(tx: broadcast::Sender<LogLine>) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let listening = tx.clone();
+    BroadcastLayer { tx }.with_filter(filter_fn(move |meta| {
+        *meta.level() <= MAX_LEVEL && listening.receiver_count() > 0
+    }))
+}
+
+struct BroadcastLayer {
+    tx: broadcast::Sender<LogLine>,
+}
+
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for BroadcastLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let _ = self.tx.send(LogLine {
+            timestamp: now_millis(),
+            level: meta.level().as_str(),
+            target: meta.target().to_string(),
+            message: crate::privacy::redact(
+                format!("{}{}", visitor.message, visitor.fields).trim_start(),
+            )
+            .into_owned(),
+        });
+    }
+}
@@ -1,8 +1,63 @@
+mod attachments;
+mod auth;
+mod autoblock;
+mod automations;
+mod aws_sink;
+mod canary;
+mod captcha;
+mod case;
+mod change_number;
+mod commands;
+mod compat;
+mod compression;
+mod cursor;
 mod daemon;
+mod deadline;
+mod decryption;
+mod dedup;
+mod devices;
+mod envelope;
+mod event;
+mod exposition;
+mod filter;
+mod group_events;
+mod group_id;
+mod groups;
 mod jsonrpc;
+mod jwt;
+mod kv;
+mod language;
+mod leader;
+mod link;
+mod logstream;
+mod maintenance;
 mod middleware;
+mod migrate;
+mod oidc;
+mod outbound;
+mod outbox;
+mod pacing;
+mod plugin;
+mod preflight;
+mod privacy;
+mod profile_refresh;
+mod quota;
+mod redis_bridge;
+mod reply_context;
 mod routes;
+mod runtime_config;
+mod scan;
+mod secrets;
+mod send_result;
+mod shed;
+mod spam;
 mod state;
+mod store;
+mod supervisor;
+mod sync;
+mod tenant;
+mod topics;
+mod trust;
 mod webhooks;
 
 use axum::middleware as axum_mw;
@@ -10,7 +65,9 @@ use clap::Parser;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
 use tower_http::cors::CorsLayer;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 #[derive(Parser)]
 #[command(name = "signal-cli-api", about = "REST + WebSocket API for signal-cli")]
@@ -31,22 +88,463 @@ struct Cli {
     /// Path to TLS private key file (PEM format). Required with --tls-cert.
     #[arg(long)]
     tls_key: Option<String>,
+
+    /// Coalesce identical sends (same account, recipients, text and
+    /// attachments) within this many seconds; a single "(xN)" summary follows
+    /// when the window closes.
+    #[arg(long)]
+    dedup_window: Option<u64>,
+
+    /// Dedup window for one API token's sends as `id:secs` (repeatable),
+    /// in place of `--dedup-window`; 0 turns suppression off for the token.
+    #[arg(long = "dedup-window-token", value_name = "ID:SECS")]
+    dedup_token_windows: Vec<String>,
+
+    /// Every this many seconds, have signal-cli fetch the profiles and keys
+    /// of contacts messaged in the last week again (off by default).
+    #[arg(long, value_name = "SECS")]
+    profile_refresh_secs: Option<u64>,
+
+    /// Log a warning for RPC calls to signal-cli slower than this many
+    /// milliseconds (0 disables).
+    #[arg(long, default_value_t = 1000)]
+    slow_rpc_ms: u64,
+
+    /// Give up on RPC calls for a request after this many milliseconds,
+    /// unless the client sends a shorter `x-request-timeout-ms`.
+    #[arg(long)]
+    request_timeout_ms: Option<u64>,
+
+    /// Refuse low-priority requests with 503 while this many RPC calls
+    /// await signal-cli.
+    #[arg(long)]
+    shed_pending: Option<usize>,
+
+    /// Refuse low-priority requests with 503 while this many messages wait
+    /// in the outbox.
+    #[arg(long)]
+    shed_outbox: Option<usize>,
+
+    /// Request class refused under load (repeatable; default: typing,
+    /// receipts and reactions).
+    #[arg(long = "shed-class", value_enum)]
+    shed_classes: Vec<shed::RouteClass>,
+
+    /// Space sends across all clients to at most this many per second;
+    /// sends that come too soon are queued in the outbox.
+    #[arg(long, value_name = "PER_SEC")]
+    send_rate: Option<f64>,
+
+    /// Longest random delay added to each gap between sends with
+    /// --send-rate (default: a quarter of the gap).
+    #[arg(long, value_name = "MS")]
+    send_jitter_ms: Option<u64>,
+
+    /// Require `Authorization: Bearer <token>` to scrape /metrics.
+    #[arg(long)]
+    metrics_token: Option<String>,
+
+    /// Serve /metrics on this separate address instead of the public listener.
+    #[arg(long)]
+    metrics_listen: Option<String>,
+
+    /// Log every JSON-RPC line to/from signal-cli at trace level, with
+    /// message bodies, attachments and credentials redacted.
+    #[arg(long)]
+    trace_rpc: bool,
+
+    /// With --trace-rpc, log frames verbatim (no redaction).
+    #[arg(long, requires = "trace_rpc")]
+    trace_rpc_raw: bool,
+
+    /// Stop serving deprecated routes (they return 410 Gone).
+    #[arg(long)]
+    disable_deprecated: bool,
+
+    /// Delivery workers per webhook that sets no `max_concurrency`.
+    #[arg(long, default_value_t = webhooks::DEFAULT_MAX_CONCURRENCY)]
+    webhook_workers: usize,
+
+    /// Directory holding the PEM files webhooks name as `client_cert` and
+    /// `client_key`; webhooks can't use files anywhere else.
+    #[arg(long, value_name = "DIR")]
+    webhook_cert_dir: Option<std::path::PathBuf>,
+
+    /// Seconds an account's group list is served from cache (0 disables).
+    #[arg(long, default_value_t = groups::DEFAULT_CACHE_TTL.as_secs())]
+    group_cache_secs: u64,
+
+    /// Most members a group send with `"mention": "all"` may mention.
+    #[arg(long, default_value_t = groups::DEFAULT_MAX_MENTIONS)]
+    max_mentions: usize,
+
+    /// Send an end-session message to contacts whose messages fail to
+    /// decrypt, so the next message starts a fresh session.
+    #[arg(long)]
+    reset_sessions: bool,
+
+    /// Send webhook and other outbound HTTP through this proxy
+    /// (http://, https://, socks5:// or socks5h://).
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Comma-separated hosts, domains or CIDRs that bypass --proxy.
+    /// Defaults to the NO_PROXY environment variable.
+    #[arg(long, requires = "proxy")]
+    no_proxy: Option<String>,
+
+    /// API token as `id:secret` (repeatable). When any token is set, requests
+    /// must send `Authorization: Bearer <secret>` or an HMAC signature.
+    #[arg(long = "api-token", value_name = "ID:SECRET")]
+    api_tokens: Vec<String>,
+
+    /// Daily quota for an API token as `id:messages[:attachment-bytes]`
+    /// (repeatable; leave a limit empty for unlimited). Over-quota sends get 429.
+    #[arg(long = "token-quota", value_name = "ID:MESSAGES[:BYTES]")]
+    token_quotas: Vec<String>,
+
+    /// Tenant as `name:account,...:token,...` (repeatable). A tenant's tokens
+    /// reach only its accounts and its own webhooks; metrics are labelled
+    /// per tenant.
+    #[arg(long = "tenant", value_name = "NAME:ACCOUNTS:TOKENS")]
+    tenants: Vec<String>,
+
+    /// Daily quota shared by a tenant's tokens as
+    /// `name:messages[:attachment-bytes]` (repeatable).
+    #[arg(long = "tenant-quota", value_name = "NAME:MESSAGES[:BYTES]")]
+    tenant_quotas: Vec<String>,
+
+    /// Keep per-token usage in this file so quotas survive restarts.
+    #[arg(long)]
+    usage_file: Option<std::path::PathBuf>,
+
+    /// Accepted clock skew, in seconds, for HMAC-signed requests.
+    #[arg(long, default_value_t = 300)]
+    hmac_window: u64,
+
+    /// File holding the key used to encrypt secrets at rest (defaults to the
+    /// SIGNAL_CLI_API_SECRET_KEY environment variable).
+    #[arg(long)]
+    secret_key_file: Option<String>,
+
+    /// Print the sealed (encrypted) form of this secret and exit. Sealed
+    /// values are accepted wherever a secret is configured.
+    #[arg(long, value_name = "SECRET")]
+    encrypt_secret: Option<String>,
+
+    /// Print the migrations --usage-file and --cursor-file need to reach
+    /// this release's format, without changing them, and exit.
+    #[arg(long)]
+    migrate_dry_run: bool,
+
+    /// Accept RS256/ES256 JWTs from this issuer as bearer credentials for
+    /// machine callers (scopes and an account allowlist come from the claims).
+    #[arg(long, requires_all = ["jwt_jwks_url", "jwt_audience"])]
+    jwt_issuer: Option<String>,
+
+    /// JWKS URL holding the issuer's signing keys.
+    #[arg(long, requires = "jwt_issuer")]
+    jwt_jwks_url: Option<String>,
+
+    /// Accepted `aud` value (repeatable).
+    #[arg(long, requires = "jwt_issuer")]
+    jwt_audience: Vec<String>,
+
+    /// Claim listing the accounts a JWT may use.
+    #[arg(long, default_value = jwt::DEFAULT_ACCOUNTS_CLAIM)]
+    jwt_accounts_claim: String,
+
+    /// OpenID Connect issuer URL; enables SSO login for the web UI and admin
+    /// endpoints (API tokens keep working for machine callers).
+    #[arg(long, requires_all = ["oidc_client_id", "oidc_redirect_url"])]
+    oidc_issuer: Option<String>,
+
+    /// OIDC client ID registered with the issuer.
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_client_id: Option<String>,
+
+    /// OIDC client secret (may be sealed); omit for public clients.
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_client_secret: Option<String>,
+
+    /// This server's /auth/callback URL as registered with the issuer.
+    #[arg(long, requires = "oidc_issuer")]
+    oidc_redirect_url: Option<String>,
+
+    /// Email address or `@domain` allowed to log in (repeatable; default:
+    /// anyone the issuer authenticates).
+    #[arg(
+        long = "oidc-allow",
+        value_name = "EMAIL|@DOMAIN",
+        requires = "oidc_issuer"
+    )]
+    oidc_allow: Vec<String>,
+
+    /// Account alias as `name=+E164` (repeatable), usable wherever an
+    /// account number appears in a path.
+    #[arg(long = "alias", value_name = "NAME=+E164")]
+    aliases: Vec<String>,
+
+    /// Topic as `name=group-id` (repeatable), usable as `"topic"` in sends
+    /// instead of the group ID. More can be added at /v1/topics.
+    #[arg(long = "topic", value_name = "NAME=GROUP_ID")]
+    topics: Vec<String>,
+
+    /// When the auto-spawned daemon receives messages.
+    #[arg(long, value_enum, default_value_t, conflicts_with = "signal_cli")]
+    receive_mode: daemon::ReceiveMode,
+
+    /// Don't download attachments in the auto-spawned daemon.
+    #[arg(long, conflicts_with = "signal_cli")]
+    ignore_attachments: bool,
+
+    /// Don't receive story messages in the auto-spawned daemon.
+    #[arg(long, conflicts_with = "signal_cli")]
+    ignore_stories: bool,
+
+    /// Have the auto-spawned daemon send read receipts for received messages.
+    #[arg(long, conflicts_with = "signal_cli")]
+    send_read_receipts: bool,
+
+    /// Route the auto-spawned daemon's connections to Signal through this
+    /// SOCKS proxy, e.g. Tor's socks5h://127.0.0.1:9050. Checked at startup.
+    #[arg(long, value_name = "URL", conflicts_with = "signal_cli")]
+    daemon_proxy: Option<String>,
+
+    /// Write `base64_attachments` to files in this directory before sending
+    /// (must be readable by signal-cli).
+    #[arg(long)]
+    attachment_dir: Option<std::path::PathBuf>,
+
+    /// signal-cli's attachment folder (e.g. ~/.local/share/signal-cli/attachments),
+    /// pruned and measured along with --attachment-dir.
+    #[arg(long)]
+    signal_attachment_dir: Option<std::path::PathBuf>,
+
+    /// Messages kept per account in the message store (older ones are
+    /// dropped as new ones arrive).
+    #[arg(long, default_value_t = store::DEFAULT_CAPACITY)]
+    history_max_rows: usize,
+
+    /// Drop stored messages older than this many seconds.
+    #[arg(long, value_name = "SECS")]
+    history_max_age: Option<u64>,
+
+    /// Per-account message store limits, overriding --history-max-rows and
+    /// --history-max-age (repeatable; an empty limit keeps the default).
+    #[arg(long = "history-retention", value_name = "E164:ROWS[:SECS]")]
+    history_retention: Vec<String>,
+
+    /// Recent events kept for pull consumers (GET /v1/events/cursor/{consumer}).
+    #[arg(long, default_value_t = cursor::DEFAULT_CAPACITY)]
+    event_log_size: usize,
+
+    /// Keep pull consumers' cursors in this file so they survive restarts.
+    #[arg(long)]
+    cursor_file: Option<std::path::PathBuf>,
+
+    /// Delete cached attachments older than this many seconds.
+    #[arg(long)]
+    attachment_retention: Option<u64>,
+
+    /// Keep this many megabytes free on the filesystems of --attachment-dir
+    /// and --signal-attachment-dir: below it, base64 attachments are refused
+    /// with 507 and the oldest cached attachments are pruned.
+    #[arg(long, value_name = "MB")]
+    min_free_disk: Option<u64>,
+
+    /// Comma-separated MIME types (`image/*` for a family) allowed in
+    /// base64_attachments, checked against the type sniffed from the content.
+    #[arg(long, value_name = "TYPES")]
+    attachment_types: Option<String>,
+
+    /// With --attachment-types, accept attachments whose declared type
+    /// doesn't match their content.
+    #[arg(long, requires = "attachment_types")]
+    allow_attachment_mismatch: bool,
+
+    /// Scan attachments before sending, and incoming ones in
+    /// --signal-attachment-dir: a command reading the file on stdin (exit 0
+    /// clean, 1 rejected, e.g. "clamdscan --no-summary -") or an http(s) URL.
+    #[arg(long, value_name = "COMMAND|URL")]
+    attachment_scanner: Option<String>,
+
+    /// Match another REST API's routes, status codes and field names so its
+    /// clients work unchanged (e.g. Home Assistant's signal_messenger).
+    #[arg(long, value_enum)]
+    compat: Option<compat::Compat>,
+
+    /// Write the keys of JSON responses in one case (camel or snake) instead
+    /// of the mix of signal-cli's and the API's own. A request's ?case= wins.
+    #[arg(long, value_enum, default_value_t)]
+    json_case: case::KeyCase,
+
+    /// Gzip Server-Sent Event streams for clients whose Accept-Encoding
+    /// allows it.
+    #[arg(long)]
+    sse_gzip: bool,
+
+    /// Before serving, check the expected accounts are registered with
+    /// signal-cli and load their groups and contacts; exit with an error
+    /// if one is missing.
+    #[arg(long)]
+    preflight: bool,
+
+    /// Account that must be registered with signal-cli (repeatable; implies
+    /// --preflight). Alias targets are expected too.
+    #[arg(long = "expect-account", value_name = "E164")]
+    expect_accounts: Vec<String>,
+
+    /// Recipient of the POST /v1/admin/canary probe: a device that sends
+    /// delivery receipts, such as a second phone.
+    #[arg(long, value_name = "E164", requires = "canary_account")]
+    canary_recipient: Option<String>,
+
+    /// Account the canary is sent from.
+    #[arg(long, value_name = "E164", requires = "canary_recipient")]
+    canary_account: Option<String>,
+
+    /// Seconds the canary waits for its delivery receipt.
+    #[arg(long, default_value_t = canary::DEFAULT_TIMEOUT.as_secs())]
+    canary_timeout: u64,
+
+    /// Enable a plugin compiled into this build (repeatable; run in the
+    /// order given), e.g. pii-scrub with the pii-scrub feature.
+    #[arg(long = "plugin", value_name = "NAME")]
+    plugins: Vec<String>,
+
+    /// Check incoming messages against a spam rule (repeatable):
+    /// unknown-sender, url or rate:<max>/<secs>, followed by =tag (default)
+    /// or =drop. Matches are kept in GET /v1/quarantine/{number}.
+    #[arg(long = "spam-rule", value_name = "RULE[=ACTION]")]
+    spam_rules: Vec<String>,
+
+    /// Block senders with signal-cli when they trip a trigger (repeatable):
+    /// spam (a --spam-rule match) or rate:<max>/<secs>. Undo with
+    /// DELETE /v1/auto-blocks/{number}/{id}.
+    #[arg(long = "auto-block", value_name = "TRIGGER")]
+    auto_block: Vec<String>,
+
+    /// Rewrite phone numbers in logs, metrics labels and error responses:
+    /// `mask` keeps the country code and last three digits (+49****678),
+    /// `hash` the country code and a short hash (+49#3f2a9c1d).
+    #[arg(long, value_enum, default_value_t)]
+    redact_numbers: privacy::PrivacyMode,
+
+    /// Run as one of several replicas sharing a signal-cli daemon: only the
+    /// replica holding the lease on this file (on storage all of them reach)
+    /// delivers webhooks and runs bot commands, automations and auto-block.
+    #[arg(long, value_name = "PATH")]
+    leader_lease: Option<std::path::PathBuf>,
+
+    /// This replica's name in the --leader-lease file (default: host name
+    /// and process ID).
+    #[arg(long, requires = "leader_lease")]
+    instance_id: Option<String>,
+
+    /// Seconds the lease lasts without renewal, i.e. how long a failover
+    /// takes at most.
+    #[arg(long, default_value_t = leader::DEFAULT_LEASE_TTL.as_secs(), requires = "leader_lease")]
+    leader_lease_secs: u64,
+
+    /// Publish incoming envelopes and system events to Redis at this URL
+    /// (redis://[:password@]host[:port][/db]; needs the `redis` feature).
+    #[arg(long, value_name = "URL")]
+    redis_url: Option<String>,
+
+    /// Redis channel events are published to.
+    #[arg(long, default_value = redis_bridge::DEFAULT_CHANNEL, requires = "redis_url")]
+    redis_channel: String,
+
+    /// Publish incoming envelopes to this SNS topic ARN or SQS queue URL,
+    /// with an IAM user's access key from AWS_ACCESS_KEY_ID and
+    /// AWS_SECRET_ACCESS_KEY.
+    #[arg(long, value_name = "ARN|URL")]
+    aws_sink: Option<String>,
+
+    /// Send --aws-sink requests to this endpoint instead of AWS, e.g. a
+    /// local emulator (http://localhost:4566).
+    #[arg(long, value_name = "URL", requires = "aws_sink")]
+    aws_endpoint: Option<String>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
-        .init();
-
     let cli = Cli::parse();
 
+    let secret_key =
+        secrets::SecretKey::load(cli.secret_key_file.as_deref()).map_err(anyhow::Error::msg)?;
+    if let Some(plain) = &cli.encrypt_secret {
+        let key = secret_key.ok_or_else(|| {
+            anyhow::anyhow!(
+                "--encrypt-secret needs --secret-key-file or {}",
+                secrets::KEY_ENV
+            )
+        })?;
+        println!("{}", key.seal(plain));
+        return Ok(());
+    }
+    if cli.migrate_dry_run {
+        let files = [
+            (&migrate::USAGE, &cli.usage_file),
+            (&migrate::CURSORS, &cli.cursor_file),
+        ];
+        let mut steps = Vec::new();
+        for (format, path) in files {
+            if let Some(path) = path {
+                steps.extend(migrate::dry_run(format, path).map_err(anyhow::Error::msg)?);
+            }
+        }
+        if steps.is_empty() {
+            println!("State files are up to date");
+        }
+        for step in steps {
+            println!("{step}");
+        }
+        return Ok(());
+    }
+
+    let mut filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into());
+    let log_filter = filter.to_string();
+    let trace_mode = match (cli.trace_rpc, cli.trace_rpc_raw) {
+        (false, _) => jsonrpc::TraceMode::Off,
+        (true, false) => jsonrpc::TraceMode::Redacted,
+        (true, true) => jsonrpc::TraceMode::Raw,
+    };
+    if trace_mode != jsonrpc::TraceMode::Off {
+        filter = filter.add_directive(format!("{}=trace", jsonrpc::TRACE_TARGET).parse()?);
+    }
+    jsonrpc::set_trace_mode(trace_mode);
+    privacy::set_mode(cli.redact_numbers);
+    let log_tx = logstream::channel();
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(privacy::Redacting(std::io::stdout))
+                .with_filter(filter),
+        )
+        .with(logstream::layer(log_tx.clone()))
+        .init();
+
     // Either connect to an existing daemon or auto-spawn one.
     let _managed_daemon; // held alive so child process isn't dropped
+    let daemon_options = daemon::DaemonOptions {
+        receive_mode: cli.receive_mode,
+        ignore_attachments: cli.ignore_attachments,
+        ignore_stories: cli.ignore_stories,
+        send_read_receipts: cli.send_read_receipts,
+        proxy: cli
+            .daemon_proxy
+            .as_deref()
+            .map(daemon::DaemonProxy::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)?,
+    };
+    let managed = cli.signal_cli.is_none();
     let signal_cli_addr = match cli.signal_cli {
         Some(addr) => addr,
         None => {
-            let d = daemon::spawn().await?;
+            let d = daemon::spawn(&daemon_options).await?;
             let addr = d.addr.clone();
             _managed_daemon = d;
             addr
@@ -58,32 +556,444 @@ async fn main() -> anyhow::Result<()> {
     let (reader, writer) = stream.into_split();
 
     let (writer_tx, writer_rx) = tokio::sync::mpsc::channel::<String>(256);
-    tokio::spawn(jsonrpc::writer_loop(writer_rx, writer));
-
-    let app_state = state::AppState::new(writer_tx);
+    let mut app_state = state::AppState::new(writer_tx);
+    let tasks = app_state.tasks.clone();
+    tasks.spawn_critical("writer", jsonrpc::writer_loop(writer_rx, writer));
+    app_state.dedup_window = cli.dedup_window.map(std::time::Duration::from_secs);
+    app_state.profile_refresh = cli
+        .profile_refresh_secs
+        .filter(|&s| s > 0)
+        .map(std::time::Duration::from_secs);
+    app_state.slow_rpc =
+        (cli.slow_rpc_ms > 0).then(|| std::time::Duration::from_millis(cli.slow_rpc_ms));
+    app_state.request_timeout = cli.request_timeout_ms.map(std::time::Duration::from_millis);
+    app_state.shedder = std::sync::Arc::new(shed::LoadShedder::new(
+        cli.shed_pending,
+        cli.shed_outbox,
+        cli.shed_classes,
+    ));
+    if let Some(rate) = cli.send_rate {
+        let jitter = cli.send_jitter_ms.map(std::time::Duration::from_millis);
+        app_state.pacer = Some(std::sync::Arc::new(
+            pacing::Pacer::new(rate, jitter).map_err(anyhow::Error::msg)?,
+        ));
+    }
+    app_state.metrics_token = cli.metrics_token;
+    app_state.metrics_public = cli.metrics_listen.is_none();
+    app_state.deprecated_routes = !cli.disable_deprecated;
+    app_state.webhook_workers = cli.webhook_workers;
+    if let Some(dir) = &cli.webhook_cert_dir {
+        let dir = dir
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("--webhook-cert-dir {}: {e}", dir.display()))?;
+        app_state.webhook_cert_dir = Some(dir);
+    }
+    app_state.reset_sessions = cli.reset_sessions;
+    app_state.group_cache = std::sync::Arc::new(groups::GroupCache::new(
+        std::time::Duration::from_secs(cli.group_cache_secs),
+    ));
+    app_state.max_mentions = cli.max_mentions;
+    if let Some(url) = cli.proxy {
+        let proxy = outbound::ProxyConfig {
+            url,
+            no_proxy: cli.no_proxy,
+        };
+        proxy.to_proxy().map_err(anyhow::Error::msg)?;
+        app_state.outbound_proxy = Some(proxy);
+    }
+    let tokens = cli
+        .api_tokens
+        .iter()
+        .map(|spec| {
+            let mut token = auth::ApiToken::parse(spec)?;
+            token.secret = secrets::reveal(&token.secret, secret_key.as_ref())?;
+            Ok(token)
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(anyhow::Error::msg)?;
+    let mut quotas = cli
+        .token_quotas
+        .iter()
+        .map(|spec| quota::Quota::parse(spec))
+        .collect::<Result<std::collections::HashMap<_, _>, _>>()
+        .map_err(anyhow::Error::msg)?;
+    if let Some(unknown) = quotas
+        .keys()
+        .find(|id| !tokens.iter().any(|t| &t.id == *id))
+    {
+        anyhow::bail!("--token-quota for unknown token {unknown:?}");
+    }
+    let dedup_token_windows = cli
+        .dedup_token_windows
+        .iter()
+        .map(|spec| dedup::parse_token_window(spec))
+        .collect::<Result<std::collections::HashMap<_, _>, _>>()
+        .map_err(anyhow::Error::msg)?;
+    if let Some(unknown) = dedup_token_windows
+        .keys()
+        .find(|id| !tokens.iter().any(|t| &t.id == *id))
+    {
+        anyhow::bail!("--dedup-window-token for unknown token {unknown:?}");
+    }
+    app_state.dedup_token_windows = std::sync::Arc::new(dedup_token_windows);
+    let tenants = cli
+        .tenants
+        .iter()
+        .map(|spec| tenant::Tenant::parse(spec))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|t| tenant::Tenants::new(t, &tokens))
+        .map_err(anyhow::Error::msg)?;
+    for spec in &cli.tenant_quotas {
+        let (name, quota) = quota::Quota::parse(spec).map_err(anyhow::Error::msg)?;
+        let Some(tenant) = tenants.get(&name) else {
+            anyhow::bail!("--tenant-quota for unknown tenant {name:?}");
+        };
+        quotas.insert(tenant.quota_key(), quota);
+    }
+    app_state.tenants = std::sync::Arc::new(tenants);
+    app_state.quotas = std::sync::Arc::new(
+        quota::QuotaTracker::new(quotas, cli.usage_file).map_err(anyhow::Error::msg)?,
+    );
+    app_state.tokens = std::sync::Arc::new(tokio::sync::RwLock::new(tokens));
+    if let (Some(issuer), Some(jwks_url)) = (cli.jwt_issuer, cli.jwt_jwks_url) {
+        let config = jwt::JwtConfig {
+            issuer,
+            jwks_url,
+            audiences: cli.jwt_audience,
+            accounts_claim: cli.jwt_accounts_claim,
+        };
+        let client = outbound::client_builder(app_state.outbound_proxy.as_ref())
+            .and_then(|b| {
+                b.timeout(std::time::Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(anyhow::Error::msg)?;
+        app_state.jwt = Some(std::sync::Arc::new(jwt::JwtAuth::new(config, client)));
+    }
+    if let (Some(issuer), Some(client_id), Some(redirect_url)) =
+        (cli.oidc_issuer, cli.oidc_client_id, cli.oidc_redirect_url)
+    {
+        let client_secret = cli
+            .oidc_client_secret
+            .map(|s| secrets::reveal(&s, secret_key.as_ref()))
+            .transpose()
+            .map_err(anyhow::Error::msg)?;
+        let config = oidc::OidcConfig {
+            issuer,
+            client_id,
+            client_secret,
+            redirect_url,
+            allowed: cli.oidc_allow,
+        };
+        let client = outbound::client_builder(app_state.outbound_proxy.as_ref())
+            .and_then(|b| {
+                b.timeout(std::time::Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(anyhow::Error::msg)?;
+        app_state.oidc = Some(std::sync::Arc::new(oidc::Oidc::new(config, client)));
+    }
+    app_state.hmac_window = std::time::Duration::from_secs(cli.hmac_window);
+    app_state.secret_key = secret_key.map(std::sync::Arc::new);
+    let aliases = cli
+        .aliases
+        .iter()
+        .map(|spec| state::parse_alias(spec))
+        .collect::<Result<_, _>>()
+        .map_err(anyhow::Error::msg)?;
+    app_state.aliases = std::sync::Arc::new(aliases);
+    let topics = cli
+        .topics
+        .iter()
+        .map(|spec| topics::parse(spec))
+        .collect::<Result<_, _>>()
+        .map_err(anyhow::Error::msg)?;
+    app_state.topics = std::sync::Arc::new(tokio::sync::RwLock::new(topics));
+    app_state.daemon_options = managed.then_some(daemon_options);
+    app_state.log_tx = log_tx;
+    let mut message_store = store::MessageStore::new(cli.history_max_rows);
+    message_store.retention.max_age = cli.history_max_age.map(std::time::Duration::from_secs);
+    message_store.overrides = cli
+        .history_retention
+        .iter()
+        .map(|spec| store::Retention::parse(spec))
+        .collect::<Result<_, _>>()
+        .map_err(anyhow::Error::msg)?;
+    app_state.store = std::sync::Arc::new(message_store);
+    app_state.event_log = std::sync::Arc::new(
+        cursor::EventLog::new(cli.event_log_size, cli.cursor_file).map_err(anyhow::Error::msg)?,
+    );
+    let mut attachment_store = attachments::AttachmentStore::new(
+        cli.attachment_dir,
+        cli.signal_attachment_dir,
+        cli.attachment_retention.map(std::time::Duration::from_secs),
+    );
+    attachment_store.policy = cli
+        .attachment_types
+        .map(|list| attachments::MimePolicy::parse(&list, cli.allow_attachment_mismatch))
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    attachment_store.min_free = cli.min_free_disk.map(|mb| mb * 1024 * 1024);
+    app_state.attachments = std::sync::Arc::new(attachment_store);
+    app_state.scanner = cli
+        .attachment_scanner
+        .map(|spec| scan::Scanner::parse(&spec).map(std::sync::Arc::new))
+        .transpose()
+        .map_err(anyhow::Error::msg)?;
+    app_state.compat = cli.compat;
+    app_state.json_case = cli.json_case;
+    app_state.sse_gzip = cli.sse_gzip;
+    let mut plugins = plugin::Plugins::default();
+    if !cli.spam_rules.is_empty() {
+        let rules = cli
+            .spam_rules
+            .iter()
+            .map(|spec| spam::parse_rule(spec))
+            .collect::<Result<_, _>>()
+            .map_err(anyhow::Error::msg)?;
+        let filter = std::sync::Arc::new(spam::SpamFilter::new(rules));
+        plugins.register(filter.clone());
+        app_state.spam = Some(filter);
+    }
+    if !cli.auto_block.is_empty() {
+        let triggers: Vec<autoblock::Trigger> = cli
+            .auto_block
+            .iter()
+            .map(|spec| autoblock::Trigger::parse(spec))
+            .collect::<Result<_, _>>()
+            .map_err(anyhow::Error::msg)?;
+        if triggers.contains(&autoblock::Trigger::Spam) && app_state.spam.is_none() {
+            anyhow::bail!("--auto-block spam needs at least one --spam-rule");
+        }
+        app_state.auto_block = Some(std::sync::Arc::new(autoblock::AutoBlocker::new(triggers)));
+    }
+    plugins.register(app_state.reply_contexts.clone());
+    for name in &cli.plugins {
+        plugins.register(plugin::builtin(name).map_err(anyhow::Error::msg)?);
+    }
+    app_state.plugins = std::sync::Arc::new(plugins);
+    if let Some(path) = cli.leader_lease.clone() {
+        if cli.leader_lease_secs < 3 {
+            anyhow::bail!("--leader-lease-secs must be at least 3");
+        }
+        let instance = cli.instance_id.clone().unwrap_or_else(|| {
+            let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "replica".to_string());
+            format!("{host}-{}", std::process::id())
+        });
+        app_state.leadership = std::sync::Arc::new(leader::Leadership::new(leader::Lease {
+            path,
+            instance,
+            ttl: std::time::Duration::from_secs(cli.leader_lease_secs),
+        }));
+    }
+    if let Some(spec) = &cli.aws_sink {
+        let target = aws_sink::Target::parse(spec).map_err(anyhow::Error::msg)?;
+        let credentials = aws_sink::Credentials::from_env().map_err(anyhow::Error::msg)?;
+        let sink = aws_sink::AwsSink::new(target, credentials, cli.aws_endpoint.clone());
+        app_state.aws_sink = Some(std::sync::Arc::new(sink));
+    }
+    if let Some(url) = cli.redis_url.clone() {
+        if !redis_bridge::ENABLED {
+            anyhow::bail!("--redis-url needs a build with --features redis");
+        }
+        let bridge = redis_bridge::RedisBridge::new(url, cli.redis_channel.clone());
+        app_state.redis = Some(std::sync::Arc::new(bridge));
+    }
+    if let (Some(account), Some(recipient)) = (&cli.canary_account, &cli.canary_recipient) {
+        let timeout =
+            std::time::Duration::from_secs(cli.canary_timeout.clamp(1, canary::MAX_TIMEOUT_SECS));
+        let canary = canary::Canary::new(
+            app_state.resolve_account(account),
+            recipient.clone(),
+            timeout,
+        );
+        app_state.canary = Some(std::sync::Arc::new(canary));
+    }
+    app_state.server_config = std::sync::Arc::new(runtime_config::ServerConfig {
+        listen: cli.listen.clone(),
+        tls_cert: cli.tls_cert.clone(),
+        metrics_listen: cli.metrics_listen.clone(),
+        signal_cli: signal_cli_addr,
+        log_filter,
+    });
 
     // Spawn the reader loop
     let broadcast_tx = app_state.broadcast_tx.clone();
     let pending = app_state.pending.clone();
     let metrics = app_state.metrics.clone();
-    tokio::spawn(jsonrpc::reader_loop(reader, broadcast_tx, pending, metrics));
+    let plugins = app_state.plugins.clone();
+    tasks.spawn_critical(
+        "reader",
+        jsonrpc::reader_loop(reader, broadcast_tx, pending, metrics, plugins),
+    );
+    app_state.detect_signal_cli_version().await;
+
+    if cli.preflight || !cli.expect_accounts.is_empty() {
+        let mut expected = cli.expect_accounts.clone();
+        for number in app_state.aliases.values() {
+            if !expected.contains(number) {
+                expected.push(number.clone());
+            }
+        }
+        preflight::run(&app_state, &expected)
+            .await
+            .map_err(anyhow::Error::msg)?;
+    }
 
     // Spawn webhook dispatcher
-    let webhook_state = app_state.clone();
-    tokio::spawn(webhooks::dispatch_loop(webhook_state));
+    supervise(&app_state, "webhooks", webhooks::dispatch_loop);
+
+    // Spawn bot command router
+    supervise(&app_state, "commands", commands::dispatch_loop);
+
+    // Welcome new group members
+    supervise(&app_state, "automations", automations::run);
+
+    // Count (and optionally repair) envelopes that fail to decrypt
+    supervise(&app_state, "decryption", decryption::watch_loop);
+
+    // Tell what signal-cli's group updates changed
+    supervise(&app_state, "group-events", group_events::watch_loop);
+
+    // Publish incoming envelopes to SNS or SQS
+    supervise_if(
+        &app_state,
+        app_state.aws_sink.is_some(),
+        "aws-sink",
+        aws_sink::run,
+    );
+
+    // Publish events to Redis
+    supervise_if(
+        &app_state,
+        app_state.redis.is_some(),
+        "redis-bridge",
+        redis_bridge::run,
+    );
+
+    // Take and renew the leader lease, when running as one of several replicas
+    supervise_if(
+        &app_state,
+        app_state.leadership.lease.is_some(),
+        "leader-lease",
+        leader::lease_loop,
+    );
+
+    // Spawn outbox release loop
+    supervise(&app_state, "outbox", outbox::run);
+
+    // Spawn message store recorder
+    supervise(&app_state, "store", store::record_loop);
+    supervise_if(
+        &app_state,
+        app_state.store.retention.max_age.is_some()
+            || app_state
+                .store
+                .overrides
+                .values()
+                .any(|r| r.max_age.is_some()),
+        "store-retention",
+        store::prune_loop,
+    );
+
+    // Keep recent events for pull consumers, and save their cursors
+    supervise(&app_state, "event-log", cursor::record_loop);
+    supervise_if(
+        &app_state,
+        app_state.event_log.file.is_some(),
+        "cursors",
+        cursor::save_loop,
+    );
+
+    // Spawn expiry of conversation state keys
+    supervise(&app_state, "kv-expiry", kv::expiry_loop);
+
+    // Spawn sync message tracker for linked devices
+    supervise(&app_state, "sync", sync::record_loop);
+
+    // Spawn the token usage saver
+    supervise_if(
+        &app_state,
+        app_state.quotas.file.is_some(),
+        "quota",
+        quota::run,
+    );
 
+    // Spawn the polling queue for --compat clients
+    supervise_if(
+        &app_state,
+        app_state.compat.is_some(),
+        "compat",
+        compat::record_loop,
+    );
+
+    // Spawn attachment retention job and incoming attachment scanner
+    supervise_if(
+        &app_state,
+        app_state.attachments.staging_dir.is_some() || app_state.attachments.signal_dir.is_some(),
+        "attachments",
+        attachments::run,
+    );
+    supervise_if(
+        &app_state,
+        app_state.scanner.is_some() && app_state.attachments.signal_dir.is_some(),
+        "scan",
+        scan::incoming_loop,
+    );
+
+    // Keep contact lists loaded for the unknown-sender spam rule
+    supervise_if(
+        &app_state,
+        app_state.spam.as_ref().is_some_and(|f| f.checks_contacts()),
+        "spam-contacts",
+        spam::contacts_loop,
+    );
+
+    // Block abusive senders
+    supervise_if(
+        &app_state,
+        app_state.auto_block.is_some(),
+        "auto-block",
+        autoblock::run,
+    );
+
+    // Keep active contacts' profiles current
+    supervise_if(
+        &app_state,
+        app_state.profile_refresh.is_some(),
+        "profile-refresh",
+        profile_refresh::run,
+    );
+
+    if let Some(addr) = &cli.metrics_listen {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        tracing::info!("Metrics on http://{}/metrics", listener.local_addr()?);
+        let metrics_app = routes::metrics::routes().with_state(app_state.clone());
+        tokio::spawn(async move { axum::serve(listener, metrics_app).await });
+    }
+
+    let quotas = app_state.quotas.clone();
+    let event_log = app_state.event_log.clone();
+    let leadership = app_state.leadership.clone();
     let app = routes::router(app_state)
         .layer(axum_mw::from_fn(middleware::request_tracing))
         .layer(CorsLayer::permissive());
 
     let requested: SocketAddr = cli.listen.parse()?;
+    let mut failure = None;
 
     match (cli.tls_cert, cli.tls_key) {
         (Some(cert), Some(key)) => {
-            let tls_config = axum_server::tls_rustls::RustlsConfig::from_pem_file(&cert, &key).await?;
+            let tls_config =
+                axum_server::tls_rustls::RustlsConfig::from_pem_file(&cert, &key).await?;
             // Probe with a regular TcpListener; if busy, fall back to OS-assigned port.
             let addr = match tokio::net::TcpListener::bind(requested).await {
-                Ok(probe) => { drop(probe); requested }
+                Ok(probe) => {
+                    drop(probe);
+                    requested
+                }
                 Err(_) => {
                     let fallback = SocketAddr::from(([127, 0, 0, 1], 0));
                     let probe = tokio::net::TcpListener::bind(fallback).await?;
@@ -100,6 +1010,7 @@ async fn main() -> anyhow::Result<()> {
                 _ = shutdown_signal() => {
                     tracing::info!("Shutdown signal received, stopping...");
                 }
+                reason = tasks.failure() => failure = Some(reason),
             }
         }
         (None, None) => {
@@ -122,6 +1033,7 @@ async fn main() -> anyhow::Result<()> {
                 _ = shutdown_signal() => {
                     tracing::info!("Shutdown signal received, stopping...");
                 }
+                reason = tasks.failure() => failure = Some(reason),
             }
         }
         _ => {
@@ -129,16 +1041,57 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
+    if let Err(e) = quotas.save() {
+        tracing::warn!("{e}");
+    }
+    if let Err(e) = event_log.save() {
+        tracing::warn!("{e}");
+    }
+    if let Some(lease) = &leadership.lease {
+        if let Err(e) = lease.release() {
+            tracing::warn!(path = %lease.path.display(), "Could not release the leader lease: {e}");
+        }
+    }
+    if let Some(reason) = failure {
+        anyhow::bail!("Shutting down: {reason}");
+    }
+
     // _managed_daemon drops here → process group killed
     Ok(())
 }
 
+/// Run a background task under the supervisor; a panicked one is started
+/// again with the same state.
+fn supervise<Fut>(state: &state::AppState, name: &str, task: fn(state::AppState) -> Fut)
+where
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let st = state.clone();
+    state.tasks.spawn(name, move || task(st.clone()));
+}
+
+/// Like [`supervise`], for a task that only has work to do when its feature
+/// is on; otherwise it is listed as disabled and not started.
+fn supervise_if<Fut>(
+    state: &state::AppState,
+    enabled: bool,
+    name: &str,
+    task: fn(state::AppState) -> Fut,
+) where
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    if enabled {
+        supervise(state, name, task);
+    } else {
+        state.tasks.disable(name);
+    }
+}
+
 /// Wait for SIGTERM or Ctrl+C, whichever comes first.
 async fn shutdown_signal() {
     let ctrl_c = tokio::signal::ctrl_c();
-    let mut sigterm =
-        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-            .expect("failed to register SIGTERM handler");
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to register SIGTERM handler");
     tokio::select! {
         _ = ctrl_c => {}
         _ = sigterm.recv() => {}
@@ -0,0 +1,54 @@
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::state::AppState;
+
+/// Retry-After sent while in maintenance, unless the operator gives one.
+pub const DEFAULT_RETRY_AFTER: u64 = 60;
+
+/// An active maintenance window, set through POST /v1/admin/maintenance.
+#[derive(Clone, Debug, Serialize)]
+pub struct Maintenance {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Seconds clients are told to wait before retrying.
+    pub retry_after: u64,
+    /// When maintenance began (ms since epoch).
+    pub since: u64,
+}
+
+/// Requests that keep working during maintenance: reads (but not sends made
+/// with GET), the admin API (so maintenance can be ended) and login.
+fn allowed(method: &Method, path: &str) -> bool {
+    *method == Method::OPTIONS
+        || (matches!(*method, Method::GET | Method::HEAD) && !crate::routes::sends_messages(path))
+        || path == "/v1/admin"
+        || path.starts_with("/v1/admin/")
+        || path.starts_with("/auth/")
+}
+
+/// Middleware rejecting mutating calls with 503 while in maintenance.
+/// Incoming messages are still recorded to the store, and queued outbox
+/// messages are held until maintenance ends.
+pub async fn reject_writes(State(st): State<AppState>, request: Request, next: Next) -> Response {
+    if !allowed(request.method(), request.uri().path()) {
+        if let Some(m) = st.maintenance.read().await.clone() {
+            let error = match &m.reason {
+                Some(reason) => format!("API is in maintenance: {reason}"),
+                None => "API is in maintenance".to_string(),
+            };
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, m.retry_after.to_string())],
+                Json(json!({ "error": error })),
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
@@ -25,10 +25,9 @@ pub async fn request_tracing(request: Request, next: Next) -> Response {
         latency_ms,
     );
 
-    response.headers_mut().insert(
-        "x-request-id",
-        request_id.to_string().parse().unwrap(),
-    );
+    response
+        .headers_mut()
+        .insert("x-request-id", request_id.to_string().parse().unwrap());
 
     response
 }
@@ -0,0 +1,137 @@
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// One step in a state file's format, from the version before it to `to`.
+pub struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    pub apply: fn(Value) -> Result<Value, String>,
+}
+
+/// A kind of state file and the migrations that bring older files to the
+/// current format. A file records its format in a top-level
+/// `schema_version`, written since the file was introduced.
+pub struct Format {
+    pub name: &'static str,
+    /// The version this release writes.
+    pub current: u32,
+    /// In order, the last one's `to` being `current`. A release that changes
+    /// the format adds a step here.
+    pub migrations: &'static [Migration],
+}
+
+/// `--usage-file`: per-token quota usage.
+pub const USAGE: Format = Format {
+    name: "usage file",
+    current: crate::quota::SCHEMA_VERSION,
+    migrations: &[],
+};
+
+/// `--cursor-file`: pull consumers' cursors.
+pub const CURSORS: Format = Format {
+    name: "cursor file",
+    current: crate::cursor::SCHEMA_VERSION,
+    migrations: &[],
+};
+
+impl Format {
+    /// The migrations a file at `version` still needs, or an error when it
+    /// was written by a newer release, which this one mustn't load and
+    /// overwrite.
+    pub fn pending(&self, path: &Path, version: u32) -> Result<Vec<&Migration>, String> {
+        if version > self.current {
+            return Err(format!(
+                "{} {} has schema version {version}, newer than this release's {}; upgrade signal-cli-api or restore a backup",
+                self.name,
+                path.display(),
+                self.current
+            ));
+        }
+        Ok(self.migrations.iter().filter(|m| m.to > version).collect())
+    }
+}
+
+fn version_of(format: &Format, path: &Path, value: &Value) -> Result<u32, String> {
+    value
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .ok_or_else(|| format!("{} {} has no schema_version", format.name, path.display()))
+}
+
+fn read(format: &Format, path: &Path) -> Result<Value, String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("{} {}: {e}", format.name, path.display()))?;
+    serde_json::from_slice(&data).map_err(|e| format!("{} {}: {e}", format.name, path.display()))
+}
+
+/// Where the file is kept as it was before migrating from `version`.
+pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".v{version}.bak"));
+    PathBuf::from(name)
+}
+
+/// Read a state file in the current format, migrating it first if it's
+/// older. The original is kept next to it (see [`backup_path`]) and the
+/// migrated file written in its place before anything is loaded, so an
+/// upgrade never drops what the file held. None when the file doesn't exist.
+pub fn load(format: &Format, path: &Path) -> Result<Option<Value>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut value = read(format, path)?;
+    let version = version_of(format, path, &value)?;
+    let pending = format.pending(path, version)?;
+    if pending.is_empty() {
+        return Ok(Some(value));
+    }
+    for migration in pending {
+        value = (migration.apply)(value).map_err(|e| {
+            format!(
+                "migrating {} {} to version {}: {e}",
+                format.name,
+                path.display(),
+                migration.to
+            )
+        })?;
+        tracing::info!(
+            "Migrated {} {} to version {}: {}",
+            format.name,
+            path.display(),
+            migration.to,
+            migration.description
+        );
+    }
+    let backup = backup_path(path, version);
+    std::fs::copy(path, &backup)
+        .map_err(|e| format!("backing up {} to {}: {e}", path.display(), backup.display()))?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, value.to_string())
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| format!("writing migrated {} {}: {e}", format.name, path.display()))?;
+    Ok(Some(value))
+}
+
+/// What [`load`] would do to a state file, without changing it: one line
+/// per pending migration, none when it's current or doesn't exist.
+pub fn dry_run(format: &Format, path: &Path) -> Result<Vec<String>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let value = read(format, path)?;
+    let version = version_of(format, path, &value)?;
+    let mut from = version;
+    let mut steps = Vec::new();
+    for migration in format.pending(path, version)? {
+        steps.push(format!(
+            "{} {}: {from} -> {}: {}",
+            format.name,
+            path.display(),
+            migration.to,
+            migration.description
+        ));
+        from = migration.to;
+    }
+    Ok(steps)
+}
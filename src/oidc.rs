@@ -0,0 +1,317 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use crate::jwt::{self, Expected, KeySet};
+use crate::state::now_millis;
+
+/// Cookie carrying an operator's session after an OIDC login.
+pub const SESSION_COOKIE: &str = "signal_api_session";
+
+/// How long a login session lasts.
+pub const SESSION_TTL: Duration = Duration::from_secs(8 * 3600);
+
+/// How long a user has to complete the login at the identity provider.
+const LOGIN_TTL: Duration = Duration::from_secs(600);
+
+/// Settings for logging operators in with an OpenID Connect provider.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// Issuer URL; `/.well-known/openid-configuration` is read from it.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// This API's `/auth/callback` URL as registered with the provider.
+    pub redirect_url: String,
+    /// Emails (`ops@example.com`) or domains (`@example.com`) allowed in;
+    /// empty = anyone the provider authenticates.
+    pub allowed: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    next: String,
+    expires_at: u64,
+}
+
+/// A logged-in operator.
+#[derive(Clone, Debug, Serialize)]
+pub struct Session {
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub expires_at: u64,
+}
+
+/// OIDC authorization-code login (with PKCE) for the web UI and admin
+/// endpoints, plus validation of the provider's JWTs as bearer credentials.
+/// Sessions live in memory and end on restart.
+pub struct Oidc {
+    pub config: OidcConfig,
+    client: reqwest::Client,
+    discovery: OnceCell<Discovery>,
+    keys: OnceCell<KeySet>,
+    logins: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system RNG available");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Only same-site paths may be redirected to after login.
+fn safe_next(next: Option<&str>) -> String {
+    match next {
+        Some(n) if n.starts_with('/') && !n.starts_with("//") && !n.contains('\\') => n.to_string(),
+        _ => "/ui".to_string(),
+    }
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig, client: reqwest::Client) -> Self {
+        Self {
+            config,
+            client,
+            discovery: OnceCell::new(),
+            keys: OnceCell::new(),
+            logins: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The provider's metadata, read on first use so a provider that is down
+    /// at startup doesn't stop the API.
+    async fn discovery(&self) -> Result<&Discovery, String> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                self.client
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| format!("OIDC discovery: {e}"))?
+                    .json()
+                    .await
+                    .map_err(|e| format!("OIDC discovery: {e}"))
+            })
+            .await
+    }
+
+    async fn keys(&self) -> Result<&KeySet, String> {
+        let jwks_uri = self.discovery().await?.jwks_uri.clone();
+        Ok(self
+            .keys
+            .get_or_init(|| async { KeySet::new(jwks_uri, self.client.clone()) })
+            .await)
+    }
+
+    /// Verify a token issued by the provider for this client and check the
+    /// user is allowed in. Returns its claims.
+    pub async fn verify_token(&self, token: &str) -> Result<Value, String> {
+        let claims = self.keys().await?.verify(token).await?;
+        let audiences = [self.config.client_id.clone()];
+        jwt::validate_claims(
+            &claims,
+            &Expected {
+                issuer: &self.config.issuer,
+                audiences: &audiences,
+            },
+        )?;
+        if !self.allowed(&claims) {
+            return Err("user is not allowed to use this API".into());
+        }
+        Ok(claims)
+    }
+
+    fn allowed(&self, claims: &Value) -> bool {
+        if self.config.allowed.is_empty() {
+            return true;
+        }
+        let verified = claims
+            .get("email_verified")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        let Some(email) = claims
+            .get("email")
+            .and_then(|v| v.as_str())
+            .filter(|_| verified)
+        else {
+            return false;
+        };
+        let email = email.to_ascii_lowercase();
+        self.config.allowed.iter().any(|a| {
+            let a = a.to_ascii_lowercase();
+            if a.starts_with('@') {
+                email.ends_with(&a)
+            } else {
+                email == a
+            }
+        })
+    }
+
+    /// Start a login: returns the provider URL to send the browser to.
+    pub async fn begin_login(&self, next: Option<&str>) -> Result<String, String> {
+        let discovery = self.discovery().await?;
+        let (state, nonce, verifier) = (random_token(), random_token(), random_token());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_url),
+                ("scope", "openid email profile"),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| format!("invalid authorization endpoint: {e}"))?;
+        let now = now_millis();
+        let mut logins = self.logins.lock().unwrap();
+        logins.retain(|_, l| l.expires_at > now);
+        logins.insert(
+            state,
+            PendingLogin {
+                nonce,
+                verifier,
+                next: safe_next(next),
+                expires_at: now + LOGIN_TTL.as_millis() as u64,
+            },
+        );
+        Ok(url.into())
+    }
+
+    /// Finish a login from the provider's callback: exchange the code, check
+    /// the ID token, and open a session. Returns the session ID and the path
+    /// to continue to.
+    pub async fn complete_login(
+        &self,
+        code: &str,
+        state: &str,
+    ) -> Result<(String, String), String> {
+        let pending = self
+            .logins
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|l| l.expires_at > now_millis())
+            .ok_or("unknown or expired login state")?;
+        let discovery = self.discovery().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_url),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &pending.verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let tokens: TokenResponse = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("token exchange: {e}"))?
+            .json()
+            .await
+            .map_err(|e| format!("token exchange: {e}"))?;
+        let claims = self.verify_token(&tokens.id_token).await?;
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(pending.nonce.as_str()) {
+            return Err("ID token nonce mismatch".into());
+        }
+        let text = |key: &str| claims.get(key).and_then(|v| v.as_str()).map(String::from);
+        let session = Session {
+            subject: text("sub").ok_or("ID token has no subject")?,
+            email: text("email"),
+            name: text("name"),
+            expires_at: now_millis() + SESSION_TTL.as_millis() as u64,
+        };
+        tracing::info!(
+            subject = session.subject,
+            email = session.email,
+            "Operator logged in"
+        );
+        let id = random_token();
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = now_millis();
+        sessions.retain(|_, s| s.expires_at > now);
+        sessions.insert(id.clone(), session);
+        Ok((id, pending.next))
+    }
+
+    /// The live session named by a request's `Cookie` header, if any.
+    pub fn session(&self, cookie_header: Option<&str>) -> Option<Session> {
+        let id = session_id(cookie_header?)?;
+        let sessions = self.sessions.lock().unwrap();
+        sessions
+            .get(id)
+            .filter(|s| s.expires_at > now_millis())
+            .cloned()
+    }
+
+    pub fn logout(&self, cookie_header: Option<&str>) {
+        if let Some(id) = cookie_header.and_then(session_id) {
+            self.sessions.lock().unwrap().remove(id);
+        }
+    }
+
+    /// `Set-Cookie` value for a new session (or, with None, clearing it).
+    pub fn session_cookie(&self, id: Option<&str>) -> String {
+        let secure = if self.config.redirect_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        };
+        match id {
+            Some(id) => format!(
+                "{SESSION_COOKIE}={id}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}{secure}",
+                SESSION_TTL.as_secs()
+            ),
+            None => format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0{secure}"),
+        }
+    }
+}
+
+fn session_id(cookie_header: &str) -> Option<&str> {
+    cookie_header
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+        .filter(|v| !v.is_empty())
+}
@@ -0,0 +1,32 @@
+/// Proxy used for all outbound HTTP the server makes (webhook deliveries,
+/// bot command handlers).
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` proxy URL.
+    pub url: String,
+    /// Comma-separated hosts/domains/CIDRs that bypass the proxy, in NO_PROXY
+    /// syntax. When unset, the NO_PROXY environment variable is honored.
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn to_proxy(&self) -> Result<reqwest::Proxy, String> {
+        let proxy = reqwest::Proxy::all(&self.url)
+            .map_err(|e| format!("invalid proxy {}: {e}", self.url))?;
+        let no_proxy = match &self.no_proxy {
+            Some(list) => reqwest::NoProxy::from_string(list),
+            None => reqwest::NoProxy::from_env(),
+        };
+        Ok(proxy.no_proxy(no_proxy))
+    }
+}
+
+/// A client builder with the configured proxy applied. Without a proxy,
+/// reqwest's default HTTP_PROXY/HTTPS_PROXY/NO_PROXY handling applies.
+pub fn client_builder(proxy: Option<&ProxyConfig>) -> Result<reqwest::ClientBuilder, String> {
+    let builder = reqwest::Client::builder();
+    match proxy {
+        Some(p) => Ok(builder.proxy(p.to_proxy()?)),
+        None => Ok(builder),
+    }
+}
@@ -0,0 +1,361 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::exposition::{Exposition, Labels};
+use crate::pacing;
+use crate::reply_context;
+use crate::send_result::{Outcome, SendReport};
+use crate::state::{now_millis, AppState};
+
+// ---------------------------------------------------------------------------
+// Quiet hours
+// ---------------------------------------------------------------------------
+
+/// A daily do-not-disturb window in "HH:MM" local time, where local time is
+/// UTC shifted by `utc_offset_minutes`. Windows may wrap midnight (22:00–07:00).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: String,
+    pub end: String,
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+/// Parse "HH:MM" into minutes since midnight.
+fn parse_hhmm(s: &str) -> Option<u32> {
+    let (h, m) = s.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+impl QuietHours {
+    /// Returns an error message if the window is malformed.
+    pub fn validate(&self) -> Result<(), String> {
+        if parse_hhmm(&self.start).is_none() || parse_hhmm(&self.end).is_none() {
+            return Err("start and end must be HH:MM".to_string());
+        }
+        if self.utc_offset_minutes.abs() > 14 * 60 {
+            return Err("utc_offset_minutes out of range".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether `now_ms` (Unix epoch millis) falls inside the window.
+    /// An empty window (start == end) never matches.
+    pub fn contains(&self, now_ms: u64) -> bool {
+        let (Some(start), Some(end)) = (parse_hhmm(&self.start), parse_hhmm(&self.end)) else {
+            return false;
+        };
+        let minutes = (now_ms / 60_000) as i64 + self.utc_offset_minutes as i64;
+        let local = minutes.rem_euclid(24 * 60) as u32;
+        if start <= end {
+            start <= local && local < end
+        } else {
+            local >= start || local < end
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Outbox
+// ---------------------------------------------------------------------------
+
+/// Outbox lane. Higher lanes get more send slots per scheduling round, but
+/// every lane gets at least one, so bulk traffic can't starve.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Low];
+
+    /// Send slots per weighted round-robin round.
+    fn weight(self) -> usize {
+        match self {
+            Priority::High => 4,
+            Priority::Normal => 2,
+            Priority::Low => 1,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueuedMessage {
+    pub id: String,
+    pub account: String,
+    pub queued_at: u64,
+    pub reason: &'static str,
+    pub priority: Priority,
+    pub params: Value,
+    #[serde(skip)]
+    seq: u64,
+}
+
+/// Sends that were accepted but deferred, released by [`run`].
+#[derive(Default)]
+pub struct Outbox {
+    lanes: Mutex<[VecDeque<QueuedMessage>; 3]>,
+    next_id: AtomicU64,
+    sent: [AtomicU64; 3],
+}
+
+impl Outbox {
+    /// Defer a send, which the route answers with 202. `reason` says why:
+    /// `quiet_hours` for a non-urgent send in the account's quiet hours,
+    /// [`pacing::REASON`] for one that came too soon, or `low_priority`.
+    pub fn enqueue(
+        &self,
+        account: &str,
+        params: Value,
+        reason: &'static str,
+        priority: Priority,
+    ) -> QueuedMessage {
+        let seq = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let msg = QueuedMessage {
+            id: seq.to_string(),
+            account: account.to_string(),
+            queued_at: now_millis(),
+            reason,
+            priority,
+            params,
+            seq,
+        };
+        self.lanes.lock().unwrap()[priority.index()].push_back(msg.clone());
+        msg
+    }
+
+    /// All queued messages, highest lane first.
+    pub fn list(&self) -> Vec<QueuedMessage> {
+        self.lanes
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Take a queued message out of the outbox so it is never sent; None
+    /// when it isn't queued (any more) or `allowed` refuses it.
+    pub fn cancel(
+        &self,
+        id: &str,
+        allowed: impl Fn(&QueuedMessage) -> bool,
+    ) -> Option<QueuedMessage> {
+        let mut lanes = self.lanes.lock().unwrap();
+        lanes.iter_mut().find_map(|lane| {
+            let index = lane.iter().position(|m| m.id == id && allowed(m))?;
+            lane.remove(index)
+        })
+    }
+
+    /// Whether any queued message was deferred for `reason`.
+    pub fn holds(&self, reason: &str) -> bool {
+        self.lanes
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|m| m.reason == reason)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lanes.lock().unwrap().iter().map(|l| l.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove and return up to `max` due messages, interleaving lanes by
+    /// weighted round-robin. Within a lane, queue order is preserved.
+    pub fn take_batch(
+        &self,
+        is_due: impl Fn(&QueuedMessage) -> bool,
+        max: usize,
+    ) -> Vec<QueuedMessage> {
+        let mut lanes = self.lanes.lock().unwrap();
+        let mut due: [VecDeque<QueuedMessage>; 3] = Default::default();
+        let mut keep: [VecDeque<QueuedMessage>; 3] = Default::default();
+        for (i, lane) in lanes.iter_mut().enumerate() {
+            for m in lane.drain(..) {
+                if is_due(&m) {
+                    due[i].push_back(m)
+                } else {
+                    keep[i].push_back(m)
+                }
+            }
+        }
+
+        let mut batch = Vec::new();
+        while batch.len() < max && due.iter().any(|d| !d.is_empty()) {
+            for p in Priority::ALL {
+                for _ in 0..p.weight() {
+                    if batch.len() >= max {
+                        break;
+                    }
+                    if let Some(m) = due[p.index()].pop_front() {
+                        batch.push(m);
+                    }
+                }
+            }
+        }
+
+        for (i, lane) in lanes.iter_mut().enumerate() {
+            lane.extend(due[i].drain(..).chain(keep[i].drain(..)));
+            lane.make_contiguous().sort_by_key(|m| m.seq);
+        }
+        batch
+    }
+
+    /// Append per-lane queue depth and released-message counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let depths: Vec<u64> = {
+            let lanes = self.lanes.lock().unwrap();
+            Priority::ALL
+                .iter()
+                .map(|p| lanes[p.index()].len() as u64)
+                .collect()
+        };
+        let labels: Vec<[(&str, &str); 1]> = Priority::ALL
+            .iter()
+            .map(|p| [("lane", p.as_str())])
+            .collect();
+        let queued: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(&depths)
+            .map(|(l, d)| (&l[..], *d))
+            .collect();
+        exp.gauge(
+            "signal_outbox_queued",
+            "Messages waiting in the outbox",
+            &queued,
+        );
+        let sent: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(&self.sent)
+            .map(|(l, c)| (&l[..], c.load(Ordering::Relaxed)))
+            .collect();
+        exp.counter(
+            "signal_outbox_sent",
+            "Messages released from the outbox",
+            &sent,
+        );
+    }
+}
+
+/// Periodically releases queued messages whose account is no longer inside
+/// its quiet-hours window (at most `outbox_batch` per tick), and queues "(xN)"
+/// summaries for closed duplicate windows. Nothing is released while the API
+/// is in maintenance. With `--send-rate`, each release waits for its slot,
+/// and sends queued for pacing are released as soon as they are queued
+/// rather than at the next tick, a batch after another.
+pub async fn run(state: AppState) {
+    loop {
+        match &state.pacer {
+            Some(pacer) => tokio::select! {
+                _ = tokio::time::sleep(state.outbox_tick) => {}
+                _ = pacer.wait_queued() => {}
+            },
+            None => tokio::time::sleep(state.outbox_tick).await,
+        }
+        for (account, params) in state.dedup.expire() {
+            state
+                .outbox
+                .enqueue(&account, params, "dedup_summary", Priority::Normal);
+        }
+        if state.outbox.is_empty() || state.maintenance.read().await.is_some() {
+            continue;
+        }
+        let quiet: HashMap<String, QuietHours> = state.quiet_hours.read().await.clone();
+        let now = now_millis();
+        let due = state.outbox.take_batch(
+            |m| !quiet.get(&m.account).is_some_and(|q| q.contains(now)),
+            state.outbox_batch,
+        );
+        let full = due.len() >= state.outbox_batch;
+        for mut msg in due {
+            if let Err(e) = state.attachments.stage(&mut msg.params) {
+                tracing::warn!(outbox_id = msg.id, account = msg.account, error = %e, "Dropping queued message");
+                continue;
+            }
+            let metadata = reply_context::take_metadata(&mut msg.params);
+            if let Some(pacer) = &state.pacer {
+                pacer.acquire().await;
+            }
+            match state.rpc("send", msg.params.clone()).await {
+                Ok(result) => {
+                    state.metrics.inc_sent();
+                    state.outbox.sent[msg.priority.index()].fetch_add(1, Ordering::Relaxed);
+                    let timestamp = result
+                        .get("timestamp")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or_else(now_millis);
+                    state
+                        .store
+                        .record_outgoing(&msg.account, &msg.params, timestamp);
+                    if let Some(metadata) = metadata {
+                        state
+                            .reply_contexts
+                            .record(&msg.account, &msg.params, timestamp, metadata);
+                    }
+                    let report = SendReport::from_result(&result);
+                    state
+                        .metrics
+                        .add_failed_recipients(report.summary.failed as u64);
+                    if report.summary.failed > 0 {
+                        let failed: Vec<&str> = report
+                            .recipients
+                            .iter()
+                            .filter(|r| r.status != Outcome::Sent)
+                            .map(|r| r.recipient.as_str())
+                            .collect();
+                        tracing::warn!(
+                            outbox_id = msg.id,
+                            account = msg.account,
+                            ?failed,
+                            "Released queued message, some recipients failed"
+                        );
+                    } else {
+                        tracing::info!(
+                            outbox_id = msg.id,
+                            account = msg.account,
+                            "Released queued message"
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(outbox_id = msg.id, account = msg.account, error = %e, "Queued message failed to send");
+                }
+            }
+        }
+        // More paced sends than a batch: go on with the next one at once.
+        if let Some(pacer) = state
+            .pacer
+            .as_ref()
+            .filter(|_| full && state.outbox.holds(pacing::REASON))
+        {
+            pacer.queued();
+        }
+    }
+}
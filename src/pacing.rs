@@ -0,0 +1,128 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::exposition::Exposition;
+
+/// Outbox reason of sends queued because they came too soon after the last.
+pub const REASON: &str = "pacing";
+
+/// Spaces outgoing sends across all clients and accounts: one every
+/// `interval`, each gap lengthened by a random share of `jitter`, so sends
+/// don't arrive at Signal in a burst or at an exact rhythm.
+pub struct Pacer {
+    pub interval: Duration,
+    pub jitter: Duration,
+    /// When the next send may go out.
+    next: Mutex<Instant>,
+    /// Woken when a send is queued for a slot, so the outbox releases it
+    /// when its slot comes up rather than at its next tick.
+    queued: Notify,
+    immediate: AtomicU64,
+    deferred: AtomicU64,
+    waited_ms: AtomicU64,
+}
+
+impl Pacer {
+    /// `rate` sends per second; jitter defaults to a quarter of the interval.
+    pub fn new(rate: f64, jitter: Option<Duration>) -> Result<Self, String> {
+        if !(rate.is_finite() && rate > 0.0) {
+            return Err(format!(
+                "send rate must be a positive number of messages per second, got {rate}"
+            ));
+        }
+        let interval = Duration::try_from_secs_f64(1.0 / rate).map_err(|_| {
+            format!("send rate {rate} is too low, the gap between sends would be too long")
+        })?;
+        Ok(Self {
+            interval,
+            jitter: jitter.unwrap_or(interval / 4),
+            next: Mutex::new(Instant::now()),
+            queued: Notify::new(),
+            immediate: AtomicU64::new(0),
+            deferred: AtomicU64::new(0),
+            waited_ms: AtomicU64::new(0),
+        })
+    }
+
+    /// The gap before the send after this one.
+    fn gap(&self) -> Duration {
+        let mut b = [0u8; 4];
+        SystemRandom::new()
+            .fill(&mut b)
+            .expect("system RNG available");
+        let share = f64::from(u32::from_le_bytes(b)) / f64::from(u32::MAX);
+        self.interval + self.jitter.mul_f64(share)
+    }
+
+    /// Take the next slot if it is due now and no earlier send (`waiting`)
+    /// is queued for one. A send that gets none goes to the outbox (counted
+    /// as deferred).
+    pub fn try_acquire(&self, waiting: bool) -> bool {
+        let mut next = self.next.lock().unwrap();
+        let now = Instant::now();
+        if waiting || *next > now {
+            self.deferred.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        *next = now + self.gap();
+        self.immediate.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Note that a send was queued for a slot (see [`Pacer::wait_queued`]).
+    pub fn queued(&self) {
+        self.queued.notify_one();
+    }
+
+    /// Wait until a send is queued for a slot.
+    pub async fn wait_queued(&self) {
+        self.queued.notified().await;
+    }
+
+    /// Reserve the next slot and wait for it, for sends released from the
+    /// outbox one after another and for sends the API makes itself (the
+    /// canary, `/v1/ask`, welcome messages).
+    pub async fn acquire(&self) {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + self.gap();
+            at
+        };
+        let wait = at.saturating_duration_since(Instant::now());
+        self.waited_ms
+            .fetch_add(wait.as_millis() as u64, Ordering::Relaxed);
+        tokio::time::sleep(wait).await;
+    }
+
+    /// Append pacing counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        exp.gauge(
+            "signal_pacing_interval_ms",
+            "Configured gap between sends",
+            &[(&[], self.interval.as_millis() as u64)],
+        );
+        exp.counter(
+            "signal_pacing_sends",
+            "Sends by whether they went out at once or were queued for pacing",
+            &[
+                (
+                    &[("result", "immediate")],
+                    self.immediate.load(Ordering::Relaxed),
+                ),
+                (
+                    &[("result", "deferred")],
+                    self.deferred.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+        exp.counter(
+            "signal_pacing_wait_ms",
+            "Time sends waited for their slots",
+            &[(&[], self.waited_ms.load(Ordering::Relaxed))],
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use serde_json::Value;
+use std::sync::Arc;
+
+/// A hook into the message path, for transforms such as PII scrubbing or
+/// routing without forking the server. Hooks run inline on the hot path, so
+/// they should be quick; anything slow belongs in a task of its own.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Whether the plugin has anything to do right now. While no plugin is
+    /// active, incoming notifications skip the hooks and are passed on as
+    /// signal-cli wrote them.
+    fn active(&self) -> bool {
+        true
+    }
+
+    /// Called with each incoming envelope (and its account, when known)
+    /// before it is published; it may be changed in place. Returning false
+    /// drops it: streams, webhooks and the message store never see it.
+    fn on_incoming_envelope(&self, _account: Option<&str>, _envelope: &mut Value) -> bool {
+        true
+    }
+
+    /// Called with the parameters of each `send` call before it goes to
+    /// signal-cli; an error refuses the send with 400.
+    fn before_send(&self, _params: &mut Value) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once signal-cli has answered a `send` call.
+    fn after_send(&self, _params: &Value, _result: Result<&Value, &str>) {}
+}
+
+/// The plugins of a server, run in the order they were registered.
+#[derive(Default)]
+pub struct Plugins(Vec<Arc<dyn Plugin>>);
+
+impl Plugins {
+    pub fn register(&mut self, plugin: Arc<dyn Plugin>) {
+        tracing::info!(plugin = plugin.name(), "Plugin registered");
+        self.0.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.0.iter().any(|p| p.active())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|p| p.name()).collect()
+    }
+
+    /// Run `on_incoming_envelope` over a notification; false when a plugin
+    /// dropped it. Returns true for anything that isn't an envelope.
+    pub fn incoming(&self, notification: &mut Value) -> bool {
+        if self.0.is_empty() {
+            return true;
+        }
+        let root = if notification.get("params").is_some() {
+            &mut notification["params"]
+        } else {
+            notification
+        };
+        let account = root
+            .get("account")
+            .and_then(|a| a.as_str())
+            .map(String::from);
+        let Some(envelope) = root.get_mut("envelope") else {
+            return true;
+        };
+        self.0
+            .iter()
+            .all(|p| p.on_incoming_envelope(account.as_deref(), envelope))
+    }
+
+    pub fn before_send(&self, params: &mut Value) -> Result<(), String> {
+        self.0.iter().try_for_each(|p| p.before_send(params))
+    }
+
+    pub fn after_send(&self, params: &Value, result: Result<&Value, &str>) {
+        for plugin in &self.0 {
+            plugin.after_send(params, result);
+        }
+    }
+}
+
+/// Names accepted by `--plugin` in this build.
+pub fn builtin_names() -> Vec<&'static str> {
+    let mut names = Vec::new();
+    if cfg!(feature = "pii-scrub") {
+        names.push("pii-scrub");
+    }
+    names
+}
+
+/// A plugin compiled into this build, by the name given to `--plugin`.
+pub fn builtin(name: &str) -> Result<Arc<dyn Plugin>, String> {
+    match name {
+        #[cfg(feature = "pii-scrub")]
+        "pii-scrub" => Ok(Arc::new(PiiScrubber)),
+        _ => {
+            let available = builtin_names();
+            let available = if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            };
+            Err(format!("unknown plugin {name:?} (built in: {available})"))
+        }
+    }
+}
+
+/// Masks email addresses and long digit runs (card, account and phone
+/// numbers) in the text of incoming messages.
+#[cfg(feature = "pii-scrub")]
+pub struct PiiScrubber;
+
+/// Digits in a run, spaces and dashes aside, from which it is masked.
+#[cfg(feature = "pii-scrub")]
+pub const PII_MIN_DIGITS: usize = 9;
+
+#[cfg(feature = "pii-scrub")]
+impl PiiScrubber {
+    pub fn scrub(text: &str) -> String {
+        let words: Vec<String> = text
+            .split(' ')
+            .map(|word| {
+                let at = word.find('@');
+                let is_email = at.is_some_and(|i| i > 0 && word[i + 1..].contains('.'));
+                if is_email {
+                    "[email]".to_string()
+                } else {
+                    word.to_string()
+                }
+            })
+            .collect();
+        Self::mask_digits(&words.join(" "))
+    }
+
+    fn mask_digits(text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            if !chars[i].is_ascii_digit() {
+                out.push(chars[i]);
+                i += 1;
+                continue;
+            }
+            // A run of digits, allowing single separators between them.
+            let (mut end, mut digits) = (i, 0);
+            let mut j = i;
+            while j < chars.len() {
+                if chars[j].is_ascii_digit() {
+                    digits += 1;
+                    end = j + 1;
+                    j += 1;
+                } else if matches!(chars[j], ' ' | '-')
+                    && chars.get(j + 1).is_some_and(|c| c.is_ascii_digit())
+                {
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            if digits >= PII_MIN_DIGITS {
+                out.push_str("[number]");
+            } else {
+                out.extend(&chars[i..end]);
+            }
+            i = end;
+        }
+        out
+    }
+}
+
+#[cfg(feature = "pii-scrub")]
+impl Plugin for PiiScrubber {
+    fn name(&self) -> &str {
+        "pii-scrub"
+    }
+
+    fn on_incoming_envelope(&self, _account: Option<&str>, envelope: &mut Value) -> bool {
+        if let Some(Value::String(text)) = envelope
+            .get_mut("dataMessage")
+            .and_then(|d| d.get_mut("message"))
+        {
+            *text = Self::scrub(text);
+        }
+        true
+    }
+}
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Instant;
+
+use crate::state::AppState;
+
+/// What the preflight found for one account.
+#[derive(Debug, Serialize)]
+pub struct AccountSummary {
+    pub number: String,
+    /// Groups and contacts signal-cli loaded (None if the call failed).
+    pub groups: Option<usize>,
+    pub contacts: Option<usize>,
+}
+
+/// The numbers in a `listAccounts` result.
+pub fn listed_numbers(accounts: &Value) -> Vec<String> {
+    accounts
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|a| {
+            a.as_str()
+                .or_else(|| a.get("number").and_then(|n| n.as_str()))
+        })
+        .map(String::from)
+        .collect()
+}
+
+/// Check that every `expected` account is registered with the daemon, then
+/// list each account's groups and contacts so signal-cli has loaded them
+/// before the first real request. With no expected accounts, every account
+/// the daemon lists is warmed and at least one must exist.
+///
+/// A missing account is an error; a failed warm-up call is only logged.
+pub async fn run(state: &AppState, expected: &[String]) -> Result<Vec<AccountSummary>, String> {
+    let listed = state
+        .rpc("listAccounts", json!({}))
+        .await
+        .map_err(|e| format!("preflight: signal-cli did not answer listAccounts: {e}"))?;
+    let available = listed_numbers(&listed);
+    let missing: Vec<&String> = expected.iter().filter(|n| !available.contains(n)).collect();
+    if !missing.is_empty() {
+        let missing: Vec<&str> = missing.iter().map(|n| n.as_str()).collect();
+        return Err(format!(
+            "preflight: account(s) {} not registered with signal-cli (available: {})",
+            missing.join(", "),
+            if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            },
+        ));
+    }
+    let accounts = if expected.is_empty() {
+        available
+    } else {
+        expected.to_vec()
+    };
+    if accounts.is_empty() {
+        return Err(
+            "preflight: signal-cli has no registered accounts; register or link one first".into(),
+        );
+    }
+
+    let mut summaries = Vec::new();
+    for number in accounts {
+        let start = Instant::now();
+        let count = |method: &'static str| {
+            let number = number.clone();
+            async move {
+                match state.rpc(method, json!({ "account": number })).await {
+                    Ok(v) => v.as_array().map(|a| a.len()),
+                    Err(e) => {
+                        tracing::warn!(
+                            account = number,
+                            rpc_method = method,
+                            "Preflight warm-up failed: {e}"
+                        );
+                        None
+                    }
+                }
+            }
+        };
+        let groups = count("listGroups").await;
+        let contacts = count("listContacts").await;
+        tracing::info!(
+            account = number,
+            groups,
+            contacts,
+            latency_ms = start.elapsed().as_millis() as u64,
+            "Preflight passed"
+        );
+        summaries.push(AccountSummary {
+            number,
+            groups,
+            contacts,
+        });
+    }
+    Ok(summaries)
+}
@@ -0,0 +1,182 @@
+use axum::body::{to_bytes, Body, HttpBody};
+use axum::extract::Request;
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::Response;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How phone numbers are written in logs, metrics labels and error messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrivacyMode {
+    /// Written as they are.
+    #[default]
+    Off,
+    /// Country code and last three digits kept: `+49****678`.
+    Mask,
+    /// Country code and a hash, so log lines about one number can be
+    /// correlated: `+49#3f2a9c1d`. Numbers are few enough to brute-force the
+    /// hash, so this hides them from casual readers only.
+    Hash,
+}
+
+impl PrivacyMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PrivacyMode::Off => "off",
+            PrivacyMode::Mask => "mask",
+            PrivacyMode::Hash => "hash",
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+/// Set once at startup, before logging begins.
+pub fn set_mode(mode: PrivacyMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn mode() -> PrivacyMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => PrivacyMode::Mask,
+        2 => PrivacyMode::Hash,
+        _ => PrivacyMode::Off,
+    }
+}
+
+/// Digits an E.164 number can have.
+const E164_DIGITS: std::ops::RangeInclusive<usize> = 7..=15;
+
+/// How a number (its digits, without the `+`) is written in `mode`.
+fn rewrite(mode: PrivacyMode, digits: &str) -> String {
+    let country = &digits[..2];
+    match mode {
+        PrivacyMode::Off => format!("+{digits}"),
+        PrivacyMode::Mask => format!("+{country}****{}", &digits[digits.len() - 3..]),
+        PrivacyMode::Hash => format!(
+            "+{country}#{}",
+            &hex::encode(Sha256::digest(digits.as_bytes()))[..8]
+        ),
+    }
+}
+
+/// `text` with every phone number (`+` or `%2B` followed by 7-15 digits)
+/// rewritten for `mode`.
+pub fn redact_with(mode: PrivacyMode, text: &str) -> Cow<'_, str> {
+    if mode == PrivacyMode::Off
+        || !(text.contains('+') || text.contains("%2B") || text.contains("%2b"))
+    {
+        return Cow::Borrowed(text);
+    }
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let prefix = match bytes[i] {
+            b'+' => 1,
+            b'%' if text[i..]
+                .get(..3)
+                .is_some_and(|p| p.eq_ignore_ascii_case("%2B")) =>
+            {
+                3
+            }
+            _ => 0,
+        };
+        let after_digit = i > 0 && bytes[i - 1].is_ascii_digit();
+        if prefix == 0 || after_digit {
+            i += 1;
+            continue;
+        }
+        let start = i + prefix;
+        let len = bytes[start..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if !E164_DIGITS.contains(&len) {
+            i = start + len;
+            continue;
+        }
+        out.push_str(&text[copied..i]);
+        out.push_str(&rewrite(mode, &text[start..start + len]));
+        i = start + len;
+        copied = i;
+    }
+    if copied == 0 {
+        return Cow::Borrowed(text);
+    }
+    out.push_str(&text[copied..]);
+    Cow::Owned(out)
+}
+
+/// [`redact_with`] in the configured mode.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    redact_with(mode(), text)
+}
+
+/// Makes writers that rewrite phone numbers in what is written to them, for
+/// the console log. Each formatted log event arrives in a single write.
+pub struct Redacting<M>(pub M);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+pub struct RedactingWriter<W>(W);
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) if mode() != PrivacyMode::Off => self.0.write_all(redact(text).as_bytes())?,
+            _ => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Largest error body rewritten; bigger ones pass through as they are.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Middleware rewriting phone numbers in error responses (4xx/5xx JSON
+/// bodies) when a privacy mode is set. Bodies that may be bigger than
+/// [`MAX_ERROR_BODY`] are left alone rather than cut off.
+pub async fn redact_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    let fits = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|len| len <= MAX_ERROR_BODY as u64);
+    if mode() == PrivacyMode::Off
+        || !(response.status().is_client_error() || response.status().is_server_error())
+        || !is_json
+        || !fits
+    {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_ERROR_BODY).await else {
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    };
+    let text = String::from_utf8_lossy(&bytes);
+    let redacted = redact(&text).into_owned();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(redacted))
+}
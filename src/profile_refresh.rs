@@ -0,0 +1,64 @@
+use serde_json::json;
+use std::time::Duration;
+
+use crate::preflight::listed_numbers;
+use crate::state::{now_millis, AppState};
+
+/// Contacts count as active while they have a message in the store this recent.
+pub const ACTIVE_WINDOW: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Most contacts refreshed per account and run, the most recent first, so a
+/// busy account doesn't run into Signal's rate limits.
+pub const MAX_PER_RUN: usize = 100;
+
+/// Every `--profile-refresh-secs`, has signal-cli fetch the profiles and keys
+/// of each account's active contacts again: `getUserStatus` looks them up
+/// with Signal, and `listContacts` with `detailed` refreshes their profiles
+/// (name, about, avatar). Contact listings and group member names then stay
+/// current without a manual sync. Only the leader refreshes.
+pub async fn run(state: AppState) {
+    let Some(interval) = state.profile_refresh else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(interval).await;
+        if !state.leadership.is_leader() {
+            continue;
+        }
+        let accounts = match state.rpc("listAccounts", json!({})).await {
+            Ok(accounts) => listed_numbers(&accounts),
+            Err(e) => {
+                tracing::warn!("Could not list accounts for the profile refresh: {e}");
+                continue;
+            }
+        };
+        let since = now_millis().saturating_sub(ACTIVE_WINDOW.as_millis() as u64);
+        for account in accounts {
+            let mut contacts = state.store.peers_since(&account, since);
+            contacts.truncate(MAX_PER_RUN);
+            if contacts.is_empty() {
+                continue;
+            }
+            let lookup = json!({ "account": account, "recipient": contacts });
+            let profiles = json!({ "account": account, "recipient": contacts, "detailed": true });
+            match state.rpc("getUserStatus", lookup).await {
+                Ok(_) => match state.rpc("listContacts", profiles).await {
+                    Ok(_) => {
+                        state.metrics.add_profiles_refreshed(contacts.len() as u64);
+                        state.group_cache.invalidate(&account);
+                        tracing::debug!(
+                            account,
+                            contacts = contacts.len(),
+                            "Refreshed contact profiles"
+                        );
+                    }
+                    Err(e) => tracing::warn!(account, "Could not refresh contact profiles: {e}"),
+                },
+                Err(e) => tracing::warn!(
+                    account,
+                    "Could not look up contacts for the profile refresh: {e}"
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::attachments;
+use crate::migrate;
+use crate::state::{now_millis, AppState};
+use crate::tenant::Tenant;
+
+pub const DAY_MS: u64 = 24 * 3600 * 1000;
+
+/// How often usage is written to `--usage-file` when it changed.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Format of `--usage-file`, recorded in it as `schema_version`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Daily limits for one API token (None = unlimited).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Quota {
+    pub messages: Option<u64>,
+    pub attachment_bytes: Option<u64>,
+}
+
+impl Quota {
+    /// Parse `id:messages[:bytes]` as given on the command line; an empty
+    /// limit means unlimited (e.g. `ci::50000000`).
+    pub fn parse(spec: &str) -> Result<(String, Self), String> {
+        let invalid = || format!("invalid quota {spec:?}, expected id:messages[:bytes]");
+        let mut parts = spec.split(':');
+        let id = parts
+            .next()
+            .filter(|id| !id.is_empty())
+            .ok_or_else(invalid)?;
+        let limit = |part: Option<&str>| -> Result<Option<u64>, String> {
+            match part {
+                None | Some("") => Ok(None),
+                Some(n) => n.parse().map(Some).map_err(|_| invalid()),
+            }
+        };
+        let quota = Quota {
+            messages: limit(parts.next())?,
+            attachment_bytes: limit(parts.next())?,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok((id.to_string(), quota))
+    }
+}
+
+/// One token's usage during a UTC day.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Days since the Unix epoch (UTC).
+    pub day: u64,
+    pub messages: u64,
+    pub attachment_bytes: u64,
+}
+
+/// What `--usage-file` holds.
+#[derive(Default, Serialize, Deserialize)]
+struct Saved {
+    schema_version: u32,
+    usage: HashMap<String, Usage>,
+}
+
+/// A send refused because it would exceed a daily quota.
+#[derive(Debug)]
+pub struct Exceeded {
+    pub message: String,
+    /// Seconds until the quota resets (midnight UTC).
+    pub retry_after: u64,
+}
+
+/// Answered with 429 and `Retry-After`.
+impl IntoResponse for Exceeded {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, self.retry_after.to_string())],
+            Json(json!({ "error": self.message })),
+        )
+            .into_response()
+    }
+}
+
+/// Per-token daily message and attachment-byte accounting. Usage is kept in
+/// memory and, with `--usage-file`, saved periodically and reloaded on start.
+#[derive(Default)]
+pub struct QuotaTracker {
+    pub quotas: HashMap<String, Quota>,
+    usage: Mutex<HashMap<String, Usage>>,
+    pub file: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+fn today() -> u64 {
+    now_millis() / DAY_MS
+}
+
+impl QuotaTracker {
+    /// Load usage saved in `file`, if any.
+    pub fn new(quotas: HashMap<String, Quota>, file: Option<PathBuf>) -> Result<Self, String> {
+        let saved: Saved = match &file {
+            Some(path) => match migrate::load(&migrate::USAGE, path)? {
+                Some(value) => serde_json::from_value(value)
+                    .map_err(|e| format!("usage file {}: {e}", path.display()))?,
+                None => Saved::default(),
+            },
+            None => Saved::default(),
+        };
+        Ok(Self {
+            quotas,
+            usage: Mutex::new(saved.usage),
+            file,
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// Count a send of `attachment_bytes` by `token`, unless it would go over
+    /// the token's quota for today.
+    pub fn record(&self, token: &str, attachment_bytes: u64) -> Result<(), Exceeded> {
+        let quota = self.quotas.get(token).copied().unwrap_or_default();
+        let day = today();
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(token.to_string()).or_default();
+        if entry.day != day {
+            *entry = Usage {
+                day,
+                ..Default::default()
+            };
+        }
+        let retry_after = ((day + 1) * DAY_MS)
+            .saturating_sub(now_millis())
+            .div_ceil(1000);
+        if let Some(limit) = quota.messages.filter(|limit| entry.messages + 1 > *limit) {
+            return Err(Exceeded {
+                message: format!("daily message quota of {limit} exceeded"),
+                retry_after,
+            });
+        }
+        if let Some(limit) = quota
+            .attachment_bytes
+            .filter(|limit| entry.attachment_bytes + attachment_bytes > *limit)
+        {
+            return Err(Exceeded {
+                message: format!("daily attachment quota of {limit} bytes exceeded"),
+                retry_after,
+            });
+        }
+        entry.messages += 1;
+        entry.attachment_bytes += attachment_bytes;
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Give back a send counted by [`record`](Self::record) that wasn't
+    /// attempted after all.
+    pub fn refund(&self, token: &str, attachment_bytes: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(entry) = usage.get_mut(token).filter(|u| u.day == today()) {
+            entry.messages = entry.messages.saturating_sub(1);
+            entry.attachment_bytes = entry.attachment_bytes.saturating_sub(attachment_bytes);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Today's usage for `token`.
+    pub fn usage(&self, token: &str) -> Usage {
+        let day = today();
+        let usage = self.usage.lock().unwrap();
+        match usage.get(token) {
+            Some(u) if u.day == day => *u,
+            _ => Usage {
+                day,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Write usage to the usage file if it changed since the last save.
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved = Saved {
+            schema_version: SCHEMA_VERSION,
+            usage: self.usage.lock().unwrap().clone(),
+        };
+        let data = serde_json::to_vec(&saved).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| {
+                self.dirty.store(true, Ordering::Relaxed);
+                format!("saving usage to {}: {e}", path.display())
+            })
+    }
+}
+
+/// Response extension marking a send answered without being attempted, such
+/// as a suppressed duplicate, whose charge is given back.
+#[derive(Clone, Copy, Debug)]
+pub struct NotAttempted;
+
+/// What a send admitted by [`admit_send`] was charged, kept only if the
+/// send is attempted.
+#[must_use]
+pub struct Charge {
+    keys: Vec<String>,
+    attachment_bytes: u64,
+}
+
+impl Charge {
+    /// Keep the charge if `response` is a send that was attempted: a
+    /// success not marked [`NotAttempted`]. Otherwise give it back.
+    pub fn settle(self, quotas: &QuotaTracker, response: &Response) {
+        if !response.status().is_success() || response.extensions().get::<NotAttempted>().is_some()
+        {
+            self.refund(quotas);
+        }
+    }
+
+    pub fn refund(self, quotas: &QuotaTracker) {
+        for key in &self.keys {
+            quotas.refund(key, self.attachment_bytes);
+        }
+    }
+}
+
+/// Charge a send of `body` to the daily quotas of the caller's tenant and
+/// API token. Refused when either would go over, and then neither is
+/// charged.
+pub fn admit_send(
+    state: &AppState,
+    token: Option<&str>,
+    tenant: Option<&Tenant>,
+    body: &Value,
+) -> Result<Charge, Exceeded> {
+    let attachment_bytes: u64 = body
+        .get("base64_attachments")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.as_str())
+        .filter_map(|a| attachments::decode(a).ok())
+        .map(|(_, data)| data.len() as u64)
+        .sum();
+    let tenant = tenant.map(|t| (t.quota_key(), format!("tenant {}", t.name)));
+    let token = token.map(|id| (id.to_string(), format!("token {id}")));
+    let mut charge = Charge {
+        keys: Vec::new(),
+        attachment_bytes,
+    };
+    for (key, whose) in tenant.into_iter().chain(token) {
+        if let Err(mut exceeded) = state.quotas.record(&key, attachment_bytes) {
+            tracing::warn!(quota = key, "Send refused: {}", exceeded.message);
+            charge.refund(&state.quotas);
+            exceeded.message = format!("{} for {whose}", exceeded.message);
+            return Err(exceeded);
+        }
+        charge.keys.push(key);
+    }
+    Ok(charge)
+}
+
+/// Periodically saves token usage to `--usage-file`.
+pub async fn run(state: AppState) {
+    if state.quotas.file.is_none() {
+        return;
+    }
+    loop {
+        tokio::time::sleep(SAVE_INTERVAL).await;
+        let quotas = state.quotas.clone();
+        match tokio::task::spawn_blocking(move || quotas.save()).await {
+            Ok(Err(e)) => tracing::warn!("{e}"),
+            Err(e) => tracing::warn!("Saving token usage failed: {e}"),
+            Ok(Ok(())) => {}
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::exposition::Exposition;
+use crate::state::AppState;
+
+/// Channel events are published to unless `--redis-channel` says otherwise.
+pub const DEFAULT_CHANNEL: &str = "signal-cli-api:events";
+
+/// Wait before reconnecting after Redis failed.
+#[cfg(feature = "redis")]
+pub const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Whether the server was built with the Redis bridge (the `redis` feature).
+pub const ENABLED: bool = cfg!(feature = "redis");
+
+/// Publishes every event on the broadcast channel (incoming envelopes, in
+/// the normalized form streams get, and system events) to a Redis channel,
+/// for other processes to subscribe to.
+pub struct RedisBridge {
+    pub url: String,
+    pub channel: String,
+    published: AtomicU64,
+    /// Events that couldn't be published, including those missed while
+    /// disconnected.
+    failed: AtomicU64,
+}
+
+impl RedisBridge {
+    pub fn new(url: String, channel: String) -> Self {
+        Self {
+            url,
+            channel,
+            published: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    /// Append bridge counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        exp.counter(
+            "signal_redis_published",
+            "Events published to Redis",
+            &[(&[], self.published.load(Ordering::Relaxed))],
+        );
+        exp.counter(
+            "signal_redis_failed",
+            "Events that couldn't be published to Redis",
+            &[(&[], self.failed.load(Ordering::Relaxed))],
+        );
+    }
+}
+
+/// Publishes events to Redis while this replica is the leader (see
+/// [`crate::leader`]), reconnecting after [`RECONNECT_DELAY`] when the
+/// connection fails. Events are not queued while disconnected.
+#[cfg(feature = "redis")]
+pub async fn run(state: AppState) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let Some(bridge) = state.redis.clone() else {
+        return;
+    };
+    let client = match redis::Client::open(bridge.url.as_str()) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(
+                url = crate::runtime_config::redact_url(&bridge.url),
+                "Redis bridge disabled: {e}"
+            );
+            return;
+        }
+    };
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        let mut conn = match client.get_multiplexed_async_connection().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                tracing::warn!("Could not connect to Redis: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                // What arrived meanwhile is lost.
+                let missed = drain(&mut rx);
+                bridge.failed.fetch_add(missed, Ordering::Relaxed);
+                continue;
+            }
+        };
+        tracing::info!(channel = bridge.channel, "Publishing events to Redis");
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("Redis bridge lagged, {n} events not published");
+                    bridge.failed.fetch_add(n, Ordering::Relaxed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if !state.leadership.is_leader() {
+                continue;
+            }
+            let payload: &str = event.for_client(false);
+            let published: redis::RedisResult<i64> = redis::cmd("PUBLISH")
+                .arg(&bridge.channel)
+                .arg(payload)
+                .query_async(&mut conn)
+                .await;
+            match published {
+                Ok(_) => bridge.published.fetch_add(1, Ordering::Relaxed),
+                Err(e) => {
+                    bridge.failed.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!("Publishing to Redis failed: {e}");
+                    break;
+                }
+            };
+        }
+    }
+}
+
+#[cfg(not(feature = "redis"))]
+pub async fn run(_state: AppState) {}
+
+/// Discard the events waiting in `rx`, returning how many there were.
+#[cfg(feature = "redis")]
+fn drain(rx: &mut tokio::sync::broadcast::Receiver<crate::event::Event>) -> u64 {
+    let mut missed = 0;
+    loop {
+        match rx.try_recv() {
+            Ok(_) => missed += 1,
+            Err(tokio::sync::broadcast::error::TryRecvError::Lagged(n)) => missed += n,
+            Err(_) => return missed,
+        }
+    }
+}
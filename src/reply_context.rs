@@ -0,0 +1,142 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::group_id::GroupId;
+use crate::plugin::Plugin;
+use crate::store::envelope_source;
+
+/// How long a sent message's metadata is attached to replies.
+pub const CONTEXT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Clone, Debug)]
+struct Context {
+    timestamp: u64,
+    metadata: Value,
+    at: Instant,
+}
+
+impl Context {
+    fn to_json(&self) -> Value {
+        json!({ "timestamp": self.timestamp, "metadata": self.metadata })
+    }
+}
+
+#[derive(Default)]
+struct Contexts {
+    /// By (account, sent timestamp), for replies quoting the message.
+    by_timestamp: HashMap<(String, u64), Context>,
+    /// The latest per (account, recipient number or group ID), for replies
+    /// that don't quote it.
+    latest: HashMap<(String, String), Context>,
+}
+
+/// Metadata sent along with messages (Signal has no buttons, so bots send a
+/// menu as text and keep the options here), attached to the replies as
+/// `dataMessage.replyContext`. Runs as a [`Plugin`].
+#[derive(Default)]
+pub struct ReplyContexts {
+    contexts: Mutex<Contexts>,
+    /// Set once any metadata is held, so the hook costs nothing until then.
+    used: AtomicBool,
+}
+
+/// Remove the `metadata` of a send payload, which is kept by the API and
+/// never passed to signal-cli.
+pub fn take_metadata(params: &mut Value) -> Option<Value> {
+    params
+        .as_object_mut()
+        .and_then(|o| o.remove("metadata"))
+        .filter(|m| !m.is_null())
+}
+
+impl ReplyContexts {
+    /// Keep `metadata` for a message `account` sent at `timestamp` with the
+    /// given send params (`recipients`/`recipient` or `group-id`).
+    pub fn record(&self, account: &str, params: &Value, timestamp: u64, metadata: Value) {
+        let peers: Vec<String> = match params
+            .get("group-id")
+            .or_else(|| params.get("groupId"))
+            .and_then(|g| g.as_str())
+        {
+            Some(group) => vec![GroupId::from(group).to_string()],
+            None => match params.get("recipients").or_else(|| params.get("recipient")) {
+                Some(Value::Array(list)) => list
+                    .iter()
+                    .filter_map(|r| r.as_str().map(String::from))
+                    .collect(),
+                Some(Value::String(r)) => vec![r.clone()],
+                _ => Vec::new(),
+            },
+        };
+        let context = Context {
+            timestamp,
+            metadata,
+            at: Instant::now(),
+        };
+        let mut contexts = self.contexts.lock().unwrap();
+        contexts
+            .by_timestamp
+            .retain(|_, c| c.at.elapsed() < CONTEXT_TTL);
+        contexts.latest.retain(|_, c| c.at.elapsed() < CONTEXT_TTL);
+        contexts
+            .by_timestamp
+            .insert((account.to_string(), timestamp), context.clone());
+        for peer in peers {
+            contexts
+                .latest
+                .insert((account.to_string(), peer), context.clone());
+        }
+        self.used.store(true, Ordering::Relaxed);
+    }
+
+    /// The context of a reply: the quoted message's, or else the latest sent
+    /// to the conversation.
+    fn lookup(&self, account: &str, envelope: &Value) -> Option<Value> {
+        let data = envelope.get("dataMessage")?;
+        let contexts = self.contexts.lock().unwrap();
+        let fresh = |c: &&Context| c.at.elapsed() < CONTEXT_TTL;
+        let quoted = data
+            .get("quote")
+            .and_then(|q| q.get("id"))
+            .and_then(|id| id.as_u64());
+        if let Some(context) = quoted
+            .and_then(|ts| contexts.by_timestamp.get(&(account.to_string(), ts)))
+            .filter(fresh)
+        {
+            return Some(context.to_json());
+        }
+        let peer = match data
+            .get("groupInfo")
+            .and_then(|g| g.get("groupId"))
+            .and_then(|g| g.as_str())
+        {
+            Some(group) => GroupId::from(group).to_string(),
+            None => envelope_source(envelope).to_string(),
+        };
+        contexts
+            .latest
+            .get(&(account.to_string(), peer))
+            .filter(fresh)
+            .map(Context::to_json)
+    }
+}
+
+impl Plugin for ReplyContexts {
+    fn name(&self) -> &str {
+        "reply-context"
+    }
+
+    fn active(&self) -> bool {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn on_incoming_envelope(&self, account: Option<&str>, envelope: &mut Value) -> bool {
+        if let Some(context) = self.lookup(account.unwrap_or_default(), envelope) {
+            envelope["dataMessage"]["replyContext"] = context;
+        }
+        true
+    }
+}
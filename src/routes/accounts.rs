@@ -1,23 +1,51 @@
-use axum::extract::{Path, State};
-use axum::response::Response;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post, put};
-use axum::{Json, Router};
+use axum::{Extension, Json, Router};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_no_content};
+use super::helpers::{rpc_no_content, rpc_ok, Account, JsonBody};
+use crate::captcha;
+use crate::case::DataKeys;
+use crate::change_number::{self, Stage};
+use crate::state::{now_millis, rpc_error_status, AppState};
+use crate::sync;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/accounts", get(list_accounts))
         .route("/v1/register/{number}", post(register))
+        .route(
+            "/v1/register/{number}/captcha",
+            get(stored_captcha).post(store_captcha),
+        )
         .route("/v1/register/{number}/verify/{token}", post(verify))
         .route("/v1/unregister/{number}", post(unregister))
         .route(
             "/v1/accounts/{number}/rate-limit-challenge",
             post(rate_limit_challenge),
         )
+        .route("/v1/accounts/{number}/health", get(account_health))
+        .route(
+            "/v1/accounts/{number}/sync",
+            get(sync_status).post(request_sync),
+        )
+        .route(
+            "/v1/accounts/{number}/change-number",
+            get(change_number_status)
+                .post(start_change_number)
+                .delete(cancel_change_number),
+        )
+        .route(
+            "/v1/accounts/{number}/change-number/verify",
+            post(verify_change_number),
+        )
+        .route(
+            "/v1/accounts/{number}/change-number/finalize",
+            post(finalize_change_number),
+        )
         .route("/v1/accounts/{number}/settings", put(update_settings))
         .route(
             "/v1/accounts/{number}/pin",
@@ -25,12 +53,239 @@ pub fn routes() -> Router<AppState> {
         )
         .route(
             "/v1/accounts/{number}/username",
-            post(set_username).delete(remove_username),
+            get(get_username).post(set_username).delete(remove_username),
         )
 }
 
-async fn list_accounts(State(st): State<AppState>) -> Response {
-    rpc_ok(&st, "listAccounts", json!({})).await
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    aliases: bool,
+}
+
+/// Lists accounts as E164 strings; with `?aliases=true`, as
+/// `{"number", "aliases"}` objects including every configured alias.
+async fn list_accounts(State(st): State<AppState>, Query(q): Query<ListQuery>) -> Response {
+    if !q.aliases {
+        return rpc_ok(&st, "listAccounts", json!({})).await;
+    }
+    let mut numbers = match listed_numbers(&st).await {
+        Ok(numbers) => numbers,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    for number in st.aliases.values() {
+        if !numbers.contains(number) {
+            numbers.push(number.clone());
+        }
+    }
+    let accounts: Vec<_> = numbers
+        .into_iter()
+        .map(|number| {
+            let aliases: Vec<&String> = st
+                .aliases
+                .iter()
+                .filter(|(_, n)| **n == number)
+                .map(|(a, _)| a)
+                .collect();
+            json!({ "number": number, "aliases": aliases })
+        })
+        .collect();
+    Json(accounts).into_response()
+}
+
+/// Numbers of the accounts signal-cli lists (as strings or `{number}` objects).
+async fn listed_numbers(st: &AppState) -> Result<Vec<String>, String> {
+    let listed = st.rpc("listAccounts", json!({})).await?;
+    Ok(listed
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|a| {
+            a.as_str()
+                .or_else(|| a.get("number").and_then(|n| n.as_str()))
+        })
+        .map(String::from)
+        .collect())
+}
+
+#[derive(Deserialize)]
+struct HealthQuery {
+    /// Report `degraded` when nothing was sent or received for this many seconds.
+    max_idle: Option<u64>,
+}
+
+/// One named check of an account health report.
+fn check(name: &str, ok: bool, detail: impl Into<String>) -> serde_json::Value {
+    json!({ "name": name, "ok": ok, "detail": detail.into() })
+}
+
+/// GET /v1/accounts/{number}/health — cheap checks for monitoring a number:
+/// the daemon answers, the account is registered with it, and (with
+/// `?max_idle=`) it sent or received a message recently. 200 when healthy or
+/// degraded, 503 when unhealthy.
+async fn account_health(
+    Account(number): Account,
+    State(st): State<AppState>,
+    Query(q): Query<HealthQuery>,
+) -> Response {
+    let mut checks = Vec::new();
+    let listed = st.rpc("listAccounts", json!({})).await;
+    checks.push(match &listed {
+        Ok(_) => check("daemon", true, "signal-cli answered listAccounts"),
+        Err(e) => check("daemon", false, format!("listAccounts failed: {e}")),
+    });
+    let entry = listed.ok().and_then(|accounts| {
+        accounts
+            .as_array()?
+            .iter()
+            .find(|a| {
+                a.as_str()
+                    .or_else(|| a.get("number").and_then(|n| n.as_str()))
+                    == Some(number.as_str())
+            })
+            .cloned()
+    });
+    // signal-cli lists only registered accounts, unless an entry says otherwise.
+    let registered = entry.as_ref().map(|e| {
+        e.get("registered")
+            .and_then(|r| r.as_bool())
+            .unwrap_or(true)
+    });
+    checks.push(match registered {
+        Some(true) => check("registered", true, "account is registered with the daemon"),
+        Some(false) => check(
+            "registered",
+            false,
+            "account is known to the daemon but not registered",
+        ),
+        None => check("registered", false, "account not found in listAccounts"),
+    });
+
+    let (last_sent, last_received) = st.store.last_activity(&number);
+    let mut degraded = false;
+    if let Some(max_idle) = q.max_idle {
+        let last = last_sent.max(last_received);
+        let idle_ms = last.map(|ts| now_millis().saturating_sub(ts));
+        let active = idle_ms.is_some_and(|idle| idle <= max_idle * 1000);
+        degraded = !active;
+        checks.push(match idle_ms {
+            Some(idle) => check(
+                "activity",
+                active,
+                format!("last message {}s ago", idle / 1000),
+            ),
+            None => check("activity", false, "no messages since startup"),
+        });
+    }
+
+    let healthy = checks
+        .iter()
+        .filter(|c| c["name"] != "activity")
+        .all(|c| c["ok"] == true);
+    let (status, code) = match (healthy, degraded) {
+        (false, _) => ("unhealthy", StatusCode::SERVICE_UNAVAILABLE),
+        (true, true) => ("degraded", StatusCode::OK),
+        (true, false) => ("healthy", StatusCode::OK),
+    };
+    let report = json!({
+        "account": number,
+        "status": status,
+        "registered": registered == Some(true),
+        "last_sent": last_sent,
+        "last_received": last_received,
+        "checks": checks,
+    });
+    (code, Json(report)).into_response()
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    /// Seconds after which synced data counts as stale (default: a day).
+    stale_after: Option<u64>,
+    /// POST only: request a sync only when the data is stale.
+    #[serde(default)]
+    if_stale: bool,
+}
+
+impl SyncQuery {
+    fn stale_after(&self) -> std::time::Duration {
+        self.stale_after
+            .map_or(sync::DEFAULT_STALE_AFTER, std::time::Duration::from_secs)
+    }
+}
+
+/// The sync state of an account as reported to clients. The keys of
+/// `received` are signal-cli's sync type names.
+fn sync_report(
+    st: &AppState,
+    number: &str,
+    q: &SyncQuery,
+) -> (Extension<DataKeys>, Json<serde_json::Value>) {
+    let state = st.syncs.get(number);
+    let stale = state.is_stale(q.stale_after(), now_millis());
+    let mut report = json!(state);
+    report["account"] = json!(number);
+    report["stale"] = json!(stale);
+    (Extension(DataKeys(&["/received"])), Json(report))
+}
+
+/// GET /v1/accounts/{number}/sync — when sync data was last requested from
+/// the primary device and last arrived, for accounts that are linked devices.
+async fn sync_status(
+    Account(number): Account,
+    State(st): State<AppState>,
+    Query(q): Query<SyncQuery>,
+) -> Response {
+    sync_report(&st, &number, &q).into_response()
+}
+
+#[derive(Deserialize)]
+struct SyncBody {
+    /// What to request (default: everything).
+    #[serde(default)]
+    types: Option<Vec<sync::SyncKind>>,
+}
+
+/// POST /v1/accounts/{number}/sync — ask the primary device to sync the
+/// given `types` again; `202` once requested. The body is optional. With
+/// `?if_stale=true`, fresh data is left alone and the state is returned
+/// with `200`: with `types`, only those types are checked and the stale
+/// ones requested.
+async fn request_sync(
+    Account(number): Account,
+    State(st): State<AppState>,
+    Query(q): Query<SyncQuery>,
+    body: Option<JsonBody<SyncBody>>,
+) -> Response {
+    let types = body.and_then(|JsonBody(body)| body.types);
+    let mut kinds = types
+        .clone()
+        .unwrap_or_else(|| sync::SyncKind::ALL.to_vec());
+    kinds.sort();
+    kinds.dedup();
+    if kinds.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "types must not be empty" })),
+        )
+            .into_response();
+    }
+    if q.if_stale {
+        let state = st.syncs.get(&number);
+        let now = now_millis();
+        if types.is_some() {
+            kinds = state.stale_kinds(&kinds, q.stale_after(), now);
+        } else if !state.is_stale(q.stale_after(), now) {
+            kinds.clear();
+        }
+        if kinds.is_empty() {
+            return sync_report(&st, &number, &q).into_response();
+        }
+    }
+    if let Err(e) = sync::request(&st, &number, &kinds).await {
+        return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response();
+    }
+    (StatusCode::ACCEPTED, sync_report(&st, &number, &q)).into_response()
 }
 
 #[derive(Deserialize)]
@@ -42,12 +297,17 @@ struct RegisterBody {
 }
 
 async fn register(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<RegisterBody>,
 ) -> Response {
     let mut params = json!({ "account": number });
-    if let Some(captcha) = body.captcha {
+    // A captcha in the body wins; otherwise one stored via /captcha is used.
+    let captcha = body
+        .captcha
+        .map(|c| captcha::normalize(&c))
+        .or_else(|| st.captchas.take(&number).map(|c| c.token));
+    if let Some(captcha) = captcha {
         params["captcha"] = json!(captcha);
     }
     if let Some(voice) = body.voice {
@@ -56,14 +316,54 @@ async fn register(
     rpc_no_content(&st, "register", params).await
 }
 
+#[derive(Deserialize)]
+struct CaptchaBody {
+    /// The `signalcaptcha://...` link copied from the captcha page.
+    captcha: String,
+}
+
+/// POST /v1/register/{number}/captcha — check a pasted captcha link and keep
+/// its token for the account's next register call (for two minutes).
+async fn store_captcha(
+    Account(number): Account,
+    State(st): State<AppState>,
+    JsonBody(body): JsonBody<CaptchaBody>,
+) -> Response {
+    match captcha::parse(&body.captcha) {
+        Ok(captcha) => {
+            st.captchas.put(&number, captcha.clone());
+            (StatusCode::CREATED, Json(captcha)).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// GET /v1/register/{number}/captcha — the stored captcha, if still valid.
+async fn stored_captcha(Account(number): Account, State(st): State<AppState>) -> Response {
+    match st.captchas.get(&number) {
+        Some(captcha) => Json(captcha).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "no captcha stored" })),
+        )
+            .into_response(),
+    }
+}
+
 async fn verify(
-    Path((number, token)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, token)): Path<(String, String)>,
     State(st): State<AppState>,
 ) -> Response {
-    rpc_no_content(&st, "verify", json!({ "account": number, "verificationCode": token })).await
+    rpc_no_content(
+        &st,
+        "verify",
+        json!({ "account": number, "verificationCode": token }),
+    )
+    .await
 }
 
-async fn unregister(Path(number): Path<String>, State(st): State<AppState>) -> Response {
+async fn unregister(Account(number): Account, State(st): State<AppState>) -> Response {
     rpc_no_content(&st, "unregister", json!({ "account": number })).await
 }
 
@@ -74,15 +374,20 @@ struct RateLimitBody {
 }
 
 async fn rate_limit_challenge(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<RateLimitBody>,
 ) -> Response {
-    rpc_no_content(&st, "submitRateLimitChallenge", json!({
-        "account": number,
-        "challenge": body.challenge,
-        "captcha": body.captcha,
-    })).await
+    rpc_no_content(
+        &st,
+        "submitRateLimitChallenge",
+        json!({
+            "account": number,
+            "challenge": body.challenge,
+            "captcha": captcha::normalize(&body.captcha),
+        }),
+    )
+    .await
 }
 
 #[derive(Deserialize)]
@@ -92,7 +397,7 @@ struct SettingsBody {
 }
 
 async fn update_settings(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<SettingsBody>,
 ) -> Response {
@@ -109,14 +414,14 @@ struct PinBody {
 }
 
 async fn set_pin(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<PinBody>,
 ) -> Response {
     rpc_no_content(&st, "setPin", json!({ "account": number, "pin": body.pin })).await
 }
 
-async fn remove_pin(Path(number): Path<String>, State(st): State<AppState>) -> Response {
+async fn remove_pin(Account(number): Account, State(st): State<AppState>) -> Response {
     rpc_no_content(&st, "removePin", json!({ "account": number })).await
 }
 
@@ -125,14 +430,262 @@ struct UsernameBody {
     username: String,
 }
 
+/// GET /v1/accounts/{number}/username — `{"username", "username_link"}`,
+/// 404 when none is set. The username is the account's own contact entry's;
+/// the link is only known for a username set through the API since startup.
+async fn get_username(Account(number): Account, State(st): State<AppState>) -> Response {
+    let params = json!({ "account": number, "recipient": [number] });
+    let contacts = match st.rpc("listContacts", params).await {
+        Ok(contacts) => contacts,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let own = contacts
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["number"] == number.as_str());
+    let remembered = st.usernames.read().await.get(&number).cloned();
+    let username = own
+        .and_then(|c| c["username"].as_str())
+        .or_else(|| remembered.as_ref().and_then(|r| r["username"].as_str()));
+    let Some(username) = username else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "no username set" })),
+        )
+            .into_response();
+    };
+    let link = remembered
+        .as_ref()
+        .filter(|r| r["username"] == username)
+        .map(|r| r["username_link"].clone());
+    Json(json!({ "username": username, "username_link": link })).into_response()
+}
+
+/// POST /v1/accounts/{number}/username — set the username and answer with
+/// the one signal-cli settled on (it adds the discriminator when asked for a
+/// bare nickname) and its `username_link` for clients to show as a QR code.
 async fn set_username(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<UsernameBody>,
 ) -> Response {
-    rpc_no_content(&st, "setUsername", json!({ "account": number, "username": body.username })).await
+    let result = match st
+        .rpc(
+            "setUsername",
+            json!({ "account": number, "username": body.username }),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let username = result
+        .get("username")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&body.username);
+    let link = result
+        .get("usernameLink")
+        .filter(|v| v.is_string())
+        .cloned();
+    let set = json!({ "username": username, "username_link": link });
+    st.usernames.write().await.insert(number, set.clone());
+    Json(set).into_response()
 }
 
-async fn remove_username(Path(number): Path<String>, State(st): State<AppState>) -> Response {
-    rpc_no_content(&st, "removeUsername", json!({ "account": number })).await
+async fn remove_username(Account(number): Account, State(st): State<AppState>) -> Response {
+    let response = rpc_no_content(&st, "removeUsername", json!({ "account": number })).await;
+    if response.status().is_success() {
+        st.usernames.write().await.remove(&number);
+    }
+    response
+}
+
+// ---- Change number ---------------------------------------------------------
+
+fn change_error(status: StatusCode, e: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": e.into() }))).into_response()
+}
+
+/// Publish a change-number progress event on the system event stream.
+fn change_event(st: &AppState, account: &str, new_number: &str, stage: &str) {
+    st.publish_system(
+        "change_number",
+        json!({ "account": account, "new_number": new_number, "stage": stage }),
+    );
+}
+
+/// GET /v1/accounts/{number}/change-number — the change in progress, if any.
+async fn change_number_status(Account(number): Account, State(st): State<AppState>) -> Response {
+    match st.change_numbers.get(&number) {
+        Some(change) => Json(change).into_response(),
+        None => change_error(StatusCode::NOT_FOUND, "no number change in progress"),
+    }
+}
+
+#[derive(Deserialize)]
+struct StartChangeBody {
+    /// The number to move the account to, E.164.
+    number: String,
+    voice: Option<bool>,
+    captcha: Option<String>,
+}
+
+/// POST /v1/accounts/{number}/change-number — send a verification code to
+/// the new number. Refused when the new number is malformed, is this
+/// account, or is already registered with signal-cli, and while a verified
+/// change awaits finalization. Starting again resends the code.
+async fn start_change_number(
+    Account(number): Account,
+    State(st): State<AppState>,
+    Json(body): Json<StartChangeBody>,
+) -> Response {
+    let new_number = body.number.trim();
+    if !change_number::is_e164(new_number) {
+        return change_error(
+            StatusCode::BAD_REQUEST,
+            "number must be in E.164 form, e.g. +15551234567",
+        );
+    }
+    if new_number == number {
+        return change_error(
+            StatusCode::BAD_REQUEST,
+            "number is already this account's number",
+        );
+    }
+    if st
+        .change_numbers
+        .get(&number)
+        .is_some_and(|c| c.stage == Stage::Verified)
+    {
+        return change_error(StatusCode::CONFLICT, "a verified change awaits finalize");
+    }
+    match listed_numbers(&st).await {
+        Ok(numbers) if numbers.iter().any(|n| n == new_number) => {
+            return change_error(
+                StatusCode::CONFLICT,
+                "number is already registered with signal-cli",
+            );
+        }
+        Ok(numbers) if !numbers.contains(&number) => {
+            return change_error(
+                StatusCode::NOT_FOUND,
+                "account is not registered with signal-cli",
+            );
+        }
+        Ok(_) => {}
+        Err(e) => return change_error(rpc_error_status(&e), e),
+    }
+    let mut params = json!({ "account": number, "number": new_number });
+    if let Some(voice) = body.voice {
+        params["voice"] = json!(voice);
+    }
+    if let Some(captcha) = body.captcha {
+        params["captcha"] = json!(captcha);
+    }
+    match st.rpc("startChangeNumber", params).await {
+        Ok(_) => {
+            let change = st.change_numbers.start(&number, new_number);
+            tracing::info!(account = %number, new_number, "Number change started");
+            change_event(&st, &number, new_number, "started");
+            Json(change).into_response()
+        }
+        Err(e) => {
+            change_event(&st, &number, new_number, "failed");
+            change_error(rpc_error_status(&e), e)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyChangeBody {
+    code: String,
+    /// Registration lock PIN, if the new number has one.
+    pin: Option<String>,
+}
+
+/// POST /v1/accounts/{number}/change-number/verify — submit the code sent
+/// to the new number; signal-cli then moves the account over.
+async fn verify_change_number(
+    Account(number): Account,
+    State(st): State<AppState>,
+    Json(body): Json<VerifyChangeBody>,
+) -> Response {
+    let Some(change) = st.change_numbers.get(&number) else {
+        return change_error(StatusCode::NOT_FOUND, "no number change in progress");
+    };
+    if change.stage != Stage::Started {
+        return change_error(
+            StatusCode::CONFLICT,
+            "change is already verified; finalize it",
+        );
+    }
+    let mut params =
+        json!({ "account": number, "number": change.new_number, "verificationCode": body.code });
+    if let Some(pin) = body.pin {
+        params["pin"] = json!(pin);
+    }
+    match st.rpc("finishChangeNumber", params).await {
+        Ok(_) => {
+            let change = st.change_numbers.verified(&number).unwrap_or(change);
+            tracing::info!(account = %number, new_number = change.new_number, "Number change verified");
+            change_event(&st, &number, &change.new_number, "verified");
+            Json(change).into_response()
+        }
+        Err(e) => {
+            change_event(&st, &number, &change.new_number, "failed");
+            change_error(rpc_error_status(&e), e)
+        }
+    }
+}
+
+/// POST /v1/accounts/{number}/change-number/finalize — confirm signal-cli
+/// lists the new number and carry this API's per-account state over to it.
+/// Aliases come from the command line, so ones still naming the old number
+/// are reported for the operator to update.
+async fn finalize_change_number(Account(number): Account, State(st): State<AppState>) -> Response {
+    let Some(change) = st.change_numbers.get(&number) else {
+        return change_error(StatusCode::NOT_FOUND, "no number change in progress");
+    };
+    if change.stage != Stage::Verified {
+        return change_error(StatusCode::CONFLICT, "change is not verified yet");
+    }
+    match listed_numbers(&st).await {
+        Ok(numbers) if numbers.contains(&change.new_number) => {}
+        Ok(_) => {
+            return change_error(
+                StatusCode::CONFLICT,
+                "signal-cli does not list the new number yet",
+            )
+        }
+        Err(e) => return change_error(rpc_error_status(&e), e),
+    }
+    {
+        let mut subscriptions = st.receive_subscriptions.write().await;
+        if let Some(id) = subscriptions.remove(&number) {
+            subscriptions.insert(change.new_number.clone(), id);
+        }
+    }
+    st.group_cache.invalidate(&number);
+    st.change_numbers.remove(&number);
+    let stale_aliases: Vec<&String> = st
+        .aliases
+        .iter()
+        .filter(|(_, n)| **n == number)
+        .map(|(a, _)| a)
+        .collect();
+    tracing::info!(old_number = %number, new_number = change.new_number, "Number change finalized");
+    change_event(&st, &number, &change.new_number, "finalized");
+    Json(json!({ "old_number": number, "new_number": change.new_number, "stale_aliases": stale_aliases })).into_response()
+}
+
+/// DELETE /v1/accounts/{number}/change-number — forget an unfinished change.
+async fn cancel_change_number(Account(number): Account, State(st): State<AppState>) -> Response {
+    match st.change_numbers.remove(&number) {
+        Some(change) => {
+            change_event(&st, &number, &change.new_number, "cancelled");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => change_error(StatusCode::NOT_FOUND, "no number change in progress"),
+    }
 }
@@ -0,0 +1,301 @@
+use std::convert::Infallible;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use tracing::Level;
+
+use super::helpers::{Account, JsonBody};
+use crate::case::DataKeys;
+use crate::compression;
+use crate::jsonrpc;
+use crate::logstream::MAX_LEVEL;
+use crate::maintenance::{Maintenance, DEFAULT_RETRY_AFTER};
+use crate::runtime_config;
+use crate::state::{now_millis, rpc_error_status, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/admin/logs/stream", get(stream_logs))
+        .route(
+            "/v1/admin/receive-subscriptions",
+            get(list_receive_subscriptions),
+        )
+        .route(
+            "/v1/admin/subscribe-receive/{number}",
+            post(subscribe_receive),
+        )
+        .route(
+            "/v1/admin/unsubscribe-receive/{number}",
+            post(unsubscribe_receive),
+        )
+        .route(
+            "/v1/admin/maintenance",
+            get(get_maintenance).post(set_maintenance),
+        )
+        .route("/v1/admin/tasks", get(list_tasks))
+        .route("/v1/admin/rpc/pending", get(list_pending_rpcs))
+        .route(
+            "/v1/admin/rpc/pending/{id}",
+            axum::routing::delete(cancel_pending_rpc),
+        )
+        .route("/v1/admin/config", get(get_config))
+        .route("/v1/admin/canary", post(run_canary))
+}
+
+#[derive(Deserialize)]
+struct LogQuery {
+    /// Most verbose level to stream (default "info").
+    level: Option<String>,
+    /// Only events from this target or its submodules (e.g. "signal_cli").
+    target: Option<String>,
+}
+
+/// GET /v1/admin/logs/stream?level=debug&target=... — tail the API's (and the
+/// managed daemon's) log output as Server-Sent Events.
+async fn stream_logs(
+    State(st): State<AppState>,
+    Query(q): Query<LogQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let level = match q.level.as_deref().map(str::parse::<Level>) {
+        None => Level::INFO,
+        Some(Ok(level)) if level <= MAX_LEVEL => level,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "level must be one of: error, warn, info, debug" })),
+            )
+                .into_response();
+        }
+    };
+    let target = q.target.filter(|t| !t.is_empty());
+    let stream = BroadcastStream::new(st.log_tx.subscribe()).filter_map(move |line| {
+        let line = line.ok()?;
+        let in_target = target.as_deref().is_none_or(|t| {
+            line.target == t
+                || line
+                    .target
+                    .strip_prefix(t)
+                    .is_some_and(|rest| rest.starts_with("::"))
+        });
+        if line.level() > level || !in_target {
+            return None;
+        }
+        Some(Ok::<_, Infallible>(
+            Event::default().event("log").json_data(&line).ok()?,
+        ))
+    });
+    compression::negotiate(&st, &headers, Sse::new(stream).into_response())
+}
+
+/// GET /v1/admin/receive-subscriptions — accounts subscribed through this API.
+async fn list_receive_subscriptions(State(st): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!(*st.receive_subscriptions.read().await))
+}
+
+/// POST /v1/admin/subscribe-receive/{number} — start delivering the account's
+/// incoming messages. Idempotent: an existing subscription is returned as-is.
+async fn subscribe_receive(State(st): State<AppState>, Account(number): Account) -> Response {
+    // Held across the RPC so concurrent calls can't open two subscriptions.
+    let mut subscriptions = st.receive_subscriptions.write().await;
+    if let Some(id) = subscriptions.get(&number) {
+        return Json(json!({ "account": number, "subscription": id })).into_response();
+    }
+    match st
+        .rpc("subscribeReceive", json!({ "account": number }))
+        .await
+    {
+        Ok(result) => match result.as_u64() {
+            Some(id) => {
+                tracing::info!(account = %number, subscription = id, "Receive subscription opened");
+                subscriptions.insert(number.clone(), id);
+                Json(json!({ "account": number, "subscription": id })).into_response()
+            }
+            None => (
+                StatusCode::BAD_GATEWAY,
+                Json(json!({ "error": format!("unexpected subscribeReceive result: {result}") })),
+            )
+                .into_response(),
+        },
+        Err(e) => (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+/// POST /v1/admin/unsubscribe-receive/{number} — pause the account's incoming
+/// messages. Only subscriptions opened via subscribe-receive can be closed.
+async fn unsubscribe_receive(State(st): State<AppState>, Account(number): Account) -> Response {
+    let mut subscriptions = st.receive_subscriptions.write().await;
+    let Some(&id) = subscriptions.get(&number) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "account has no receive subscription" })),
+        )
+            .into_response();
+    };
+    match st
+        .rpc(
+            "unsubscribeReceive",
+            json!({ "account": number, "subscription": id }),
+        )
+        .await
+    {
+        Ok(_) => {
+            tracing::info!(account = %number, subscription = id, "Receive subscription closed");
+            subscriptions.remove(&number);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    }
+}
+
+fn maintenance_json(m: &Option<Maintenance>) -> serde_json::Value {
+    match m {
+        Some(m) => {
+            json!({ "enabled": true, "reason": m.reason, "retry_after": m.retry_after, "since": m.since })
+        }
+        None => json!({ "enabled": false }),
+    }
+}
+
+/// GET /v1/admin/tasks — the server's background tasks: state, restarts
+/// and the last error of each.
+async fn list_tasks(State(st): State<AppState>) -> Json<serde_json::Value> {
+    Json(json!(st.tasks.list()))
+}
+
+/// GET /v1/admin/rpc/pending — RPC calls waiting for signal-cli, oldest
+/// first, with their age in milliseconds.
+async fn list_pending_rpcs(State(st): State<AppState>) -> Json<serde_json::Value> {
+    let mut calls: Vec<(u64, String, u64)> = st
+        .pending
+        .iter()
+        .map(|call| {
+            (
+                *call.key(),
+                call.method.clone(),
+                call.started.elapsed().as_millis() as u64,
+            )
+        })
+        .collect();
+    calls.sort_unstable_by_key(|(id, ..)| *id);
+    let calls: Vec<_> = calls
+        .into_iter()
+        .map(|(id, method, age_ms)| json!({ "id": id, "method": method, "age_ms": age_ms }))
+        .collect();
+    Json(json!(calls))
+}
+
+/// DELETE /v1/admin/rpc/pending/{id} — stop waiting for a stuck call. The
+/// HTTP request waiting on it gets 499; a response signal-cli sends later is
+/// ignored.
+async fn cancel_pending_rpc(State(st): State<AppState>, Path(id): Path<u64>) -> Response {
+    let Some(method) = jsonrpc::cancel(&st.pending, id) else {
+        let error = format!("no pending RPC call {id}");
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+    };
+    tracing::warn!(rpc_id = id, rpc_method = method, "Pending RPC cancelled");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// GET /v1/admin/config — the configuration in effect (flags, environment
+/// and defaults merged), with secrets shown as fingerprints.
+async fn get_config(State(st): State<AppState>) -> Response {
+    let config = runtime_config::effective(&st).await;
+    (
+        Extension(DataKeys(&["/messages/aliases", "/messages/topics"])),
+        Json(config),
+    )
+        .into_response()
+}
+
+/// GET /v1/admin/maintenance — whether the API is in maintenance.
+async fn get_maintenance(State(st): State<AppState>) -> Json<serde_json::Value> {
+    Json(maintenance_json(&*st.maintenance.read().await))
+}
+
+#[derive(Deserialize)]
+struct MaintenanceRequest {
+    enabled: bool,
+    reason: Option<String>,
+    /// Seconds to send in Retry-After (default 60).
+    retry_after: Option<u64>,
+}
+
+/// POST /v1/admin/maintenance — enter or leave maintenance. While enabled,
+/// reads keep working, mutating calls get 503 with Retry-After, incoming
+/// messages keep being recorded, and the outbox holds queued messages.
+async fn set_maintenance(
+    State(st): State<AppState>,
+    JsonBody(body): JsonBody<MaintenanceRequest>,
+) -> Json<serde_json::Value> {
+    let mut maintenance = st.maintenance.write().await;
+    if body.enabled {
+        let since = maintenance.as_ref().map_or_else(now_millis, |m| m.since);
+        let m = Maintenance {
+            reason: body.reason.filter(|r| !r.is_empty()),
+            retry_after: body.retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+            since,
+        };
+        tracing::warn!(
+            reason = m.reason,
+            retry_after = m.retry_after,
+            "Entering maintenance"
+        );
+        *maintenance = Some(m);
+    } else if let Some(m) = maintenance.take() {
+        tracing::info!(
+            duration_ms = now_millis().saturating_sub(m.since),
+            "Leaving maintenance"
+        );
+    }
+    Json(maintenance_json(&maintenance))
+}
+
+#[derive(Deserialize)]
+struct CanaryQuery {
+    /// Seconds to wait for the delivery receipt (default `--canary-timeout`).
+    timeout: Option<u64>,
+}
+
+/// POST /v1/admin/canary — send a message to the canary recipient and wait
+/// for its delivery receipt: 200 with `"pass": true` and the latency when it
+/// arrives, 503 with `"pass": false` and the reason when it doesn't.
+async fn run_canary(State(st): State<AppState>, Query(q): Query<CanaryQuery>) -> Response {
+    let Some(canary) = st.canary.clone() else {
+        let error = "no canary configured (--canary-account and --canary-recipient)";
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response();
+    };
+    let timeout = match q.timeout {
+        None => canary.timeout,
+        Some(secs @ 1..=crate::canary::MAX_TIMEOUT_SECS) => std::time::Duration::from_secs(secs),
+        Some(_) => {
+            let error = format!(
+                "timeout must be between 1 and {} seconds",
+                crate::canary::MAX_TIMEOUT_SECS
+            );
+            return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+        }
+    };
+    match canary.probe(&st, timeout).await {
+        Ok(pass) => Json(json!({
+            "pass": true,
+            "recipient": canary.recipient,
+            "timestamp": pass.timestamp,
+            "latency_ms": pass.latency.as_millis() as u64,
+        }))
+        .into_response(),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "pass": false, "recipient": canary.recipient, "reason": reason })),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,139 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::helpers::Account;
+use crate::auth::TokenId;
+use crate::quota;
+use crate::send_result::SendReport;
+use crate::state::{rpc_error_status, AppState};
+use crate::store::{envelope_source, split_notification};
+use crate::tenant::Tenant;
+
+/// Default and maximum time to wait for a reply.
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+const MAX_TIMEOUT_MS: u64 = 300_000;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/ask/{number}", post(ask))
+}
+
+#[derive(Deserialize)]
+struct AskBody {
+    recipient: String,
+    message: String,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+/// POST /v1/ask/{number} — send a message, then long-poll until the recipient
+/// replies and return the reply. The send counts against the caller's daily
+/// quotas like any other. The wait ends at `timeout_ms` or the request's
+/// deadline, whichever comes first; without a reply by then the answer is
+/// still 200, with `"reply": null`, since the message went out.
+async fn ask(
+    State(st): State<AppState>,
+    token: Option<Extension<TokenId>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Account(number): Account,
+    Json(body): Json<AskBody>,
+) -> Response {
+    // Subscribe before sending so a fast reply can't slip past us.
+    let mut rx = st.broadcast_tx.subscribe();
+
+    let params = json!({
+        "account": number,
+        "recipient": [body.recipient],
+        "message": body.message,
+    });
+    if let Err(denied) = super::messages::apply_trust_policy(&st, &number, &params).await {
+        return denied;
+    }
+    let token = token.as_ref().map(|Extension(TokenId(id))| id.as_str());
+    let charge = match quota::admit_send(&st, token, tenant.as_deref().map(|t| &**t), &params) {
+        Ok(charge) => charge,
+        Err(exceeded) => return exceeded.into_response(),
+    };
+    if let Some(pacer) = &st.pacer {
+        pacer.acquire().await;
+    }
+    let sent = match st.rpc("send", params.clone()).await {
+        Ok(result) => result,
+        Err(e) => {
+            charge.refund(&st.quotas);
+            return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response();
+        }
+    };
+    st.metrics.inc_sent();
+    if let Some(Extension(tenant)) = &tenant {
+        tenant.inc_sent();
+    }
+    let report = SendReport::from_result(&sent);
+    st.metrics
+        .add_failed_recipients(report.summary.failed as u64);
+    if report.summary.failed > 0 {
+        // Nobody to wait for: the only recipient wasn't reached.
+        let mut sent = sent;
+        report.annotate(&mut sent);
+        return (report.status(), Json(sent)).into_response();
+    }
+    let sent_ts = sent.get("timestamp").and_then(|v| v.as_u64()).unwrap_or(0);
+    st.store.record_outgoing(&number, &params, sent_ts);
+
+    let timeout = crate::deadline::clamp(Duration::from_millis(
+        body.timeout_ms
+            .unwrap_or(DEFAULT_TIMEOUT_MS)
+            .min(MAX_TIMEOUT_MS),
+    ));
+    let wait = async {
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if let Some(reply) = match_reply(event.json(), &number, &body.recipient) {
+                        return Some(reply);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(Some(reply)) => Json(json!({ "timestamp": sent_ts, "reply": reply })).into_response(),
+        Ok(None) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "error": "signal-cli connection closed", "timestamp": sent_ts })),
+        )
+            .into_response(),
+        Err(_) => Json(json!({ "timestamp": sent_ts, "reply": null })).into_response(),
+    }
+}
+
+/// If `parsed` is a text message from `recipient` to `account`, return the reply body.
+fn match_reply(parsed: &Value, account: &str, recipient: &str) -> Option<Value> {
+    let (to, envelope) = split_notification(parsed)?;
+    if !to.is_empty() && to != account {
+        return None;
+    }
+    if envelope_source(envelope) != recipient {
+        return None;
+    }
+    let data = envelope.get("dataMessage")?;
+    let message = data.get("message")?.as_str()?;
+    Some(json!({
+        "source": recipient,
+        "message": message,
+        "timestamp": data.get("timestamp").or_else(|| envelope.get("timestamp")),
+        "envelope": envelope,
+    }))
+}
@@ -1,13 +1,13 @@
 use axum::{
-    Router,
     extract::{Path, State},
     response::Response,
     routing::{delete, get},
+    Router,
 };
 use serde_json::json;
 
+use super::helpers::{rpc_no_content, rpc_ok};
 use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_no_content};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -22,17 +22,11 @@ async fn list_attachments(State(st): State<AppState>) -> Response {
 }
 
 /// GET /v1/attachments/{attachment} — retrieve a specific attachment.
-async fn get_attachment(
-    State(st): State<AppState>,
-    Path(attachment): Path<String>,
-) -> Response {
+async fn get_attachment(State(st): State<AppState>, Path(attachment): Path<String>) -> Response {
     rpc_ok(&st, "getAttachment", json!({ "id": attachment })).await
 }
 
 /// DELETE /v1/attachments/{attachment} — delete a locally cached attachment.
-async fn delete_attachment(
-    State(st): State<AppState>,
-    Path(attachment): Path<String>,
-) -> Response {
+async fn delete_attachment(State(st): State<AppState>, Path(attachment): Path<String>) -> Response {
     rpc_no_content(&st, "deleteAttachment", json!({ "id": attachment })).await
 }
@@ -0,0 +1,103 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::state::AppState;
+
+/// OIDC login endpoints; only mounted when `--oidc-issuer` is set.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/auth/login", get(login))
+        .route("/auth/callback", get(callback))
+        .route("/auth/logout", post(logout))
+        .route("/auth/me", get(me))
+}
+
+fn cookie(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::COOKIE).and_then(|v| v.to_str().ok())
+}
+
+fn error(status: StatusCode, e: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": e.into() }))).into_response()
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+/// GET /auth/login?next=/ui — redirect to the identity provider.
+async fn login(State(st): State<AppState>, Query(q): Query<LoginQuery>) -> Response {
+    let Some(oidc) = &st.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match oidc.begin_login(q.next.as_deref()).await {
+        Ok(url) => Redirect::to(&url).into_response(),
+        Err(e) => {
+            tracing::warn!("OIDC login failed: {e}");
+            error(StatusCode::BAD_GATEWAY, e)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// GET /auth/callback — the provider's redirect back; opens a session.
+async fn callback(State(st): State<AppState>, Query(q): Query<CallbackQuery>) -> Response {
+    let Some(oidc) = &st.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if let Some(e) = q.error {
+        return error(
+            StatusCode::UNAUTHORIZED,
+            format!("login failed at the identity provider: {e}"),
+        );
+    }
+    let (Some(code), Some(state)) = (q.code, q.state) else {
+        return error(StatusCode::BAD_REQUEST, "code and state are required");
+    };
+    match oidc.complete_login(&code, &state).await {
+        Ok((session, next)) => (
+            [(header::SET_COOKIE, oidc.session_cookie(Some(&session)))],
+            Redirect::to(&next),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::warn!("OIDC login rejected: {e}");
+            error(StatusCode::UNAUTHORIZED, e)
+        }
+    }
+}
+
+/// POST /auth/logout — end the session.
+async fn logout(State(st): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(oidc) = &st.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    oidc.logout(cookie(&headers));
+    (
+        StatusCode::NO_CONTENT,
+        [(header::SET_COOKIE, oidc.session_cookie(None))],
+    )
+        .into_response()
+}
+
+/// GET /auth/me — the logged-in operator.
+async fn me(State(st): State<AppState>, headers: HeaderMap) -> Response {
+    let Some(oidc) = &st.oidc else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match oidc.session(cookie(&headers)) {
+        Some(session) => Json(session).into_response(),
+        None => error(StatusCode::UNAUTHORIZED, "not logged in"),
+    }
+}
@@ -0,0 +1,55 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use serde_json::json;
+
+use super::helpers::Account;
+use crate::state::{rpc_error_status, AppState};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/auto-blocks/{number}", get(list))
+        .route("/v1/auto-blocks/{number}/{id}", delete(undo))
+}
+
+fn not_found(error: &str) -> Response {
+    (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response()
+}
+
+/// GET /v1/auto-blocks/{number} — senders blocked by the auto-block policy.
+async fn list(State(st): State<AppState>, Account(number): Account) -> Response {
+    match &st.auto_block {
+        Some(blocker) => Json(blocker.list(&number)).into_response(),
+        None => not_found("auto-block is off (see --auto-block)"),
+    }
+}
+
+/// DELETE /v1/auto-blocks/{number}/{id} — unblock the sender and keep the
+/// policy from blocking them again.
+async fn undo(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, id)): Path<(String, String)>,
+) -> Response {
+    let Some(blocker) = &st.auto_block else {
+        return not_found("auto-block is off (see --auto-block)");
+    };
+    let Some(block) = blocker.get(&number, &id) else {
+        return not_found(&format!("no auto-block {id}"));
+    };
+    if let Err(e) = st
+        .rpc(
+            "unblock",
+            json!({ "account": number, "recipient": [block.sender] }),
+        )
+        .await
+    {
+        return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response();
+    }
+    blocker.undo(&block);
+    tracing::info!(account = number, sender = block.sender, "Auto-block undone");
+    st.publish_system("auto_block_undone", json!(block));
+    Json(block).into_response()
+}
@@ -0,0 +1,68 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::state::{AppState, CommandConfig};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/commands", post(create_command).get(list_commands))
+        .route("/v1/commands/{id}", delete(delete_command))
+}
+
+#[derive(Deserialize)]
+struct CreateCommand {
+    prefix: String,
+    url: String,
+    #[serde(default)]
+    account: Option<String>,
+}
+
+async fn create_command(State(st): State<AppState>, Json(body): Json<CreateCommand>) -> Response {
+    if body.prefix.is_empty() || body.prefix.contains(char::is_whitespace) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "prefix must be non-empty and contain no whitespace" })),
+        )
+            .into_response();
+    }
+
+    let id = format!(
+        "{:016x}",
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    );
+
+    let config = CommandConfig {
+        id,
+        prefix: body.prefix,
+        url: body.url,
+        account: body.account,
+    };
+
+    st.commands.write().await.push(config.clone());
+
+    (StatusCode::CREATED, Json(config)).into_response()
+}
+
+async fn list_commands(State(st): State<AppState>) -> Response {
+    let commands = st.commands.read().await;
+    Json(commands.clone()).into_response()
+}
+
+async fn delete_command(State(st): State<AppState>, Path(id): Path<String>) -> Response {
+    let mut commands = st.commands.write().await;
+    let len_before = commands.len();
+    commands.retain(|c| c.id != id);
+    if commands.len() < len_before {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
@@ -1,11 +1,11 @@
-use axum::extract::{Path, State};
+use axum::extract::State;
 use axum::response::Response;
 use axum::routing::get;
 use axum::{Json, Router};
 use serde_json::json;
 
+use super::helpers::{rpc_no_content, rpc_ok, Account};
 use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_no_content};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -30,15 +30,12 @@ async fn set_global_config(
     rpc_no_content(&st, "setConfiguration", body).await
 }
 
-async fn get_account_config(
-    Path(number): Path<String>,
-    State(st): State<AppState>,
-) -> Response {
+async fn get_account_config(Account(number): Account, State(st): State<AppState>) -> Response {
     rpc_ok(&st, "getAccountSettings", json!({ "account": number })).await
 }
 
 async fn set_account_config(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<serde_json::Value>,
 ) -> Response {
@@ -4,31 +4,95 @@ use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 
-use crate::state::AppState;
-use super::helpers::rpc_ok;
+use super::helpers::{rpc_ok, Account};
+use crate::state::{rpc_error_status, AppState};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
-        .route("/v1/contacts/{number}", get(list_contacts).put(update_contact))
+        .route(
+            "/v1/contacts/{number}",
+            get(list_contacts).put(update_contact),
+        )
         .route("/v1/contacts/{number}/{recipient}", get(get_contact))
         .route("/v1/contacts/{number}/sync", post(sync_contacts))
         .route("/v1/contacts/{number}/{recipient}/avatar", get(get_avatar))
 }
 
-async fn list_contacts(
-    State(st): State<AppState>,
-    Path(number): Path<String>,
-) -> Response {
-    rpc_ok(&st, "listContacts", json!({ "account": number })).await
+/// GET /v1/contacts/{number} — contacts, each with its identity's trust status.
+async fn list_contacts(State(st): State<AppState>, Account(number): Account) -> Response {
+    let identities = json!({ "account": number });
+    contacts_with_identities(&st, json!({ "account": number }), identities).await
 }
 
 async fn get_contact(
     State(st): State<AppState>,
-    Path((number, recipient)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, recipient)): Path<(String, String)>,
 ) -> Response {
-    rpc_ok(&st, "listContacts", json!({ "account": number, "recipient": [recipient] })).await
+    let identities = json!({ "account": number, "number": recipient });
+    contacts_with_identities(
+        &st,
+        json!({ "account": number, "recipient": [recipient] }),
+        identities,
+    )
+    .await
+}
+
+/// `listContacts` with each contact's identity merged in from
+/// `listIdentities` (fetched concurrently). If identities can't be listed the
+/// contacts are returned without them.
+async fn contacts_with_identities(st: &AppState, contacts: Value, identities: Value) -> Response {
+    let (contacts, identities) = tokio::join!(
+        st.rpc("listContacts", contacts),
+        st.rpc("listIdentities", identities)
+    );
+    let mut contacts = match contacts {
+        Ok(contacts) => contacts,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let identities = identities.unwrap_or_else(|e| {
+        tracing::warn!("Listing identities for contacts failed: {e}");
+        Value::Null
+    });
+    let identities = identities.as_array().map(Vec::as_slice).unwrap_or_default();
+    for contact in contacts.as_array_mut().into_iter().flatten() {
+        let identity = identities
+            .iter()
+            .find(|i| same_address(contact, i))
+            .map(identity_summary);
+        contact["identity"] = identity.unwrap_or(Value::Null);
+    }
+    Json(contacts).into_response()
+}
+
+fn same_address(a: &Value, b: &Value) -> bool {
+    ["number", "uuid"].iter().any(|key| {
+        let field = |v: &Value| {
+            v.get(*key)
+                .and_then(|f| f.as_str())
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+        };
+        field(a).is_some() && field(a) == field(b)
+    })
+}
+
+/// `{trustLevel, verified, safetyNumber, changedAt}` from a `listIdentities`
+/// entry; `changedAt` is when the current identity key was first seen.
+fn identity_summary(identity: &Value) -> Value {
+    let trust_level = identity
+        .get("trustLevel")
+        .or_else(|| identity.get("status"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    json!({
+        "trustLevel": trust_level,
+        "verified": trust_level.as_str() == Some("TRUSTED_VERIFIED"),
+        "safetyNumber": identity.get("safetyNumber").cloned().unwrap_or(Value::Null),
+        "changedAt": identity.get("addedTimestamp").cloned().unwrap_or(Value::Null),
+    })
 }
 
 #[derive(Deserialize)]
@@ -40,7 +104,7 @@ struct UpdateContactBody {
 
 async fn update_contact(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<UpdateContactBody>,
 ) -> Response {
     let mut params = json!({ "account": number });
@@ -56,15 +120,14 @@ async fn update_contact(
     rpc_ok(&st, "updateContact", params).await
 }
 
-async fn sync_contacts(
-    State(st): State<AppState>,
-    Path(number): Path<String>,
-) -> Response {
+async fn sync_contacts(State(st): State<AppState>, Account(number): Account) -> Response {
     rpc_ok(&st, "sendContacts", json!({ "account": number })).await
 }
 
-async fn get_avatar(
-    Path((_number, _recipient)): Path<(String, String)>,
-) -> Response {
-    (StatusCode::NOT_IMPLEMENTED, Json(json!({ "error": "Avatar retrieval not yet implemented" }))).into_response()
+async fn get_avatar(Path((_number, _recipient)): Path<(String, String)>) -> Response {
+    (
+        StatusCode::NOT_IMPLEMENTED,
+        Json(json!({ "error": "Avatar retrieval not yet implemented" })),
+    )
+        .into_response()
 }
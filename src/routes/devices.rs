@@ -1,24 +1,23 @@
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, get, post};
 use axum::{Json, Router};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_no_content};
+use super::helpers::{rpc_no_content, rpc_ok, Account};
+use crate::devices;
+use crate::state::{now_millis, AppState};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/qrcodelink", get(qrcodelink))
         .route("/v1/qrcodelink/raw", get(qrcodelink_raw))
         .route("/v1/devices/{number}", post(link_device).get(list_devices))
+        .route("/v1/devices/{number}/links/{id}", get(get_link))
         .route("/v1/devices/{number}/{device_id}", delete(remove_device))
-        .route(
-            "/v1/devices/{number}/local-data",
-            delete(delete_local_data),
-        )
+        .route("/v1/devices/{number}/local-data", delete(delete_local_data))
 }
 
 #[derive(Deserialize)]
@@ -69,29 +68,75 @@ struct LinkDeviceBody {
     device_name: Option<String>,
 }
 
+/// Start linking in the background and return the session to poll; progress
+/// is also published as `device_link` system events.
 async fn link_device(
-    Path(number): Path<String>,
+    Account(number): Account,
     State(st): State<AppState>,
     Json(body): Json<LinkDeviceBody>,
 ) -> Response {
     let mut params = json!({ "account": number, "uri": body.uri });
-    if let Some(name) = body.device_name {
+    if let Some(name) = &body.device_name {
         params["deviceName"] = json!(name);
     }
-    rpc_no_content(&st, "finishLink", params).await
+    let session = st.link_sessions.start(&number, body.device_name);
+    st.publish_system("device_link", json!(session));
+    tokio::spawn(crate::link::run(st.clone(), session.id.clone(), params));
+    let location = format!("/v1/devices/{}/links/{}", number, session.id);
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, location)],
+        Json(json!(session)),
+    )
+        .into_response()
+}
+
+async fn get_link(
+    Account(number): Account,
+    Path((_, id)): Path<(String, String)>,
+    State(st): State<AppState>,
+) -> Response {
+    match st.link_sessions.get(&id).filter(|s| s.account == number) {
+        Some(session) => Json(json!(session)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "link session not found" })),
+        )
+            .into_response(),
+    }
 }
 
-async fn list_devices(Path(number): Path<String>, State(st): State<AppState>) -> Response {
-    rpc_ok(&st, "listDevices", json!({ "account": number })).await
+/// GET /v1/devices/{number} — signal-cli's device list with normalized
+/// fields (see [`devices::enrich`]).
+async fn list_devices(Account(number): Account, State(st): State<AppState>) -> Response {
+    match st.rpc("listDevices", json!({ "account": number })).await {
+        Ok(mut list) => {
+            for device in list.as_array_mut().into_iter().flatten() {
+                devices::enrich(device, now_millis());
+            }
+            Json(list).into_response()
+        }
+        Err(e) => (
+            crate::state::rpc_error_status(&e),
+            Json(json!({ "error": e })),
+        )
+            .into_response(),
+    }
 }
 
 async fn remove_device(
-    Path((number, device_id)): Path<(String, i64)>,
+    Account(number): Account,
+    Path((_, device_id)): Path<(String, i64)>,
     State(st): State<AppState>,
 ) -> Response {
-    rpc_no_content(&st, "removeDevice", json!({ "account": number, "deviceId": device_id })).await
+    rpc_no_content(
+        &st,
+        "removeDevice",
+        json!({ "account": number, "deviceId": device_id }),
+    )
+    .await
 }
 
-async fn delete_local_data(Path(number): Path<String>, State(st): State<AppState>) -> Response {
+async fn delete_local_data(Account(number): Account, State(st): State<AppState>) -> Response {
     rpc_no_content(&st, "deleteLocalAccountData", json!({ "account": number })).await
 }
@@ -1,26 +1,213 @@
 use std::convert::Infallible;
 
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::sse::{Event, Sse};
-use axum::routing::get;
-use axum::Router;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 
+use super::helpers::{streamed, visible, Account, JsonBody, StreamFormat};
+use crate::case::DataKeys;
+use crate::compression;
+use crate::cursor::{AckError, DEFAULT_BATCH, MAX_BATCH};
+use crate::event::EventKind;
+use crate::jwt::Grant;
 use crate::state::AppState;
 
 pub fn routes() -> Router<AppState> {
-    Router::new().route("/v1/events/{number}", get(sse_events))
+    Router::new()
+        .route("/v1/events/{number}", get(sse_events))
+        .route("/v1/events/cursor", get(list_consumers))
+        .route(
+            "/v1/events/cursor/{consumer}",
+            get(fetch).delete(remove_consumer),
+        )
+        .route("/v1/events/cursor/{consumer}/ack", post(ack))
 }
 
+/// GET /v1/events/{number} — incoming messages and system events as
+/// Server-Sent Events, gzipped with `--sse-gzip` when the client accepts it.
+/// A tenant or a JWT limited to some accounts only gets `{number}`'s events.
 async fn sse_events(
     State(st): State<AppState>,
-    Path(_number): Path<String>,
-) -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
+    grant: Option<Extension<Grant>>,
+    Account(number): Account,
+    Query(format): Query<StreamFormat>,
+    headers: HeaderMap,
+) -> Response {
     let rx = st.broadcast_tx.subscribe();
-    let stream = BroadcastStream::new(rx).filter_map(|result| match result {
-        Ok(msg) => Some(Ok(Event::default().event("message").data(msg))),
+    let grant = grant.map(|Extension(grant)| grant);
+    let filter_st = st.clone();
+    let stream = BroadcastStream::new(rx).filter_map(move |result| match result {
+        Ok(event) if !streamed(&filter_st, grant.as_ref(), &number, &event) => None,
+        Ok(event) => {
+            // Events from the API itself (see AppState::publish_system).
+            let kind = if event.kind() == EventKind::System {
+                "system"
+            } else {
+                "message"
+            };
+            Some(Ok::<_, Infallible>(
+                Event::default()
+                    .event(kind)
+                    .data(event.for_client(format.raw)),
+            ))
+        }
         Err(_) => None,
     });
-    Sse::new(stream)
+    compression::negotiate(&st, &headers, Sse::new(stream).into_response())
+}
+
+fn valid_consumer(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn invalid_consumer() -> Response {
+    let error = "consumer must be 1-64 letters, digits, '-' or '_'";
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
+
+fn unknown_consumer(name: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("no consumer {name:?}") })),
+    )
+        .into_response()
+}
+
+/// The log's name for a caller's consumer. A JWT limited to some accounts
+/// has consumers of its own, `<subject>/<name>`, which no other caller can
+/// reach (names can't hold a '/').
+fn consumer_key(grant: Option<&Grant>, name: &str) -> String {
+    match grant.filter(|g| g.accounts.is_some()) {
+        Some(grant) => format!("{}/{name}", grant.subject),
+        None => name.to_string(),
+    }
+}
+
+/// GET /v1/events/cursor — every pull consumer with its cursor and how many
+/// events it hasn't acknowledged. A JWT limited to some accounts sees only
+/// its own consumers.
+async fn list_consumers(State(st): State<AppState>, grant: Option<Extension<Grant>>) -> Response {
+    let (consumers, last) = st.event_log.consumers();
+    let prefix = consumer_key(grant.as_deref(), "");
+    let consumers: serde_json::Map<String, Value> = consumers
+        .into_iter()
+        .filter_map(|(key, cursor)| {
+            if prefix.is_empty() {
+                Some((key, cursor))
+            } else {
+                key.strip_prefix(&prefix)
+                    .map(|name| (name.to_string(), cursor))
+            }
+        })
+        .map(|(name, cursor)| {
+            (
+                name,
+                json!({ "cursor": cursor, "lag": last.saturating_sub(cursor) }),
+            )
+        })
+        .collect();
+    (
+        Extension(DataKeys(&["/consumers"])),
+        Json(json!({ "sequence": last, "consumers": consumers })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct FetchQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    raw: bool,
+}
+
+/// GET /v1/events/cursor/{consumer}?limit= — the events after the
+/// consumer's cursor, oldest first. The cursor only moves on an ack, so the
+/// same events are returned until then. A JWT limited to some accounts only
+/// gets those accounts' events.
+async fn fetch(
+    State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    Path(consumer): Path<String>,
+    Query(q): Query<FetchQuery>,
+) -> Response {
+    if !valid_consumer(&consumer) {
+        return invalid_consumer();
+    }
+    let limit = q.limit.unwrap_or(DEFAULT_BATCH);
+    if !(1..=MAX_BATCH).contains(&limit) {
+        let error = format!("limit must be between 1 and {MAX_BATCH}");
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    let grant = grant.as_deref();
+    let batch = st
+        .event_log
+        .fetch(&consumer_key(grant, &consumer), limit, |e| {
+            visible(&st, grant, e)
+        });
+    let events: Vec<Value> = batch
+        .events
+        .iter()
+        .map(|(seq, event)| {
+            let text = event.for_client(q.raw);
+            let event =
+                serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()));
+            json!({ "seq": seq, "event": event })
+        })
+        .collect();
+    Json(json!({ "consumer": consumer, "cursor": batch.cursor, "missed": batch.missed, "events": events }))
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct AckBody {
+    seq: u64,
+}
+
+/// POST /v1/events/cursor/{consumer}/ack — `{"seq": n}` acknowledges every
+/// event up to and including `n`.
+async fn ack(
+    State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    Path(consumer): Path<String>,
+    JsonBody(body): JsonBody<AckBody>,
+) -> Response {
+    match st
+        .event_log
+        .ack(&consumer_key(grant.as_deref(), &consumer), body.seq)
+    {
+        Ok(cursor) => Json(json!({ "consumer": consumer, "cursor": cursor })).into_response(),
+        Err(AckError::UnknownConsumer) => unknown_consumer(&consumer),
+        Err(AckError::Ahead(last)) => {
+            let error = format!("seq {} is past the newest event ({last})", body.seq);
+            (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+        }
+    }
+}
+
+/// DELETE /v1/events/cursor/{consumer} — forget a consumer and its cursor.
+async fn remove_consumer(
+    State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    Path(consumer): Path<String>,
+) -> Response {
+    if st
+        .event_log
+        .remove(&consumer_key(grant.as_deref(), &consumer))
+    {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        unknown_consumer(&consumer)
+    }
 }
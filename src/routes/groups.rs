@@ -4,10 +4,14 @@ use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use axum::{Json, Router};
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 
-use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_created};
+use super::helpers::{rpc_created, rpc_ok, Account};
+use crate::automations::{self, Automations};
+use crate::compat::{self, Compat};
+use crate::group_id::GroupId;
+use crate::groups;
+use crate::state::{rpc_error_status, AppState};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -28,22 +32,90 @@ pub fn routes() -> Router<AppState> {
         .route("/v1/groups/{number}/{groupid}/join", post(join_group))
         .route("/v1/groups/{number}/{groupid}/quit", post(quit_group))
         .route("/v1/groups/{number}/{groupid}/block", post(block_group))
+        .route("/v1/groups/{number}/{groupid}/stats", get(group_stats))
+        .route(
+            "/v1/groups/{number}/{groupid}/automations",
+            get(get_automations)
+                .put(set_automations)
+                .delete(delete_automations),
+        )
 }
 
 // ---- List / Get -----------------------------------------------------------
 
-async fn list_groups(
-    State(st): State<AppState>,
-    Path(number): Path<String>,
-) -> Response {
-    rpc_ok(&st, "listGroups", json!({ "account": number })).await
+async fn list_groups(State(st): State<AppState>, Account(number): Account) -> Response {
+    let params = json!({ "account": number });
+    if st.compat == Some(Compat::Bbernhard) {
+        return compat_groups(&st, params, false).await;
+    }
+    if let Some(groups) = st.group_cache.get(&number) {
+        return Json(groups).into_response();
+    }
+    match enriched_groups(&st, &number, params).await {
+        Ok(groups) => {
+            st.group_cache.put(&number, groups.clone());
+            Json(groups).into_response()
+        }
+        Err(e) => (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    }
 }
 
 async fn get_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
 ) -> Response {
-    rpc_ok(&st, "listGroups", json!({ "account": number, "group-id": groupid })).await
+    let params = json!({ "account": number, "group-id": groupid });
+    match st.compat {
+        Some(Compat::Bbernhard) => compat_groups(&st, params, true).await,
+        None => match enriched_groups(&st, &number, params).await {
+            Ok(groups) => Json(groups).into_response(),
+            Err(e) => (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+        },
+    }
+}
+
+/// `listGroups` with the normalized fields added to each group.
+async fn enriched_groups(st: &AppState, account: &str, params: Value) -> Result<Value, String> {
+    let mut groups = st.rpc("listGroups", params).await?;
+    st.group_snapshots.seed(account, &groups);
+    for group in groups.as_array_mut().into_iter().flatten() {
+        groups::enrich(group, account);
+    }
+    Ok(groups)
+}
+
+/// Make a group-changing RPC and drop the account's cached group list.
+async fn change(st: &AppState, account: &str, method: &str, params: Value) -> Response {
+    let response = rpc_ok(st, method, params).await;
+    st.group_cache.invalidate(account);
+    response
+}
+
+/// `listGroups` in bbernhard's field names; a single group is returned as an
+/// object (404 if signal-cli doesn't know it), a listing as an array.
+async fn compat_groups(st: &AppState, params: Value, single: bool) -> Response {
+    let groups = match st.rpc("listGroups", params).await {
+        Ok(result) => result,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let mut groups: Vec<Value> = groups
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(compat::group)
+        .collect();
+    if !single {
+        return Json(groups).into_response();
+    }
+    match groups.pop() {
+        Some(group) => Json(group).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "No group with that id" })),
+        )
+            .into_response(),
+    }
 }
 
 // ---- Create / Update / Delete ---------------------------------------------
@@ -65,7 +137,7 @@ struct GroupPermissions {
 
 async fn create_group(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<CreateGroupBody>,
 ) -> Response {
     let mut params = json!({
@@ -84,7 +156,23 @@ async fn create_group(
             params["set-permission-edit-details"] = json!(edit);
         }
     }
-    rpc_created(&st, "updateGroup", params).await
+    if st.compat != Some(Compat::Bbernhard) {
+        let response = rpc_created(&st, "updateGroup", params).await;
+        st.group_cache.invalidate(&number);
+        return response;
+    }
+    // bbernhard answers with just the new group's ID, in its own form.
+    match st.rpc("updateGroup", params).await {
+        Ok(result) => {
+            let id = result.get("groupId").and_then(|v| v.as_str()).unwrap_or("");
+            (
+                StatusCode::CREATED,
+                Json(json!({ "id": compat::group_id(id) })),
+            )
+                .into_response()
+        }
+        Err(e) => (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    }
 }
 
 #[derive(Deserialize)]
@@ -99,7 +187,8 @@ struct UpdateGroupBody {
 
 async fn update_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
     Json(body): Json<UpdateGroupBody>,
 ) -> Response {
     let mut params = json!({
@@ -126,14 +215,21 @@ async fn update_group(
             params["set-permission-edit-details"] = json!(edit);
         }
     }
-    rpc_ok(&st, "updateGroup", params).await
+    change(&st, &number, "updateGroup", params).await
 }
 
 async fn delete_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
 ) -> Response {
-    rpc_ok(&st, "quitGroup", json!({ "account": number, "group-id": groupid, "delete": true })).await
+    change(
+        &st,
+        &number,
+        "quitGroup",
+        json!({ "account": number, "group-id": groupid, "delete": true }),
+    )
+    .await
 }
 
 // ---- Members / Admins -----------------------------------------------------
@@ -145,26 +241,40 @@ struct MembersBody {
 
 async fn add_members(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
     Json(body): Json<MembersBody>,
 ) -> Response {
-    rpc_ok(&st, "updateGroup", json!({
-        "account": number,
-        "group-id": groupid,
-        "addMember": body.members,
-    })).await
+    change(
+        &st,
+        &number,
+        "updateGroup",
+        json!({
+            "account": number,
+            "group-id": groupid,
+            "addMember": body.members,
+        }),
+    )
+    .await
 }
 
 async fn remove_members(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
     Json(body): Json<MembersBody>,
 ) -> Response {
-    rpc_ok(&st, "updateGroup", json!({
-        "account": number,
-        "group-id": groupid,
-        "removeMember": body.members,
-    })).await
+    change(
+        &st,
+        &number,
+        "updateGroup",
+        json!({
+            "account": number,
+            "group-id": groupid,
+            "removeMember": body.members,
+        }),
+    )
+    .await
 }
 
 #[derive(Deserialize)]
@@ -174,53 +284,161 @@ struct AdminsBody {
 
 async fn add_admins(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
     Json(body): Json<AdminsBody>,
 ) -> Response {
-    rpc_ok(&st, "updateGroup", json!({
-        "account": number,
-        "group-id": groupid,
-        "addAdmin": body.admins,
-    })).await
+    change(
+        &st,
+        &number,
+        "updateGroup",
+        json!({
+            "account": number,
+            "group-id": groupid,
+            "addAdmin": body.admins,
+        }),
+    )
+    .await
 }
 
 async fn remove_admins(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
     Json(body): Json<AdminsBody>,
 ) -> Response {
-    rpc_ok(&st, "updateGroup", json!({
-        "account": number,
-        "group-id": groupid,
-        "removeAdmin": body.admins,
-    })).await
+    change(
+        &st,
+        &number,
+        "updateGroup",
+        json!({
+            "account": number,
+            "group-id": groupid,
+            "removeAdmin": body.admins,
+        }),
+    )
+    .await
 }
 
 // ---- Avatar / Join / Quit / Block -----------------------------------------
 
-async fn get_avatar(
-    Path((_number, _groupid)): Path<(String, String)>,
-) -> Response {
-    (StatusCode::NOT_IMPLEMENTED, Json(json!({ "error": "Group avatar retrieval not yet implemented" }))).into_response()
+async fn get_avatar(Path((_number, _groupid)): Path<(String, String)>) -> Response {
+    (
+        StatusCode::NOT_IMPLEMENTED,
+        Json(json!({ "error": "Group avatar retrieval not yet implemented" })),
+    )
+        .into_response()
 }
 
 async fn join_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
 ) -> Response {
-    rpc_ok(&st, "joinGroup", json!({ "account": number, "group-id": groupid })).await
+    change(
+        &st,
+        &number,
+        "joinGroup",
+        json!({ "account": number, "group-id": groupid }),
+    )
+    .await
 }
 
 async fn quit_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
 ) -> Response {
-    rpc_ok(&st, "quitGroup", json!({ "account": number, "group-id": groupid })).await
+    change(
+        &st,
+        &number,
+        "quitGroup",
+        json!({ "account": number, "group-id": groupid }),
+    )
+    .await
 }
 
 async fn block_group(
     State(st): State<AppState>,
-    Path((number, groupid)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
+) -> Response {
+    change(
+        &st,
+        &number,
+        "block",
+        json!({ "account": number, "group-id": groupid }),
+    )
+    .await
+}
+
+// ---- Stats ----------------------------------------------------------------
+
+/// GET /v1/groups/{number}/{groupid}/stats — message counts per member, per
+/// day and per hour, from the message store.
+async fn group_stats(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
+) -> Response {
+    let stats = st.store.group_stats(&number, &groupid);
+    let mut body = serde_json::to_value(stats).unwrap_or_default();
+    body["group_id"] = json!(groupid);
+    Json(body).into_response()
+}
+
+// ---- Automations ----------------------------------------------------------
+
+/// GET /v1/groups/{number}/{groupid}/automations — the group's automations;
+/// none are set when the group has no entry.
+async fn get_automations(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
 ) -> Response {
-    rpc_ok(&st, "block", json!({ "account": number, "group-id": groupid })).await
+    Json(st.automations.get(&number, &groupid).unwrap_or_default()).into_response()
+}
+
+/// PUT /v1/groups/{number}/{groupid}/automations — replace the group's
+/// automations. The current members are recorded so only later joins are
+/// welcomed.
+async fn set_automations(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
+    Json(body): Json<Automations>,
+) -> Response {
+    if body
+        .welcome
+        .as_ref()
+        .is_some_and(|w| w.message.trim().is_empty())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "welcome message must not be empty" })),
+        )
+            .into_response();
+    }
+    match automations::group_members(&st, &number, &groupid).await {
+        Ok((_, members)) => {
+            st.automations.update_members(&number, &groupid, members);
+        }
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    }
+    st.automations.set(&number, &groupid, body.clone());
+    Json(body).into_response()
+}
+
+/// DELETE /v1/groups/{number}/{groupid}/automations — stop the group's
+/// automations; 404 if it had none.
+async fn delete_automations(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, groupid)): Path<(String, GroupId)>,
+) -> Response {
+    if st.automations.remove(&number, &groupid) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
 }
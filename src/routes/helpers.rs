@@ -1,16 +1,29 @@
-use axum::http::StatusCode;
+use axum::extract::{FromRequest, FromRequestParts, OptionalFromRequest, RawPathParams, Request};
+use axum::http::header::{CACHE_CONTROL, IF_MODIFIED_SINCE, LAST_MODIFIED};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::sync::OnceLock;
 
-use crate::state::{rpc_error_status, AppState};
+use crate::event::Event;
+use crate::group_id::GroupId;
+use crate::jwt::Grant;
+use crate::state::{now_millis, rpc_error_status, AppState};
+use crate::store::civil_date;
 
 /// Make an RPC call and return 200 OK with the JSON result on success.
 pub async fn rpc_ok(st: &AppState, method: &str, params: Value) -> Response {
     let start = std::time::Instant::now();
     match st.rpc(method, params).await {
         Ok(result) => {
-            tracing::info!(rpc_method = method, status = 200, latency_ms = start.elapsed().as_millis() as u64);
+            tracing::info!(
+                rpc_method = method,
+                status = 200,
+                latency_ms = start.elapsed().as_millis() as u64
+            );
             Json(result).into_response()
         }
         Err(e) => {
@@ -26,7 +39,11 @@ pub async fn rpc_created(st: &AppState, method: &str, params: Value) -> Response
     let start = std::time::Instant::now();
     match st.rpc(method, params).await {
         Ok(result) => {
-            tracing::info!(rpc_method = method, status = 201, latency_ms = start.elapsed().as_millis() as u64);
+            tracing::info!(
+                rpc_method = method,
+                status = 201,
+                latency_ms = start.elapsed().as_millis() as u64
+            );
             (StatusCode::CREATED, Json(result)).into_response()
         }
         Err(e) => {
@@ -42,7 +59,11 @@ pub async fn rpc_no_content(st: &AppState, method: &str, params: Value) -> Respo
     let start = std::time::Instant::now();
     match st.rpc(method, params).await {
         Ok(_) => {
-            tracing::info!(rpc_method = method, status = 204, latency_ms = start.elapsed().as_millis() as u64);
+            tracing::info!(
+                rpc_method = method,
+                status = 204,
+                latency_ms = start.elapsed().as_millis() as u64
+            );
             StatusCode::NO_CONTENT.into_response()
         }
         Err(e) => {
@@ -52,3 +73,198 @@ pub async fn rpc_no_content(st: &AppState, method: &str, params: Value) -> Respo
         }
     }
 }
+
+/// The account from a `{number}` path parameter, with aliases resolved to E164.
+pub struct Account(pub String);
+
+impl FromRequestParts<AppState> for Account {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, st: &AppState) -> Result<Self, Self::Rejection> {
+        let params = RawPathParams::from_request_parts(parts, st)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        match params.iter().find(|(key, _)| *key == "number") {
+            Some((_, number)) => Ok(Account(st.resolve_account(number))),
+            None => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+        }
+    }
+}
+
+/// Whether the caller may see `event`: a JWT limited to some accounts only
+/// sees notifications for those accounts.
+pub fn visible(st: &AppState, grant: Option<&Grant>, event: &Event) -> bool {
+    match grant.filter(|g| g.accounts.is_some()) {
+        Some(grant) => event.notification().is_some_and(|(account, _)| {
+            grant.allows_account(&st.resolve_account(account), |a| st.resolve_account(a))
+        }),
+        None => true,
+    }
+}
+
+/// Whether a stream opened for `account` carries `event` to the caller. A
+/// caller limited to some accounts (a tenant, or a JWT with an accounts
+/// claim) only gets notifications for that account, which the auth
+/// middleware has checked it may use.
+pub fn streamed(st: &AppState, grant: Option<&Grant>, account: &str, event: &Event) -> bool {
+    match grant.filter(|g| g.accounts.is_some()) {
+        Some(_) => event
+            .notification()
+            .is_some_and(|(a, _)| st.resolve_account(a) == account),
+        None => true,
+    }
+}
+
+/// `?raw=true` on streaming endpoints: emit signal-cli's envelopes verbatim
+/// instead of the normalized format.
+#[derive(Deserialize, Default)]
+pub struct StreamFormat {
+    #[serde(default)]
+    pub raw: bool,
+}
+
+/// A JSON request body whose rejections (bad syntax, missing or mistyped
+/// fields) are answered with 400 and `{"error": ...}`, as bbernhard's API does,
+/// instead of axum's plain-text 422.
+pub struct JsonBody<T>(pub T);
+
+impl<T: serde::de::DeserializeOwned, S: Send + Sync> FromRequest<S> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match <Json<T> as FromRequest<S>>::from_request(req, state).await {
+            Ok(Json(body)) => Ok(JsonBody(body)),
+            Err(rejection) => Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": rejection.body_text() })),
+            )
+                .into_response()),
+        }
+    }
+}
+
+/// An optional body: none when the request has no `Content-Type`.
+impl<T: serde::de::DeserializeOwned, S: Send + Sync> OptionalFromRequest<S> for JsonBody<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        match <Json<T> as OptionalFromRequest<S>>::from_request(req, state).await {
+            Ok(body) => Ok(body.map(|Json(body)| JsonBody(body))),
+            Err(rejection) => Err((
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": rejection.body_text() })),
+            )
+                .into_response()),
+        }
+    }
+}
+
+/// Who a typing indicator (or similar) is addressed to: a contact, or a group
+/// given as `group-id` or as a `group.<base64>` recipient.
+pub enum Recipient {
+    Number(String),
+    Group(GroupId),
+}
+
+impl Recipient {
+    /// Resolve from a body's `recipient` and `group-id`; the group wins when
+    /// both are given.
+    pub fn resolve(
+        recipient: Option<String>,
+        group_id: Option<GroupId>,
+    ) -> Result<Self, &'static str> {
+        match (recipient, group_id) {
+            (_, Some(group)) => Ok(Recipient::Group(group)),
+            (Some(r), None) if r.starts_with("group.") => Ok(Recipient::Group(GroupId::from(r))),
+            (Some(r), None) if !r.is_empty() => Ok(Recipient::Number(r)),
+            _ => Err("recipient or group-id is required"),
+        }
+    }
+
+    /// Add the signal-cli addressing params (`recipient` list or `group-id`).
+    pub fn apply(self, params: &mut Value) {
+        match self {
+            Recipient::Number(n) => params["recipient"] = json!([n]),
+            Recipient::Group(g) => params["group-id"] = json!(g),
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Day names from the epoch on, which was a Thursday.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// When the server started, in seconds since the epoch: the `Last-Modified`
+/// of responses that only change with a restart. Set when the router is built.
+pub fn started_at() -> u64 {
+    static STARTED: OnceLock<u64> = OnceLock::new();
+    *STARTED.get_or_init(|| now_millis() / 1000)
+}
+
+/// An HTTP date (`Sun, 06 Nov 1994 08:49:37 GMT`) of a time in seconds since
+/// the epoch.
+pub fn http_date(secs: u64) -> String {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    let date = civil_date(days);
+    let month = MONTHS[date[5..7].parse::<usize>().unwrap_or(1) - 1];
+    format!(
+        "{}, {} {month} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        &date[8..10],
+        &date[..4],
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Parse an HTTP date as [`http_date`] writes it.
+fn parse_http_date(s: &str) -> Option<u64> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let mut hms = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    // Days since the epoch, the inverse of `civil_date`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+    Some(days * 86_400 + h * 3600 + m * 60 + sec)
+}
+
+/// A response that only changes when the server restarts, with
+/// `Cache-Control: cache_control` and a `Last-Modified` of the start, or
+/// 304 Not Modified when the request's `If-Modified-Since` is no earlier.
+pub fn cacheable(
+    headers: &HeaderMap,
+    cache_control: &'static str,
+    response: impl IntoResponse,
+) -> Response {
+    let modified = started_at();
+    let fresh = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+        .is_some_and(|since| since >= modified);
+    let mut response = if fresh {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        response.into_response()
+    };
+    let headers = response.headers_mut();
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Ok(date) = HeaderValue::from_str(&http_date(modified)) {
+        headers.insert(LAST_MODIFIED, date);
+    }
+    response
+}
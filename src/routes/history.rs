@@ -0,0 +1,169 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+
+use super::helpers::Account;
+use crate::state::AppState;
+use crate::store::{Direction, StoredMessage};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/messages/{number}", get(history))
+        .route("/v1/messages/{number}/export", get(export))
+        .route("/v1/messages/{number}/{peer}", get(conversation))
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// GET /v1/messages/{number} — stored message history with aggregated reactions.
+async fn history(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Query(q): Query<HistoryQuery>,
+) -> Response {
+    Json(st.store.history(&number, None, q.limit)).into_response()
+}
+
+/// GET /v1/messages/{number}/{peer} — history of a single conversation (contact or group).
+async fn conversation(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, peer)): Path<(String, String)>,
+    Query(q): Query<HistoryQuery>,
+) -> Response {
+    Json(st.store.history(&number, Some(&peer), q.limit)).into_response()
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    /// Earliest timestamp included (ms since the epoch).
+    #[serde(default)]
+    from: Option<u64>,
+    /// Timestamp from which messages are left out (ms since the epoch).
+    #[serde(default)]
+    to: Option<u64>,
+    /// Only the conversation with this number or group ID.
+    #[serde(default)]
+    peer: Option<String>,
+}
+
+const CSV_HEADER: &str = "direction,timestamp,source,recipients,group_id,message,reactions\r\n";
+
+/// A CSV field, quoted when it holds a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One CSV record. Recipients are separated by `;`, reactions written as
+/// `emoji:count` separated by `;`.
+fn csv_row(m: &StoredMessage) -> String {
+    let direction = match m.direction {
+        Direction::Incoming => "incoming",
+        Direction::Outgoing => "outgoing",
+    };
+    let reactions: Vec<String> = m
+        .reactions
+        .iter()
+        .map(|r| format!("{}:{}", r.emoji, r.count))
+        .collect();
+    let fields = [
+        direction.to_string(),
+        m.timestamp.to_string(),
+        m.source.clone(),
+        m.recipients.join(";"),
+        m.group_id.clone().unwrap_or_default(),
+        m.message.clone().unwrap_or_default(),
+        reactions.join(";"),
+    ];
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    format!("{}\r\n", fields.join(","))
+}
+
+/// GET /v1/messages/{number}/export — the stored history (optionally one
+/// conversation, between `from` and `to`) as a downloadable JSON or CSV file,
+/// serialized a message at a time as the body is sent.
+async fn export(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Query(q): Query<ExportQuery>,
+) -> Response {
+    if let (Some(from), Some(to)) = (q.from, q.to) {
+        if from > to {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": "from must not be after to" })),
+            )
+                .into_response();
+        }
+    }
+    let messages: Vec<StoredMessage> = st
+        .store
+        .history(&number, q.peer.as_deref(), None)
+        .into_iter()
+        .filter(|m| {
+            q.from.is_none_or(|from| m.timestamp >= from) && q.to.is_none_or(|to| m.timestamp < to)
+        })
+        .collect();
+
+    let format = q.format;
+    let (content_type, extension, open, close) = match format {
+        ExportFormat::Json => ("application/json", "json", "[", "]"),
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv", CSV_HEADER, ""),
+    };
+    let rows = messages
+        .into_iter()
+        .enumerate()
+        .map(move |(i, m)| match format {
+            ExportFormat::Json => {
+                let row = serde_json::to_string(&m).unwrap_or_default();
+                if i == 0 {
+                    row
+                } else {
+                    format!(",{row}")
+                }
+            }
+            ExportFormat::Csv => csv_row(&m),
+        });
+    let chunks = std::iter::once(open.to_string())
+        .chain(rows)
+        .chain(std::iter::once(close.to_string()));
+    let disposition = format!(
+        "attachment; filename=\"messages-{}.{extension}\"",
+        number.trim_start_matches('+')
+    );
+    let body = Body::from_stream(tokio_stream::iter(chunks.map(Ok::<_, Infallible>)));
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
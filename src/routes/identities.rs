@@ -1,23 +1,33 @@
 use axum::extract::{Path, State};
-use axum::response::Response;
-use axum::routing::{get, put};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
 use axum::{Json, Router};
 use serde::Deserialize;
 use serde_json::json;
 
-use crate::state::AppState;
-use super::helpers::rpc_ok;
+use super::helpers::{rpc_ok, Account};
+use crate::state::{rpc_error_status, AppState};
+use crate::trust::{self, TrustPolicy};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/identities/{number}", get(list_identities))
-        .route("/v1/identities/{number}/trust/{number_to_trust}", put(trust_identity))
+        .route(
+            "/v1/identities/{number}/policy",
+            get(get_policy).put(set_policy),
+        )
+        .route(
+            "/v1/identities/{number}/trust",
+            post(trust_recipients).put(trust_batch),
+        )
+        .route(
+            "/v1/identities/{number}/trust/{number_to_trust}",
+            put(trust_identity),
+        )
 }
 
-async fn list_identities(
-    State(st): State<AppState>,
-    Path(number): Path<String>,
-) -> Response {
+async fn list_identities(State(st): State<AppState>, Account(number): Account) -> Response {
     rpc_ok(&st, "listIdentities", json!({ "account": number })).await
 }
 
@@ -30,7 +40,8 @@ struct TrustBody {
 
 async fn trust_identity(
     State(st): State<AppState>,
-    Path((number, number_to_trust)): Path<(String, String)>,
+    Account(number): Account,
+    Path((_, number_to_trust)): Path<(String, String)>,
     Json(body): Json<TrustBody>,
 ) -> Response {
     let mut params = json!({
@@ -45,3 +56,119 @@ async fn trust_identity(
     }
     rpc_ok(&st, "trust", params).await
 }
+
+#[derive(Deserialize)]
+struct PolicyBody {
+    policy: TrustPolicy,
+}
+
+/// GET /v1/identities/{number}/policy — the account's trust policy
+/// (`on_first_use` unless set).
+async fn get_policy(State(st): State<AppState>, Account(number): Account) -> Response {
+    let policy = st
+        .trust_policies
+        .read()
+        .await
+        .get(&number)
+        .copied()
+        .unwrap_or_default();
+    Json(json!({ "policy": policy })).into_response()
+}
+
+/// PUT /v1/identities/{number}/policy — `{"policy": "always" | "on_first_use" | "manual"}`.
+async fn set_policy(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Json(body): Json<PolicyBody>,
+) -> Response {
+    tracing::info!(account = number, policy = ?body.policy, "Trust policy set");
+    st.trust_policies.write().await.insert(number, body.policy);
+    Json(json!({ "policy": body.policy })).into_response()
+}
+
+#[derive(Deserialize)]
+struct TrustRecipientsBody {
+    recipients: Vec<String>,
+}
+
+/// POST /v1/identities/{number}/trust — mark the current key of each
+/// recipient as verified, as a manual-policy send refusal suggests. Answers
+/// with a result per recipient, and 207 when some couldn't be trusted.
+async fn trust_recipients(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Json(body): Json<TrustRecipientsBody>,
+) -> Response {
+    let identities = match st.rpc("listIdentities", json!({ "account": number })).await {
+        Ok(identities) => identities,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let mut results = Vec::new();
+    for recipient in body.recipients {
+        match trust::identity(&identities, &recipient).and_then(|i| i["safetyNumber"].as_str()) {
+            Some(safety_number) => {
+                let identity = VerifiedIdentity { safety_number: safety_number.to_string(), recipient };
+                results.extend(verify(&st, &number, vec![identity]).await);
+            }
+            None => results.push(json!({ "recipient": recipient, "trusted": false, "error": "no known identity key" })),
+        }
+    }
+    batch_response(results)
+}
+
+/// Most identities one PUT /v1/identities/{number}/trust may verify.
+const MAX_TRUST_BATCH: usize = 500;
+
+#[derive(Deserialize)]
+struct VerifiedIdentity {
+    recipient: String,
+    safety_number: String,
+}
+
+/// PUT /v1/identities/{number}/trust — verify many contacts at once from a
+/// list of `{"recipient", "safety_number"}` pairs, each compared by
+/// signal-cli with the recipient's key. Answers with a result per pair, and
+/// 207 when some weren't trusted (e.g. a safety number that doesn't match).
+async fn trust_batch(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Json(body): Json<Vec<VerifiedIdentity>>,
+) -> Response {
+    if body.is_empty() || body.len() > MAX_TRUST_BATCH {
+        let error = format!("expected 1 to {MAX_TRUST_BATCH} {{recipient, safety_number}} pairs");
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    batch_response(verify(&st, &number, body).await)
+}
+
+/// Trust each recipient's key as verified with its safety number, one
+/// `trust` call each, so one failure doesn't stop the rest.
+async fn verify(
+    st: &AppState,
+    number: &str,
+    identities: Vec<VerifiedIdentity>,
+) -> Vec<serde_json::Value> {
+    let mut results = Vec::new();
+    for VerifiedIdentity {
+        recipient,
+        safety_number,
+    } in identities
+    {
+        let params = json!({ "account": number, "recipient": [recipient], "verified-safety-number": safety_number });
+        results.push(match st.rpc("trust", params).await {
+            Ok(_) => json!({ "recipient": recipient, "trusted": true }),
+            Err(e) => json!({ "recipient": recipient, "trusted": false, "error": e }),
+        });
+    }
+    results
+}
+
+/// 200 when every result is trusted, 207 otherwise.
+fn batch_response(results: Vec<serde_json::Value>) -> Response {
+    let status = if results.iter().all(|r| r["trusted"] == true) {
+        StatusCode::OK
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    (status, Json(json!({ "results": results }))).into_response()
+}
@@ -0,0 +1,119 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::helpers::{Account, JsonBody};
+use crate::case::DataKeys;
+use crate::kv::{PutError, MAX_CONVERSATIONS, MAX_KEYS, MAX_VALUE_BYTES};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/state/{number}/{conversation}", get(list).delete(clear))
+        .route(
+            "/v1/state/{number}/{conversation}/{key}",
+            get(get_key).put(put_key).delete(delete_key),
+        )
+}
+
+#[derive(Deserialize)]
+struct PutBody {
+    value: Value,
+    /// Seconds until the key expires (none = kept until deleted).
+    ttl: Option<u64>,
+}
+
+/// GET /v1/state/{number}/{conversation} — every live key of the
+/// conversation, with its value and expiry.
+async fn list(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, conversation)): Path<(String, String)>,
+) -> Response {
+    (
+        Extension(DataKeys(&[""])),
+        Json(st.kv.list(&number, &conversation)),
+    )
+        .into_response()
+}
+
+/// DELETE /v1/state/{number}/{conversation} — forget the conversation's state.
+async fn clear(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, conversation)): Path<(String, String)>,
+) -> Response {
+    Json(json!({ "removed": st.kv.clear(&number, &conversation) })).into_response()
+}
+
+/// GET /v1/state/{number}/{conversation}/{key}
+async fn get_key(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, conversation, key)): Path<(String, String, String)>,
+) -> Response {
+    match st.kv.get(&number, &conversation, &key) {
+        Some(entry) => Json(entry).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no key {key:?}") })),
+        )
+            .into_response(),
+    }
+}
+
+/// PUT /v1/state/{number}/{conversation}/{key} — set `{"value": ..., "ttl": secs}`.
+async fn put_key(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, conversation, key)): Path<(String, String, String)>,
+    JsonBody(body): JsonBody<PutBody>,
+) -> Response {
+    if body.ttl == Some(0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "ttl must be at least 1 second" })),
+        )
+            .into_response();
+    }
+    match st.kv.put(&number, &conversation, &key, body.value, body.ttl.map(Duration::from_secs)) {
+        Ok(entry) => Json(entry).into_response(),
+        Err(PutError::TooLarge) => (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({ "error": format!("value is larger than {MAX_VALUE_BYTES} bytes") })),
+        )
+            .into_response(),
+        Err(PutError::TooManyKeys) => (
+            StatusCode::INSUFFICIENT_STORAGE,
+            Json(json!({ "error": format!("conversation already holds {MAX_KEYS} keys") })),
+        )
+            .into_response(),
+        Err(PutError::AccountFull) => (
+            StatusCode::INSUFFICIENT_STORAGE,
+            Json(json!({ "error": format!("account already holds state for {MAX_CONVERSATIONS} conversations") })),
+        )
+            .into_response(),
+    }
+}
+
+/// DELETE /v1/state/{number}/{conversation}/{key}
+async fn delete_key(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, conversation, key)): Path<(String, String, String)>,
+) -> Response {
+    if st.kv.remove(&number, &conversation, &key) {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no key {key:?}") })),
+        )
+            .into_response()
+    }
+}
@@ -1,43 +1,400 @@
 use axum::{
-    Router,
-    extract::{Path, State, WebSocketUpgrade, ws},
+    extract::{ws, ws::rejection::WebSocketUpgradeRejection, Path, Query, State, WebSocketUpgrade},
+    http::{header, HeaderValue},
     response::{IntoResponse, Response},
     routing::{delete, get, post},
-    Json,
+    Extension, Json, Router,
 };
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
 
-use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_created};
+use super::helpers::{rpc_ok, streamed, Account, StreamFormat};
+use crate::auth::TokenId;
+use crate::compat;
+use crate::dedup::{self, Decision};
+use crate::event::Event;
+use crate::group_id;
+use crate::groups;
+use crate::jwt::Grant;
+use crate::outbox::Priority;
+use crate::pacing;
+use crate::quota::{self, Charge, NotAttempted};
+use crate::reply_context;
+use crate::scan::{ScanError, Verdict};
+use crate::send_result::{Outcome, SendReport};
+use crate::state::{now_millis, AppState};
+use crate::store::{delivery_receipt, sent_by};
+use crate::tenant::Tenant;
+use crate::topics;
+use crate::trust;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/send", post(send_v1))
         .route("/v2/send", post(send_v2))
+        .route(
+            "/v1/send-to/{number}/{recipient}",
+            get(send_to).post(send_to),
+        )
         .route("/v1/receive/{number}", get(receive_ws))
         .route("/v1/remote-delete/{number}", delete(remote_delete))
 }
 
-/// POST /v1/send — send a message (v1, simple).
+/// Sunset date advertised for deprecated v1 routes.
+pub const V1_SUNSET: &str = "Thu, 01 Jul 2027 00:00:00 GMT";
+
+/// POST /v1/send — send a message (v1, deprecated in favour of /v2/send).
 async fn send_v1(
     State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    token: Option<Extension<TokenId>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
     Json(body): Json<Value>,
 ) -> Response {
-    rpc_created(&st, "send", body).await
+    if !st.deprecated_routes {
+        return (
+            axum::http::StatusCode::GONE,
+            [(header::LINK, r#"</v2/send>; rel="successor-version""#)],
+            Json(json!({ "error": "/v1/send is disabled; use /v2/send" })),
+        )
+            .into_response();
+    }
+    let window = dedup_window(&st, token.as_ref());
+    let charge = match admit_send(&st, grant, token, tenant.as_deref(), &body) {
+        Ok(charge) => charge,
+        Err(denied) => return *denied,
+    };
+    let mut response = send(&st, body, window, false).await;
+    charge.settle(&st.quotas, &response);
+    count_for_tenant(tenant.as_deref(), &response);
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    headers.insert("sunset", HeaderValue::from_static(V1_SUNSET));
+    headers.insert(
+        header::LINK,
+        HeaderValue::from_static(r#"</v2/send>; rel="successor-version""#),
+    );
+    response
 }
 
 /// POST /v2/send — send a message (v2, extended). Increments sent counter.
 async fn send_v2(
     State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    token: Option<Extension<TokenId>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
     Json(body): Json<Value>,
 ) -> Response {
+    let window = dedup_window(&st, token.as_ref());
+    let charge = match admit_send(&st, grant, token, tenant.as_deref(), &body) {
+        Ok(charge) => charge,
+        Err(denied) => return *denied,
+    };
+    let response = send(&st, body, window, true).await;
+    charge.settle(&st.quotas, &response);
+    count_for_tenant(tenant.as_deref(), &response);
+    response
+}
+
+/// Checks made before a send on behalf of the caller: a JWT's (or tenant's)
+/// account allowlist (the sending account is in the body, so the auth
+/// middleware can't check it), then the daily quotas of the tenant and the
+/// API token (see [`quota::admit_send`]).
+fn admit_send(
+    st: &AppState,
+    grant: Option<Extension<Grant>>,
+    token: Option<Extension<TokenId>>,
+    tenant: Option<&Arc<Tenant>>,
+    body: &Value,
+) -> Result<Charge, Box<Response>> {
+    if let Some(Extension(grant)) = grant {
+        let account = st.resolve_account(&sender_account(body));
+        if !grant.allows_account(&account, |a| st.resolve_account(a)) {
+            let error = format!("token may not use account {account}");
+            return Err(Box::new(
+                (
+                    axum::http::StatusCode::FORBIDDEN,
+                    Json(json!({ "error": error })),
+                )
+                    .into_response(),
+            ));
+        }
+    }
+    let token = token.as_ref().map(|Extension(TokenId(id))| id.as_str());
+    quota::admit_send(st, token, tenant.map(|t| &**t), body)
+        .map_err(|exceeded| Box::new(exceeded.into_response()))
+}
+
+/// The dedup window of the caller's sends (see [`AppState::dedup_window_for`]).
+fn dedup_window(st: &AppState, token: Option<&Extension<TokenId>>) -> Option<Duration> {
+    st.dedup_window_for(token.map(|Extension(TokenId(id))| id.as_str()))
+}
+
+/// Count a send that went out (or was queued) for the caller's tenant.
+fn count_for_tenant(tenant: Option<&Arc<Tenant>>, response: &Response) {
+    if let Some(tenant) = tenant.filter(|_| response.status().is_success()) {
+        tenant.inc_sent();
+    }
+}
+
+#[derive(Deserialize)]
+struct SendToQuery {
+    message: Option<String>,
+}
+
+/// GET/POST /v1/send-to/{number}/{recipient}?message=... — send plain text
+/// without JSON, for curl one-liners. A POST body is used as the message when
+/// `message` isn't given. A `group.`-prefixed recipient addresses a group.
+/// Goes through the same path (validation, dedup, outbox, metrics) as /v2/send.
+async fn send_to(
+    State(st): State<AppState>,
+    token: Option<Extension<TokenId>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Account(number): Account,
+    Path((_, recipient)): Path<(String, String)>,
+    Query(q): Query<SendToQuery>,
+    body: String,
+) -> Response {
+    let message = q.message.filter(|m| !m.is_empty()).unwrap_or(body);
+    if message.trim().is_empty() {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "message is required (?message= or request body)" })),
+        )
+            .into_response();
+    }
+    let mut params = json!({ "number": number, "message": message });
+    if recipient.starts_with("group.") {
+        params["group-id"] = json!(recipient);
+        params["recipients"] = json!([]);
+    } else {
+        params["recipients"] = json!([recipient]);
+    }
+    let window = dedup_window(&st, token.as_ref());
+    let charge = match admit_send(&st, None, token, tenant.as_deref(), &params) {
+        Ok(charge) => charge,
+        Err(denied) => return *denied,
+    };
+    let response = send(&st, params, window, true).await;
+    charge.settle(&st.quotas, &response);
+    count_for_tenant(tenant.as_deref(), &response);
+    response
+}
+
+/// POST /v1/send, /v2/send, /v1/send-to — shared send path: checks the send,
+/// then sends it or queues it in the outbox.
+async fn send(
+    st: &AppState,
+    mut body: Value,
+    dedup_window: Option<Duration>,
+    count_sent: bool,
+) -> Response {
+    let urgent = body
+        .as_object_mut()
+        .and_then(|o| o.remove("urgent"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let wait_for_delivery = match body
+        .as_object_mut()
+        .and_then(|o| o.remove("wait_for_delivery_ms"))
+    {
+        None => None,
+        Some(v) => match v.as_u64().filter(|ms| *ms <= MAX_WAIT_FOR_DELIVERY_MS) {
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => {
+                let error = format!("wait_for_delivery_ms must be a number of milliseconds up to {MAX_WAIT_FOR_DELIVERY_MS}");
+                return (
+                    axum::http::StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": error })),
+                )
+                    .into_response();
+            }
+        },
+    };
+    let priority: Priority = match body.as_object_mut().and_then(|o| o.remove("priority")) {
+        Some(v) => match serde_json::from_value(v) {
+            Ok(p) => p,
+            Err(_) => {
+                return (
+                    axum::http::StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": "priority must be one of: high, normal, low" })),
+                )
+                    .into_response();
+            }
+        },
+        None => Priority::default(),
+    };
+    if let Err(e) = topics::resolve(&mut body, &*st.topics.read().await) {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e })),
+        )
+            .into_response();
+    }
+    group_id::normalize_params(&mut body);
+    let account = sender_account(&body);
+    if body.get("mention").is_some_and(|m| m == "all") {
+        if let Err(denied) = expand_mention_all(st, &account, &mut body).await {
+            return denied;
+        }
+    }
+    if let Err(denied) = apply_trust_policy(st, &account, &body).await {
+        return denied;
+    }
+    // Released again by any return below that neither sends nor queues.
+    let reservation = match dedup_window {
+        Some(window) => match st.dedup.check(&account, &body, window) {
+            Decision::Send(reservation) => {
+                dedup::remove_recipients(&mut body, reservation.suppressed());
+                Some(reservation)
+            }
+            Decision::Suppressed(count) => {
+                return (
+                    Extension(NotAttempted),
+                    Json(json!({ "suppressed": true, "count": count })),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+    if let Some(scanner) = &st.scanner {
+        let rejected = match scanner.scan_outgoing(&body).await {
+            Ok(Verdict::Clean) => None,
+            Ok(Verdict::Infected(reason)) => Some((
+                axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+                format!("attachment rejected by scanner: {reason}"),
+            )),
+            Err(ScanError::Attachment(e)) => Some((axum::http::StatusCode::BAD_REQUEST, e)),
+            Err(ScanError::Scanner(e)) => Some((
+                axum::http::StatusCode::SERVICE_UNAVAILABLE,
+                format!("attachment scanner unavailable: {e}"),
+            )),
+        };
+        if let Some((status, error)) = rejected {
+            tracing::warn!(account, error, "Send vetoed by attachment scanner");
+            return (status, Json(json!({ "error": error }))).into_response();
+        }
+    }
+    let quiet = !urgent
+        && st
+            .quiet_hours
+            .read()
+            .await
+            .get(&account)
+            .is_some_and(|q| q.contains(now_millis()));
+    let paced = !quiet
+        && priority != Priority::Low
+        && st
+            .pacer
+            .as_ref()
+            .is_some_and(|p| !p.try_acquire(st.outbox.holds(pacing::REASON)));
+    if quiet || paced || priority == Priority::Low {
+        let reason = if quiet {
+            "quiet_hours"
+        } else if paced {
+            pacing::REASON
+        } else {
+            "low_priority"
+        };
+        if let Some(reservation) = reservation {
+            reservation.keep();
+        }
+        let queued = st.outbox.enqueue(&account, body, reason, priority);
+        if let Some(pacer) = st.pacer.as_ref().filter(|_| paced) {
+            pacer.queued();
+        }
+        return (
+            axum::http::StatusCode::ACCEPTED,
+            Json(json!({ "queued": true, "id": queued.id, "reason": queued.reason, "priority": queued.priority })),
+        )
+            .into_response();
+    }
+
+    if body.get("base64_attachments").is_some() {
+        if let Err(e) = st.attachments.check_space() {
+            return (
+                axum::http::StatusCode::INSUFFICIENT_STORAGE,
+                Json(json!({ "error": e })),
+            )
+                .into_response();
+        }
+    }
+    if let Err(e) = st.attachments.stage(&mut body) {
+        return (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e })),
+        )
+            .into_response();
+    }
+    let metadata = reply_context::take_metadata(&mut body);
+    // Subscribed before sending, so a quick receipt isn't missed.
+    let receipts = wait_for_delivery
+        .filter(|_| st.compat.is_none())
+        .map(|_| st.broadcast_tx.subscribe());
     let start = std::time::Instant::now();
-    match st.rpc("send", body).await {
+    match st.rpc("send", body.clone()).await {
         Ok(result) => {
-            st.metrics.inc_sent();
-            tracing::info!(rpc_method = "send", status = 201, latency_ms = start.elapsed().as_millis() as u64);
-            (axum::http::StatusCode::CREATED, Json(result)).into_response()
+            if count_sent {
+                st.metrics.inc_sent();
+            }
+            let timestamp = result
+                .get("timestamp")
+                .and_then(|v| v.as_u64())
+                .unwrap_or_else(now_millis);
+            st.store.record_outgoing(&account, &body, timestamp);
+            if let Some(reservation) = reservation {
+                reservation.keep();
+            }
+            if let Some(metadata) = metadata {
+                st.reply_contexts
+                    .record(&account, &body, timestamp, metadata);
+            }
+            let mut result = result;
+            let report = SendReport::from_result(&result);
+            st.metrics
+                .add_failed_recipients(report.summary.failed as u64);
+            let (status, result) = match st.compat {
+                Some(compat::Compat::Bbernhard) => match compat::send_response(&result, &report) {
+                    Ok(body) => (axum::http::StatusCode::CREATED, body),
+                    Err(e) => (axum::http::StatusCode::BAD_REQUEST, json!({ "error": e })),
+                },
+                None => {
+                    report.annotate(&mut result);
+                    if let Some((rx, timeout)) = receipts.zip(wait_for_delivery) {
+                        // Without per-recipient results, everyone asked for was reached.
+                        let recipients = if report.recipients.is_empty() {
+                            let asked = body
+                                .get("recipients")
+                                .and_then(|v| v.as_array())
+                                .into_iter()
+                                .flatten();
+                            asked.filter_map(|r| r.as_str().map(String::from)).collect()
+                        } else {
+                            let reached = report
+                                .recipients
+                                .iter()
+                                .filter(|r| r.status == Outcome::Sent);
+                            reached.map(|r| r.recipient.clone()).collect()
+                        };
+                        result["delivery"] =
+                            wait_for_receipts(rx, &account, timestamp, recipients, timeout).await;
+                    }
+                    (report.status(), result)
+                }
+            };
+            tracing::info!(
+                rpc_method = "send",
+                status = status.as_u16(),
+                failed_recipients = report.summary.failed,
+                latency_ms = start.elapsed().as_millis() as u64
+            );
+            (status, Json(result)).into_response()
         }
         Err(e) => {
             let status = crate::state::rpc_error_status(&e);
@@ -47,30 +404,274 @@ async fn send_v2(
     }
 }
 
+/// Turn a group send's `"mention": "all"` into a mention of every other
+/// member, looked up with `listGroups`. Refused above `--max-mentions`.
+async fn expand_mention_all(
+    st: &AppState,
+    account: &str,
+    body: &mut Value,
+) -> Result<(), Response> {
+    let bad_request = |error: String| {
+        (
+            axum::http::StatusCode::BAD_REQUEST,
+            Json(json!({ "error": error })),
+        )
+            .into_response()
+    };
+    let Some(group) = body
+        .get("group-id")
+        .or_else(|| body.get("groupId"))
+        .cloned()
+    else {
+        return Err(bad_request(
+            "\"mention\": \"all\" needs a group send".into(),
+        ));
+    };
+    let params = json!({ "account": account, "group-id": group });
+    let groups = st.rpc("listGroups", params).await.map_err(|e| {
+        (
+            crate::state::rpc_error_status(&e),
+            Json(json!({ "error": e })),
+        )
+            .into_response()
+    })?;
+    let Some(group) = groups.as_array().and_then(|g| g.first()) else {
+        return Err(bad_request(format!(
+            "no group {}",
+            group.as_str().unwrap_or("")
+        )));
+    };
+    let members = groups::other_members(group, account);
+    if members.len() > st.max_mentions {
+        let error = format!(
+            "group has {} other members, more than the {} that may be mentioned",
+            members.len(),
+            st.max_mentions
+        );
+        return Err(bad_request(error));
+    }
+    groups::mention_all(body, &members);
+    Ok(())
+}
+
+/// Apply the account's trust policy to a send (see [`trust::check`]). In
+/// `manual` mode a send to any recipient whose key isn't verified is refused
+/// with 409, listing them and the call that trusts them all.
+pub(super) async fn apply_trust_policy(
+    st: &AppState,
+    account: &str,
+    body: &Value,
+) -> Result<(), Response> {
+    match trust::check(st, account, body).await {
+        Ok(untrusted) if untrusted.is_empty() => Ok(()),
+        Ok(untrusted) => Err((
+            axum::http::StatusCode::CONFLICT,
+            Json(trust::refusal(account, &untrusted)),
+        )
+            .into_response()),
+        Err(e) => Err((
+            crate::state::rpc_error_status(&e),
+            Json(json!({ "error": e })),
+        )
+            .into_response()),
+    }
+}
+
+/// Longest `wait_for_delivery_ms` a send may ask for.
+const MAX_WAIT_FOR_DELIVERY_MS: u64 = 60_000;
+
+/// Wait up to `timeout` for delivery receipts from `recipients` for the
+/// message `account` sent at `timestamp`. Returns who confirmed delivery and
+/// who hadn't yet.
+async fn wait_for_receipts(
+    mut rx: broadcast::Receiver<Event>,
+    account: &str,
+    timestamp: u64,
+    mut pending: Vec<String>,
+    timeout: Duration,
+) -> Value {
+    let start = std::time::Instant::now();
+    let mut delivered = Vec::new();
+    let wait = async {
+        while !pending.is_empty() {
+            match rx.recv().await {
+                Ok(event) => {
+                    let Some(envelope) = delivery_receipt(event.json(), account, timestamp) else {
+                        continue;
+                    };
+                    if let Some(i) = pending.iter().position(|r| sent_by(envelope, r)) {
+                        delivered.push(pending.remove(i));
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+    let _ = tokio::time::timeout(timeout, wait).await;
+    json!({ "delivered": delivered, "pending": pending, "waited_ms": start.elapsed().as_millis() as u64 })
+}
+
+/// The sending account of a send payload ("number", or signal-cli's "account").
+fn sender_account(body: &Value) -> String {
+    body.get("number")
+        .or_else(|| body.get("account"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string()
+}
+
+#[derive(Deserialize)]
+struct PollQuery {
+    /// Seconds to wait for messages when none are buffered.
+    timeout: Option<u64>,
+}
+
+/// Longest a polling request may wait, whatever `?timeout=` asks for.
+const MAX_POLL_TIMEOUT: u64 = 60;
+
 /// GET /v1/receive/{number} — WebSocket endpoint for real-time messages.
+/// Each connection buffers up to `?buffer=` messages; with `?gaps=true`,
+/// messages dropped for a slow client are reported as gap frames.
+/// In `--compat bbernhard` mode a plain GET polls instead: it returns the
+/// messages received since the last poll as a JSON array, waiting up to
+/// `?timeout=` seconds (default 1) for the first one. A tenant or a JWT
+/// limited to some accounts only gets `{number}`'s messages.
 async fn receive_ws(
     State(st): State<AppState>,
-    Path(_number): Path<String>,
-    upgrade: WebSocketUpgrade,
-) -> impl IntoResponse {
-    upgrade.on_upgrade(move |socket| handle_ws(socket, st))
+    grant: Option<Extension<Grant>>,
+    Account(number): Account,
+    Query(format): Query<StreamFormat>,
+    Query(poll): Query<PollQuery>,
+    Query(options): Query<WsOptions>,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    match (upgrade, st.compat) {
+        (Ok(upgrade), _) => {
+            let grant = grant.map(|Extension(grant)| grant);
+            upgrade
+                .on_upgrade(move |socket| handle_ws(socket, st, grant, number, format.raw, options))
+        }
+        (Err(_), Some(compat::Compat::Bbernhard)) => {
+            let timeout = poll.timeout.unwrap_or(1).min(MAX_POLL_TIMEOUT);
+            let messages = st
+                .poll_queue
+                .poll(&number, std::time::Duration::from_secs(timeout))
+                .await;
+            Json(messages).into_response()
+        }
+        (Err(rejection), None) => rejection.into_response(),
+    }
 }
 
-async fn handle_ws(mut socket: ws::WebSocket, st: AppState) {
+/// Messages buffered per WebSocket connection unless `?buffer=` says otherwise.
+pub const WS_BUFFER: usize = 1024;
+
+/// Largest `?buffer=` a client may ask for.
+const WS_MAX_BUFFER: usize = 65536;
+
+#[derive(Deserialize)]
+struct WsOptions {
+    /// Send a `{"type":"gap"}` frame wherever messages were dropped.
+    #[serde(default)]
+    gaps: bool,
+    /// Messages buffered for this connection while the client catches up.
+    buffer: Option<usize>,
+}
+
+enum Frame {
+    Message(Event),
+    /// This many messages were dropped here.
+    Gap(u64),
+}
+
+/// Move notifications from the broadcast channel into a connection's own
+/// bounded buffer. Messages that don't fit, or were lost to broadcast lag,
+/// are counted and reported as a gap in sequence once there is room again.
+/// Messages the caller may not see are skipped (see [`streamed`]).
+async fn pump(
+    mut rx: broadcast::Receiver<Event>,
+    tx: mpsc::Sender<Frame>,
+    st: AppState,
+    grant: Option<Grant>,
+    account: String,
+) {
+    let mut missed = 0u64;
+    let lose = |missed: &mut u64, n: u64| {
+        *missed += n;
+        st.metrics.ws_dropped.fetch_add(n, Ordering::Relaxed);
+    };
+    loop {
+        let msg = match rx.recv().await {
+            Ok(msg) => msg,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                lose(&mut missed, n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        if !streamed(&st, grant.as_ref(), &account, &msg) {
+            continue;
+        }
+        if missed > 0 {
+            match tx.try_send(Frame::Gap(missed)) {
+                Ok(()) => missed = 0,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    lose(&mut missed, 1);
+                    continue;
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => break,
+            }
+        }
+        match tx.try_send(Frame::Message(msg)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => lose(&mut missed, 1),
+            Err(mpsc::error::TrySendError::Closed(_)) => break,
+        }
+    }
+}
+
+async fn handle_ws(
+    mut socket: ws::WebSocket,
+    st: AppState,
+    grant: Option<Grant>,
+    account: String,
+    raw: bool,
+    options: WsOptions,
+) {
     st.metrics.ws_clients.fetch_add(1, Ordering::Relaxed);
-    let mut rx = st.broadcast_tx.subscribe();
+    let capacity = options.buffer.unwrap_or(WS_BUFFER).clamp(1, WS_MAX_BUFFER);
+    let (tx, mut frames) = mpsc::channel(capacity);
+    let pump = tokio::spawn(pump(
+        st.broadcast_tx.subscribe(),
+        tx,
+        st.clone(),
+        grant,
+        account,
+    ));
 
     loop {
         tokio::select! {
-            msg = rx.recv() => {
-                match msg {
-                    Ok(text) => {
-                        if socket.send(ws::Message::Text(text.into())).await.is_err() {
-                            break;
+            frame = frames.recv() => {
+                let (text, event) = match frame {
+                    Some(Frame::Message(event)) => match ws::Utf8Bytes::try_from(event.bytes_for_client(raw)) {
+                        Ok(text) => (text, Some(event)),
+                        Err(_) => continue,
+                    },
+                    Some(Frame::Gap(missed)) => {
+                        tracing::warn!(missed, "WebSocket client too slow, messages dropped");
+                        if !options.gaps {
+                            continue;
                         }
+                        (json!({ "type": "gap", "missed": missed, "at": now_millis() }).to_string().into(), None)
                     }
-                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
-                    Err(_) => break,
+                    None => break,
+                };
+                if socket.send(ws::Message::Text(text)).await.is_err() {
+                    break;
+                }
+                if let Some(event) = event.filter(|e| e.notification().is_some()) {
+                    st.metrics.ws_delivery_latency.observe(event.age().as_millis() as u64);
                 }
             }
             incoming = socket.recv() => {
@@ -82,13 +683,14 @@ async fn handle_ws(mut socket: ws::WebSocket, st: AppState) {
         }
     }
 
+    pump.abort();
     st.metrics.ws_clients.fetch_sub(1, Ordering::Relaxed);
 }
 
 /// DELETE /v1/remote-delete/{number} — remotely delete a sent message.
 async fn remote_delete(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
@@ -1,20 +1,67 @@
 use axum::extract::State;
-use axum::http::header;
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 
+use crate::auth::constant_time_eq;
+use crate::exposition::{Exposition, Format};
 use crate::state::AppState;
 
 pub fn routes() -> Router<AppState> {
     Router::new().route("/metrics", get(prometheus_metrics))
 }
 
-async fn prometheus_metrics(State(st): State<AppState>) -> Response {
-    let body = st.metrics.to_prometheus();
+/// GET /metrics — Prometheus text format, or OpenMetrics when the scraper
+/// asks for it via `Accept`. Requires a bearer token if one is configured.
+async fn prometheus_metrics(State(st): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = &st.metrics_token {
+        let presented = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !presented.is_some_and(|p| constant_time_eq(p.as_bytes(), token.as_bytes())) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response();
+        }
+    }
+
+    let format = Format::negotiate(headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()));
+    let mut exp = Exposition::new(format);
+    st.metrics.render(&mut exp);
+    st.outbox.render(&mut exp);
+    st.attachments.render(&mut exp);
+    st.store.render(&mut exp);
+    st.event_log.render(&mut exp);
+    st.shedder.render(&mut exp);
+    st.tenants.render(&mut exp);
+    if let Some(sink) = &st.aws_sink {
+        sink.render(&mut exp);
+    }
+    if let Some(redis) = &st.redis {
+        redis.render(&mut exp);
+    }
+    if let Some(pacer) = &st.pacer {
+        pacer.render(&mut exp);
+    }
+    if let Some(canary) = &st.canary {
+        canary.render(&mut exp);
+    }
+    if st.leadership.lease.is_some() {
+        st.leadership.render(&mut exp);
+    }
+    if let Some(scanner) = &st.scanner {
+        scanner.render(&mut exp);
+    }
+    if let Some(spam) = &st.spam {
+        spam.render(&mut exp);
+    }
     (
-        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
-        body,
+        [(header::CONTENT_TYPE, format.content_type())],
+        exp.finish(),
     )
         .into_response()
 }
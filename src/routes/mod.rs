@@ -1,29 +1,66 @@
 pub mod accounts;
-pub mod helpers;
+pub mod admin;
+pub mod ask;
 pub mod attachments;
+pub mod auth_routes;
+pub mod auto_blocks;
+pub mod command_routes;
 pub mod config;
 pub mod contacts;
 pub mod devices;
 pub mod events;
 pub mod groups;
+pub mod helpers;
+pub mod history;
 pub mod identities;
+pub mod kv;
 pub mod messages;
 pub mod metrics;
 pub mod openapi;
+pub mod outbox;
 pub mod polls;
 pub mod profiles;
+pub mod quarantine;
 pub mod reactions;
 pub mod receipts;
 pub mod search;
 pub mod stickers;
 pub mod system;
+pub mod tokens;
+pub mod topics;
 pub mod typing;
+#[cfg(feature = "ui")]
+pub mod ui;
 pub mod webhook_routes;
 
-use axum::Router;
 use crate::state::AppState;
+use axum::Router;
+
+/// Whether requests to `path` send messages, whatever their method
+/// (`/v1/send-to` answers GET as well as POST).
+pub fn sends_messages(path: &str) -> bool {
+    ["/v2/send", "/v1/send", "/v1/send-to", "/v1/ask"]
+        .iter()
+        .any(|p| {
+            path.strip_prefix(p)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+}
 
 pub fn router(state: AppState) -> Router {
+    helpers::started_at();
+    let metrics_public = state.metrics_public;
+    let oidc = state.oidc.is_some();
+    #[cfg(feature = "ui")]
+    let ui = ui::routes();
+    #[cfg(not(feature = "ui"))]
+    let ui = Router::new();
+    let auth = axum::middleware::from_fn_with_state(state.clone(), crate::auth::require_auth);
+    let maintenance =
+        axum::middleware::from_fn_with_state(state.clone(), crate::maintenance::reject_writes);
+    let deadline = axum::middleware::from_fn_with_state(state.clone(), crate::deadline::propagate);
+    let shed = axum::middleware::from_fn_with_state(state.clone(), crate::shed::shed);
+    let case = axum::middleware::from_fn_with_state(state.clone(), crate::case::rename_keys);
     Router::new()
         .merge(system::routes())
         .merge(accounts::routes())
@@ -43,8 +80,34 @@ pub fn router(state: AppState) -> Router {
         .merge(config::routes())
         // Extras beyond bbernhard parity
         .merge(webhook_routes::routes())
+        .merge(command_routes::routes())
         .merge(events::routes())
-        .merge(metrics::routes())
+        .merge(history::routes())
+        .merge(ask::routes())
+        .merge(outbox::routes())
+        .merge(quarantine::routes())
+        .merge(auto_blocks::routes())
+        .merge(kv::routes())
+        .merge(topics::routes())
+        .merge(admin::routes())
+        .merge(tokens::routes())
+        .merge(if metrics_public {
+            metrics::routes()
+        } else {
+            Router::new()
+        })
+        .merge(if oidc {
+            auth_routes::routes()
+        } else {
+            Router::new()
+        })
         .merge(openapi::routes())
+        .merge(ui)
+        .layer(deadline)
+        .layer(shed)
+        .layer(maintenance)
+        .layer(auth)
+        .layer(case)
+        .layer(axum::middleware::from_fn(crate::privacy::redact_errors))
         .with_state(state)
 }
@@ -1,190 +1,688 @@
-use axum::response::{IntoResponse, Response};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::HeaderMap;
+use axum::response::Response;
 use axum::routing::get;
-use axum::{Json, Router};
-use serde_json::json;
+use axum::Router;
+use serde_json::{json, Value};
+use std::sync::OnceLock;
 
+use super::helpers::cacheable;
 use crate::state::AppState;
 
 pub fn routes() -> Router<AppState> {
     Router::new().route("/v1/openapi.json", get(openapi_spec))
 }
 
-async fn openapi_spec() -> Response {
-    let spec = json!({
-        "openapi": "3.0.3",
-        "info": {
-            "title": "signal-cli REST API",
-            "description": "REST API bridge for signal-cli",
-            "version": env!("CARGO_PKG_VERSION")
-        },
-        "paths": {
-            "/v2/send": {
-                "post": {
-                    "tags": ["Messages"],
-                    "summary": "Send a message",
-                    "operationId": "send",
-                    "requestBody": {
-                        "required": true,
+/// GET /v1/openapi.json — built once; it only changes with the binary.
+async fn openapi_spec(headers: HeaderMap) -> Response {
+    static SPEC: OnceLock<String> = OnceLock::new();
+    let body = SPEC.get_or_init(|| spec().to_string()).as_str();
+    cacheable(
+        &headers,
+        "public, max-age=3600",
+        ([(CONTENT_TYPE, "application/json")], body),
+    )
+}
+
+fn spec() -> Value {
+    let message = json!({
+        "account": "+15550001111",
+        "envelope": {
+            "source": "+15550002222",
+            "sourceNumber": "+15550002222",
+            "sourceName": "Alice",
+            "timestamp": 1700000000000u64,
+            "dataMessage": { "timestamp": 1700000000000u64, "message": "Hello" }
+        }
+    });
+    let webhook = json!({
+        "id": "3f6c2a9e-6d1b-4c1e-9a57-0c4f7e2b8d10",
+        "url": "https://example.com/signal",
+        "events": ["message"]
+    });
+    let paths = json!({
+        "/v2/send": {
+            "post": {
+                "tags": ["Messages"],
+                "summary": "Send a message",
+                "operationId": "send",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/SendPayload" },
+                            "example": { "number": "+15550001111", "recipients": ["+15550002222"], "message": "Hello" }
+                        }
+                    }
+                },
+                "responses": {
+                    "201": {
+                        "description": "Message sent",
                         "content": {
                             "application/json": {
-                                "schema": { "$ref": "#/components/schemas/SendPayload" }
+                                "schema": { "$ref": "#/components/schemas/SendResult" },
+                                "example": {
+                                    "timestamp": 1700000000000u64,
+                                    "recipients": [{ "recipient": "+15550002222", "status": "sent" }],
+                                    "summary": { "total": 1, "sent": 1, "failed": 0 }
+                                }
                             }
                         }
                     },
-                    "responses": {
-                        "201": { "description": "Message sent" },
-                        "400": { "description": "Invalid request" }
-                    }
+                    "207": {
+                        "description": "Some recipients could not be reached; see recipients and summary",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/SendResult" },
+                                "example": {
+                                    "timestamp": 1700000000000u64,
+                                    "recipients": [
+                                        { "recipient": "+15550002222", "status": "sent" },
+                                        { "recipient": "+15550003333", "status": "unregistered" }
+                                    ],
+                                    "summary": { "total": 2, "sent": 1, "failed": 1 }
+                                }
+                            }
+                        }
+                    },
+                    "400": { "description": "Invalid request" }
                 }
-            },
-            "/v1/receive/{number}": {
-                "get": {
-                    "tags": ["Messages"],
-                    "summary": "Receive messages",
-                    "operationId": "receive",
-                    "parameters": [{
-                        "name": "number",
-                        "in": "path",
-                        "required": true,
-                        "schema": { "type": "string" }
-                    }],
-                    "responses": {
-                        "200": { "description": "Array of messages" }
+            }
+        },
+        "/v1/receive/{number}": {
+            "get": {
+                "tags": ["Messages"],
+                "summary": "Receive messages",
+                "operationId": "receive",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Array of messages",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Message" } },
+                                "example": [message]
+                            }
+                        }
                     }
                 }
-            },
-            "/v1/health": {
-                "get": {
-                    "tags": ["System"],
-                    "summary": "Health check",
-                    "operationId": "health",
-                    "responses": {
-                        "204": { "description": "Healthy" }
+            }
+        },
+        "/v1/health": {
+            "get": {
+                "tags": ["System"],
+                "summary": "Health check",
+                "operationId": "health",
+                "responses": {
+                    "204": { "description": "Healthy" }
+                }
+            }
+        },
+        "/v1/about": {
+            "get": {
+                "tags": ["System"],
+                "summary": "API version info",
+                "operationId": "about",
+                "responses": {
+                    "200": {
+                        "description": "Version information",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "object" },
+                                "example": {
+                                    "versions": { "signal-cli-api": env!("CARGO_PKG_VERSION") },
+                                    "build": { "target": "x86_64", "os": "linux" },
+                                    "daemon": { "managed": false }
+                                }
+                            }
+                        }
                     }
                 }
-            },
-            "/v1/about": {
-                "get": {
-                    "tags": ["System"],
-                    "summary": "API version info",
-                    "operationId": "about",
-                    "responses": {
-                        "200": { "description": "Version information" }
+            }
+        },
+        "/v1/groups/{number}": {
+            "get": {
+                "tags": ["Groups"],
+                "summary": "List groups for an account",
+                "operationId": "listGroups",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "Array of groups",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": { "$ref": "#/components/schemas/Group" } },
+                                "example": [{
+                                    "id": "ZXhhbXBsZS1ncm91cC1pZC0xMjM0NTY3ODkwYWJjZGU=",
+                                    "name": "On-call",
+                                    "description": null,
+                                    "members": [{ "number": "+15550001111" }, { "number": "+15550002222" }],
+                                    "admins": [{ "number": "+15550001111" }],
+                                    "memberCount": 2,
+                                    "isAdmin": true,
+                                    "isBlocked": false,
+                                    "inviteLink": null,
+                                    "expirationTimer": 0
+                                }]
+                            }
+                        }
                     }
                 }
-            },
-            "/v1/groups/{number}": {
-                "get": {
-                    "tags": ["Groups"],
-                    "summary": "List groups for an account",
-                    "operationId": "listGroups",
-                    "parameters": [{
-                        "name": "number",
-                        "in": "path",
-                        "required": true,
-                        "schema": { "type": "string" }
-                    }],
-                    "responses": {
-                        "200": { "description": "Array of groups" }
+            }
+        },
+        "/v1/webhooks": {
+            "get": {
+                "tags": ["Webhooks"],
+                "summary": "List registered webhooks",
+                "operationId": "listWebhooks",
+                "responses": {
+                    "200": {
+                        "description": "Array of webhook configs",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": { "$ref": "#/components/schemas/WebhookConfig" } },
+                                "example": [webhook]
+                            }
+                        }
                     }
                 }
             },
-            "/v1/webhooks": {
-                "get": {
-                    "tags": ["Webhooks"],
-                    "summary": "List registered webhooks",
-                    "operationId": "listWebhooks",
-                    "responses": {
-                        "200": { "description": "Array of webhook configs" }
+            "post": {
+                "tags": ["Webhooks"],
+                "summary": "Register a webhook",
+                "operationId": "createWebhook",
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/WebhookConfig" },
+                            "example": { "url": "https://example.com/signal", "events": ["message"] }
+                        }
                     }
                 },
-                "post": {
-                    "tags": ["Webhooks"],
-                    "summary": "Register a webhook",
-                    "operationId": "createWebhook",
-                    "requestBody": {
-                        "required": true,
+                "responses": {
+                    "201": {
+                        "description": "Webhook registered",
                         "content": {
                             "application/json": {
-                                "schema": { "$ref": "#/components/schemas/WebhookConfig" }
+                                "schema": { "$ref": "#/components/schemas/WebhookConfig" },
+                                "example": webhook
                             }
                         }
                     },
-                    "responses": {
-                        "201": { "description": "Webhook registered" }
+                    "400": { "description": "Invalid webhook configuration" },
+                    "409": { "description": "A webhook with that URL already exists" }
+                }
+            }
+        },
+        "/v1/webhooks/{id}": {
+            "put": {
+                "tags": ["Webhooks"],
+                "summary": "Replace a webhook's configuration",
+                "operationId": "updateWebhook",
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/WebhookConfig" },
+                            "example": { "url": "https://example.com/signal", "events": ["message"] }
+                        }
                     }
+                },
+                "responses": {
+                    "200": {
+                        "description": "Webhook updated",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/WebhookConfig" },
+                                "example": webhook
+                            }
+                        }
+                    },
+                    "400": { "description": "Invalid webhook configuration" },
+                    "404": { "description": "Webhook not found" }
                 }
             },
-            "/v1/events/{number}": {
-                "get": {
-                    "tags": ["Events"],
-                    "summary": "Server-Sent Events stream",
-                    "operationId": "sseEvents",
-                    "parameters": [{
-                        "name": "number",
-                        "in": "path",
-                        "required": true,
-                        "schema": { "type": "string" }
-                    }],
-                    "responses": {
-                        "200": { "description": "SSE stream of messages" }
+            "delete": {
+                "tags": ["Webhooks"],
+                "summary": "Remove a webhook",
+                "operationId": "deleteWebhook",
+                "parameters": [{
+                    "name": "id",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "204": { "description": "Webhook removed" },
+                    "404": { "description": "Webhook not found" }
+                }
+            }
+        },
+        "/v1/webhooks/{id}/replay": {
+            "post": {
+                "tags": ["Webhooks"],
+                "summary": "Re-deliver stored messages to a webhook",
+                "operationId": "replayWebhook",
+                "parameters": [
+                    { "name": "id", "in": "path", "required": true, "schema": { "type": "string" } },
+                    { "name": "from", "in": "query", "required": true, "schema": { "type": "integer" } },
+                    { "name": "to", "in": "query", "required": false, "schema": { "type": "integer" } }
+                ],
+                "responses": {
+                    "202": {
+                        "description": "Replay started, with the number of events",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": { "events": { "type": "integer" } }
+                                },
+                                "example": { "events": 12 }
+                            }
+                        }
+                    },
+                    "400": { "description": "Invalid time range" },
+                    "404": { "description": "Webhook not found" }
+                }
+            }
+        },
+        "/v1/typing-indicator/{number}": {
+            "put": {
+                "tags": ["Messages"],
+                "summary": "Show typing indicator",
+                "operationId": "startTyping",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/TypingIndicatorRequest" },
+                            "example": { "recipient": "+15550002222" }
+                        }
                     }
+                },
+                "responses": {
+                    "204": { "description": "Typing indicator shown" },
+                    "400": { "description": "Invalid request" }
                 }
             },
-            "/metrics": {
-                "get": {
-                    "tags": ["System"],
-                    "summary": "Prometheus metrics",
-                    "operationId": "metrics",
-                    "responses": {
-                        "200": {
-                            "description": "Prometheus-formatted metrics",
-                            "content": {
-                                "text/plain": {
-                                    "schema": { "type": "string" }
+            "delete": {
+                "tags": ["Messages"],
+                "summary": "Hide typing indicator",
+                "operationId": "stopTyping",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/TypingIndicatorRequest" },
+                            "example": { "recipient": "+15550002222" }
+                        }
+                    }
+                },
+                "responses": {
+                    "204": { "description": "Typing indicator hidden" },
+                    "400": { "description": "Invalid request" }
+                }
+            }
+        },
+        "/v1/receipts/{number}": {
+            "post": {
+                "tags": ["Messages"],
+                "summary": "Send a read or viewed receipt",
+                "operationId": "sendReceipt",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/ReceiptRequest" },
+                            "example": { "recipient": "+15550002222", "timestamp": 1700000000000u64, "receipt_type": "read" }
+                        }
+                    }
+                },
+                "responses": {
+                    "204": { "description": "Receipt sent" },
+                    "400": { "description": "Invalid request" }
+                }
+            }
+        },
+        "/v1/events/{number}": {
+            "get": {
+                "tags": ["Events"],
+                "summary": "Server-Sent Events stream",
+                "operationId": "sseEvents",
+                "parameters": [{
+                    "name": "number",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "responses": {
+                    "200": {
+                        "description": "SSE stream of messages",
+                        "content": {
+                            "text/event-stream": {
+                                "schema": { "type": "string" },
+                                "example": format!("data: {message}\n\n")
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        "/v1/events/cursor/{consumer}": {
+            "get": {
+                "tags": ["Events"],
+                "summary": "Fetch the events after a pull consumer's cursor",
+                "operationId": "fetchEvents",
+                "parameters": [
+                    { "name": "consumer", "in": "path", "required": true, "schema": { "type": "string" } },
+                    { "name": "limit", "in": "query", "required": false, "schema": { "type": "integer" } }
+                ],
+                "responses": {
+                    "200": {
+                        "description": "Cursor and the events after it",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": {
+                                        "consumer": { "type": "string" },
+                                        "cursor": { "type": "integer", "description": "Sequence number acknowledged last" },
+                                        "missed": { "type": "integer", "description": "Events dropped before they were fetched" },
+                                        "events": {
+                                            "type": "array",
+                                            "items": {
+                                                "type": "object",
+                                                "properties": {
+                                                    "seq": { "type": "integer" },
+                                                    "event": { "$ref": "#/components/schemas/Message" }
+                                                }
+                                            }
+                                        }
+                                    }
+                                },
+                                "example": {
+                                    "consumer": "billing",
+                                    "cursor": 41,
+                                    "missed": 0,
+                                    "events": [{ "seq": 42, "event": message }]
                                 }
                             }
                         }
+                    },
+                    "400": { "description": "Invalid consumer name or limit" }
+                }
+            }
+        },
+        "/v1/events/cursor/{consumer}/ack": {
+            "post": {
+                "tags": ["Events"],
+                "summary": "Acknowledge events up to a sequence number",
+                "operationId": "ackEvents",
+                "parameters": [{
+                    "name": "consumer",
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" }
+                }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "required": ["seq"],
+                                "properties": { "seq": { "type": "integer" } }
+                            },
+                            "example": { "seq": 42 }
+                        }
                     }
+                },
+                "responses": {
+                    "200": {
+                        "description": "The consumer's new cursor",
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "object",
+                                    "properties": { "consumer": { "type": "string" }, "cursor": { "type": "integer" } }
+                                },
+                                "example": { "consumer": "billing", "cursor": 42 }
+                            }
+                        }
+                    },
+                    "400": { "description": "seq is past the newest event" },
+                    "404": { "description": "Consumer not found" }
                 }
             }
         },
-        "components": {
-            "schemas": {
-                "SendPayload": {
+        "/metrics": {
+            "get": {
+                "tags": ["System"],
+                "summary": "Prometheus metrics",
+                "operationId": "metrics",
+                "responses": {
+                    "200": {
+                        "description": "Prometheus-formatted metrics",
+                        "content": {
+                            "text/plain": {
+                                "schema": { "type": "string" },
+                                "example": "# HELP signal_messages_sent_total Messages sent\n# TYPE signal_messages_sent_total counter\nsignal_messages_sent_total 42\n"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    });
+    let schemas = json!({
+        "Error": {
+            "type": "object",
+            "description": "Body of every 4xx and 5xx response. The HTTP status is the error code; some errors add fields with details, such as the id of a conflicting webhook",
+            "required": ["error"],
+            "properties": {
+                "error": { "type": "string", "description": "What went wrong" }
+            },
+            "additionalProperties": true,
+            "example": { "error": "priority must be one of: high, normal, low" }
+        },
+        "SendPayload": {
+            "type": "object",
+            "required": ["message", "number", "recipients"],
+            "properties": {
+                "message": { "type": "string", "description": "Message text" },
+                "number": { "type": "string", "description": "Sender account number" },
+                "recipients": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Recipient numbers"
+                },
+                "base64_attachments": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Base64-encoded attachments"
+                },
+                "mention": {
+                    "oneOf": [
+                        { "type": "string", "enum": ["all"] },
+                        { "type": "array", "items": { "type": "string" } }
+                    ],
+                    "description": "\"all\" mentions every other member of the group; a list of start:length:recipient is passed to signal-cli"
+                },
+                "wait_for_delivery_ms": {
+                    "type": "integer",
+                    "maximum": 60000,
+                    "description": "Wait this long for the recipients' delivery receipts before answering"
+                }
+            }
+        },
+        "SendResult": {
+            "type": "object",
+            "properties": {
+                "timestamp": { "type": "integer", "format": "int64", "description": "Timestamp of the sent message" },
+                "recipients": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "recipient": { "type": "string" },
+                            "status": {
+                                "type": "string",
+                                "enum": ["sent", "untrusted_identity", "unregistered", "rate_limited", "network_failure", "proof_required", "failed"]
+                            },
+                            "hint": { "type": "string" },
+                            "retryAfterSeconds": { "type": "integer" }
+                        }
+                    }
+                },
+                "summary": {
                     "type": "object",
-                    "required": ["message", "number", "recipients"],
                     "properties": {
-                        "message": { "type": "string", "description": "Message text" },
-                        "number": { "type": "string", "description": "Sender account number" },
-                        "recipients": {
-                            "type": "array",
-                            "items": { "type": "string" },
-                            "description": "Recipient numbers"
-                        },
-                        "base64_attachments": {
-                            "type": "array",
-                            "items": { "type": "string" },
-                            "description": "Base64-encoded attachments"
-                        }
+                        "total": { "type": "integer" },
+                        "sent": { "type": "integer" },
+                        "failed": { "type": "integer" }
                     }
                 },
-                "WebhookConfig": {
+                "delivery": {
                     "type": "object",
-                    "required": ["url"],
+                    "description": "With wait_for_delivery_ms: recipients whose delivery receipt arrived, and those still pending",
                     "properties": {
-                        "id": { "type": "string", "description": "Webhook ID (server-generated)" },
-                        "url": { "type": "string", "description": "Callback URL" },
-                        "events": {
-                            "type": "array",
-                            "items": { "type": "string" },
-                            "description": "Event types to subscribe to (empty = all)"
-                        }
+                        "delivered": { "type": "array", "items": { "type": "string" } },
+                        "pending": { "type": "array", "items": { "type": "string" } },
+                        "waited_ms": { "type": "integer" }
                     }
                 }
             }
+        },
+        "Message": {
+            "type": "object",
+            "description": "A signal-cli envelope with the account that received it",
+            "properties": {
+                "account": { "type": "string" },
+                "envelope": { "type": "object" }
+            }
+        },
+        "Group": {
+            "type": "object",
+            "description": "signal-cli's listGroups entry plus normalized fields; other signal-cli fields pass through",
+            "properties": {
+                "id": { "type": "string", "description": "Group ID (standard base64)" },
+                "name": { "type": "string" },
+                "description": { "type": "string", "nullable": true },
+                "members": { "type": "array", "items": {} },
+                "admins": { "type": "array", "items": {} },
+                "memberCount": { "type": "integer", "description": "Number of members" },
+                "isAdmin": { "type": "boolean", "description": "Whether the account is an admin of the group" },
+                "isBlocked": { "type": "boolean", "description": "Whether the account has blocked the group" },
+                "inviteLink": { "type": "string", "nullable": true, "description": "Group invite link, if enabled" },
+                "expirationTimer": { "type": "integer", "description": "Disappearing-message timer in seconds (0 = off)" }
+            }
+        },
+        "TypingIndicatorRequest": {
+            "type": "object",
+            "properties": {
+                "recipient": { "type": "string", "description": "Phone number, or a group as group.<base64>" },
+                "group-id": { "type": "string", "description": "Group ID (takes precedence over recipient)" }
+            }
+        },
+        "ReceiptRequest": {
+            "type": "object",
+            "required": ["recipient", "timestamp"],
+            "properties": {
+                "receipt_type": { "type": "string", "enum": ["read", "viewed"], "default": "read" },
+                "recipient": { "type": "string", "description": "Author of the message being acknowledged" },
+                "timestamp": { "type": "integer", "format": "int64", "description": "Timestamp of the message being acknowledged" }
+            }
+        },
+        "WebhookConfig": {
+            "type": "object",
+            "required": ["url"],
+            "properties": {
+                "id": { "type": "string", "description": "Webhook ID (server-generated)" },
+                "url": { "type": "string", "description": "Callback URL" },
+                "events": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Event types to subscribe to (empty = all)"
+                },
+                "max_concurrency": { "type": "integer", "minimum": 1, "description": "Maximum concurrent deliveries (default 16)" },
+                "ordered": { "type": "boolean", "description": "Deliver one at a time, in event order" },
+                "headers": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" },
+                    "description": "Static headers sent with every delivery"
+                },
+                "client_cert": { "type": "string", "description": "PEM client certificate for mTLS, a file in --webhook-cert-dir" },
+                "client_key": { "type": "string", "description": "PEM private key for mTLS, a file in --webhook-cert-dir" },
+                "timeout_ms": { "type": "integer", "minimum": 1, "description": "Per-delivery timeout in milliseconds" },
+                "raw": { "type": "boolean", "description": "Deliver signal-cli envelopes verbatim instead of the normalized format" }
+            }
         }
     });
+    let mut spec = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "signal-cli REST API",
+            "description": "REST API bridge for signal-cli",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths,
+        "components": { "schemas": schemas }
+    });
 
-    Json(spec).into_response()
+    // Every error response has the same body.
+    for path in spec["paths"]
+        .as_object_mut()
+        .into_iter()
+        .flat_map(|p| p.values_mut())
+    {
+        for operation in path
+            .as_object_mut()
+            .into_iter()
+            .flat_map(|p| p.values_mut())
+        {
+            for (status, response) in operation["responses"].as_object_mut().into_iter().flatten() {
+                if status.starts_with('4') || status.starts_with('5') {
+                    response["content"] = json!({
+                        "application/json": { "schema": { "$ref": "#/components/schemas/Error" } }
+                    });
+                }
+            }
+        }
+    }
+    spec
 }
@@ -0,0 +1,93 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Extension, Json, Router};
+use serde_json::json;
+
+use super::helpers::Account;
+use crate::jwt::Grant;
+use crate::outbox::{QueuedMessage, QuietHours};
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/v1/outbox", get(list_outbox))
+        .route("/v1/outbox/{id}", delete(cancel_queued))
+        .route(
+            "/v1/accounts/{number}/quiet-hours",
+            get(get_quiet_hours)
+                .put(set_quiet_hours)
+                .delete(delete_quiet_hours),
+        )
+}
+
+/// Whether the caller may see a queued message: a JWT limited to some
+/// accounts only sees those accounts' messages.
+fn allowed(st: &AppState, grant: Option<&Grant>, msg: &QueuedMessage) -> bool {
+    grant.is_none_or(|grant| {
+        grant.allows_account(&st.resolve_account(&msg.account), |a| st.resolve_account(a))
+    })
+}
+
+/// GET /v1/outbox — messages accepted but not yet sent.
+async fn list_outbox(State(st): State<AppState>, grant: Option<Extension<Grant>>) -> Response {
+    let queued: Vec<QueuedMessage> = st
+        .outbox
+        .list()
+        .into_iter()
+        .filter(|msg| allowed(&st, grant.as_deref(), msg))
+        .collect();
+    Json(queued).into_response()
+}
+
+/// DELETE /v1/outbox/{id} — drop a queued message before it is sent. 404
+/// once it has been released, or when it belongs to an account the caller
+/// may not use.
+async fn cancel_queued(
+    State(st): State<AppState>,
+    grant: Option<Extension<Grant>>,
+    Path(id): Path<String>,
+) -> Response {
+    match st
+        .outbox
+        .cancel(&id, |msg| allowed(&st, grant.as_deref(), msg))
+    {
+        Some(msg) => {
+            tracing::info!(id, account = msg.account, "Queued message cancelled");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => {
+            let error = format!("no queued message {id}");
+            (StatusCode::NOT_FOUND, Json(json!({ "error": error }))).into_response()
+        }
+    }
+}
+
+async fn get_quiet_hours(State(st): State<AppState>, Account(number): Account) -> Response {
+    match st.quiet_hours.read().await.get(&number) {
+        Some(q) => Json(q.clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// PUT /v1/accounts/{number}/quiet-hours — non-urgent sends inside the window
+/// are queued until it closes.
+async fn set_quiet_hours(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Json(body): Json<QuietHours>,
+) -> Response {
+    if let Err(e) = body.validate() {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response();
+    }
+    st.quiet_hours.write().await.insert(number, body.clone());
+    Json(body).into_response()
+}
+
+async fn delete_quiet_hours(State(st): State<AppState>, Account(number): Account) -> Response {
+    match st.quiet_hours.write().await.remove(&number) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
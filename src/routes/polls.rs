@@ -1,14 +1,13 @@
 use axum::{
-    Router,
-    extract::{Path, State},
+    extract::State,
     response::Response,
     routing::{delete, post},
-    Json,
+    Json, Router,
 };
 use serde_json::{json, Value};
 
+use super::helpers::{rpc_created, rpc_ok, Account};
 use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_created};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -20,7 +19,7 @@ pub fn routes() -> Router<AppState> {
 /// POST /v1/polls/{number} — create and send a poll.
 async fn create_poll(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
@@ -31,7 +30,7 @@ async fn create_poll(
 /// POST /v1/polls/{number}/vote — vote on an existing poll.
 async fn vote_poll(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
@@ -42,7 +41,7 @@ async fn vote_poll(
 /// DELETE /v1/polls/{number} — close a poll.
 async fn close_poll(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
@@ -1,16 +1,15 @@
-use axum::extract::{Path, State};
+use axum::extract::State;
 use axum::response::Response;
 use axum::routing::put;
 use axum::{Json, Router};
 use serde::Deserialize;
 use serde_json::json;
 
+use super::helpers::{rpc_ok, Account};
 use crate::state::AppState;
-use super::helpers::rpc_ok;
 
 pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/v1/profiles/{number}", put(update_profile))
+    Router::new().route("/v1/profiles/{number}", put(update_profile))
 }
 
 #[derive(Deserialize)]
@@ -22,7 +21,7 @@ struct UpdateProfileBody {
 
 async fn update_profile(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<UpdateProfileBody>,
 ) -> Response {
     let mut params = json!({ "account": number });
@@ -0,0 +1,38 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use super::helpers::Account;
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/quarantine/{number}", get(list).delete(clear))
+}
+
+fn not_enabled() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": "no spam rules configured (see --spam-rule)" })),
+    )
+        .into_response()
+}
+
+/// GET /v1/quarantine/{number} — incoming messages that matched a spam rule,
+/// tagged or dropped, oldest first.
+async fn list(State(st): State<AppState>, Account(number): Account) -> Response {
+    match &st.spam {
+        Some(spam) => Json(spam.quarantined(&number)).into_response(),
+        None => not_enabled(),
+    }
+}
+
+/// DELETE /v1/quarantine/{number} — empty the account's quarantine.
+async fn clear(State(st): State<AppState>, Account(number): Account) -> Response {
+    match &st.spam {
+        Some(spam) => Json(json!({ "removed": spam.clear(&number) })).into_response(),
+        None => not_enabled(),
+    }
+}
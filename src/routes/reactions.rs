@@ -1,14 +1,14 @@
 use axum::{
-    Router,
-    extract::{Path, State},
+    extract::State,
     response::Response,
     routing::{delete, post},
-    Json,
+    Json, Router,
 };
 use serde_json::{json, Value};
 
+use super::helpers::{rpc_created, rpc_no_content, Account};
+use crate::group_id;
 use crate::state::AppState;
-use super::helpers::{rpc_created, rpc_no_content};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -19,21 +19,51 @@ pub fn routes() -> Router<AppState> {
 /// POST /v1/reactions/{number} — send a reaction to a message.
 async fn send_reaction(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
     params["account"] = json!(number);
-    rpc_created(&st, "sendReaction", params).await
+    group_id::normalize_params(&mut params);
+    let response = rpc_created(&st, "sendReaction", params.clone()).await;
+    if response.status().is_success() {
+        record_reaction(&st, &number, &params, false);
+    }
+    response
 }
 
 /// DELETE /v1/reactions/{number} — remove a reaction from a message.
 async fn remove_reaction(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
     params["account"] = json!(number);
-    rpc_no_content(&st, "removeReaction", params).await
+    group_id::normalize_params(&mut params);
+    let response = rpc_no_content(&st, "removeReaction", params.clone()).await;
+    if response.status().is_success() {
+        record_reaction(&st, &number, &params, true);
+    }
+    response
+}
+
+/// Mirror a reaction sent by this account onto the stored target message.
+fn record_reaction(st: &AppState, number: &str, params: &Value, remove: bool) {
+    let emoji = params
+        .get("reaction")
+        .or_else(|| params.get("emoji"))
+        .and_then(|v| v.as_str());
+    let author = params
+        .get("target_author")
+        .or_else(|| params.get("targetAuthor"))
+        .and_then(|v| v.as_str());
+    let timestamp = params
+        .get("timestamp")
+        .or_else(|| params.get("targetTimestamp"))
+        .and_then(|v| v.as_u64());
+    if let (Some(emoji), Some(author), Some(timestamp)) = (emoji, author, timestamp) {
+        st.store
+            .record_reaction(number, number, emoji, author, timestamp, remove);
+    }
 }
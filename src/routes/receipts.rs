@@ -1,27 +1,58 @@
 use axum::{
-    Router,
-    extract::{Path, State},
-    response::Response,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     routing::post,
-    Json,
+    Json, Router,
 };
-use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use super::helpers::{rpc_no_content, Account, JsonBody};
 use crate::state::AppState;
-use super::helpers::rpc_ok;
 
 pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/v1/receipts/{number}", post(send_receipt))
+    Router::new().route("/v1/receipts/{number}", post(send_receipt))
 }
 
-/// POST /v1/receipts/{number} — send a read/delivery receipt.
+/// Receipts a client can send; delivery receipts are sent by signal-cli itself.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ReceiptType {
+    #[default]
+    Read,
+    Viewed,
+}
+
+/// Body of POST /v1/receipts (bbernhard's `ReceiptRequest`). Receipts go to
+/// the author of the message, also for messages received in a group.
+#[derive(Deserialize)]
+struct ReceiptBody {
+    #[serde(default)]
+    receipt_type: ReceiptType,
+    recipient: String,
+    /// Timestamp of the message being acknowledged.
+    timestamp: u64,
+}
+
+/// POST /v1/receipts/{number} — send a read/viewed receipt.
 async fn send_receipt(
     State(st): State<AppState>,
-    Path(number): Path<String>,
-    Json(body): Json<Value>,
+    Account(number): Account,
+    JsonBody(body): JsonBody<ReceiptBody>,
 ) -> Response {
-    let mut params = body;
-    params["account"] = json!(number);
-    rpc_ok(&st, "sendReceipt", params).await
+    if body.recipient.starts_with("group.") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "receipts are sent to the message author, not a group" })),
+        )
+            .into_response();
+    }
+    let params = json!({
+        "account": number,
+        "recipient": body.recipient,
+        "targetTimestamp": [body.timestamp],
+        "type": body.receipt_type,
+    });
+    rpc_no_content(&st, "sendReceipt", params).await
 }
@@ -1,18 +1,17 @@
 use axum::{
-    Router,
-    extract::{Path, Query, State},
+    extract::{Query, State},
     response::Response,
     routing::get,
+    Router,
 };
 use serde::Deserialize;
 use serde_json::json;
 
+use super::helpers::{rpc_ok, Account};
 use crate::state::AppState;
-use super::helpers::rpc_ok;
 
 pub fn routes() -> Router<AppState> {
-    Router::new()
-        .route("/v1/search/{number}", get(search_numbers))
+    Router::new().route("/v1/search/{number}", get(search_numbers))
 }
 
 #[derive(Deserialize)]
@@ -24,9 +23,14 @@ struct SearchQuery {
 /// GET /v1/search/{number}?numbers=... — check if phone numbers are registered on Signal.
 async fn search_numbers(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Query(q): Query<SearchQuery>,
 ) -> Response {
     let recipients: Vec<&str> = q.numbers.split(',').filter(|s| !s.is_empty()).collect();
-    rpc_ok(&st, "getUserStatus", json!({ "account": number, "recipient": recipients })).await
+    rpc_ok(
+        &st,
+        "getUserStatus",
+        json!({ "account": number, "recipient": recipients }),
+    )
+    .await
 }
@@ -1,36 +1,103 @@
 use axum::{
-    Router,
     extract::{Path, State},
-    response::Response,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, post},
-    Json,
+    Json, Router,
 };
+use base64::Engine;
 use serde_json::{json, Value};
 
-use crate::state::AppState;
-use super::helpers::{rpc_ok, rpc_created};
+use super::helpers::{rpc_created, rpc_ok, Account};
+use crate::attachments;
+use crate::state::{rpc_error_status, AppState};
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/sticker-packs/{number}", get(list_sticker_packs))
         .route("/v1/sticker-packs/{number}", post(install_sticker_pack))
+        .route(
+            "/v1/sticker-packs/{number}/{pack_id}",
+            get(get_sticker_pack),
+        )
+        .route(
+            "/v1/sticker-packs/{number}/{pack_id}/stickers/{sticker_id}",
+            get(get_sticker),
+        )
 }
 
 /// GET /v1/sticker-packs/{number} — list installed sticker packs.
-async fn list_sticker_packs(
-    State(st): State<AppState>,
-    Path(number): Path<String>,
-) -> Response {
+async fn list_sticker_packs(State(st): State<AppState>, Account(number): Account) -> Response {
     rpc_ok(&st, "listStickerPacks", json!({ "account": number })).await
 }
 
 /// POST /v1/sticker-packs/{number} — install a sticker pack.
 async fn install_sticker_pack(
     State(st): State<AppState>,
-    Path(number): Path<String>,
+    Account(number): Account,
     Json(body): Json<Value>,
 ) -> Response {
     let mut params = body;
     params["account"] = json!(number);
     rpc_created(&st, "uploadStickerPack", params).await
 }
+
+/// GET /v1/sticker-packs/{number}/{pack_id} — manifest of an installed pack
+/// (title, author, cover and stickers with their emojis).
+async fn get_sticker_pack(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, pack_id)): Path<(String, String)>,
+) -> Response {
+    let packs = match st
+        .rpc("listStickerPacks", json!({ "account": number }))
+        .await
+    {
+        Ok(packs) => packs,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    let pack = packs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|p| p.get("packId").and_then(|v| v.as_str()) == Some(pack_id.as_str()));
+    match pack {
+        Some(pack) => Json(pack.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "sticker pack not installed" })),
+        )
+            .into_response(),
+    }
+}
+
+/// GET /v1/sticker-packs/{number}/{pack_id}/stickers/{sticker_id} — the
+/// decrypted sticker image. Stickers never change, so it is cacheable forever.
+async fn get_sticker(
+    State(st): State<AppState>,
+    Account(number): Account,
+    Path((_, pack_id, sticker_id)): Path<(String, String, u32)>,
+) -> Response {
+    let params = json!({ "account": number, "packId": pack_id, "stickerId": sticker_id });
+    let result = match st.rpc("getSticker", params).await {
+        Ok(result) => result,
+        Err(e) => return (rpc_error_status(&e), Json(json!({ "error": e }))).into_response(),
+    };
+    // signal-cli returns the image base64-encoded, bare or as {"data": ...}.
+    let encoded = result.get("data").unwrap_or(&result).as_str().unwrap_or("");
+    let Ok(image) = base64::engine::general_purpose::STANDARD.decode(encoded) else {
+        return (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({ "error": "signal-cli returned an undecodable sticker" })),
+        )
+            .into_response();
+    };
+    (
+        [
+            (header::CONTENT_TYPE, attachments::sniff(&image)),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        image,
+    )
+        .into_response()
+}
@@ -1,30 +1,75 @@
-use axum::http::StatusCode;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::{Json, Router};
 use serde_json::json;
 
+use super::helpers::cacheable;
+use crate::compat::Compat;
 use crate::state::AppState;
 
+/// `/v1/about` only changes with a restart, but is behind auth, so only the
+/// client may cache it.
+const ABOUT_CACHE: &str = "private, max-age=300";
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/health", get(health))
         .route("/v1/about", get(about))
+        .route("/v1/versions", get(versions))
 }
 
 async fn health() -> Response {
     StatusCode::NO_CONTENT.into_response()
 }
 
-async fn about() -> Response {
+/// GET /v1/about — API and build information. In `--compat bbernhard` mode
+/// this is bbernhard's shape, which clients use to detect the API.
+async fn about(State(st): State<AppState>, headers: HeaderMap) -> Response {
+    if st.compat == Some(Compat::Bbernhard) {
+        return cacheable(&headers, ABOUT_CACHE, Json(crate::compat::about()));
+    }
+    let daemon = match &st.daemon_options {
+        Some(options) => json!({ "managed": true, "options": options }),
+        None => json!({ "managed": false }),
+    };
     let info = json!({
         "versions": {
             "signal-cli-api": env!("CARGO_PKG_VERSION"),
+            "signal-cli": st.signal_cli_version.get().map(|v| v.to_string()),
         },
         "build": {
             "target": std::env::consts::ARCH,
             "os": std::env::consts::OS,
-        }
+        },
+        "daemon": daemon,
+    });
+    cacheable(&headers, ABOUT_CACHE, Json(info))
+}
+
+/// GET /v1/versions — supported API versions and how they differ.
+async fn versions(State(st): State<AppState>) -> Response {
+    let info = json!({
+        "current": "v2",
+        "versions": [
+            {
+                "version": "v1",
+                "status": if st.deprecated_routes { "deprecated" } else { "disabled" },
+                "sunset": super::messages::V1_SUNSET,
+                "deprecated_endpoints": [
+                    {
+                        "endpoint": "POST /v1/send",
+                        "successor": "POST /v2/send",
+                        "differences": "v1 sends are not counted in signal_messages_sent_total",
+                    }
+                ],
+            },
+            {
+                "version": "v2",
+                "status": "current",
+            }
+        ],
     });
     Json(info).into_response()
 }
@@ -0,0 +1,48 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde_json::json;
+
+use crate::auth::TokenId;
+use crate::quota::DAY_MS;
+use crate::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/tokens/{id}/usage", get(usage))
+}
+
+/// GET /v1/tokens/{id}/usage — today's sends and attachment bytes for an API
+/// token, with its limits (null = unlimited). Days are UTC. An API token
+/// may only read its own; a JWT needs the admin scope.
+async fn usage(
+    State(st): State<AppState>,
+    token: Option<Extension<TokenId>>,
+    Path(id): Path<String>,
+) -> Response {
+    if token.is_some_and(|Extension(TokenId(own))| own != id) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({ "error": "a token may only read its own usage" })),
+        )
+            .into_response();
+    }
+    if !st.tokens.read().await.iter().any(|t| t.id == id) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Token not found" })),
+        )
+            .into_response();
+    }
+    let usage = st.quotas.usage(&id);
+    let quota = st.quotas.quotas.get(&id).copied().unwrap_or_default();
+    Json(json!({
+        "id": id,
+        "period_start": usage.day * DAY_MS,
+        "resets_at": (usage.day + 1) * DAY_MS,
+        "messages": { "used": usage.messages, "limit": quota.messages },
+        "attachment_bytes": { "used": usage.attachment_bytes, "limit": quota.attachment_bytes },
+    }))
+    .into_response()
+}
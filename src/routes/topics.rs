@@ -0,0 +1,84 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::helpers::JsonBody;
+use crate::case::DataKeys;
+use crate::group_id::GroupId;
+use crate::state::AppState;
+use crate::topics::{valid_name, Topic};
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/v1/topics", get(list_topics)).route(
+        "/v1/topics/{name}",
+        get(get_topic).put(put_topic).delete(delete_topic),
+    )
+}
+
+#[derive(Deserialize)]
+struct TopicSpec {
+    group_id: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+fn no_topic(name: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(json!({ "error": format!("no topic {name:?}") })),
+    )
+        .into_response()
+}
+
+/// GET /v1/topics — every topic with its group.
+async fn list_topics(State(st): State<AppState>) -> Response {
+    (
+        Extension(DataKeys(&[""])),
+        Json(st.topics.read().await.clone()),
+    )
+        .into_response()
+}
+
+async fn get_topic(State(st): State<AppState>, Path(name): Path<String>) -> Response {
+    match st.topics.read().await.get(&name) {
+        Some(topic) => Json(topic.clone()).into_response(),
+        None => no_topic(&name),
+    }
+}
+
+/// PUT /v1/topics/{name} — point a topic at a group (`{"group_id": ...}`),
+/// creating it (201) or replacing its group (200).
+async fn put_topic(
+    State(st): State<AppState>,
+    Path(name): Path<String>,
+    JsonBody(body): JsonBody<TopicSpec>,
+) -> Response {
+    if !valid_name(&name) {
+        let error = "topic must be 1-64 letters, digits, '-' or '_'";
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    }
+    let Some(group_id) = GroupId::parse(&body.group_id) else {
+        let error = format!("invalid group ID {:?}", body.group_id);
+        return (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response();
+    };
+    let topic = Topic {
+        group_id,
+        description: body.description,
+    };
+    let status = match st.topics.write().await.insert(name, topic.clone()) {
+        Some(_) => StatusCode::OK,
+        None => StatusCode::CREATED,
+    };
+    (status, Json(topic)).into_response()
+}
+
+async fn delete_topic(State(st): State<AppState>, Path(name): Path<String>) -> Response {
+    match st.topics.write().await.remove(&name) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => no_topic(&name),
+    }
+}
@@ -1,14 +1,16 @@
 use axum::{
-    Router,
-    extract::{Path, State},
-    response::Response,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     routing::{delete, put},
-    Json,
+    Json, Router,
 };
-use serde_json::{json, Value};
+use serde::Deserialize;
+use serde_json::json;
 
+use super::helpers::{rpc_no_content, Account, JsonBody, Recipient};
+use crate::group_id::GroupId;
 use crate::state::AppState;
-use super::helpers::rpc_no_content;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
@@ -16,26 +18,40 @@ pub fn routes() -> Router<AppState> {
         .route("/v1/typing-indicator/{number}", delete(stop_typing))
 }
 
+/// Body of the typing-indicator routes (bbernhard's `TypingIndicatorRequest`,
+/// plus an explicit `group-id`).
+#[derive(Deserialize)]
+struct TypingBody {
+    /// A phone number, or a group as `group.<base64>`.
+    recipient: Option<String>,
+    #[serde(rename = "group-id", alias = "groupId", alias = "group_id")]
+    group_id: Option<GroupId>,
+}
+
 /// PUT /v1/typing-indicator/{number} — start typing indicator.
 async fn start_typing(
     State(st): State<AppState>,
-    Path(number): Path<String>,
-    Json(body): Json<Value>,
+    Account(number): Account,
+    JsonBody(body): JsonBody<TypingBody>,
 ) -> Response {
-    let mut params = body;
-    params["account"] = json!(number);
-    params["stop"] = json!(false);
-    rpc_no_content(&st, "sendTyping", params).await
+    send_typing(&st, number, body, false).await
 }
 
 /// DELETE /v1/typing-indicator/{number} — stop typing indicator.
 async fn stop_typing(
     State(st): State<AppState>,
-    Path(number): Path<String>,
-    Json(body): Json<Value>,
+    Account(number): Account,
+    JsonBody(body): JsonBody<TypingBody>,
 ) -> Response {
-    let mut params = body;
-    params["account"] = json!(number);
-    params["stop"] = json!(true);
-    rpc_no_content(&st, "sendTyping", params).await
+    send_typing(&st, number, body, true).await
+}
+
+async fn send_typing(st: &AppState, number: String, body: TypingBody, stop: bool) -> Response {
+    let recipient = match Recipient::resolve(body.recipient, body.group_id) {
+        Ok(r) => r,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response(),
+    };
+    let mut params = json!({ "account": number, "stop": stop });
+    recipient.apply(&mut params);
+    rpc_no_content(st, "sendTyping", params).await
 }
@@ -0,0 +1,19 @@
+use axum::http::HeaderMap;
+use axum::response::{Html, Response};
+use axum::routing::get;
+use axum::Router;
+
+use super::helpers::cacheable;
+use crate::state::AppState;
+
+/// Single-page UI for checking a deployment: send a test message, watch
+/// incoming messages over the WebSocket, and manage webhooks.
+const INDEX: &str = include_str!("ui/index.html");
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/ui", get(index))
+}
+
+async fn index(headers: HeaderMap) -> Response {
+    cacheable(&headers, "public, max-age=3600", Html(INDEX))
+}
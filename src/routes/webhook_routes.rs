@@ -1,63 +1,390 @@
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use axum::routing::{delete, post};
-use axum::{Json, Router};
+use axum::routing::{post, put};
+use axum::{Extension, Json, Router};
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
 
-use crate::state::{AppState, WebhookConfig};
+use crate::filter::Filter;
+use crate::language;
+use crate::secrets;
+use crate::state::{now_millis, AppState, WebhookBatch, WebhookConfig};
+use crate::tenant::Tenant;
+use crate::webhooks;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/v1/webhooks", post(create_webhook).get(list_webhooks))
-        .route("/v1/webhooks/{id}", delete(delete_webhook))
+        .route(
+            "/v1/webhooks/{id}",
+            put(update_webhook).delete(delete_webhook),
+        )
+        .route("/v1/webhooks/{id}/replay", post(replay_webhook))
 }
 
 #[derive(Deserialize)]
-struct CreateWebhook {
+struct WebhookSpec {
+    /// Client-chosen id, making create idempotent (POST only; default: a random UUID).
+    #[serde(default)]
+    id: Option<String>,
     url: String,
     #[serde(default)]
     events: Vec<String>,
+    #[serde(default)]
+    max_concurrency: Option<usize>,
+    #[serde(default)]
+    ordered: bool,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    client_cert: Option<String>,
+    #[serde(default)]
+    client_key: Option<String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    raw: bool,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    filter: Option<String>,
+    #[serde(default)]
+    metadata_only: bool,
+    #[serde(default)]
+    max_payload_bytes: Option<usize>,
+    #[serde(default)]
+    batch: Option<WebhookBatch>,
+}
+
+impl WebhookSpec {
+    /// Turn the request body into a validated config for webhook `id`,
+    /// belonging to `tenant`.
+    fn into_config(
+        self,
+        id: String,
+        tenant: Option<String>,
+        st: &AppState,
+    ) -> Result<WebhookConfig, String> {
+        if self.max_concurrency == Some(0) {
+            return Err("max_concurrency must be at least 1".into());
+        }
+        if self.timeout_ms == Some(0) {
+            return Err("timeout_ms must be at least 1".into());
+        }
+        if self.max_payload_bytes == Some(0) {
+            return Err("max_payload_bytes must be at least 1".into());
+        }
+        if let Some(batch) = &self.batch {
+            if !(1..=webhooks::MAX_BATCH_EVENTS).contains(&batch.max_events) {
+                return Err(format!(
+                    "batch.max_events must be 1-{}",
+                    webhooks::MAX_BATCH_EVENTS
+                ));
+            }
+            if !(1..=webhooks::MAX_BATCH_WAIT_MS).contains(&batch.max_wait_ms) {
+                return Err(format!(
+                    "batch.max_wait_ms must be 1-{}",
+                    webhooks::MAX_BATCH_WAIT_MS
+                ));
+            }
+        }
+        if !self.languages.is_empty() && !language::ENABLED {
+            return Err(
+                "languages needs a server built with language detection (--features lang)".into(),
+            );
+        }
+        if let Some(code) = self.languages.iter().find(|c| !language::valid_code(c)) {
+            return Err(format!(
+                "invalid language {code:?}, expected an ISO 639-3 code such as \"eng\""
+            ));
+        }
+        let filter = self
+            .filter
+            .as_deref()
+            .map(Filter::parse)
+            .transpose()
+            .map_err(|e| format!("invalid filter: {e}"))?;
+        // Header values are secrets (e.g. Authorization); seal them at rest
+        // when a key is configured.
+        let headers = match st.secret_key.as_deref() {
+            Some(key) => self
+                .headers
+                .into_iter()
+                .map(|(k, v)| {
+                    if secrets::is_sealed(&v) {
+                        (k, v)
+                    } else {
+                        (k, key.seal(&v))
+                    }
+                })
+                .collect(),
+            None => self.headers,
+        };
+        let config = WebhookConfig {
+            id,
+            url: self.url,
+            events: self.events,
+            max_concurrency: self.max_concurrency,
+            ordered: self.ordered,
+            headers,
+            client_cert: self.client_cert,
+            client_key: self.client_key,
+            timeout_ms: self.timeout_ms,
+            raw: self.raw,
+            languages: self.languages,
+            filter,
+            metadata_only: self.metadata_only,
+            max_payload_bytes: self.max_payload_bytes,
+            batch: self.batch,
+            tenant,
+        };
+        webhooks::build_client(&config, st)?;
+        Ok(config)
+    }
 }
 
+/// The webhook as shown by the API: header values are replaced by fingerprints.
+fn redacted(hook: &WebhookConfig, st: &AppState) -> WebhookConfig {
+    let mut hook = hook.clone();
+    for value in hook.headers.values_mut() {
+        let plain =
+            secrets::reveal(value, st.secret_key.as_deref()).unwrap_or_else(|_| value.clone());
+        *value = secrets::fingerprint(&plain);
+    }
+    hook
+}
+
+/// A random (version 4) UUID for a new webhook.
+fn new_id() -> String {
+    let mut b = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut b)
+        .expect("system RNG available");
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex = hex::encode(b);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Whether the caller may see a webhook: an operator sees every webhook, a
+/// tenant only its own.
+fn visible(hook: &WebhookConfig, tenant: Option<&Arc<Tenant>>) -> bool {
+    tenant.is_none_or(|t| hook.tenant.as_deref() == Some(t.name.as_str()))
+}
+
+/// Whether two webhooks deliver the same events to the same URL.
+fn same_target(a: &WebhookConfig, b: &WebhookConfig) -> bool {
+    let set = |list: &[String]| list.iter().cloned().collect::<BTreeSet<_>>();
+    a.url == b.url
+        && set(&a.events) == set(&b.events)
+        && set(&a.languages) == set(&b.languages)
+        && a.filter == b.filter
+}
+
+/// What POST /v1/webhooks does when the webhook duplicates an existing one
+/// (same URL and event filter).
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OnDuplicate {
+    /// Answer 409 with the existing webhook's id.
+    #[default]
+    Reject,
+    /// Answer 200 with the existing webhook.
+    Return,
+    /// Register it anyway.
+    Allow,
+}
+
+#[derive(Deserialize)]
+struct CreateQuery {
+    #[serde(default)]
+    on_duplicate: OnDuplicate,
+}
+
+fn bad_request(e: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": e }))).into_response()
+}
+
+/// POST /v1/webhooks — register a webhook. With a client-supplied `id`,
+/// repeating the call returns the webhook instead of creating another; the
+/// same id with a different configuration, or an id taken by a webhook the
+/// caller can't see, is a 409. A webhook duplicating
+/// another's URL and event filter is handled per `?on_duplicate=`. A
+/// tenant's webhook belongs to the tenant.
 async fn create_webhook(
     State(st): State<AppState>,
-    Json(body): Json<CreateWebhook>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Query(q): Query<CreateQuery>,
+    Json(mut body): Json<WebhookSpec>,
 ) -> Response {
-    let id = format!(
-        "{:016x}",
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos()
-    );
-
-    let config = WebhookConfig {
-        id,
-        url: body.url,
-        events: body.events,
+    let id = match body.id.take() {
+        Some(id) if !valid_id(&id) => {
+            return bad_request("id must be 1-64 letters, digits, '-' or '_'".into())
+        }
+        Some(id) => id,
+        None => new_id(),
     };
 
-    st.webhooks.write().await.push(config.clone());
+    let tenant = tenant.as_deref();
+    let config = match body.into_config(id, tenant.map(|t| t.name.clone()), &st) {
+        Ok(c) => c,
+        Err(e) => return bad_request(e),
+    };
 
-    (StatusCode::CREATED, Json(config)).into_response()
+    let mut hooks = st.webhooks.write().await;
+    if let Some(existing) = hooks.iter().find(|h| h.id == config.id) {
+        if !visible(existing, tenant) {
+            // Ids are global; say nothing about whose webhook holds it.
+            let error = format!("webhook id {} is taken", config.id);
+            return (StatusCode::CONFLICT, Json(json!({ "error": error }))).into_response();
+        }
+        // Compared as shown: sealed header values differ on every seal.
+        let shown = redacted(existing, &st);
+        return if shown == redacted(&config, &st) {
+            Json(shown).into_response()
+        } else {
+            let error = format!(
+                "webhook {} exists with a different configuration",
+                config.id
+            );
+            (StatusCode::CONFLICT, Json(json!({ "error": error }))).into_response()
+        };
+    }
+    if let Some(existing) = hooks
+        .iter()
+        .find(|h| visible(h, tenant) && same_target(h, &config))
+    {
+        match q.on_duplicate {
+            OnDuplicate::Reject => {
+                let error = format!(
+                    "webhook {} already delivers these events to this URL",
+                    existing.id
+                );
+                return (
+                    StatusCode::CONFLICT,
+                    Json(json!({ "error": error, "id": existing.id })),
+                )
+                    .into_response();
+            }
+            OnDuplicate::Return => return Json(redacted(existing, &st)).into_response(),
+            OnDuplicate::Allow => {}
+        }
+    }
+
+    let shown = redacted(&config, &st);
+    hooks.push(config);
+    st.webhooks_changed.notify_one();
+
+    (StatusCode::CREATED, Json(shown)).into_response()
+}
+
+async fn update_webhook(
+    State(st): State<AppState>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<String>,
+    Json(body): Json<WebhookSpec>,
+) -> Response {
+    let mut hooks = st.webhooks.write().await;
+    match hooks
+        .iter_mut()
+        .find(|h| h.id == id && visible(h, tenant.as_deref()))
+    {
+        Some(hook) => {
+            // Validated as the owner's: a tenant's cert files are its own.
+            let config = match body.into_config(id, hook.tenant.clone(), &st) {
+                Ok(c) => c,
+                Err(e) => return bad_request(e),
+            };
+            let shown = redacted(&config, &st);
+            *hook = config;
+            st.webhooks_changed.notify_one();
+            Json(shown).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
 
-async fn list_webhooks(State(st): State<AppState>) -> Response {
+async fn list_webhooks(
+    State(st): State<AppState>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+) -> Response {
     let hooks = st.webhooks.read().await;
-    Json(hooks.clone()).into_response()
+    let shown: Vec<WebhookConfig> = hooks
+        .iter()
+        .filter(|h| visible(h, tenant.as_deref()))
+        .map(|h| redacted(h, &st))
+        .collect();
+    Json(shown).into_response()
 }
 
 async fn delete_webhook(
     State(st): State<AppState>,
+    tenant: Option<Extension<Arc<Tenant>>>,
     Path(id): Path<String>,
 ) -> Response {
     let mut hooks = st.webhooks.write().await;
     let len_before = hooks.len();
-    hooks.retain(|h| h.id != id);
+    hooks.retain(|h| h.id != id || !visible(h, tenant.as_deref()));
     if hooks.len() < len_before {
+        st.webhooks_changed.notify_one();
         StatusCode::NO_CONTENT.into_response()
     } else {
         StatusCode::NOT_FOUND.into_response()
     }
 }
+
+#[derive(Deserialize)]
+struct ReplayQuery {
+    /// Earliest timestamp replayed (ms since the epoch).
+    from: u64,
+    /// Timestamp from which messages are left out (ms since the epoch; default: now).
+    #[serde(default)]
+    to: Option<u64>,
+}
+
+/// POST /v1/webhooks/{id}/replay?from=&to= — re-deliver the incoming
+/// messages held in the message store between `from` and `to`, so a
+/// consumer that was down can catch up. Answers 202 with the number of
+/// events to be delivered.
+async fn replay_webhook(
+    State(st): State<AppState>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<String>,
+    Query(q): Query<ReplayQuery>,
+) -> Response {
+    let to = q.to.unwrap_or_else(|| now_millis() + 1);
+    if q.from >= to {
+        return bad_request("from must be before to".into());
+    }
+    let Some(hook) = st
+        .webhooks
+        .read()
+        .await
+        .iter()
+        .find(|h| h.id == id && visible(h, tenant.as_deref()))
+        .cloned()
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let events = webhooks::replay(&st, &hook, st.store.events_between(q.from, to));
+    (StatusCode::ACCEPTED, Json(json!({ "events": events }))).into_response()
+}
@@ -0,0 +1,324 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use crate::attachments;
+use crate::exposition::{Exposition, Labels};
+use crate::state::AppState;
+use crate::store::split_notification;
+
+/// How long a single scan may take before it counts as a scanner failure.
+pub const SCAN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of scanning one attachment.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Rejected, with the scanner's reason (e.g. the signature name).
+    Infected(String),
+}
+
+/// Why an outgoing send couldn't be scanned.
+#[derive(Debug)]
+pub enum ScanError {
+    /// An attachment couldn't be decoded or read (the client's fault).
+    Attachment(String),
+    /// The scanner failed or timed out.
+    Scanner(String),
+}
+
+#[derive(Clone, Copy)]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+impl Direction {
+    const ALL: [Direction; 2] = [Direction::Outgoing, Direction::Incoming];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Outgoing => "outgoing",
+            Direction::Incoming => "incoming",
+        }
+    }
+}
+
+enum Backend {
+    /// Program and arguments; the attachment is written to its stdin. Exit 0
+    /// is clean, 1 is infected (first stdout line is the reason), anything
+    /// else is a scanner failure. Matches `clamdscan --no-summary -`.
+    Command(Vec<String>),
+    /// The attachment is POSTed as the body; the endpoint answers
+    /// `{"clean": bool, "reason": "..."}`.
+    Http {
+        url: String,
+        client: reqwest::Client,
+    },
+}
+
+#[derive(Deserialize)]
+struct HttpVerdict {
+    clean: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Attachment scanning hook: an external command or an HTTP scanning service
+/// that can veto outgoing sends and removes rejected incoming attachments.
+pub struct Scanner {
+    backend: Backend,
+    scanned: [AtomicU64; 2],
+    rejected: [AtomicU64; 2],
+}
+
+impl Scanner {
+    /// An `http://` or `https://` URL selects the HTTP scanner; anything else
+    /// is a command line (split on whitespace, no shell).
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let backend = if spec.starts_with("http://") || spec.starts_with("https://") {
+            let client = reqwest::Client::builder()
+                .timeout(SCAN_TIMEOUT)
+                .build()
+                .map_err(|e| e.to_string())?;
+            Backend::Http {
+                url: spec.to_string(),
+                client,
+            }
+        } else {
+            let argv: Vec<String> = spec.split_whitespace().map(String::from).collect();
+            if argv.is_empty() {
+                return Err("attachment scanner command is empty".into());
+            }
+            Backend::Command(argv)
+        };
+        Ok(Self {
+            backend,
+            scanned: Default::default(),
+            rejected: Default::default(),
+        })
+    }
+
+    /// The command line or URL scanned with, as configured.
+    pub fn describe(&self) -> String {
+        match &self.backend {
+            Backend::Command(argv) => argv.join(" "),
+            Backend::Http { url, .. } => url.clone(),
+        }
+    }
+
+    /// Scan one attachment. `name` is passed to the scanner for its logs.
+    pub async fn scan(
+        &self,
+        direction: Direction,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Verdict, String> {
+        let verdict = match &self.backend {
+            Backend::Command(argv) => scan_command(argv, name, data).await?,
+            Backend::Http { url, client } => scan_http(client, url, name, data).await?,
+        };
+        self.scanned[direction as usize].fetch_add(1, Ordering::Relaxed);
+        if verdict != Verdict::Clean {
+            self.rejected[direction as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(verdict)
+    }
+
+    /// Scan every attachment of a send payload: `base64_attachments` and
+    /// `attachments` (file paths or data URIs). Returns the first rejection.
+    pub async fn scan_outgoing(&self, params: &Value) -> Result<Verdict, ScanError> {
+        let list = |key: &str| -> Vec<String> {
+            params
+                .get(key)
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        };
+        let mut items: Vec<(String, Vec<u8>)> = Vec::new();
+        for (i, item) in list("base64_attachments").iter().enumerate() {
+            let (filename, data) = attachments::decode(item).map_err(ScanError::Attachment)?;
+            items.push((
+                filename.unwrap_or_else(|| format!("base64_attachments[{i}]")),
+                data,
+            ));
+        }
+        for item in list("attachments") {
+            if item.starts_with("data:") {
+                let (filename, data) = attachments::decode(&item).map_err(ScanError::Attachment)?;
+                items.push((filename.unwrap_or_else(|| "attachment".into()), data));
+            } else {
+                let data = tokio::fs::read(&item)
+                    .await
+                    .map_err(|e| ScanError::Attachment(format!("reading {item}: {e}")))?;
+                items.push((item, data));
+            }
+        }
+        for (name, data) in items {
+            let verdict = self
+                .scan(Direction::Outgoing, &name, &data)
+                .await
+                .map_err(ScanError::Scanner)?;
+            if let Verdict::Infected(reason) = verdict {
+                return Ok(Verdict::Infected(format!("{name}: {reason}")));
+            }
+        }
+        Ok(Verdict::Clean)
+    }
+
+    /// Append scan counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let labels: Vec<[(&str, &str); 1]> = Direction::ALL
+            .iter()
+            .map(|d| [("direction", d.as_str())])
+            .collect();
+        let samples = |counters: &[AtomicU64; 2]| -> Vec<(Labels, u64)> {
+            labels
+                .iter()
+                .zip(counters)
+                .map(|(l, c)| (&l[..], c.load(Ordering::Relaxed)))
+                .collect()
+        };
+        exp.counter(
+            "signal_attachments_scanned",
+            "Attachments checked by the scanning hook",
+            &samples(&self.scanned),
+        );
+        exp.counter(
+            "signal_attachments_rejected",
+            "Attachments rejected by the scanning hook",
+            &samples(&self.rejected),
+        );
+    }
+}
+
+async fn scan_command(argv: &[String], name: &str, data: &[u8]) -> Result<Verdict, String> {
+    let mut child = tokio::process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .env("SIGNAL_ATTACHMENT_NAME", name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("starting scanner {}: {e}", argv[0]))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let data = data.to_vec();
+    // A scanner may decide without reading everything; a broken pipe is fine.
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&data).await;
+    });
+    let output = tokio::time::timeout(SCAN_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| "scanner timed out".to_string())?
+        .map_err(|e| format!("scanner failed: {e}"))?;
+    let _ = writer.await;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match output.status.code() {
+        Some(0) => Ok(Verdict::Clean),
+        Some(1) => Ok(Verdict::Infected(
+            stdout
+                .lines()
+                .next()
+                .unwrap_or("rejected")
+                .trim()
+                .to_string(),
+        )),
+        code => Err(format!("scanner exited with {code:?}")),
+    }
+}
+
+async fn scan_http(
+    client: &reqwest::Client,
+    url: &str,
+    name: &str,
+    data: &[u8],
+) -> Result<Verdict, String> {
+    let res = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+        .header("x-attachment-name", name)
+        .body(data.to_vec())
+        .send()
+        .await
+        .map_err(|e| format!("scanner request failed: {e}"))?;
+    if !res.status().is_success() {
+        return Err(format!("scanner returned {}", res.status()));
+    }
+    let verdict: HttpVerdict = res
+        .json()
+        .await
+        .map_err(|e| format!("scanner response: {e}"))?;
+    Ok(match verdict.clean {
+        true => Verdict::Clean,
+        false => Verdict::Infected(verdict.reason.unwrap_or_else(|| "rejected".into())),
+    })
+}
+
+/// Scans attachments of incoming messages in signal-cli's attachment folder
+/// (`--signal-attachment-dir`) and deletes the ones the scanner rejects.
+pub async fn incoming_loop(state: AppState) {
+    let (Some(scanner), Some(dir)) = (state.scanner.clone(), state.attachments.signal_dir.clone())
+    else {
+        return;
+    };
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Attachment scanner lagged, {n} notifications not scanned");
+                continue;
+            }
+            Err(_) => break,
+        };
+        for id in incoming_attachment_ids(event.json()) {
+            scan_incoming(&scanner, &dir, &id).await;
+        }
+    }
+}
+
+/// IDs (file names in signal-cli's attachment folder) of a data message's attachments.
+fn incoming_attachment_ids(parsed: &Value) -> Vec<String> {
+    let Some((_, envelope)) = split_notification(parsed) else {
+        return Vec::new();
+    };
+    envelope
+        .get("dataMessage")
+        .and_then(|d| d.get("attachments"))
+        .and_then(|a| a.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|a| a.get("id").and_then(|v| v.as_str()))
+        // IDs name a file directly inside the folder; never follow paths.
+        .filter(|id| !id.is_empty() && !id.contains(['/', '\\']) && *id != "..")
+        .map(String::from)
+        .collect()
+}
+
+async fn scan_incoming(scanner: &Scanner, dir: &Path, id: &str) {
+    let path = dir.join(id);
+    let Ok(data) = tokio::fs::read(&path).await else {
+        return; // not downloaded (e.g. --ignore-attachments)
+    };
+    match scanner.scan(Direction::Incoming, id, &data).await {
+        Ok(Verdict::Clean) => {}
+        Ok(Verdict::Infected(reason)) => {
+            tracing::warn!(
+                attachment = id,
+                reason,
+                "Incoming attachment rejected by scanner, deleting"
+            );
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!(attachment = id, "Could not delete rejected attachment: {e}");
+            }
+        }
+        Err(e) => tracing::warn!(attachment = id, "Incoming attachment not scanned: {e}"),
+    }
+}
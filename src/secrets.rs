@@ -0,0 +1,107 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+
+/// Environment variable holding the secret-encryption key material.
+pub const KEY_ENV: &str = "SIGNAL_CLI_API_SECRET_KEY";
+
+/// Prefix marking a sealed (encrypted) secret value.
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+/// AES-256-GCM key for secrets at rest. The key is the SHA-256 of the
+/// configured material, so any sufficiently random string or file works.
+pub struct SecretKey {
+    key: LessSafeKey,
+}
+
+impl SecretKey {
+    pub fn from_material(material: &[u8]) -> Self {
+        let digest = Sha256::digest(material);
+        let unbound =
+            UnboundKey::new(&AES_256_GCM, &digest).expect("SHA-256 output is a valid AES-256 key");
+        Self {
+            key: LessSafeKey::new(unbound),
+        }
+    }
+
+    /// Load the key from `file` (e.g. a KMS-provisioned secret file) or,
+    /// failing that, from the environment. Returns None if neither is set.
+    pub fn load(file: Option<&str>) -> Result<Option<Self>, String> {
+        let material = match file {
+            Some(path) => {
+                std::fs::read(path).map_err(|e| format!("secret key file {path}: {e}"))?
+            }
+            None => match std::env::var(KEY_ENV) {
+                Ok(v) => v.into_bytes(),
+                Err(_) => return Ok(None),
+            },
+        };
+        let material = material.trim_ascii();
+        if material.is_empty() {
+            return Err("secret key is empty".into());
+        }
+        Ok(Some(Self::from_material(material)))
+    }
+
+    /// Encrypt `plaintext` into a `enc:v1:<base64(nonce || ciphertext)>` string.
+    pub fn seal(&self, plaintext: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .expect("system RNG available");
+        let mut data = plaintext.as_bytes().to_vec();
+        self.key
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .expect("AES-GCM sealing cannot fail for in-memory buffers");
+        let mut out = nonce.to_vec();
+        out.extend(data);
+        format!("{SEALED_PREFIX}{}", BASE64.encode(out))
+    }
+
+    /// Decrypt a value produced by [`SecretKey::seal`].
+    pub fn open(&self, sealed: &str) -> Result<String, String> {
+        let encoded = sealed
+            .strip_prefix(SEALED_PREFIX)
+            .ok_or("not a sealed secret")?;
+        let mut data = BASE64
+            .decode(encoded)
+            .map_err(|_| "sealed secret is not valid base64")?;
+        if data.len() < NONCE_LEN {
+            return Err("sealed secret is truncated".into());
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&data[..NONCE_LEN]);
+        let plaintext = self
+            .key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut data[NONCE_LEN..],
+            )
+            .map_err(|_| "sealed secret could not be decrypted (wrong key?)")?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| "sealed secret is not UTF-8".into())
+    }
+}
+
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+/// The plaintext of a possibly-sealed value.
+pub fn reveal(value: &str, key: Option<&SecretKey>) -> Result<String, String> {
+    if !is_sealed(value) {
+        return Ok(value.to_string());
+    }
+    match key {
+        Some(key) => key.open(value),
+        None => Err("sealed secret found but no secret key is configured".into()),
+    }
+}
+
+/// Short, stable identifier for a secret, safe to show in API responses.
+pub fn fingerprint(plaintext: &str) -> String {
+    let digest = Sha256::digest(plaintext.as_bytes());
+    format!("sha256:{}", &hex::encode(digest)[..12])
+}
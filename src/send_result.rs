@@ -0,0 +1,130 @@
+use axum::http::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Delivery outcome for one recipient, from signal-cli's `results[].type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Sent,
+    /// The recipient's safety number changed and hasn't been trusted yet.
+    UntrustedIdentity,
+    Unregistered,
+    RateLimited,
+    NetworkFailure,
+    ProofRequired,
+    /// Any failure type this API doesn't know about.
+    Failed,
+}
+
+impl Outcome {
+    fn from_type(kind: &str) -> Self {
+        match kind {
+            "SUCCESS" => Self::Sent,
+            "IDENTITY_FAILURE" => Self::UntrustedIdentity,
+            "UNREGISTERED_FAILURE" => Self::Unregistered,
+            "RATE_LIMIT_FAILURE" => Self::RateLimited,
+            "NETWORK_FAILURE" => Self::NetworkFailure,
+            "PROOF_REQUIRED_FAILURE" => Self::ProofRequired,
+            _ => Self::Failed,
+        }
+    }
+
+    fn hint(self) -> Option<&'static str> {
+        match self {
+            Self::UntrustedIdentity => Some(
+                "the recipient's safety number changed; trust it via PUT /v1/identities/{number}/trust/{recipient} and resend",
+            ),
+            Self::ProofRequired => Some("signal requires a captcha challenge before sending to this recipient"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecipientOutcome {
+    /// Phone number, or the UUID when signal-cli doesn't know the number.
+    pub recipient: String,
+    pub status: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<&'static str>,
+    #[serde(rename = "retryAfterSeconds", skip_serializing_if = "Option::is_none")]
+    pub retry_after_seconds: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// Per-recipient view of a signal-cli `send` result, shared by every send path
+/// (v1, v2, send-to, outbox releases, ask).
+#[derive(Clone, Debug, Default)]
+pub struct SendReport {
+    pub recipients: Vec<RecipientOutcome>,
+    pub summary: Summary,
+}
+
+impl SendReport {
+    /// Parse `results[]` from a send result. Results without any entries
+    /// (older signal-cli versions, note-to-self) give an empty report.
+    pub fn from_result(result: &Value) -> Self {
+        let recipients: Vec<RecipientOutcome> = result
+            .get("results")
+            .and_then(|r| r.as_array())
+            .into_iter()
+            .flatten()
+            .map(|r| {
+                let address = r.get("recipientAddress");
+                let recipient = ["number", "uuid"]
+                    .iter()
+                    .find_map(|key| address.and_then(|a| a.get(*key)).and_then(|v| v.as_str()))
+                    .unwrap_or("")
+                    .to_string();
+                let status =
+                    Outcome::from_type(r.get("type").and_then(|t| t.as_str()).unwrap_or(""));
+                RecipientOutcome {
+                    recipient,
+                    status,
+                    hint: status.hint(),
+                    retry_after_seconds: r.get("retryAfterSeconds").and_then(|v| v.as_u64()),
+                }
+            })
+            .collect();
+        let sent = recipients
+            .iter()
+            .filter(|r| r.status == Outcome::Sent)
+            .count();
+        let summary = Summary {
+            total: recipients.len(),
+            sent,
+            failed: recipients.len() - sent,
+        };
+        Self {
+            recipients,
+            summary,
+        }
+    }
+
+    /// 201 when every recipient was reached, 207 Multi-Status otherwise.
+    pub fn status(&self) -> StatusCode {
+        if self.summary.failed == 0 {
+            StatusCode::CREATED
+        } else {
+            StatusCode::MULTI_STATUS
+        }
+    }
+
+    /// Add `recipients` and `summary` to the send result returned to the client.
+    pub fn annotate(&self, result: &mut Value) {
+        if self.recipients.is_empty() {
+            return;
+        }
+        if let Some(obj) = result.as_object_mut() {
+            obj.insert("recipients".into(), json!(self.recipients));
+            obj.insert("summary".into(), json!(self.summary));
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::exposition::{Exposition, Labels};
+use crate::state::AppState;
+
+/// Retry-After sent with a shed request.
+pub const RETRY_AFTER: u64 = 5;
+
+/// Requests that can be turned away under load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RouteClass {
+    Typing,
+    Receipts,
+    Reactions,
+    /// Sends and other calls that queue messages.
+    Sends,
+    /// GET requests not covered by another class.
+    Reads,
+}
+
+impl RouteClass {
+    pub const ALL: [RouteClass; 5] = [
+        RouteClass::Typing,
+        RouteClass::Receipts,
+        RouteClass::Reactions,
+        RouteClass::Sends,
+        RouteClass::Reads,
+    ];
+
+    /// Shed when the API is configured with thresholds but no classes:
+    /// signals nobody misses for long.
+    pub const DEFAULT: [RouteClass; 3] = [
+        RouteClass::Typing,
+        RouteClass::Receipts,
+        RouteClass::Reactions,
+    ];
+
+    /// The class of a request; None for requests that are never shed
+    /// (health, metrics, the admin API, login).
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        let under = |prefix: &str| {
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        if path == "/v1/health"
+            || path == "/metrics"
+            || under("/v1/admin")
+            || path.starts_with("/auth/")
+        {
+            None
+        } else if under("/v1/typing-indicator") {
+            Some(Self::Typing)
+        } else if under("/v1/receipts") {
+            Some(Self::Receipts)
+        } else if under("/v1/reactions") {
+            Some(Self::Reactions)
+        } else if crate::routes::sends_messages(path) {
+            // Before reads: GET /v1/send-to sends too.
+            Some(Self::Sends)
+        } else if matches!(*method, Method::GET | Method::HEAD) {
+            Some(Self::Reads)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RouteClass::Typing => "typing",
+            RouteClass::Receipts => "receipts",
+            RouteClass::Reactions => "reactions",
+            RouteClass::Sends => "sends",
+            RouteClass::Reads => "reads",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Thresholds past which low-priority requests are refused rather than
+/// queued behind calls that will time out. Disabled by default.
+#[derive(Default)]
+pub struct LoadShedder {
+    /// Refuse when this many RPC calls await signal-cli.
+    pub max_pending: Option<usize>,
+    /// Refuse when this many messages wait in the outbox.
+    pub max_outbox: Option<usize>,
+    pub classes: Vec<RouteClass>,
+    shed: [AtomicU64; RouteClass::ALL.len()],
+}
+
+impl LoadShedder {
+    /// A shedder for the given thresholds; no classes means [`RouteClass::DEFAULT`].
+    pub fn new(
+        max_pending: Option<usize>,
+        max_outbox: Option<usize>,
+        classes: Vec<RouteClass>,
+    ) -> Self {
+        let classes = if classes.is_empty() {
+            RouteClass::DEFAULT.to_vec()
+        } else {
+            classes
+        };
+        Self {
+            max_pending,
+            max_outbox,
+            classes,
+            shed: Default::default(),
+        }
+    }
+
+    /// What is over its threshold, if anything.
+    pub fn overload(&self, pending: usize, outbox: usize) -> Option<String> {
+        match (self.max_pending, self.max_outbox) {
+            (Some(max), _) if pending >= max => Some(format!("{pending} RPC calls pending")),
+            (_, Some(max)) if outbox >= max => Some(format!("{outbox} messages in the outbox")),
+            _ => None,
+        }
+    }
+
+    /// Append the per-class count of refused requests to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let labels: Vec<[(&str, &str); 1]> = RouteClass::ALL
+            .iter()
+            .map(|c| [("class", c.as_str())])
+            .collect();
+        let samples: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(&self.shed)
+            .map(|(l, c)| (&l[..], c.load(Ordering::Relaxed)))
+            .collect();
+        exp.counter(
+            "signal_requests_shed",
+            "Requests refused because the API was overloaded",
+            &samples,
+        );
+    }
+}
+
+/// Middleware refusing requests of the configured classes with 503 and
+/// Retry-After while pending RPC calls or the outbox are over threshold.
+pub async fn shed(State(st): State<AppState>, request: Request, next: Next) -> Response {
+    let shedder = &st.shedder;
+    if shedder.max_pending.is_none() && shedder.max_outbox.is_none() {
+        return next.run(request).await;
+    }
+    let Some(class) = RouteClass::of(request.method(), request.uri().path())
+        .filter(|c| shedder.classes.contains(c))
+    else {
+        return next.run(request).await;
+    };
+    let Some(overload) = shedder.overload(st.pending.len(), st.outbox.len()) else {
+        return next.run(request).await;
+    };
+    shedder.shed[class.index()].fetch_add(1, Ordering::Relaxed);
+    tracing::debug!(class = class.as_str(), "Request shed: {overload}");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, RETRY_AFTER.to_string())],
+        Json(json!({ "error": format!("API is overloaded ({overload}); retry later") })),
+    )
+        .into_response()
+}
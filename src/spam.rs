@@ -0,0 +1,359 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::exposition::{Exposition, Labels};
+use crate::plugin::Plugin;
+use crate::preflight::listed_numbers;
+use crate::state::{now_millis, AppState};
+use crate::store::envelope_source;
+
+/// Events kept in the quarantine, across accounts; the oldest go first.
+pub const QUARANTINE_CAPACITY: usize = 1000;
+
+/// How often the contact lists behind `unknown-sender` are reloaded.
+pub const CONTACTS_REFRESH: Duration = Duration::from_secs(300);
+
+/// What an incoming message is checked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// The sender isn't in the account's contacts.
+    UnknownSender,
+    /// The text contains a link.
+    Url,
+    /// The sender has sent more than `max` messages within `per`.
+    Rate { max: usize, per: Duration },
+}
+
+impl Rule {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnknownSender => "unknown-sender",
+            Rule::Url => "url",
+            Rule::Rate { .. } => "rate",
+        }
+    }
+}
+
+/// The rule as written on the command line.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Rate { max, per } => write!(f, "rate:{max}/{}", per.as_secs()),
+            rule => f.write_str(rule.name()),
+        }
+    }
+}
+
+/// What happens to a message matching a rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Delivered with the matched rules in the envelope's `spam` field, and
+    /// copied to the quarantine.
+    Tag,
+    /// Not delivered; kept in the quarantine only.
+    Drop,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Tag => "tag",
+            Action::Drop => "drop",
+        }
+    }
+}
+
+/// Parse `unknown-sender`, `url` or `rate:<max>/<secs>`, optionally followed
+/// by `=tag` (the default) or `=drop`.
+pub fn parse_rule(spec: &str) -> Result<(Rule, Action), String> {
+    let invalid = || {
+        format!("invalid spam rule {spec:?}, expected unknown-sender, url or rate:<max>/<secs>, then =tag or =drop")
+    };
+    let (rule, action) = match spec.split_once('=') {
+        Some((rule, "tag")) => (rule, Action::Tag),
+        Some((rule, "drop")) => (rule, Action::Drop),
+        Some(_) => return Err(invalid()),
+        None => (spec, Action::Tag),
+    };
+    let rule = match rule {
+        "unknown-sender" => Rule::UnknownSender,
+        "url" => Rule::Url,
+        _ => {
+            let (max, secs) = rule
+                .strip_prefix("rate:")
+                .and_then(|r| r.split_once('/'))
+                .ok_or_else(invalid)?;
+            let max: usize = max.parse().map_err(|_| invalid())?;
+            let secs: u64 = secs.parse().map_err(|_| invalid())?;
+            if max == 0 || secs == 0 {
+                return Err(invalid());
+            }
+            Rule::Rate {
+                max,
+                per: Duration::from_secs(secs),
+            }
+        }
+    };
+    Ok((rule, action))
+}
+
+/// Whether `text` holds something a client would show as a link.
+pub fn contains_url(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    lower.contains("http://")
+        || lower.contains("https://")
+        || lower.split_whitespace().any(|w| w.starts_with("www."))
+}
+
+/// An incoming message that matched a rule.
+#[derive(Clone, Debug, Serialize)]
+pub struct Quarantined {
+    pub account: String,
+    pub received_at: u64,
+    pub rules: Vec<&'static str>,
+    pub action: Action,
+    pub envelope: Value,
+}
+
+/// Checks incoming data messages against the configured rules, tagging or
+/// dropping the ones that match. Runs as a [`Plugin`] ahead of any others.
+pub struct SpamFilter {
+    rules: Vec<(Rule, Action)>,
+    /// Numbers and UUIDs of each account's contacts, once loaded.
+    contacts: RwLock<HashMap<String, HashSet<String>>>,
+    /// Recent message times per (account, sender), for rate rules.
+    recent: Mutex<HashMap<(String, String), VecDeque<Instant>>>,
+    quarantine: Mutex<VecDeque<Quarantined>>,
+    matched: [AtomicU64; 2],
+    /// Every match, tagged or dropped, as it happens.
+    matches: broadcast::Sender<Quarantined>,
+}
+
+impl SpamFilter {
+    pub fn new(rules: Vec<(Rule, Action)>) -> Self {
+        Self {
+            rules,
+            contacts: RwLock::new(HashMap::new()),
+            recent: Mutex::new(HashMap::new()),
+            quarantine: Mutex::new(VecDeque::new()),
+            matched: Default::default(),
+            matches: broadcast::channel(256).0,
+        }
+    }
+
+    /// Matches from now on, dropped messages included.
+    pub fn subscribe(&self) -> broadcast::Receiver<Quarantined> {
+        self.matches.subscribe()
+    }
+
+    pub fn rules(&self) -> &[(Rule, Action)] {
+        &self.rules
+    }
+
+    pub fn checks_contacts(&self) -> bool {
+        self.rules.iter().any(|(r, _)| *r == Rule::UnknownSender)
+    }
+
+    /// Replace the contacts of `account` from a `listContacts` result.
+    pub fn set_contacts(&self, account: &str, contacts: &Value) {
+        let known: HashSet<String> = contacts
+            .as_array()
+            .into_iter()
+            .flatten()
+            .flat_map(|c| {
+                ["number", "uuid"].map(|k| c.get(k).and_then(|v| v.as_str()).map(String::from))
+            })
+            .flatten()
+            .collect();
+        self.contacts
+            .write()
+            .unwrap()
+            .insert(account.to_string(), known);
+    }
+
+    /// Whether `sender` is unknown to `account`. Senders of accounts whose
+    /// contacts haven't loaded yet count as known.
+    fn unknown_sender(&self, account: &str, envelope: &Value) -> bool {
+        let contacts = self.contacts.read().unwrap();
+        let Some(known) = contacts.get(account) else {
+            return false;
+        };
+        let uuid = envelope.get("sourceUuid").and_then(|v| v.as_str());
+        let sender = envelope_source(envelope);
+        sender != account && !known.contains(sender) && !uuid.is_some_and(|u| known.contains(u))
+    }
+
+    /// Record a message from `sender`, returning the ages of the sender's
+    /// messages within the longest rate window, this one included.
+    fn record(&self, account: &str, sender: &str) -> Vec<Duration> {
+        let longest = self
+            .rules
+            .iter()
+            .filter_map(|(r, _)| match r {
+                Rule::Rate { per, .. } => Some(*per),
+                _ => None,
+            })
+            .max()
+            .unwrap_or_default();
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        recent
+            .entry((account.to_string(), sender.to_string()))
+            .or_default()
+            .push_back(now);
+        // Drop times past every window, and senders that have gone quiet.
+        recent.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) > longest)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = &recent[&(account.to_string(), sender.to_string())];
+        times.iter().map(|t| now.duration_since(*t)).collect()
+    }
+
+    /// The rules `envelope` matches, and the strongest of their actions.
+    fn check(&self, account: &str, envelope: &Value) -> Option<(Vec<&'static str>, Action)> {
+        let data = envelope.get("dataMessage")?;
+        let text = data.get("message").and_then(|m| m.as_str()).unwrap_or("");
+        let sender = envelope_source(envelope);
+        let has_rate = self
+            .rules
+            .iter()
+            .any(|(r, _)| matches!(r, Rule::Rate { .. }));
+        let ages = if has_rate {
+            self.record(account, sender)
+        } else {
+            Vec::new()
+        };
+        let mut matched = Vec::new();
+        let mut action = Action::Tag;
+        for &(rule, rule_action) in &self.rules {
+            let hit = match rule {
+                Rule::UnknownSender => self.unknown_sender(account, envelope),
+                Rule::Url => contains_url(text),
+                Rule::Rate { max, per } => ages.iter().filter(|age| **age <= per).count() > max,
+            };
+            if hit {
+                if !matched.contains(&rule.name()) {
+                    matched.push(rule.name());
+                }
+                action = action.max(rule_action);
+            }
+        }
+        (!matched.is_empty()).then_some((matched, action))
+    }
+
+    fn quarantine(&self, entry: Quarantined) {
+        let mut quarantine = self.quarantine.lock().unwrap();
+        if quarantine.len() == QUARANTINE_CAPACITY {
+            quarantine.pop_front();
+        }
+        quarantine.push_back(entry);
+    }
+
+    /// Quarantined events of `account`, oldest first.
+    pub fn quarantined(&self, account: &str) -> Vec<Quarantined> {
+        let quarantine = self.quarantine.lock().unwrap();
+        quarantine
+            .iter()
+            .filter(|q| q.account == account)
+            .cloned()
+            .collect()
+    }
+
+    /// Remove the quarantined events of `account`, returning how many there were.
+    pub fn clear(&self, account: &str) -> usize {
+        let mut quarantine = self.quarantine.lock().unwrap();
+        let before = quarantine.len();
+        quarantine.retain(|q| q.account != account);
+        before - quarantine.len()
+    }
+
+    /// Append spam counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let labels = [[("action", "tag")], [("action", "drop")]];
+        let samples: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(&self.matched)
+            .map(|(l, c)| (&l[..], c.load(Ordering::Relaxed)))
+            .collect();
+        exp.counter(
+            "signal_spam_matched",
+            "Incoming messages matching a spam rule, by action taken",
+            &samples,
+        );
+    }
+}
+
+impl Plugin for SpamFilter {
+    fn name(&self) -> &str {
+        "spam"
+    }
+
+    fn on_incoming_envelope(&self, account: Option<&str>, envelope: &mut Value) -> bool {
+        let account = account.unwrap_or_default();
+        let Some((rules, action)) = self.check(account, envelope) else {
+            return true;
+        };
+        self.matched[action as usize].fetch_add(1, Ordering::Relaxed);
+        if action == Action::Tag {
+            envelope["spam"] = json!(rules);
+        }
+        tracing::debug!(
+            account,
+            sender = envelope_source(envelope),
+            ?rules,
+            action = action.as_str(),
+            "Spam rule matched"
+        );
+        let entry = Quarantined {
+            account: account.to_string(),
+            received_at: now_millis(),
+            rules,
+            action,
+            envelope: envelope.clone(),
+        };
+        let _ = self.matches.send(entry.clone());
+        self.quarantine(entry);
+        action == Action::Tag
+    }
+}
+
+/// Keeps the contact lists behind the `unknown-sender` rule loaded,
+/// reloading them every [`CONTACTS_REFRESH`].
+pub async fn contacts_loop(state: AppState) {
+    let Some(filter) = state.spam.clone().filter(|f| f.checks_contacts()) else {
+        return;
+    };
+    loop {
+        match state.rpc("listAccounts", json!({})).await {
+            Ok(accounts) => {
+                for account in listed_numbers(&accounts) {
+                    match state
+                        .rpc("listContacts", json!({ "account": account }))
+                        .await
+                    {
+                        Ok(contacts) => filter.set_contacts(&account, &contacts),
+                        Err(e) => tracing::warn!(
+                            account,
+                            "Could not load contacts for the spam filter: {e}"
+                        ),
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Could not list accounts for the spam filter: {e}"),
+        }
+        tokio::time::sleep(CONTACTS_REFRESH).await;
+    }
+}
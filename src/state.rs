@@ -1,8 +1,16 @@
+use crate::auth::{ApiToken, ReplayGuard};
+use crate::daemon::DaemonOptions;
+use crate::event::Event;
+use crate::exposition::{Exposition, Histogram};
+use crate::logstream::LogLine;
+use crate::outbound::ProxyConfig;
+use crate::secrets::SecretKey;
 use dashmap::DashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, RwLock, oneshot};
+use tokio::sync::{broadcast, Notify, RwLock};
 
 pub type RpcResponse = serde_json::Value;
 
@@ -17,6 +25,44 @@ pub struct Metrics {
     pub rpc_calls: AtomicU64,
     pub rpc_errors: AtomicU64,
     pub ws_clients: AtomicU64,
+    pub send_failed_recipients: AtomicU64,
+    /// RPC calls awaiting signal-cli's response.
+    pub rpc_inflight: AtomicU64,
+    /// Slowest call seen per RPC method, in milliseconds.
+    pub rpc_max_latency_ms: std::sync::Mutex<BTreeMap<String, u64>>,
+    /// Events dropped because a webhook's queue was full.
+    pub webhook_dropped: AtomicU64,
+    /// Events not delivered because they were over a webhook's
+    /// `max_payload_bytes`.
+    pub webhook_oversized: AtomicU64,
+    /// Messages dropped for WebSocket clients that fell behind.
+    pub ws_dropped: AtomicU64,
+    /// Contact profiles refetched by the profile refresh job.
+    pub profiles_refreshed: AtomicU64,
+    /// Envelopes signal-cli couldn't decrypt, per exception type.
+    pub decryption_failures: std::sync::Mutex<BTreeMap<String, u64>>,
+    /// From the Signal server receiving an envelope to signal-cli handing it over.
+    pub receive_latency: Histogram,
+    /// From the API getting an envelope to a WebSocket write completing.
+    pub ws_delivery_latency: Histogram,
+    /// From the API getting an envelope to a webhook answering 2xx.
+    pub webhook_delivery_latency: Histogram,
+}
+
+/// Counts an RPC as in flight until dropped, so cancelled calls are released too.
+struct InflightGuard<'a>(&'a AtomicU64);
+
+impl<'a> InflightGuard<'a> {
+    fn new(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl Metrics {
@@ -32,29 +78,131 @@ impl Metrics {
     pub fn inc_rpc_error(&self) {
         self.rpc_errors.fetch_add(1, Ordering::Relaxed);
     }
-    pub fn to_prometheus(&self) -> String {
-        format!(
-            "# HELP signal_messages_sent_total Total messages sent\n\
-             # TYPE signal_messages_sent_total counter\n\
-             signal_messages_sent_total {}\n\
-             # HELP signal_messages_received_total Total messages received\n\
-             # TYPE signal_messages_received_total counter\n\
-             signal_messages_received_total {}\n\
-             # HELP signal_rpc_calls_total Total JSON-RPC calls to signal-cli\n\
-             # TYPE signal_rpc_calls_total counter\n\
-             signal_rpc_calls_total {}\n\
-             # HELP signal_rpc_errors_total Total JSON-RPC errors\n\
-             # TYPE signal_rpc_errors_total counter\n\
-             signal_rpc_errors_total {}\n\
-             # HELP signal_ws_clients_active Active WebSocket clients\n\
-             # TYPE signal_ws_clients_active gauge\n\
-             signal_ws_clients_active {}\n",
-            self.messages_sent.load(Ordering::Relaxed),
-            self.messages_received.load(Ordering::Relaxed),
-            self.rpc_calls.load(Ordering::Relaxed),
-            self.rpc_errors.load(Ordering::Relaxed),
-            self.ws_clients.load(Ordering::Relaxed),
-        )
+    pub fn add_failed_recipients(&self, n: u64) {
+        self.send_failed_recipients.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn add_profiles_refreshed(&self, n: u64) {
+        self.profiles_refreshed.fetch_add(n, Ordering::Relaxed);
+    }
+    pub fn observe_rpc_latency(&self, method: &str, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        let mut max = self.rpc_max_latency_ms.lock().unwrap();
+        match max.get_mut(method) {
+            Some(m) => *m = (*m).max(ms),
+            None => {
+                max.insert(method.to_string(), ms);
+            }
+        }
+    }
+    /// Record how long an envelope took from the Signal server to the API.
+    pub fn observe_receive(&self, event: &crate::event::Event) {
+        if let Some(server_received) = event.server_received() {
+            self.receive_latency
+                .observe(now_millis().saturating_sub(server_received));
+        }
+    }
+    pub fn inc_decryption_failure(&self, kind: &str) {
+        *self
+            .decryption_failures
+            .lock()
+            .unwrap()
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+    /// Append the core counters and gauges to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        let load = |v: &AtomicU64| v.load(Ordering::Relaxed);
+        exp.counter(
+            "signal_messages_sent",
+            "Total messages sent",
+            &[(&[], load(&self.messages_sent))],
+        );
+        exp.counter(
+            "signal_messages_received",
+            "Total messages received",
+            &[(&[], load(&self.messages_received))],
+        );
+        exp.counter(
+            "signal_rpc_calls",
+            "Total JSON-RPC calls to signal-cli",
+            &[(&[], load(&self.rpc_calls))],
+        );
+        exp.counter(
+            "signal_rpc_errors",
+            "Total JSON-RPC errors",
+            &[(&[], load(&self.rpc_errors))],
+        );
+        exp.counter(
+            "signal_send_failed_recipients",
+            "Recipients a send could not be delivered to",
+            &[(&[], load(&self.send_failed_recipients))],
+        );
+        exp.counter(
+            "signal_webhook_dropped",
+            "Webhook events dropped because the webhook's queue was full",
+            &[(&[], load(&self.webhook_dropped))],
+        );
+        exp.counter(
+            "signal_webhook_oversized",
+            "Webhook events not delivered because they were over the webhook's max_payload_bytes",
+            &[(&[], load(&self.webhook_oversized))],
+        );
+        exp.counter(
+            "signal_ws_dropped",
+            "Messages dropped for WebSocket clients that fell behind",
+            &[(&[], load(&self.ws_dropped))],
+        );
+        exp.counter(
+            "signal_profiles_refreshed",
+            "Contact profiles refetched by the profile refresh job",
+            &[(&[], load(&self.profiles_refreshed))],
+        );
+        exp.gauge(
+            "signal_ws_clients_active",
+            "Active WebSocket clients",
+            &[(&[], load(&self.ws_clients))],
+        );
+        exp.gauge(
+            "signal_rpc_inflight",
+            "JSON-RPC calls awaiting a response from signal-cli",
+            &[(&[], load(&self.rpc_inflight))],
+        );
+        let max = self.rpc_max_latency_ms.lock().unwrap();
+        let labels: Vec<[(&str, &str); 1]> = max.keys().map(|m| [("method", m.as_str())]).collect();
+        let samples: Vec<(crate::exposition::Labels, u64)> = labels
+            .iter()
+            .zip(max.values())
+            .map(|(l, v)| (&l[..], *v))
+            .collect();
+        exp.gauge(
+            "signal_rpc_max_latency_milliseconds",
+            "Slowest JSON-RPC call per method",
+            &samples,
+        );
+        drop(max);
+        let failures = self.decryption_failures.lock().unwrap();
+        let labels: Vec<[(&str, &str); 1]> =
+            failures.keys().map(|k| [("type", k.as_str())]).collect();
+        let samples: Vec<(crate::exposition::Labels, u64)> = labels
+            .iter()
+            .zip(failures.values())
+            .map(|(l, v)| (&l[..], *v))
+            .collect();
+        exp.counter(
+            "signal_decryption_failures",
+            "Envelopes signal-cli could not decrypt",
+            &samples,
+        );
+        exp.histogram(
+            "signal_receive_latency_milliseconds",
+            "Time from the Signal server receiving an envelope to signal-cli handing it to the API",
+            &[(&[], &self.receive_latency)],
+        );
+        exp.histogram(
+            "signal_delivery_latency_milliseconds",
+            "Time from the API getting an envelope to a consumer taking it (WebSocket write, webhook 2xx)",
+            &[(&[("sink", "websocket")], &self.ws_delivery_latency), (&[("sink", "webhook")], &self.webhook_delivery_latency)],
+        );
     }
 }
 
@@ -62,12 +210,92 @@ impl Metrics {
 // Webhook
 // ---------------------------------------------------------------------------
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct WebhookConfig {
     pub id: String,
     pub url: String,
     #[serde(default)]
     pub events: Vec<String>, // empty = all events
+    /// Maximum concurrent deliveries to this webhook (None = default limit).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+    /// Deliver strictly one at a time, in the order events arrived.
+    #[serde(default)]
+    pub ordered: bool,
+    /// Static headers sent with every delivery (e.g. Authorization).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// PEM client certificate for mTLS to the receiver, a file in
+    /// `--webhook-cert-dir` (requires `client_key`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    /// PEM private key for mTLS to the receiver, a file in
+    /// `--webhook-cert-dir` (requires `client_cert`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    /// Per-delivery timeout in milliseconds (None = no timeout).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Deliver signal-cli's envelopes verbatim instead of the normalized format.
+    #[serde(default)]
+    pub raw: bool,
+    /// Only deliver messages detected as one of these languages (ISO 639-3,
+    /// `und` for undetermined); other events are unaffected. Empty = all.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub languages: Vec<String>,
+    /// Only deliver events meeting this condition (see [`crate::filter::Filter`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<crate::filter::Filter>,
+    /// Deliver attachments as retrieval URLs, without inline data.
+    #[serde(default)]
+    pub metadata_only: bool,
+    /// Largest body to deliver; bigger ones have their attachment data
+    /// stripped, and are skipped if still too big (None = no limit).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_payload_bytes: Option<usize>,
+    /// Deliver events in batches, as JSON arrays (None = one per POST).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<WebhookBatch>,
+    /// Tenant that registered the webhook; it gets only events for the
+    /// tenant's accounts (None = registered by an operator, gets all).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+/// When a batching webhook's deliveries go out: once `max_events` are
+/// collected, or `max_wait_ms` after the first of them arrived.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct WebhookBatch {
+    #[serde(default = "WebhookBatch::default_max_events")]
+    pub max_events: usize,
+    #[serde(default = "WebhookBatch::default_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+impl WebhookBatch {
+    fn default_max_events() -> usize {
+        100
+    }
+
+    fn default_max_wait_ms() -> u64 {
+        1000
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Bot commands
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct CommandConfig {
+    pub id: String,
+    /// Command prefix, e.g. "!status".
+    pub prefix: String,
+    /// Handler URL that receives matching messages.
+    pub url: String,
+    /// Only match messages to this account (None = any account).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
 }
 
 // ---------------------------------------------------------------------------
@@ -77,29 +305,213 @@ pub struct WebhookConfig {
 #[derive(Clone)]
 pub struct AppState {
     pub writer_tx: tokio::sync::mpsc::Sender<String>,
-    pub broadcast_tx: broadcast::Sender<String>,
-    pub pending: Arc<DashMap<u64, oneshot::Sender<RpcResponse>>>,
+    pub broadcast_tx: broadcast::Sender<Event>,
+    pub pending: crate::jsonrpc::PendingCalls,
     pub next_id: Arc<AtomicU64>,
     pub metrics: Arc<Metrics>,
     pub webhooks: Arc<RwLock<Vec<WebhookConfig>>>,
+    /// Signalled after `webhooks` or `webhook_replays` is modified so the
+    /// dispatcher rebuilds its lanes and queues the replays.
+    pub webhooks_changed: Arc<Notify>,
+    /// Replays waiting for the dispatcher (see [`crate::webhooks::replay`]).
+    pub webhook_replays: Arc<std::sync::Mutex<Vec<crate::webhooks::Replay>>>,
+    /// Delivery workers per webhook that sets no `max_concurrency`.
+    pub webhook_workers: usize,
+    /// Directory (canonical) webhooks' `client_cert` and `client_key` must
+    /// be in; without it they are refused.
+    pub webhook_cert_dir: Option<std::path::PathBuf>,
+    /// Reset the session with contacts whose messages fail to decrypt.
+    pub reset_sessions: bool,
+    pub commands: Arc<RwLock<Vec<CommandConfig>>>,
+    /// Per-group automations such as welcome messages.
+    pub automations: Arc<crate::automations::AutomationStore>,
+    /// Enriched group lists per account.
+    pub group_cache: Arc<crate::groups::GroupCache>,
+    /// Last known state of each group, to tell what a group update changed.
+    pub group_snapshots: Arc<crate::group_events::Snapshots>,
+    /// Most members a `"mention": "all"` send may mention.
+    pub max_mentions: usize,
+    pub store: Arc<crate::store::MessageStore>,
+    pub outbox: Arc<crate::outbox::Outbox>,
+    pub quiet_hours: Arc<RwLock<HashMap<String, crate::outbox::QuietHours>>>,
+    /// Usernames set through the API, with the link signal-cli generated for
+    /// them, which it can't be asked for again.
+    pub usernames: Arc<RwLock<HashMap<String, serde_json::Value>>>,
+    /// Per-account trust policies; accounts without one use `on_first_use`.
+    pub trust_policies: Arc<RwLock<HashMap<String, crate::trust::TrustPolicy>>>,
+    /// Topic names sends can use instead of a group ID.
+    pub topics: Arc<RwLock<crate::topics::Topics>>,
+    pub dedup: Arc<crate::dedup::Deduplicator>,
+    /// Window for coalescing identical sends (None = disabled).
+    pub dedup_window: Option<Duration>,
+    /// Windows of API tokens that don't use `dedup_window`, by token id.
+    pub dedup_token_windows: Arc<HashMap<String, Duration>>,
+    /// How often active contacts' profiles are refreshed (None = never).
+    pub profile_refresh: Option<Duration>,
     pub rpc_timeout: Duration,
+    /// Time budget of a request that doesn't send a shorter one (None = unbounded).
+    pub request_timeout: Option<Duration>,
+    /// RPC calls slower than this are logged as warnings (None = never).
+    pub slow_rpc: Option<Duration>,
+    /// How often the outbox checks for queued messages that can be released.
+    pub outbox_tick: Duration,
+    /// Maximum messages released from the outbox per tick.
+    pub outbox_batch: usize,
+    /// Bearer token required to scrape /metrics (None = open).
+    pub metrics_token: Option<String>,
+    /// Whether /metrics is served on the public router (false when it has
+    /// its own listener).
+    pub metrics_public: bool,
+    /// Whether deprecated routes (e.g. /v1/send) are still served.
+    pub deprecated_routes: bool,
+    /// Proxy for outbound HTTP (webhooks, command handlers).
+    pub outbound_proxy: Option<ProxyConfig>,
+    /// API tokens; when non-empty every request must authenticate.
+    pub tokens: Arc<RwLock<Vec<ApiToken>>>,
+    /// Daily per-token message and attachment quotas and usage.
+    pub quotas: Arc<crate::quota::QuotaTracker>,
+    /// Tenants confining tokens to their accounts (empty = none).
+    pub tenants: Arc<crate::tenant::Tenants>,
+    /// Issuer of JWTs accepted from machine callers (None = not accepted).
+    pub jwt: Option<Arc<crate::jwt::JwtAuth>>,
+    /// OIDC login for operators (None = API tokens only).
+    pub oidc: Option<Arc<crate::oidc::Oidc>>,
+    /// Allowed clock skew for HMAC-signed requests.
+    pub hmac_window: Duration,
+    pub replay_guard: Arc<ReplayGuard>,
+    /// Key for secrets at rest (webhook header values); None = stored as given.
+    pub secret_key: Option<Arc<SecretKey>>,
+    /// Friendly account names, alias -> E164.
+    pub aliases: Arc<BTreeMap<String, String>>,
+    /// Options of the auto-spawned daemon (None when connected to an external one).
+    pub daemon_options: Option<DaemonOptions>,
+    /// Formatted tracing events for /v1/admin/logs/stream.
+    pub log_tx: broadcast::Sender<LogLine>,
+    /// Daemon receive subscriptions opened through the admin API, account -> id.
+    pub receive_subscriptions: Arc<RwLock<HashMap<String, u64>>>,
+    pub attachments: Arc<crate::attachments::AttachmentStore>,
+    /// Attachment scanning hook (None = attachments aren't scanned).
+    pub scanner: Option<Arc<crate::scan::Scanner>>,
+    /// Emulate another REST API's wire format (None = this API's own).
+    pub compat: Option<crate::compat::Compat>,
+    /// Case of keys in JSON responses unless a request's `?case=` says otherwise.
+    pub json_case: crate::case::KeyCase,
+    /// Gzip SSE streams for clients that accept it (`--sse-gzip`).
+    pub sse_gzip: bool,
+    /// Incoming messages awaiting polling clients (filled only in compat mode).
+    pub poll_queue: Arc<crate::compat::PollQueue>,
+    /// Captchas kept for the next register call, per account.
+    pub captchas: Arc<crate::captcha::CaptchaStore>,
+    /// Number changes in progress.
+    pub change_numbers: Arc<crate::change_number::ChangeNumbers>,
+    /// Device links started through the API.
+    pub link_sessions: Arc<crate::link::LinkSessions>,
+    /// Sync requests and sync messages per account.
+    pub syncs: Arc<crate::sync::SyncTracker>,
+    /// Refuses low-priority requests while signal-cli or the outbox is backed up.
+    pub shedder: Arc<crate::shed::LoadShedder>,
+    /// Spaces sends out (None = no `--send-rate`).
+    pub pacer: Option<Arc<crate::pacing::Pacer>>,
+    /// Publishes incoming envelopes to SNS or SQS (None = no `--aws-sink`).
+    pub aws_sink: Option<Arc<crate::aws_sink::AwsSink>>,
+    /// End-to-end probe for POST /v1/admin/canary (None = no `--canary-recipient`).
+    pub canary: Option<Arc<crate::canary::Canary>>,
+    /// The daemon's version, asked for once connected; RPC parameters are
+    /// adapted to it (see [`crate::jsonrpc::shim`]).
+    pub signal_cli_version: Arc<std::sync::OnceLock<crate::jsonrpc::Version>>,
+    /// Publishes events to Redis (None = no `--redis-url`).
+    pub redis: Option<Arc<crate::redis_bridge::RedisBridge>>,
+    /// Whether this replica delivers webhooks and runs automations (see
+    /// `--leader-lease`).
+    pub leadership: Arc<crate::leader::Leadership>,
+    /// Background tasks and their states.
+    pub tasks: Arc<crate::supervisor::Supervisor>,
+    /// Hooks into incoming messages and sends (see [`crate::plugin::Plugin`]).
+    pub plugins: Arc<crate::plugin::Plugins>,
+    /// Per-conversation key-value state for bots.
+    pub kv: Arc<crate::kv::KvStore>,
+    /// Recent events and the cursors of pull consumers.
+    pub event_log: Arc<crate::cursor::EventLog>,
+    /// Metadata of sent messages, attached to their replies. Also one of
+    /// `plugins`.
+    pub reply_contexts: Arc<crate::reply_context::ReplyContexts>,
+    /// Spam rules for incoming messages and their quarantine (None = no rules).
+    /// The filter also runs as the first of `plugins`.
+    pub spam: Option<Arc<crate::spam::SpamFilter>>,
+    /// Blocks abusive senders (None = no auto-block policy).
+    pub auto_block: Option<Arc<crate::autoblock::AutoBlocker>>,
+    /// Listener and connection settings, for GET /v1/admin/config.
+    pub server_config: Arc<crate::runtime_config::ServerConfig>,
+    /// Set while the API is in maintenance: mutating calls are refused.
+    pub maintenance: Arc<RwLock<Option<crate::maintenance::Maintenance>>>,
 }
 
+/// Default threshold above which an RPC call is logged as slow.
+pub const DEFAULT_SLOW_RPC: Duration = Duration::from_secs(1);
+
 /// Sentinel error string returned when an RPC call times out.
 pub const RPC_TIMEOUT_ERROR: &str = "RPC_TIMEOUT";
 
+/// Sentinel error string returned when an operator cancels a waiting RPC call.
+pub const RPC_CANCELLED_ERROR: &str = "RPC_CANCELLED";
+
+/// Status for a cancelled call, after nginx's "client closed request".
+pub const CANCELLED_STATUS: u16 = 499;
+
 /// Map an RPC error string to the appropriate HTTP status code.
 pub fn rpc_error_status(err: &str) -> axum::http::StatusCode {
-    if err == RPC_TIMEOUT_ERROR {
-        axum::http::StatusCode::GATEWAY_TIMEOUT
-    } else {
-        axum::http::StatusCode::BAD_REQUEST
+    match err {
+        RPC_TIMEOUT_ERROR => axum::http::StatusCode::GATEWAY_TIMEOUT,
+        RPC_CANCELLED_ERROR => {
+            axum::http::StatusCode::from_u16(CANCELLED_STATUS).expect("valid status code")
+        }
+        _ => axum::http::StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Current Unix time in milliseconds.
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Parse an `alias=+E164` pair as given on the command line.
+pub fn parse_alias(spec: &str) -> Result<(String, String), String> {
+    match spec.split_once('=') {
+        Some((alias, number))
+            if !alias.is_empty() && !alias.starts_with('+') && number.starts_with('+') =>
+        {
+            Ok((alias.to_string(), number.to_string()))
+        }
+        _ => Err(format!("invalid alias {spec:?}, expected name=+E164")),
     }
 }
 
 impl AppState {
+    /// Resolve an account alias to its E164 number; anything else is returned as-is.
+    pub fn resolve_account(&self, number: &str) -> String {
+        self.aliases
+            .get(number)
+            .cloned()
+            .unwrap_or_else(|| number.to_string())
+    }
+
+    /// The dedup window of a send made with `token`: the token's own, if it
+    /// has one, or the global `--dedup-window`. A zero window is none.
+    pub fn dedup_window_for(&self, token: Option<&str>) -> Option<Duration> {
+        token
+            .and_then(|id| self.dedup_token_windows.get(id).copied())
+            .or(self.dedup_window)
+            .filter(|window| !window.is_zero())
+    }
+
     pub fn new(writer_tx: tokio::sync::mpsc::Sender<String>) -> Self {
         let (broadcast_tx, _) = broadcast::channel(256);
+        let reply_contexts = Arc::new(crate::reply_context::ReplyContexts::default());
+        let mut plugins = crate::plugin::Plugins::default();
+        plugins.register(reply_contexts.clone());
         Self {
             writer_tx,
             broadcast_tx,
@@ -107,25 +519,180 @@ impl AppState {
             next_id: Arc::new(AtomicU64::new(1)),
             metrics: Arc::new(Metrics::default()),
             webhooks: Arc::new(RwLock::new(Vec::new())),
+            webhooks_changed: Arc::new(Notify::new()),
+            webhook_replays: Default::default(),
+            webhook_workers: crate::webhooks::DEFAULT_MAX_CONCURRENCY,
+            webhook_cert_dir: None,
+            reset_sessions: false,
+            commands: Arc::new(RwLock::new(Vec::new())),
+            automations: Arc::new(crate::automations::AutomationStore::default()),
+            group_cache: Arc::new(crate::groups::GroupCache::default()),
+            group_snapshots: Arc::new(crate::group_events::Snapshots::default()),
+            max_mentions: crate::groups::DEFAULT_MAX_MENTIONS,
+            store: Arc::new(crate::store::MessageStore::default()),
+            outbox: Arc::new(crate::outbox::Outbox::default()),
+            quiet_hours: Arc::new(RwLock::new(HashMap::new())),
+            usernames: Arc::new(RwLock::new(HashMap::new())),
+            trust_policies: Arc::new(RwLock::new(HashMap::new())),
+            topics: Arc::new(RwLock::new(BTreeMap::new())),
+            dedup: Arc::new(crate::dedup::Deduplicator::default()),
+            dedup_window: None,
+            dedup_token_windows: Arc::default(),
+            profile_refresh: None,
             rpc_timeout: Duration::from_secs(30),
+            request_timeout: None,
+            slow_rpc: Some(DEFAULT_SLOW_RPC),
+            outbox_tick: Duration::from_secs(5),
+            outbox_batch: 50,
+            metrics_token: None,
+            metrics_public: true,
+            deprecated_routes: true,
+            outbound_proxy: None,
+            tokens: Arc::new(RwLock::new(Vec::new())),
+            quotas: Arc::new(crate::quota::QuotaTracker::default()),
+            tenants: Arc::new(crate::tenant::Tenants::default()),
+            jwt: None,
+            oidc: None,
+            hmac_window: crate::auth::DEFAULT_REPLAY_WINDOW,
+            replay_guard: Arc::new(ReplayGuard::default()),
+            secret_key: None,
+            aliases: Arc::new(BTreeMap::new()),
+            daemon_options: None,
+            log_tx: crate::logstream::channel(),
+            receive_subscriptions: Arc::new(RwLock::new(HashMap::new())),
+            attachments: Arc::new(crate::attachments::AttachmentStore::default()),
+            scanner: None,
+            compat: None,
+            json_case: crate::case::KeyCase::default(),
+            sse_gzip: false,
+            poll_queue: Arc::new(crate::compat::PollQueue::default()),
+            captchas: Arc::new(crate::captcha::CaptchaStore::default()),
+            change_numbers: Arc::new(crate::change_number::ChangeNumbers::default()),
+            link_sessions: Arc::new(crate::link::LinkSessions::default()),
+            syncs: Arc::new(crate::sync::SyncTracker::default()),
+            shedder: Arc::new(crate::shed::LoadShedder::default()),
+            pacer: None,
+            aws_sink: None,
+            canary: None,
+            signal_cli_version: Arc::default(),
+            redis: None,
+            leadership: Arc::new(crate::leader::Leadership::default()),
+            tasks: Arc::new(crate::supervisor::Supervisor::default()),
+            plugins: Arc::new(plugins),
+            kv: Arc::new(crate::kv::KvStore::default()),
+            event_log: Arc::new(crate::cursor::EventLog::default()),
+            reply_contexts,
+            spam: None,
+            auto_block: None,
+            server_config: Arc::new(crate::runtime_config::ServerConfig::default()),
+            maintenance: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Publish an event generated by this API (not signal-cli) to streams and
+    /// webhooks, as `{"system": {"type": kind, "timestamp": ..., ...data}}`.
+    pub fn publish_system(&self, kind: &str, data: serde_json::Value) {
+        let mut event = data;
+        event["type"] = serde_json::json!(kind);
+        event["timestamp"] = serde_json::json!(now_millis());
+        let _ = self.broadcast_tx.send(Event::system(event));
+    }
+
     /// Helper: make a JSON-RPC call to signal-cli.
-    pub async fn rpc(&self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, String> {
+    /// Slow calls are logged, and latency and in-flight calls are recorded in
+    /// the metrics, to show which operations hold up the connection.
+    pub async fn rpc(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        self.rpc_with_timeout(method, params, self.rpc_timeout)
+            .await
+    }
+
+    /// Ask signal-cli for its version and remember it. Daemons that don't
+    /// know the `version` method are left undetected. Part of connecting, so
+    /// not counted as an RPC.
+    pub async fn detect_signal_cli_version(&self) {
+        let params = serde_json::json!({});
+        let reply = crate::jsonrpc::rpc_call(
+            &self.writer_tx,
+            &self.pending,
+            &self.next_id,
+            "version",
+            params,
+            self.rpc_timeout,
+        );
+        let reported = match reply.await {
+            Ok(result) => result
+                .get("version")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            Err(e) => {
+                tracing::warn!("Couldn't get signal-cli's version: {e}");
+                return;
+            }
+        };
+        match reported.as_deref().and_then(crate::jsonrpc::Version::parse) {
+            Some(version) => {
+                tracing::info!("Connected to signal-cli {version}");
+                let _ = self.signal_cli_version.set(version);
+            }
+            None => tracing::warn!("signal-cli reported an unrecognised version: {reported:?}"),
+        }
+    }
+
+    /// [`AppState::rpc`] for calls that legitimately take longer than
+    /// `rpc_timeout`, such as waiting for a phone to confirm a link.
+    /// Either way the call gives up when the HTTP request it serves runs out
+    /// of time (see [`crate::deadline`]), and isn't sent if it already has.
+    pub async fn rpc_with_timeout(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<serde_json::Value, String> {
+        let timeout = crate::deadline::clamp(timeout);
+        if timeout.is_zero() {
+            return Err(RPC_TIMEOUT_ERROR.to_string());
+        }
+        let mut params = params;
+        crate::jsonrpc::shim(self.signal_cli_version.get().copied(), method, &mut params);
+        let send = method == "send" && !self.plugins.is_empty();
+        if send {
+            self.plugins.before_send(&mut params)?;
+        }
+        // Kept for after_send, since the call consumes the parameters.
+        let sent = send.then(|| params.clone());
         self.metrics.inc_rpc();
+        let _inflight = InflightGuard::new(&self.metrics.rpc_inflight);
+        let start = std::time::Instant::now();
         let result = crate::jsonrpc::rpc_call(
             &self.writer_tx,
             &self.pending,
             &self.next_id,
             method,
             params,
-            self.rpc_timeout,
+            timeout,
         )
         .await;
+        let elapsed = start.elapsed();
+        self.metrics.observe_rpc_latency(method, elapsed);
+        if self.slow_rpc.is_some_and(|threshold| elapsed >= threshold) {
+            tracing::warn!(
+                rpc_method = method,
+                elapsed_ms = elapsed.as_millis() as u64,
+                inflight = self.metrics.rpc_inflight.load(Ordering::Relaxed),
+                "Slow RPC"
+            );
+        }
         if result.is_err() {
             self.metrics.inc_rpc_error();
         }
+        if let Some(params) = sent {
+            self.plugins
+                .after_send(&params, result.as_ref().map_err(String::as_str));
+        }
         result
     }
 }
@@ -0,0 +1,623 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::event::Event;
+use crate::exposition::{Exposition, Labels};
+use crate::group_id::GroupId;
+use crate::quota::DAY_MS;
+use crate::state::{now_millis, AppState};
+
+/// Default number of messages kept per account.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+const HOUR_MS: u64 = 3600 * 1000;
+
+/// How often the pruning job runs by default.
+pub const DEFAULT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How much history is kept for an account. Unset limits of a per-account
+/// override fall back to the store's defaults.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Newest messages kept; older ones are dropped as new ones arrive.
+    pub max_rows: Option<usize>,
+    /// Messages older than this (by their timestamp) are dropped by the
+    /// pruning job.
+    pub max_age: Option<Duration>,
+}
+
+impl Retention {
+    /// Parse `account:rows[:max_age_secs]`; either limit may be left empty.
+    pub fn parse(spec: &str) -> Result<(String, Self), String> {
+        let invalid =
+            || format!("invalid retention {spec:?}, expected account:rows[:max_age_secs]");
+        let mut parts = spec.split(':');
+        let account = parts.next().filter(|a| !a.is_empty()).ok_or_else(invalid)?;
+        let limit = |part: Option<&str>| -> Result<Option<u64>, String> {
+            match part {
+                None | Some("") => Ok(None),
+                Some(n) => n.parse().map(Some).map_err(|_| invalid()),
+            }
+        };
+        let retention = Retention {
+            max_rows: limit(parts.next())?.map(|n| n as usize),
+            max_age: limit(parts.next())?.map(Duration::from_secs),
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok((account.to_string(), retention))
+    }
+
+    /// These limits, with unset ones taken from `defaults`.
+    fn or(self, defaults: Retention) -> Retention {
+        Retention {
+            max_rows: self.max_rows.or(defaults.max_rows),
+            max_age: self.max_age.or(defaults.max_age),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Stored records
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StoredMessage {
+    pub direction: Direction,
+    /// Author of the message (the account itself for outgoing messages).
+    pub source: String,
+    /// Recipients of an outgoing message (empty for incoming).
+    pub recipients: Vec<String>,
+    pub group_id: Option<String>,
+    pub timestamp: u64,
+    pub message: Option<String>,
+    pub reactions: Vec<ReactionSummary>,
+    #[serde(skip)]
+    reactors: Vec<(String, String)>, // (reactor, emoji)
+    /// The line an incoming message arrived in, for replaying it to
+    /// webhooks. Shared with the event, and parsed again only on replay.
+    #[serde(skip)]
+    pub line: Option<Arc<str>>,
+}
+
+/// Aggregated reactions for a single emoji on a stored message.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: usize,
+    pub reactors: Vec<String>,
+}
+
+impl StoredMessage {
+    /// Apply (or remove) a reaction. A reactor holds at most one emoji per
+    /// message, matching Signal's semantics: a new reaction replaces the old.
+    fn react(&mut self, reactor: &str, emoji: &str, remove: bool) {
+        self.reactors.retain(|(r, _)| r != reactor);
+        if !remove {
+            self.reactors.push((reactor.to_string(), emoji.to_string()));
+        }
+        let mut summaries: Vec<ReactionSummary> = Vec::new();
+        for (reactor, emoji) in &self.reactors {
+            match summaries.iter_mut().find(|s| &s.emoji == emoji) {
+                Some(s) => {
+                    s.count += 1;
+                    s.reactors.push(reactor.clone());
+                }
+                None => summaries.push(ReactionSummary {
+                    emoji: emoji.clone(),
+                    count: 1,
+                    reactors: vec![reactor.clone()],
+                }),
+            }
+        }
+        self.reactions = summaries;
+    }
+
+    /// Whether this message belongs to the conversation with `peer`
+    /// (a phone number or group ID).
+    pub fn involves(&self, peer: &str) -> bool {
+        self.group_id.as_deref() == Some(peer)
+            || (self.group_id.is_none()
+                && (self.source == peer || self.recipients.iter().any(|r| r == peer)))
+    }
+}
+
+/// Split a notification into its account (empty if absent) and envelope.
+/// signal-cli wraps notifications in JSON-RPC "params"; both shapes are accepted.
+pub fn split_notification(parsed: &Value) -> Option<(&str, &Value)> {
+    let root = parsed.get("params").unwrap_or(parsed);
+    let envelope = root.get("envelope")?;
+    let account = root.get("account").and_then(|v| v.as_str()).unwrap_or("");
+    Some((account, envelope))
+}
+
+/// If `parsed` is a receipt to `account` covering the message it sent at
+/// `timestamp`, its envelope, to tell who sent it with [`sent_by`]. Read
+/// and viewed receipts prove delivery too.
+pub fn delivery_receipt<'a>(parsed: &'a Value, account: &str, timestamp: u64) -> Option<&'a Value> {
+    let (to, envelope) = split_notification(parsed)?;
+    if !to.is_empty() && to != account {
+        return None;
+    }
+    let timestamps = envelope
+        .get("receiptMessage")?
+        .get("timestamps")?
+        .as_array()?;
+    timestamps
+        .iter()
+        .any(|t| t.as_u64() == Some(timestamp))
+        .then_some(envelope)
+}
+
+/// Whether an envelope was sent by `who`, a number or a UUID.
+pub fn sent_by(envelope: &Value, who: &str) -> bool {
+    ["sourceNumber", "source", "sourceUuid"]
+        .iter()
+        .any(|key| envelope.get(key).and_then(|v| v.as_str()) == Some(who))
+}
+
+/// The sender's number of an envelope, preferring signal-cli's "sourceNumber".
+pub fn envelope_source(envelope: &Value) -> &str {
+    envelope
+        .get("sourceNumber")
+        .or_else(|| envelope.get("source"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+}
+
+/// Activity in a group, from the messages the store holds for it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GroupStats {
+    pub messages: usize,
+    /// Timestamps of the oldest and newest message counted.
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+    /// Messages per member, most active first.
+    pub members: Vec<MemberCount>,
+    /// Messages per UTC day (`YYYY-MM-DD`), oldest first; quiet days are left out.
+    pub days: Vec<DayCount>,
+    /// Messages per hour of the day (UTC), index 0 being 00:00-00:59.
+    pub hours: [usize; 24],
+    /// Hours with the most messages, busiest first (up to three).
+    pub most_active_hours: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemberCount {
+    pub number: String,
+    pub messages: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DayCount {
+    pub date: String,
+    pub messages: usize,
+}
+
+/// `YYYY-MM-DD` of a day counted from the Unix epoch (proleptic Gregorian).
+pub fn civil_date(days: u64) -> String {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+// ---------------------------------------------------------------------------
+// MessageStore
+// ---------------------------------------------------------------------------
+
+/// Bounded in-memory history of messages, keyed by account.
+pub struct MessageStore {
+    accounts: RwLock<HashMap<String, VecDeque<StoredMessage>>>,
+    pub retention: Retention,
+    /// Per-account limits, overriding `retention`.
+    pub overrides: HashMap<String, Retention>,
+    pub prune_interval: Duration,
+    pruned_rows: AtomicU64,
+    pruned_age: AtomicU64,
+}
+
+impl Default for MessageStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl MessageStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            accounts: RwLock::new(HashMap::new()),
+            retention: Retention {
+                max_rows: Some(capacity),
+                max_age: None,
+            },
+            overrides: HashMap::new(),
+            prune_interval: DEFAULT_PRUNE_INTERVAL,
+            pruned_rows: AtomicU64::new(0),
+            pruned_age: AtomicU64::new(0),
+        }
+    }
+
+    /// The limits in effect for `account`.
+    pub fn retention_for(&self, account: &str) -> Retention {
+        match self.overrides.get(account) {
+            Some(r) => r.or(self.retention),
+            None => self.retention,
+        }
+    }
+
+    fn push(&self, account: &str, msg: StoredMessage) {
+        let max_rows = self.retention_for(account).max_rows;
+        let mut accounts = self.accounts.write().unwrap();
+        let history = accounts.entry(account.to_string()).or_default();
+        history.push_back(msg);
+        if let Some(max_rows) = max_rows {
+            let excess = history.len().saturating_sub(max_rows);
+            history.drain(..excess);
+            self.pruned_rows.fetch_add(excess as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop messages older than their account's `max_age`, returning how
+    /// many were dropped. Accounts left without messages are forgotten.
+    pub fn prune(&self) -> usize {
+        let now = now_millis();
+        let mut accounts = self.accounts.write().unwrap();
+        let mut pruned = 0;
+        for (account, history) in accounts.iter_mut() {
+            let Some(max_age) = self.retention_for(account).max_age else {
+                continue;
+            };
+            let cutoff = now.saturating_sub(max_age.as_millis() as u64);
+            let before = history.len();
+            history.retain(|m| m.timestamp >= cutoff);
+            pruned += before - history.len();
+        }
+        accounts.retain(|_, history| !history.is_empty());
+        self.pruned_age.fetch_add(pruned as u64, Ordering::Relaxed);
+        pruned
+    }
+
+    /// Write the store's metrics: messages held per account and messages
+    /// dropped by the retention limits.
+    pub fn render(&self, exp: &mut Exposition) {
+        let held: Vec<(String, u64)> = {
+            let accounts = self.accounts.read().unwrap();
+            let mut held: Vec<_> = accounts
+                .iter()
+                .map(|(a, h)| (a.clone(), h.len() as u64))
+                .collect();
+            held.sort();
+            held
+        };
+        let labels: Vec<[(&str, &str); 1]> = held
+            .iter()
+            .map(|(a, _)| [("account", a.as_str())])
+            .collect();
+        let samples: Vec<(Labels, u64)> = labels
+            .iter()
+            .zip(&held)
+            .map(|(l, (_, n))| (&l[..], *n))
+            .collect();
+        exp.gauge(
+            "signal_store_messages",
+            "Messages held in the message store",
+            &samples,
+        );
+        exp.counter(
+            "signal_store_pruned",
+            "Messages dropped from the message store by its retention limits",
+            &[
+                (
+                    &[("reason", "rows")],
+                    self.pruned_rows.load(Ordering::Relaxed),
+                ),
+                (
+                    &[("reason", "age")],
+                    self.pruned_age.load(Ordering::Relaxed),
+                ),
+            ],
+        );
+    }
+
+    /// Record a message sent by `account` through the API.
+    pub fn record_outgoing(&self, account: &str, params: &Value, timestamp: u64) {
+        let recipients = match params.get("recipients").or_else(|| params.get("recipient")) {
+            Some(Value::Array(list)) => list
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect(),
+            Some(Value::String(s)) => vec![s.clone()],
+            _ => Vec::new(),
+        };
+        let group_id = params
+            .get("group-id")
+            .or_else(|| params.get("groupId"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        self.push(
+            account,
+            StoredMessage {
+                direction: Direction::Outgoing,
+                source: account.to_string(),
+                recipients,
+                group_id,
+                timestamp,
+                message: params
+                    .get("message")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
+                reactions: Vec::new(),
+                reactors: Vec::new(),
+                line: None,
+            },
+        );
+    }
+
+    /// Record a reaction by `reactor` on the message (`target_author`, `target_timestamp`).
+    /// Returns false if the target message isn't in the store.
+    pub fn record_reaction(
+        &self,
+        account: &str,
+        reactor: &str,
+        emoji: &str,
+        target_author: &str,
+        target_timestamp: u64,
+        remove: bool,
+    ) -> bool {
+        let mut accounts = self.accounts.write().unwrap();
+        let Some(history) = accounts.get_mut(account) else {
+            return false;
+        };
+        match history
+            .iter_mut()
+            .rev()
+            .find(|m| m.timestamp == target_timestamp && m.source == target_author)
+        {
+            Some(msg) => {
+                msg.react(reactor, emoji, remove);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Record an incoming notification line from signal-cli. Data messages are
+    /// stored; reactions are folded into the message they target.
+    /// Its line is kept so it can be replayed (see
+    /// [`MessageStore::events_between`]).
+    pub fn record_incoming(&self, event: &Event) {
+        let parsed = event.json();
+        let Some((account, envelope)) = split_notification(parsed) else {
+            return;
+        };
+        let Some(data) = envelope.get("dataMessage") else {
+            return;
+        };
+        let source = envelope_source(envelope);
+
+        if let Some(reaction) = data.get("reaction") {
+            let emoji = reaction.get("emoji").and_then(|v| v.as_str()).unwrap_or("");
+            let target_author = reaction
+                .get("targetAuthorNumber")
+                .or_else(|| reaction.get("targetAuthor"))
+                .and_then(|v| v.as_str())
+                .unwrap_or("");
+            let target_ts = reaction
+                .get("targetSentTimestamp")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0);
+            let remove = reaction
+                .get("isRemove")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false);
+            self.record_reaction(account, source, emoji, target_author, target_ts, remove);
+            return;
+        }
+
+        let timestamp = data
+            .get("timestamp")
+            .or_else(|| envelope.get("timestamp"))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        let group_id = data
+            .get("groupInfo")
+            .and_then(|g| g.get("groupId"))
+            .and_then(|v| v.as_str())
+            .map(String::from);
+        self.push(
+            account,
+            StoredMessage {
+                direction: Direction::Incoming,
+                source: source.to_string(),
+                recipients: Vec::new(),
+                group_id,
+                timestamp,
+                message: data
+                    .get("message")
+                    .and_then(|v| v.as_str())
+                    .map(String::from),
+                reactions: Vec::new(),
+                reactors: Vec::new(),
+                line: Some(event.line().clone()),
+            },
+        );
+    }
+
+    /// Timestamps of the newest outgoing and incoming messages held for `account`.
+    pub fn last_activity(&self, account: &str) -> (Option<u64>, Option<u64>) {
+        let accounts = self.accounts.read().unwrap();
+        let Some(history) = accounts.get(account) else {
+            return (None, None);
+        };
+        let newest = |direction: Direction| {
+            history
+                .iter()
+                .filter(|m| m.direction == direction)
+                .map(|m| m.timestamp)
+                .max()
+        };
+        (newest(Direction::Outgoing), newest(Direction::Incoming))
+    }
+
+    /// The people `account` exchanged messages with since `since` (ms), in
+    /// groups too, the most recently heard from first.
+    pub fn peers_since(&self, account: &str, since: u64) -> Vec<String> {
+        let accounts = self.accounts.read().unwrap();
+        let Some(history) = accounts.get(account) else {
+            return Vec::new();
+        };
+        let mut peers: Vec<String> = Vec::new();
+        for m in history.iter().rev().filter(|m| m.timestamp >= since) {
+            let people = match m.direction {
+                Direction::Incoming => std::slice::from_ref(&m.source),
+                Direction::Outgoing => &m.recipients[..],
+            };
+            for peer in people {
+                if peer != account && !peer.is_empty() && !peers.contains(peer) {
+                    peers.push(peer.clone());
+                }
+            }
+        }
+        peers
+    }
+
+    /// Activity statistics for `group` from the messages held for `account`,
+    /// the account's own messages to the group included.
+    pub fn group_stats(&self, account: &str, group: &GroupId) -> GroupStats {
+        let accounts = self.accounts.read().unwrap();
+        let mut stats = GroupStats::default();
+        let Some(history) = accounts.get(account) else {
+            return stats;
+        };
+        let mut members: HashMap<&str, usize> = HashMap::new();
+        let mut days: BTreeMap<u64, usize> = BTreeMap::new();
+        let in_group = |m: &&StoredMessage| {
+            m.group_id
+                .as_deref()
+                .is_some_and(|g| GroupId::from(g) == *group)
+        };
+        for m in history.iter().filter(in_group) {
+            stats.messages += 1;
+            stats.first = Some(stats.first.map_or(m.timestamp, |t| t.min(m.timestamp)));
+            stats.last = Some(stats.last.map_or(m.timestamp, |t| t.max(m.timestamp)));
+            *members.entry(m.source.as_str()).or_default() += 1;
+            *days.entry(m.timestamp / DAY_MS).or_default() += 1;
+            stats.hours[(m.timestamp % DAY_MS / HOUR_MS) as usize] += 1;
+        }
+        stats.members = members
+            .into_iter()
+            .map(|(number, messages)| MemberCount {
+                number: number.to_string(),
+                messages,
+            })
+            .collect();
+        stats.members.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then_with(|| a.number.cmp(&b.number))
+        });
+        stats.days = days
+            .into_iter()
+            .map(|(day, messages)| DayCount {
+                date: civil_date(day),
+                messages,
+            })
+            .collect();
+        let mut hours: Vec<u8> = (0..24).filter(|&h| stats.hours[h as usize] > 0).collect();
+        hours.sort_by_key(|&h| std::cmp::Reverse(stats.hours[h as usize]));
+        hours.truncate(3);
+        stats.most_active_hours = hours;
+        stats
+    }
+
+    /// Return the stored history for `account`, oldest first, optionally
+    /// restricted to the conversation with `peer` and limited to the newest `limit`.
+    pub fn history(
+        &self,
+        account: &str,
+        peer: Option<&str>,
+        limit: Option<usize>,
+    ) -> Vec<StoredMessage> {
+        let accounts = self.accounts.read().unwrap();
+        let Some(history) = accounts.get(account) else {
+            return Vec::new();
+        };
+        let mut msgs: Vec<StoredMessage> = history
+            .iter()
+            .filter(|m| peer.is_none_or(|p| m.involves(p)))
+            .cloned()
+            .collect();
+        if let Some(limit) = limit {
+            let skip = msgs.len().saturating_sub(limit);
+            msgs.drain(..skip);
+        }
+        msgs
+    }
+
+    /// The events of the incoming messages held for every account with a
+    /// timestamp from `from` up to (not including) `to`, oldest first.
+    pub fn events_between(&self, from: u64, to: u64) -> Vec<Event> {
+        let accounts = self.accounts.read().unwrap();
+        let mut lines: Vec<(u64, &str)> = accounts
+            .values()
+            .flatten()
+            .filter(|m| (from..to).contains(&m.timestamp))
+            .filter_map(|m| Some((m.timestamp, m.line.as_deref()?)))
+            .collect();
+        lines.sort_by_key(|(timestamp, _)| *timestamp);
+        lines
+            .into_iter()
+            .map(|(_, line)| Event::from(line.to_string()))
+            .collect()
+    }
+}
+
+/// Applies the age limits of the message store every `prune_interval`.
+/// Does nothing when no account has a `max_age`.
+pub async fn prune_loop(state: AppState) {
+    let store = state.store.clone();
+    if store.retention.max_age.is_none() && store.overrides.values().all(|r| r.max_age.is_none()) {
+        return;
+    }
+    loop {
+        let pruned = store.prune();
+        if pruned > 0 {
+            tracing::debug!(pruned, "Pruned old messages from the message store");
+        }
+        tokio::time::sleep(store.prune_interval).await;
+    }
+}
+
+/// Subscribes to the broadcast channel and records every incoming
+/// notification in the message store.
+pub async fn record_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(event) => state.store.record_incoming(&event),
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Message store lagged, {n} notifications not recorded");
+            }
+            Err(_) => break,
+        }
+    }
+}
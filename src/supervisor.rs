@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinError;
+
+use crate::state::now_millis;
+
+/// How long a panicked task waits before it is started again.
+pub const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Panicked; starts again after [`RESTART_BACKOFF`].
+    Restarting,
+    /// Returned on its own, e.g. because its channel closed.
+    Stopped,
+    /// A critical task ended; the server is shutting down.
+    Failed,
+    /// Not started, because the feature it serves is off.
+    Disabled,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskStatus {
+    pub name: String,
+    /// Whether the server shuts down when the task ends.
+    pub critical: bool,
+    pub state: TaskState,
+    pub restarts: u32,
+    /// When the task was last started (ms since epoch).
+    pub started_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Background tasks of the server, watched so that a panic doesn't leave it
+/// running half-broken: restartable tasks are started again, and the end of
+/// a critical one shuts the server down.
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Mutex<BTreeMap<String, TaskStatus>>,
+    fatal: Mutex<Option<String>>,
+    failed: Notify,
+}
+
+fn describe(error: JoinError) -> String {
+    if !error.is_panic() {
+        return "cancelled".to_string();
+    }
+    let panic = error.into_panic();
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    format!("panicked: {message}")
+}
+
+impl Supervisor {
+    fn update(&self, name: &str, change: impl FnOnce(&mut TaskStatus)) {
+        if let Some(status) = self.tasks.lock().unwrap().get_mut(name) {
+            change(status);
+        }
+    }
+
+    fn register(&self, name: &str, critical: bool) {
+        self.register_as(name, critical, TaskState::Running);
+    }
+
+    fn register_as(&self, name: &str, critical: bool, state: TaskState) {
+        let status = TaskStatus {
+            name: name.to_string(),
+            critical,
+            state,
+            restarts: 0,
+            started_at: now_millis(),
+            last_error: None,
+        };
+        self.tasks.lock().unwrap().insert(name.to_string(), status);
+    }
+
+    /// Run a task that can be started again: after a panic `start` is called
+    /// for a fresh one; when the task returns it is left stopped.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, name: &str, mut start: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.register(name, false);
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            loop {
+                match tokio::spawn(start()).await {
+                    Ok(()) => {
+                        tracing::warn!(task = name, "Background task stopped");
+                        return supervisor.update(&name, |s| s.state = TaskState::Stopped);
+                    }
+                    Err(e) => {
+                        let error = describe(e);
+                        tracing::error!(task = name, "Background task {error}; restarting");
+                        supervisor.update(&name, |s| {
+                            s.state = TaskState::Restarting;
+                            s.last_error = Some(error);
+                        });
+                    }
+                }
+                tokio::time::sleep(RESTART_BACKOFF).await;
+                supervisor.update(&name, |s| {
+                    s.state = TaskState::Running;
+                    s.restarts += 1;
+                    s.started_at = now_millis();
+                });
+            }
+        });
+    }
+
+    /// List a task whose feature is off without running it.
+    pub fn disable(&self, name: &str) {
+        tracing::debug!(task = name, "Background task disabled");
+        self.register_as(name, false, TaskState::Disabled);
+    }
+
+    /// Run a task the server can't work without and can't restart, such as
+    /// the reader of the signal-cli connection. However it ends,
+    /// [`Supervisor::failure`] resolves.
+    pub fn spawn_critical(
+        self: &Arc<Self>,
+        name: &str,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) {
+        self.register(name, true);
+        let supervisor = self.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            let error = match tokio::spawn(task).await {
+                Ok(()) => "exited".to_string(),
+                Err(e) => describe(e),
+            };
+            tracing::error!(task = name, "Critical task {error}");
+            supervisor.update(&name, |s| {
+                s.state = TaskState::Failed;
+                s.last_error = Some(error.clone());
+            });
+            supervisor
+                .fatal
+                .lock()
+                .unwrap()
+                .get_or_insert(format!("{name} {error}"));
+            supervisor.failed.notify_one();
+        });
+    }
+
+    /// Wait until a critical task ends, and say which and how.
+    pub async fn failure(&self) -> String {
+        loop {
+            if let Some(reason) = self.fatal.lock().unwrap().clone() {
+                return reason;
+            }
+            self.failed.notified().await;
+        }
+    }
+
+    pub fn list(&self) -> Vec<TaskStatus> {
+        self.tasks.lock().unwrap().values().cloned().collect()
+    }
+}
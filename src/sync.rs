@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::EventKind;
+use crate::state::{now_millis, AppState};
+
+/// Sync data older than this is reported as stale, unless the caller says otherwise.
+pub const DEFAULT_STALE_AFTER: Duration = Duration::from_secs(24 * 3600);
+
+/// Data a linked device can ask its primary device for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncKind {
+    Contacts,
+    Groups,
+    Blocked,
+    Keys,
+}
+
+impl SyncKind {
+    pub const ALL: [SyncKind; 4] = [
+        SyncKind::Contacts,
+        SyncKind::Groups,
+        SyncKind::Blocked,
+        SyncKind::Keys,
+    ];
+
+    /// The [`SyncState::received`] entry an arrival of this data is kept under.
+    fn received_as(self) -> &'static str {
+        match self {
+            SyncKind::Contacts => "CONTACTS_SYNC",
+            SyncKind::Groups => "GROUPS_SYNC",
+            SyncKind::Blocked => BLOCKED_SYNC,
+            SyncKind::Keys => "KEYS_SYNC",
+        }
+    }
+}
+
+/// The [`SyncState::received`] entry for blocked lists, which signal-cli
+/// reports as `blockedNumbers`/`blockedGroupIds` rather than with a type.
+const BLOCKED_SYNC: &str = "BLOCKED_SYNC";
+
+/// What an account (typically a linked device) knows about syncing with its
+/// primary device. Timestamps are ms since epoch and reset on restart.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SyncState {
+    /// When sync data was last requested from the primary device.
+    pub last_request: Option<u64>,
+    /// What the last request asked for.
+    pub requested: Vec<SyncKind>,
+    /// When a sync message from another device of the account last arrived.
+    pub last_sync: Option<u64>,
+    /// Last arrival per sync type signal-cli reported, e.g. `CONTACTS_SYNC`.
+    pub received: BTreeMap<String, u64>,
+}
+
+impl SyncState {
+    /// Whether nothing was synced within `after`, or ever.
+    pub fn is_stale(&self, after: Duration, now: u64) -> bool {
+        self.last_sync
+            .is_none_or(|at| now.saturating_sub(at) > after.as_millis() as u64)
+    }
+
+    /// Which of `kinds` didn't arrive within `after`, or ever. signal-cli
+    /// doesn't report key syncs, so `keys` is always stale.
+    pub fn stale_kinds(&self, kinds: &[SyncKind], after: Duration, now: u64) -> Vec<SyncKind> {
+        let stale = |at: &u64| now.saturating_sub(*at) > after.as_millis() as u64;
+        kinds
+            .iter()
+            .copied()
+            .filter(|k| self.received.get(k.received_as()).is_none_or(stale))
+            .collect()
+    }
+}
+
+/// Sync state per account.
+#[derive(Default)]
+pub struct SyncTracker {
+    accounts: Mutex<HashMap<String, SyncState>>,
+}
+
+impl SyncTracker {
+    pub fn get(&self, account: &str) -> SyncState {
+        self.accounts
+            .lock()
+            .unwrap()
+            .get(account)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn requested(&self, account: &str, kinds: &[SyncKind]) {
+        let mut accounts = self.accounts.lock().unwrap();
+        let state = accounts.entry(account.to_string()).or_default();
+        state.last_request = Some(now_millis());
+        state.requested = kinds.to_vec();
+    }
+
+    pub fn received(&self, account: &str, kind: Option<&str>) {
+        let now = now_millis();
+        let mut accounts = self.accounts.lock().unwrap();
+        let state = accounts.entry(account.to_string()).or_default();
+        state.last_sync = Some(now);
+        if let Some(kind) = kind {
+            state.received.insert(kind.to_string(), now);
+        }
+    }
+}
+
+/// Ask the account's primary device to send `kinds` again, and publish a
+/// `sync_request` system event. signal-cli's `sendSyncRequest` asks for
+/// everything at once; `kinds` records what the caller was after.
+pub async fn request(state: &AppState, account: &str, kinds: &[SyncKind]) -> Result<(), String> {
+    state
+        .rpc("sendSyncRequest", json!({ "account": account }))
+        .await?;
+    state.syncs.requested(account, kinds);
+    state.publish_system(
+        "sync_request",
+        json!({ "account": account, "types": kinds }),
+    );
+    Ok(())
+}
+
+/// Record sync messages arriving from the accounts' other devices.
+pub async fn record_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Sync tracker lagged, {n} notifications skipped");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if event.kind() != EventKind::Sync {
+            continue;
+        }
+        let Some((account, envelope)) = event.notification() else {
+            continue;
+        };
+        if account.is_empty() {
+            continue;
+        }
+        let sync = &envelope["syncMessage"];
+        let blocked = sync.get("blockedNumbers").is_some() || sync.get("blockedGroupIds").is_some();
+        let kind = sync
+            .get("type")
+            .and_then(|t| t.as_str())
+            .or(blocked.then_some(BLOCKED_SYNC));
+        state.syncs.received(account, kind);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::auth::ApiToken;
+use crate::exposition::{Exposition, Labels};
+use crate::jwt::{Grant, Scope};
+
+/// Routes a tenant may use besides those under one of its accounts
+/// (`{number}` in the path): sending, which is checked against the body's
+/// account, and its own webhooks.
+const SHARED_PATHS: &[&str] = &["/v1/send", "/v2/send", "/v1/about", "/v1/webhooks"];
+
+/// A customer of a hosted deployment: the accounts it owns and the API
+/// tokens it authenticates with. Its tokens reach only its accounts, and its
+/// webhooks get only their events.
+#[derive(Debug)]
+pub struct Tenant {
+    pub name: String,
+    pub accounts: Vec<String>,
+    pub tokens: Vec<String>,
+    requests: AtomicU64,
+    messages_sent: AtomicU64,
+    webhook_deliveries: AtomicU64,
+}
+
+impl Tenant {
+    /// Parse `name:accounts:tokens`, both lists comma-separated, as given on
+    /// the command line.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid tenant {spec:?}, expected name:account,...:token,...");
+        let mut parts = spec.split(':');
+        let (Some(name), Some(accounts), Some(tokens), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if !crate::topics::valid_name(name) {
+            return Err(format!(
+                "tenant name {name:?} must be 1-64 letters, digits, '-' or '_'"
+            ));
+        }
+        let list = |s: &str| {
+            s.split(',')
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        let (accounts, tokens) = (list(accounts), list(tokens));
+        if accounts.is_empty() || tokens.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            accounts,
+            tokens,
+            requests: AtomicU64::new(0),
+            messages_sent: AtomicU64::new(0),
+            webhook_deliveries: AtomicU64::new(0),
+        })
+    }
+
+    /// Whether `account` (resolved) is one of the tenant's.
+    pub fn owns(&self, account: &str, resolve: impl Fn(&str) -> String) -> bool {
+        self.accounts.iter().any(|a| resolve(a) == account)
+    }
+
+    /// What the tenant's tokens may do: read and send for its accounts. Its
+    /// webhooks are admin routes, let through by [`Tenant::may_use`].
+    pub fn grant(&self) -> Grant {
+        Grant {
+            subject: format!("tenant:{}", self.name),
+            scopes: vec![Scope::Read, Scope::Write],
+            accounts: Some(self.accounts.clone()),
+        }
+    }
+
+    /// Whether a route is open to the tenant at all; `account_route` when it
+    /// has a `{number}`, which the grant then checks.
+    pub fn may_use(path: &str, account_route: bool) -> bool {
+        account_route
+            || SHARED_PATHS
+                .iter()
+                .any(|p| path == *p || path.starts_with(&format!("{p}/")))
+    }
+
+    /// Key of the tenant's quota in the quota tracker. Token ids can't hold
+    /// a ':', so it never clashes with a token's.
+    pub fn quota_key(&self) -> String {
+        format!("tenant:{}", self.name)
+    }
+
+    pub fn inc_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_webhook_delivery(&self) {
+        self.webhook_deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The configured tenants (empty = single-tenant deployment).
+#[derive(Debug, Default)]
+pub struct Tenants {
+    tenants: Vec<Arc<Tenant>>,
+    by_token: HashMap<String, Arc<Tenant>>,
+}
+
+impl Tenants {
+    /// Check that tenants don't share tokens or accounts and only name
+    /// configured tokens.
+    pub fn new(tenants: Vec<Tenant>, tokens: &[ApiToken]) -> Result<Self, String> {
+        let mut this = Self::default();
+        let mut owners: HashMap<&str, &str> = HashMap::new();
+        for tenant in &tenants {
+            if tenants.iter().filter(|t| t.name == tenant.name).count() > 1 {
+                return Err(format!("tenant {:?} is defined twice", tenant.name));
+            }
+            for account in &tenant.accounts {
+                if let Some(other) = owners
+                    .insert(account, &tenant.name)
+                    .filter(|o| *o != tenant.name)
+                {
+                    return Err(format!(
+                        "account {account} belongs to tenants {other:?} and {:?}",
+                        tenant.name
+                    ));
+                }
+            }
+            if let Some(unknown) = tenant
+                .tokens
+                .iter()
+                .find(|id| !tokens.iter().any(|t| &t.id == *id))
+            {
+                return Err(format!(
+                    "tenant {:?} names unknown token {unknown:?}",
+                    tenant.name
+                ));
+            }
+        }
+        for tenant in tenants {
+            let tenant = Arc::new(tenant);
+            for token in &tenant.tokens {
+                if let Some(other) = this
+                    .by_token
+                    .insert(token.clone(), tenant.clone())
+                    .filter(|o| o.name != tenant.name)
+                {
+                    return Err(format!(
+                        "token {token:?} belongs to tenants {:?} and {:?}",
+                        other.name, tenant.name
+                    ));
+                }
+            }
+            this.tenants.push(tenant);
+        }
+        Ok(this)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.tenants.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Tenant>> {
+        self.tenants.iter().find(|t| t.name == name)
+    }
+
+    /// The tenant an API token belongs to, if any.
+    pub fn for_token(&self, token_id: &str) -> Option<&Arc<Tenant>> {
+        self.by_token.get(token_id)
+    }
+
+    /// Append per-tenant counters to an exposition.
+    pub fn render(&self, exp: &mut Exposition) {
+        if self.tenants.is_empty() {
+            return;
+        }
+        let labels: Vec<[(&str, &str); 1]> = self
+            .tenants
+            .iter()
+            .map(|t| [("tenant", t.name.as_str())])
+            .collect();
+        let samples = |counter: fn(&Tenant) -> &AtomicU64| -> Vec<(Labels, u64)> {
+            labels
+                .iter()
+                .zip(&self.tenants)
+                .map(|(l, t)| (&l[..], counter(t).load(Ordering::Relaxed)))
+                .collect()
+        };
+        exp.counter(
+            "signal_tenant_requests",
+            "Authenticated requests per tenant",
+            &samples(|t| &t.requests),
+        );
+        exp.counter(
+            "signal_tenant_messages_sent",
+            "Messages sent per tenant",
+            &samples(|t| &t.messages_sent),
+        );
+        exp.counter(
+            "signal_tenant_webhook_deliveries",
+            "Events delivered to tenants' webhooks",
+            &samples(|t| &t.webhook_deliveries),
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::group_id::GroupId;
+
+/// The group a topic sends to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topic {
+    pub group_id: GroupId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Logical names for groups ("oncall", "deploys"), so senders don't hard-code
+/// group IDs, which change when a group is recreated.
+pub type Topics = BTreeMap<String, Topic>;
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Parse a `name=group-id` pair as given on the command line.
+pub fn parse(spec: &str) -> Result<(String, Topic), String> {
+    let invalid = || format!("invalid topic {spec:?}, expected name=group-id");
+    let (name, id) = spec.split_once('=').ok_or_else(invalid)?;
+    if !valid_name(name) {
+        return Err(invalid());
+    }
+    let group_id = GroupId::parse(id).ok_or_else(invalid)?;
+    Ok((
+        name.to_string(),
+        Topic {
+            group_id,
+            description: None,
+        },
+    ))
+}
+
+/// Replace a send's `topic` with the `group-id` of its group.
+pub fn resolve(params: &mut Value, topics: &Topics) -> Result<(), String> {
+    let Some(topic) = params.as_object_mut().and_then(|o| o.remove("topic")) else {
+        return Ok(());
+    };
+    let name = topic.as_str().ok_or("topic must be a string")?;
+    let recipients = params
+        .get("recipients")
+        .and_then(|r| r.as_array())
+        .is_some_and(|r| !r.is_empty());
+    if recipients
+        || params.get("recipient").is_some()
+        || params.get("group-id").is_some()
+        || params.get("groupId").is_some()
+    {
+        return Err("topic can't be combined with recipients or a group ID".into());
+    }
+    let topic = topics
+        .get(name)
+        .ok_or_else(|| format!("no topic {name:?}"))?;
+    params["group-id"] = json!(topic.group_id);
+    if let Some(params) = params.as_object_mut() {
+        params.remove("recipients");
+    }
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::groups;
+use crate::state::AppState;
+
+/// How an account treats its contacts' identity keys when sending.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrustPolicy {
+    /// Trust every key, new or changed, so no send is held up by one.
+    Always,
+    /// signal-cli's own behaviour: a contact's first key is trusted, and a
+    /// changed one must be trusted before sends to it go through.
+    #[default]
+    OnFirstUse,
+    /// Send only to contacts whose key was verified by hand.
+    Manual,
+}
+
+/// A recipient whose key the account's policy doesn't let it send to.
+#[derive(Clone, Debug, Serialize)]
+pub struct Untrusted {
+    pub recipient: String,
+    pub safety_number: Option<String>,
+    pub trust_level: String,
+}
+
+/// The recipients of a send payload: its `recipients`/`recipient`, or for a
+/// group send the other members of the group.
+pub async fn recipients(
+    state: &AppState,
+    account: &str,
+    body: &Value,
+) -> Result<Vec<String>, String> {
+    if let Some(group) = body.get("group-id").or_else(|| body.get("groupId")) {
+        let groups = state
+            .rpc(
+                "listGroups",
+                json!({ "account": account, "group-id": group }),
+            )
+            .await?;
+        return Ok(groups
+            .as_array()
+            .and_then(|g| g.first())
+            .map_or_else(Vec::new, |g| groups::other_members(g, account)));
+    }
+    let listed = body.get("recipients").or_else(|| body.get("recipient"));
+    Ok(match listed {
+        Some(Value::Array(list)) => list
+            .iter()
+            .filter_map(|r| r.as_str().map(String::from))
+            .collect(),
+        Some(Value::String(r)) => vec![r.clone()],
+        _ => Vec::new(),
+    })
+}
+
+/// The `listIdentities` entry for `recipient`, by number or UUID.
+pub fn identity<'a>(identities: &'a Value, recipient: &str) -> Option<&'a Value> {
+    identities.as_array()?.iter().find(|i| {
+        ["number", "uuid"]
+            .iter()
+            .any(|key| i.get(key).and_then(|v| v.as_str()) == Some(recipient))
+    })
+}
+
+/// Apply `policy` to a send from `account` to `recipients` before it goes
+/// out. `always` trusts the keys of recipients signal-cli holds as untrusted;
+/// `manual` returns the recipients whose key hasn't been verified, which
+/// must not be sent to. That includes recipients signal-cli has no key for
+/// yet (trust level `UNKNOWN`): a key that was never seen can't have been
+/// verified, and would otherwise be trusted on first use by the send.
+pub async fn enforce(
+    state: &AppState,
+    account: &str,
+    policy: TrustPolicy,
+    recipients: &[String],
+) -> Result<Vec<Untrusted>, String> {
+    if policy == TrustPolicy::OnFirstUse || recipients.is_empty() {
+        return Ok(Vec::new());
+    }
+    let identities = state
+        .rpc("listIdentities", json!({ "account": account }))
+        .await?;
+    let level = |r: &str| identity(&identities, r).and_then(|i| i["trustLevel"].as_str());
+    let mut untrusted = Vec::new();
+    for recipient in recipients {
+        match (policy, level(recipient)) {
+            (TrustPolicy::Manual, None) => untrusted.push(Untrusted {
+                recipient: recipient.clone(),
+                safety_number: None,
+                trust_level: "UNKNOWN".to_string(),
+            }),
+            (_, None) => {}
+            (TrustPolicy::Always, Some("UNTRUSTED")) => {
+                let params = json!({ "account": account, "recipient": [recipient], "trust-all-known-keys": true });
+                state.rpc("trust", params).await?;
+                tracing::info!(
+                    account,
+                    recipient,
+                    "Trusted changed identity key (trust policy always)"
+                );
+            }
+            (TrustPolicy::Manual, Some(level)) if level != "TRUSTED_VERIFIED" => {
+                untrusted.push(Untrusted {
+                    recipient: recipient.clone(),
+                    safety_number: identity(&identities, recipient)
+                        .and_then(|i| i["safetyNumber"].as_str())
+                        .map(String::from),
+                    trust_level: level.to_string(),
+                })
+            }
+            _ => {}
+        }
+    }
+    Ok(untrusted)
+}
+
+/// Apply the policy `account` has set to the send `body` (see [`enforce`]),
+/// returning the recipients it must not go to.
+pub async fn check(
+    state: &AppState,
+    account: &str,
+    body: &Value,
+) -> Result<Vec<Untrusted>, String> {
+    let policy = state
+        .trust_policies
+        .read()
+        .await
+        .get(account)
+        .copied()
+        .unwrap_or_default();
+    if policy == TrustPolicy::OnFirstUse {
+        return Ok(Vec::new());
+    }
+    let recipients = recipients(state, account, body).await?;
+    enforce(state, account, policy, &recipients).await
+}
+
+/// The body of the 409 refusing a send to `untrusted` recipients, listing
+/// them and the call that trusts them all.
+pub fn refusal(account: &str, untrusted: &[Untrusted]) -> Value {
+    let names: Vec<&str> = untrusted.iter().map(|u| u.recipient.as_str()).collect();
+    json!({
+        "error": "the account's trust policy is manual and these recipients' identity keys aren't verified",
+        "code": "UNTRUSTED_IDENTITY",
+        "recipients": untrusted,
+        "resolve": {
+            "method": "POST",
+            "path": format!("/v1/identities/{account}/trust"),
+            "body": { "recipients": names },
+        },
+    })
+}
@@ -1,61 +1,448 @@
-use crate::state::AppState;
-
-/// Extract the event type from a Signal notification JSON.
-/// Maps envelope fields to event type names:
-///   dataMessage -> "message", receiptMessage -> "receipt",
-///   typingMessage -> "typing", syncMessage -> "sync"
-fn extract_event_type(msg: &str) -> Option<&'static str> {
-    let parsed: serde_json::Value = serde_json::from_str(msg).ok()?;
-    let envelope = parsed.get("envelope")?;
-    if envelope.get("dataMessage").is_some() {
-        Some("message")
-    } else if envelope.get("receiptMessage").is_some() {
-        Some("receipt")
-    } else if envelope.get("typingMessage").is_some() {
-        Some("typing")
-    } else if envelope.get("syncMessage").is_some() {
-        Some("sync")
-    } else {
-        None
+use axum::body::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex};
+
+use crate::envelope;
+use crate::event::{Event, EventKind};
+use crate::language;
+use crate::outbound;
+use crate::secrets;
+use crate::state::{AppState, Metrics, WebhookConfig};
+use crate::tenant::Tenant;
+
+/// Delivery workers per webhook when it sets no `max_concurrency`.
+pub const DEFAULT_MAX_CONCURRENCY: usize = 16;
+
+/// Build the HTTP client for a webhook: outbound proxy, static headers
+/// (decrypted if sealed), mTLS identity and timeout. Errors describe the
+/// offending setting.
+pub fn build_client(hook: &WebhookConfig, st: &AppState) -> Result<reqwest::Client, String> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &hook.headers {
+        let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid header name: {name}"))?;
+        let value = secrets::reveal(value, st.secret_key.as_deref())
+            .map_err(|e| format!("header {name}: {e}"))?;
+        let value = reqwest::header::HeaderValue::from_str(&value)
+            .map_err(|_| format!("invalid value for header {name}"))?;
+        headers.insert(name, value);
+    }
+
+    let mut builder =
+        outbound::client_builder(st.outbound_proxy.as_ref())?.default_headers(headers);
+    match (&hook.client_cert, &hook.client_key) {
+        (Some(cert), Some(key)) => {
+            let read = |field: &str, path: &str| {
+                let file = cert_file(st, hook.tenant.as_deref(), field, path)?;
+                std::fs::read(file).map_err(|e| format!("{field} {path}: {e}"))
+            };
+            let mut pem = read("client_cert", cert)?;
+            pem.push(b'\n');
+            pem.extend(read("client_key", key)?);
+            let identity = reqwest::Identity::from_pem(&pem)
+                .map_err(|e| format!("invalid client certificate/key: {e}"))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err("client_cert and client_key must be set together".into()),
+    }
+    if let Some(ms) = hook.timeout_ms {
+        builder = builder.timeout(std::time::Duration::from_millis(ms));
     }
+    builder.build().map_err(|e| e.to_string())
 }
 
-/// Subscribes to the broadcast channel and POSTs each incoming message
-/// to all registered webhook URLs. Respects the `events` filter on each webhook.
-pub async fn dispatch_loop(state: AppState) {
-    let client = reqwest::Client::new();
-    let mut rx = state.broadcast_tx.subscribe();
+/// Where a webhook's `client_cert` or `client_key` is: a path relative to
+/// `--webhook-cert-dir`, or an absolute one inside it. A tenant's webhook is
+/// confined to the tenant's subdirectory, `<dir>/<tenant>/`, so it can't use
+/// another tenant's certificate. Anything else is refused before the
+/// filesystem is touched, so API callers can't have the server read, or
+/// probe for, files elsewhere.
+fn cert_file(
+    st: &AppState,
+    tenant: Option<&str>,
+    field: &str,
+    path: &str,
+) -> Result<std::path::PathBuf, String> {
+    let Some(dir) = &st.webhook_cert_dir else {
+        return Err(format!(
+            "{field} needs the server started with --webhook-cert-dir"
+        ));
+    };
+    // Tenant names are letters, digits, '-' and '_', so this stays inside.
+    let dir = &tenant.map_or_else(|| dir.clone(), |t| dir.join(t));
+    let outside = || match tenant {
+        Some(t) => format!("{field} must be a file in {t}/ of --webhook-cert-dir"),
+        None => format!("{field} must be a file in --webhook-cert-dir"),
+    };
+    let path = std::path::Path::new(path);
+    let climbs = path
+        .components()
+        .any(|c| matches!(c, std::path::Component::ParentDir));
+    if climbs || (path.is_absolute() && !path.starts_with(dir)) {
+        return Err(outside());
+    }
+    // Symlinks in the directory may still point out of it.
+    let resolved = dir
+        .join(path)
+        .canonicalize()
+        .map_err(|e| format!("{field} {}: {e}", path.display()))?;
+    if !resolved.starts_with(dir) {
+        return Err(outside());
+    }
+    Ok(resolved)
+}
+
+/// Events queued per webhook; further events are dropped until its workers
+/// catch up.
+pub const QUEUE_CAPACITY: usize = 1024;
+
+/// Most events a batching webhook's `max_events` may ask for.
+pub const MAX_BATCH_EVENTS: usize = 1000;
+
+/// Longest `max_wait_ms` a batching webhook may ask for.
+pub const MAX_BATCH_WAIT_MS: u64 = 60_000;
+
+/// A webhook's bounded queues, one for live events and one for replays,
+/// drained by its own workers. Lanes are built when the webhook list
+/// changes; dropping one closes its queues, and its workers exit once they
+/// have delivered what was already queued.
+struct Lane {
+    config: WebhookConfig,
+    queue: mpsc::Sender<Event>,
+    replays: mpsc::Sender<Event>,
+}
+
+/// The receiving ends of a lane's queues.
+struct Queues {
+    live: mpsc::Receiver<Event>,
+    replays: mpsc::Receiver<Event>,
+}
+
+/// Header marking a delivery as a replay of a stored event (see [`replay`]).
+pub const REPLAY_HEADER: &str = "x-signal-replay";
+
+/// Stored events waiting to be put on a webhook's lane (see [`replay`]).
+pub struct Replay {
+    webhook: String,
+    events: Vec<Event>,
+}
+
+/// POST a body to a webhook; true when it answered 2xx.
+async fn deliver(client: &reqwest::Client, url: &str, body: Bytes, replay: bool) -> bool {
+    let mut request = client.post(url).header("content-type", "application/json");
+    if replay {
+        request = request.header(REPLAY_HEADER, "true");
+    }
+    match request.body(body).send().await {
+        Ok(res) => res.status().is_success(),
+        Err(e) => {
+            tracing::warn!("Webhook delivery to {url} failed: {e}");
+            false
+        }
+    }
+}
+
+/// The body delivered to a webhook: the event as streams get it, with
+/// attachment data stripped for `metadata_only` webhooks and bodies over
+/// `max_payload_bytes`. None when it is still over the limit, or for a
+/// batching webhook too big to go out even in a batch of its own.
+fn payload(hook: &WebhookConfig, event: &Event) -> Option<Bytes> {
+    let body = event.bytes_for_client(hook.raw);
+    let brackets = if hook.batch.is_some() { 2 } else { 0 };
+    let fits = |len: usize| {
+        hook.max_payload_bytes
+            .is_none_or(|max| len + brackets <= max)
+    };
+    if !hook.metadata_only && fits(body.len()) {
+        return Some(body);
+    }
+    let Ok(mut json) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return fits(body.len()).then_some(body);
+    };
+    envelope::strip_attachment_data(&mut json);
+    let body = Bytes::from(json.to_string());
+    fits(body.len()).then_some(body)
+}
+
+/// Take the next delivery's events off a webhook's queues: one event, or
+/// for a batching webhook up to `max_events` arriving within `max_wait_ms`
+/// of the first. Live events go first; replays fill in when there are none,
+/// and a delivery is either all replays (true) or none. Empty once both
+/// queues are closed and drained.
+async fn next_events(hook: &WebhookConfig, queues: &Mutex<Queues>) -> (Vec<Event>, bool) {
+    let mut queues = queues.lock().await;
+    let Queues { live, replays } = &mut *queues;
+    let (first, replay) = tokio::select! {
+        biased;
+        Some(event) = live.recv() => (event, false),
+        Some(event) = replays.recv() => (event, true),
+        else => return (Vec::new(), false),
+    };
+    let queue = if replay { replays } else { live };
+    let mut events = vec![first];
+    let Some(batch) = hook.batch else {
+        return (events, replay);
+    };
+    let deadline =
+        tokio::time::Instant::now() + std::time::Duration::from_millis(batch.max_wait_ms);
+    while events.len() < batch.max_events {
+        match tokio::time::timeout_at(deadline, queue.recv()).await {
+            Ok(Some(event)) => events.push(event),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    (events, replay)
+}
+
+/// Pack event bodies into a webhook's deliveries, each with the number of
+/// bodies in it. Without `batch` every body goes on its own; with it they
+/// go as JSON arrays of up to `max_events`, split earlier where the array
+/// would be over `max_payload_bytes`.
+fn pack(hook: &WebhookConfig, bodies: Vec<Bytes>) -> Vec<(usize, Bytes)> {
+    let Some(batch) = hook.batch else {
+        return bodies.into_iter().map(|body| (1, body)).collect();
+    };
+    let max_bytes = hook.max_payload_bytes.unwrap_or(usize::MAX);
+    let mut packed = Vec::new();
+    let mut array = Vec::new();
+    let mut count = 0;
+    for body in bodies {
+        // A comma, the body and the closing bracket.
+        if count > 0 && (count == batch.max_events || array.len() + body.len() + 2 > max_bytes) {
+            array.push(b']');
+            packed.push((count, Bytes::from(std::mem::take(&mut array))));
+            count = 0;
+        }
+        array.push(if count == 0 { b'[' } else { b',' });
+        array.extend_from_slice(&body);
+        count += 1;
+    }
+    if count > 0 {
+        array.push(b']');
+        packed.push((count, Bytes::from(array)));
+    }
+    packed
+}
 
-    while let Ok(msg) = rx.recv().await {
-        let event_type = extract_event_type(&msg);
-        let hooks = state.webhooks.read().await.clone();
-        for hook in hooks.iter() {
-            // Skip if the webhook has an event filter and this event doesn't match
-            if !hook.events.is_empty() {
-                if let Some(et) = event_type {
-                    if !hook.events.iter().any(|e| e == et) {
-                        continue;
-                    }
-                } else {
-                    // Unknown event type and webhook has a filter — skip
-                    continue;
+async fn worker(
+    client: reqwest::Client,
+    hook: WebhookConfig,
+    queues: Arc<Mutex<Queues>>,
+    metrics: Arc<Metrics>,
+    tenant: Option<Arc<Tenant>>,
+) {
+    loop {
+        // The lock is released before delivering, so the next idle worker
+        // can take the following events.
+        let (events, replay) = next_events(&hook, &queues).await;
+        if events.is_empty() {
+            break;
+        }
+        let mut bodies = Vec::with_capacity(events.len());
+        for event in &events {
+            match payload(&hook, event) {
+                Some(body) => bodies.push((event, body)),
+                None => {
+                    metrics
+                        .webhook_oversized
+                        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    tracing::warn!(
+                        webhook = hook.id,
+                        "Event over the webhook's max_payload_bytes, not delivered"
+                    );
                 }
             }
+        }
+        let mut sent = bodies.iter().map(|(event, _)| event);
+        for (count, body) in pack(&hook, bodies.iter().map(|(_, body)| body.clone()).collect()) {
+            let events: Vec<_> = sent.by_ref().take(count).collect();
+            if !deliver(&client, &hook.url, body, replay).await {
+                continue;
+            }
+            for event in events
+                .into_iter()
+                .filter(|e| !replay && e.notification().is_some())
+            {
+                metrics
+                    .webhook_delivery_latency
+                    .observe(event.age().as_millis() as u64);
+            }
+            if let Some(tenant) = &tenant {
+                tenant.inc_webhook_delivery();
+            }
+        }
+    }
+}
+
+/// Start a webhook's workers: one for ordered webhooks, otherwise its
+/// `max_concurrency` or `--webhook-workers`.
+fn open_lane(hook: &WebhookConfig, st: &AppState) -> Result<Lane, String> {
+    let client = build_client(hook, st)?;
+    let workers = if hook.ordered {
+        1
+    } else {
+        hook.max_concurrency.unwrap_or(st.webhook_workers).max(1)
+    };
+    let (tx, live) = mpsc::channel(QUEUE_CAPACITY);
+    let (replay_tx, replays) = mpsc::channel(QUEUE_CAPACITY);
+    let queues = Arc::new(Mutex::new(Queues { live, replays }));
+    let tenant = hook
+        .tenant
+        .as_deref()
+        .and_then(|name| st.tenants.get(name))
+        .cloned();
+    for _ in 0..workers {
+        tokio::spawn(worker(
+            client.clone(),
+            hook.clone(),
+            queues.clone(),
+            st.metrics.clone(),
+            tenant.clone(),
+        ));
+    }
+    Ok(Lane {
+        config: hook.clone(),
+        queue: tx,
+        replays: replay_tx,
+    })
+}
+
+/// Bring the lanes in line with the configured webhooks: unchanged webhooks
+/// keep their lanes (and queued events), changed ones get new lanes while
+/// the old ones drain, removed ones are closed.
+async fn reconfigure(state: &AppState, lanes: &mut HashMap<String, Lane>) -> Vec<WebhookConfig> {
+    let hooks = state.webhooks.read().await.clone();
+    lanes.retain(|id, lane| hooks.iter().any(|h| &h.id == id && h == &lane.config));
+    for hook in &hooks {
+        if lanes.contains_key(&hook.id) {
+            continue;
+        }
+        match open_lane(hook, state) {
+            Ok(lane) => {
+                lanes.insert(hook.id.clone(), lane);
+            }
+            Err(e) => tracing::warn!("Webhook {} skipped: {e}", hook.id),
+        }
+    }
+    tracing::debug!(
+        webhooks = hooks.len(),
+        lanes = lanes.len(),
+        "Webhook lanes configured"
+    );
+    hooks
+}
 
-            let client = client.clone();
-            let url = hook.url.clone();
-            let body = msg.clone();
-            tokio::spawn(async move {
-                if let Err(e) = client
-                    .post(&url)
-                    .header("content-type", "application/json")
-                    .body(body)
-                    .send()
-                    .await
-                {
-                    tracing::warn!("Webhook delivery to {url} failed: {e}");
+/// Whether a webhook's `events`, `filter` and `languages` accept this event,
+/// and, for a tenant's webhook, whether it is for one of the tenant's accounts.
+fn wants(hook: &WebhookConfig, event: &Event, state: &AppState) -> bool {
+    if let Some(name) = &hook.tenant {
+        let Some(((account, _), tenant)) = event.notification().zip(state.tenants.get(name)) else {
+            return false;
+        };
+        if !tenant.owns(account, |a| state.resolve_account(a)) {
+            return false;
+        }
+    }
+    let event_type = event.kind().name();
+    if !hook.events.is_empty() && !event_type.is_some_and(|et| hook.events.iter().any(|e| e == et))
+    {
+        return false;
+    }
+    if hook.filter.as_ref().is_some_and(|f| !f.matches(event)) {
+        return false;
+    }
+    if hook.languages.is_empty() || event.kind() != EventKind::Message {
+        return true;
+    }
+    let code = event.language().map_or(language::UNDETERMINED, |l| l.code);
+    hook.languages.iter().any(|l| l == code)
+}
+
+/// Re-deliver stored events to a webhook, oldest first, with its filters
+/// and the [`REPLAY_HEADER`]. Returns how many events it will get. They are
+/// handed to the dispatcher, which puts them on the webhook's lane, so they
+/// go out under its concurrency, ordering, payload and batching settings,
+/// whenever there are no live events to deliver. Failures aren't retried.
+pub fn replay(state: &AppState, hook: &WebhookConfig, events: Vec<Event>) -> usize {
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|e| wants(hook, e, state))
+        .collect();
+    let count = events.len();
+    state.webhook_replays.lock().unwrap().push(Replay {
+        webhook: hook.id.clone(),
+        events,
+    });
+    state.webhooks_changed.notify_one();
+    count
+}
+
+/// Feed waiting replays into their webhooks' lanes, each from a task of its
+/// own that waits whenever the lane's replay queue is full.
+fn queue_replays(state: &AppState, lanes: &HashMap<String, Lane>) {
+    let replays = std::mem::take(&mut *state.webhook_replays.lock().unwrap());
+    for Replay { webhook, events } in replays {
+        let Some(lane) = lanes.get(&webhook) else {
+            tracing::warn!(webhook, "Webhook has no lane, replay dropped");
+            continue;
+        };
+        let queue = lane.replays.clone();
+        tokio::spawn(async move {
+            for event in events {
+                if queue.send(event).await.is_err() {
+                    break;
                 }
-            });
+            }
+        });
+    }
+}
+
+/// Subscribes to the broadcast channel and queues each incoming message for
+/// every webhook whose `events` filter matches. Lanes are rebuilt only when
+/// the webhook list changes (see `AppState::webhooks_changed`), before any
+/// replays asked for since are queued; a full queue drops the event for
+/// that webhook rather than holding up the others.
+pub async fn dispatch_loop(state: AppState) {
+    let mut rx = state.broadcast_tx.subscribe();
+    let mut lanes: HashMap<String, Lane> = HashMap::new();
+    let mut hooks = reconfigure(&state, &mut lanes).await;
+    queue_replays(&state, &lanes);
+
+    loop {
+        let msg = tokio::select! {
+            biased;
+            _ = state.webhooks_changed.notified() => {
+                hooks = reconfigure(&state, &mut lanes).await;
+                queue_replays(&state, &lanes);
+                continue;
+            }
+            msg = rx.recv() => msg,
+        };
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Webhook dispatcher lagged, {n} notifications not delivered");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        // Another replica delivers while this one isn't the leader.
+        if !state.leadership.is_leader() {
+            continue;
+        }
+        for hook in hooks.iter().filter(|h| wants(h, &msg, &state)) {
+            let Some(lane) = lanes.get(&hook.id) else {
+                continue;
+            };
+            if let Err(mpsc::error::TrySendError::Full(_)) = lane.queue.try_send(msg.clone()) {
+                state
+                    .metrics
+                    .webhook_dropped
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                tracing::warn!(webhook = hook.id, "Webhook queue full, event dropped");
+            }
         }
     }
 }
@@ -0,0 +1,484 @@
+//! Contract tests against real signal-cli daemons.
+//!
+//! The routes are exercised against each daemon through a tap that records
+//! the JSON-RPC requests they make. Each request must name a method the
+//! daemon knows (it doesn't answer "method not found"), and each of its
+//! parameters must be an option of the signal-cli command of the same name,
+//! as `signal-cli <command> --help` lists them. That catches parameter drift
+//! ("member" vs "members") when signal-cli renames things.
+//!
+//! Skipped unless `SIGNAL_CLI_CONTRACT` lists signal-cli executables, one per
+//! pinned version, separated by commas:
+//!
+//! ```text
+//! SIGNAL_CLI_CONTRACT=/opt/signal-cli-0.13.9/bin/signal-cli,/opt/signal-cli-0.13.18/bin/signal-cli \
+//!     cargo test --test contract -- --nocapture
+//! ```
+//!
+//! The daemons run without accounts, so every account-scoped call fails
+//! inside signal-cli after the method was resolved. Routes that would reach
+//! Signal's servers even so (registration, linking, changing number) are
+//! left out.
+
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const ACCOUNT: &str = "+15550001111";
+const PEER: &str = "+15550002222";
+const GROUP: &str = "ZXhhbXBsZS1ncm91cC1pZC0xMjM0NTY3ODkwYWJjZGU=";
+
+/// JSON-RPC's "method not found".
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// How long a daemon gets to start listening; the JVM is slow to start.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// One request per route (method, path, body), covering every route module
+/// that calls signal-cli.
+fn requests() -> Vec<(&'static str, String, Option<Value>)> {
+    let a = ACCOUNT;
+    let g = GROUP;
+    let ts = 1_700_000_000_000u64;
+    vec![
+        // accounts
+        ("GET", "/v1/accounts".into(), None),
+        ("POST", format!("/v1/accounts/{a}/sync"), Some(json!({}))),
+        (
+            "PUT",
+            format!("/v1/accounts/{a}/settings"),
+            Some(json!({ "trust_mode": "always" })),
+        ),
+        (
+            "POST",
+            format!("/v1/accounts/{a}/pin"),
+            Some(json!({ "pin": "123456" })),
+        ),
+        ("DELETE", format!("/v1/accounts/{a}/pin"), None),
+        (
+            "POST",
+            format!("/v1/accounts/{a}/username"),
+            Some(json!({ "username": "contract.01" })),
+        ),
+        ("DELETE", format!("/v1/accounts/{a}/username"), None),
+        // attachments
+        ("GET", "/v1/attachments".into(), None),
+        ("GET", "/v1/attachments/contract.txt".into(), None),
+        ("DELETE", "/v1/attachments/contract.txt".into(), None),
+        // config
+        ("GET", "/v1/configuration".into(), None),
+        ("GET", format!("/v1/configuration/{a}/settings"), None),
+        (
+            "POST",
+            format!("/v1/configuration/{a}/settings"),
+            Some(json!({ "trustMode": "always" })),
+        ),
+        // contacts
+        ("GET", format!("/v1/contacts/{a}"), None),
+        (
+            "PUT",
+            format!("/v1/contacts/{a}"),
+            Some(json!({ "recipient": PEER, "name": "Contract", "expiration": 3600 })),
+        ),
+        ("POST", format!("/v1/contacts/{a}/sync"), None),
+        // devices
+        ("GET", format!("/v1/devices/{a}"), None),
+        ("DELETE", format!("/v1/devices/{a}/2"), None),
+        // groups
+        ("GET", format!("/v1/groups/{a}"), None),
+        (
+            "POST",
+            format!("/v1/groups/{a}"),
+            Some(json!({
+                "name": "Contract",
+                "members": [PEER],
+                "description": "contract test",
+                "permissions": { "add_members": "only-admins", "edit_details": "only-admins" }
+            })),
+        ),
+        (
+            "PUT",
+            format!("/v1/groups/{a}/{g}"),
+            Some(json!({ "name": "Contract", "description": "d", "expiration": 3600 })),
+        ),
+        (
+            "POST",
+            format!("/v1/groups/{a}/{g}/members"),
+            Some(json!({ "members": [PEER] })),
+        ),
+        (
+            "DELETE",
+            format!("/v1/groups/{a}/{g}/members"),
+            Some(json!({ "members": [PEER] })),
+        ),
+        (
+            "POST",
+            format!("/v1/groups/{a}/{g}/admins"),
+            Some(json!({ "admins": [PEER] })),
+        ),
+        (
+            "DELETE",
+            format!("/v1/groups/{a}/{g}/admins"),
+            Some(json!({ "admins": [PEER] })),
+        ),
+        ("POST", format!("/v1/groups/{a}/{g}/join"), None),
+        ("POST", format!("/v1/groups/{a}/{g}/quit"), None),
+        ("POST", format!("/v1/groups/{a}/{g}/block"), None),
+        ("DELETE", format!("/v1/groups/{a}/{g}"), None),
+        // identities
+        ("GET", format!("/v1/identities/{a}"), None),
+        (
+            "PUT",
+            format!("/v1/identities/{a}/trust/{PEER}"),
+            Some(json!({ "trust_all_known_keys": true })),
+        ),
+        // messages
+        (
+            "POST",
+            "/v2/send".into(),
+            Some(
+                json!({ "number": a, "recipients": [PEER], "message": "contract", "base64_attachments": ["aGk="] }),
+            ),
+        ),
+        (
+            "DELETE",
+            format!("/v1/remote-delete/{a}"),
+            Some(json!({ "recipient": PEER, "timestamp": ts })),
+        ),
+        // polls, which pass their bodies on, so only the method is checked
+        (
+            "POST",
+            format!("/v1/polls/{a}"),
+            Some(json!({ "recipient": [PEER] })),
+        ),
+        (
+            "POST",
+            format!("/v1/polls/{a}/vote"),
+            Some(json!({ "recipient": [PEER] })),
+        ),
+        (
+            "DELETE",
+            format!("/v1/polls/{a}"),
+            Some(json!({ "recipient": [PEER] })),
+        ),
+        // profiles
+        (
+            "PUT",
+            format!("/v1/profiles/{a}"),
+            Some(json!({ "name": "Contract", "about": "contract test" })),
+        ),
+        // reactions
+        (
+            "POST",
+            format!("/v1/reactions/{a}"),
+            Some(
+                json!({ "recipient": PEER, "reaction": "👍", "target_author": PEER, "timestamp": ts }),
+            ),
+        ),
+        (
+            "DELETE",
+            format!("/v1/reactions/{a}"),
+            Some(
+                json!({ "recipient": PEER, "reaction": "👍", "target_author": PEER, "timestamp": ts }),
+            ),
+        ),
+        // receipts
+        (
+            "POST",
+            format!("/v1/receipts/{a}"),
+            Some(json!({ "receipt_type": "read", "recipient": PEER, "timestamp": ts })),
+        ),
+        // search
+        ("GET", format!("/v1/search/{a}?numbers={PEER}"), None),
+        // stickers
+        ("GET", format!("/v1/sticker-packs/{a}"), None),
+        (
+            "GET",
+            format!("/v1/sticker-packs/{a}/0123456789abcdef/stickers/0"),
+            None,
+        ),
+        // typing
+        (
+            "PUT",
+            format!("/v1/typing-indicator/{a}"),
+            Some(json!({ "recipient": PEER })),
+        ),
+        (
+            "DELETE",
+            format!("/v1/typing-indicator/{a}"),
+            Some(json!({ "recipient": PEER })),
+        ),
+    ]
+}
+
+/// A JSON-RPC request seen by the tap, with the error it got, if any.
+struct Call {
+    method: String,
+    params: Value,
+    error: Option<Value>,
+}
+
+#[derive(Default)]
+struct Traffic {
+    calls: Vec<Call>,
+    /// Index into `calls` by request id, until the response arrives.
+    pending: HashMap<String, usize>,
+}
+
+/// A running daemon, killed (with the JVM the launcher script started) on drop.
+struct Daemon {
+    child: tokio::process::Child,
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Some(pid) = self.child.id() {
+            signal_cli_api::daemon::kill_process_group(pid as i32);
+        }
+    }
+}
+
+async fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+async fn start_daemon(bin: &str, config: &std::path::Path) -> (Daemon, SocketAddr) {
+    let addr: SocketAddr = format!("127.0.0.1:{}", free_port().await).parse().unwrap();
+    let child = unsafe {
+        tokio::process::Command::new(bin)
+            .arg("--config")
+            .arg(config)
+            .args(["daemon", "--tcp", &addr.to_string()])
+            .kill_on_drop(true)
+            .pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            })
+            .spawn()
+            .unwrap_or_else(|e| panic!("starting {bin}: {e}"))
+    };
+    let daemon = Daemon { child };
+    let start = std::time::Instant::now();
+    while TcpStream::connect(addr).await.is_err() {
+        assert!(
+            start.elapsed() < STARTUP_TIMEOUT,
+            "{bin} didn't listen on {addr} within {STARTUP_TIMEOUT:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    (daemon, addr)
+}
+
+/// A proxy in front of the daemon recording every request and its error.
+async fn start_tap(daemon: SocketAddr) -> (SocketAddr, Arc<Mutex<Traffic>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let traffic = Arc::new(Mutex::new(Traffic::default()));
+    let recorded = traffic.clone();
+    tokio::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let upstream = TcpStream::connect(daemon).await.unwrap();
+        let (client_read, mut client_write) = client.into_split();
+        let (upstream_read, mut upstream_write) = upstream.into_split();
+        let requests = recorded.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(client_read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Ok(request) = serde_json::from_str::<Value>(&line) {
+                    let mut traffic = requests.lock().unwrap();
+                    let index = traffic.calls.len();
+                    traffic.calls.push(Call {
+                        method: request["method"].as_str().unwrap_or("").to_string(),
+                        params: request["params"].clone(),
+                        error: None,
+                    });
+                    traffic.pending.insert(request["id"].to_string(), index);
+                }
+                if upstream_write
+                    .write_all(format!("{}\n", line.trim_end()).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let mut lines = BufReader::new(upstream_read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Ok(response) = serde_json::from_str::<Value>(&line) {
+                let mut traffic = recorded.lock().unwrap();
+                if let Some(index) = traffic.pending.remove(&response["id"].to_string()) {
+                    traffic.calls[index].error = response.get("error").cloned();
+                }
+            }
+            if client_write
+                .write_all(format!("{line}\n").as_bytes())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    (addr, traffic)
+}
+
+async fn start_api(signal_cli: SocketAddr) -> String {
+    let stream = TcpStream::connect(signal_cli).await.unwrap();
+    let (reader, writer) = stream.into_split();
+    let (writer_tx, writer_rx) = tokio::sync::mpsc::channel::<String>(256);
+    tokio::spawn(signal_cli_api::jsonrpc::writer_loop(writer_rx, writer));
+    let state = signal_cli_api::state::AppState::new(writer_tx);
+    tokio::spawn(signal_cli_api::jsonrpc::reader_loop(
+        reader,
+        state.broadcast_tx.clone(),
+        state.pending.clone(),
+        state.metrics.clone(),
+        state.plugins.clone(),
+    ));
+    state.detect_signal_cli_version().await;
+    let app = signal_cli_api::routes::router(state);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+/// `group-id`, `groupId` and `group_id` all name the same option.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The options and positional arguments of a signal-cli command, or `None`
+/// when it has no command-line form (JSON-RPC only).
+async fn command_options(bin: &str, command: &str) -> Option<BTreeSet<String>> {
+    let output = tokio::process::Command::new(bin)
+        .args([command, "--help"])
+        .output()
+        .await
+        .ok()?;
+    let help = String::from_utf8_lossy(&output.stdout);
+    if !help.contains("usage:") {
+        return None;
+    }
+    let mut options: BTreeSet<String> = help
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']')
+        .filter_map(|word| word.strip_prefix("--"))
+        .filter(|name| !name.is_empty())
+        .map(normalize)
+        .collect();
+    let mut positional = false;
+    for line in help.lines() {
+        if line.starts_with("positional arguments:") {
+            positional = true;
+        } else if positional && line.starts_with("  ") {
+            if let Some(name) = line
+                .split_whitespace()
+                .next()
+                .filter(|n| !n.starts_with('-'))
+            {
+                options.insert(normalize(name));
+            }
+        } else if positional {
+            positional = false;
+        }
+    }
+    Some(options)
+}
+
+/// Whether `param` names one of `options`. signal-cli also takes the plural
+/// of a list option ("members" for `--member`).
+fn accepts(options: &BTreeSet<String>, param: &str) -> bool {
+    let param = normalize(param);
+    options.contains(&param) || param.strip_suffix('s').is_some_and(|p| options.contains(p))
+}
+
+/// Exercise every route against one signal-cli, returning what it rejected.
+async fn check(bin: &str) -> Vec<String> {
+    let config = std::env::temp_dir().join(format!(
+        "signal-cli-contract-{}-{}",
+        std::process::id(),
+        free_port().await
+    ));
+    std::fs::create_dir_all(&config).unwrap();
+    let (_daemon, daemon_addr) = start_daemon(bin, &config).await;
+    let (tap, traffic) = start_tap(daemon_addr).await;
+    let base = start_api(tap).await;
+
+    let client = reqwest::Client::new();
+    for (method, path, body) in requests() {
+        let mut request = client.request(method.parse().unwrap(), format!("{base}{path}"));
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        request
+            .send()
+            .await
+            .unwrap_or_else(|e| panic!("{method} {path}: {e}"));
+    }
+
+    let calls = std::mem::take(&mut traffic.lock().unwrap().calls);
+    assert!(!calls.is_empty(), "no route reached {bin}");
+    let mut problems = BTreeSet::new();
+    let mut help = HashMap::new();
+    for call in calls {
+        if call.error.as_ref().and_then(|e| e["code"].as_i64()) == Some(METHOD_NOT_FOUND) {
+            problems.insert(format!("{}: no such method", call.method));
+            continue;
+        }
+        if !help.contains_key(&call.method) {
+            help.insert(
+                call.method.clone(),
+                command_options(bin, &call.method).await,
+            );
+        }
+        let Some(options) = &help[&call.method] else {
+            continue;
+        };
+        for param in call.params.as_object().into_iter().flat_map(|p| p.keys()) {
+            if param != "account" && !accepts(options, param) {
+                problems.insert(format!("{}: unknown parameter {param:?}", call.method));
+            }
+        }
+    }
+    let _ = std::fs::remove_dir_all(&config);
+    problems.into_iter().collect()
+}
+
+#[tokio::test]
+async fn rpc_calls_match_signal_cli() {
+    let Ok(bins) = std::env::var("SIGNAL_CLI_CONTRACT") else {
+        eprintln!("SIGNAL_CLI_CONTRACT not set, skipping contract tests");
+        return;
+    };
+    let mut failures = Vec::new();
+    for bin in bins.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+        let version = std::process::Command::new(bin)
+            .arg("--version")
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_else(|e| panic!("running {bin}: {e}"));
+        for problem in check(bin).await {
+            failures.push(format!("{version}: {problem}"));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "RPC calls signal-cli rejects:\n{}",
+        failures.join("\n")
+    );
+}